@@ -0,0 +1,52 @@
+/*
+ * C API of ASM-VM, toy virtual machine for 32-bits assembly.
+ *
+ * Build the shared library with `cargo build --release`, and link with `-lasm_vm`.
+ *
+ * Every function returns a negative value on failure, whose reason can be read by `asmvm_last_error`.
+ */
+
+#ifndef ASMVM_H
+#define ASMVM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Virtual machine, owned by the library. */
+typedef struct AsmVm AsmVm;
+
+/* Create a virtual machine, which must be released by `asmvm_free`. */
+AsmVm *asmvm_new(void);
+
+/* Release a virtual machine, `vm` may be null. */
+void asmvm_free(AsmVm *vm);
+
+/* Load assembly source code and stop before its first instruction, `name` is only used in error messages.
+ * Returns 0 on success and -1 on failure. */
+int asmvm_load_source(AsmVm *vm, const char *name, const char *source);
+
+/* Execute one instruction.
+ * Returns 1 if the program can continue, 0 if it is finished, and -1 on failure. */
+int asmvm_step(AsmVm *vm);
+
+/* Read a 32-bits general register by its name, such as "eax", into `value`.
+ * Returns 0 on success and -1 on failure. */
+int asmvm_get_register(AsmVm *vm, const char *name, uint32_t *value);
+
+/* Copy `size` bytes of memory at `address` into `buffer`.
+ * Returns 0 on success and -1 on failure. */
+int asmvm_read_mem(AsmVm *vm, uint32_t address, uint8_t *buffer, size_t size);
+
+/* Get the error message of the last failed call, or null if it succeeded.
+ * The message is valid until the next call with the same virtual machine. */
+const char *asmvm_last_error(const AsmVm *vm);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* ASMVM_H */
@@ -0,0 +1,1142 @@
+use crate::token::*;
+use crate::scanner::*;
+use crate::instruction::*;
+use crate::cfg::{is_data, is_directive};
+use crate::link::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// bytes of machine code on one line of listing
+const LISTING_BYTES: usize = 10;
+
+/// One line of assembly listing
+pub struct ListingLine {
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    pub statement: Statement,
+}
+
+/// Machine code assembled from source files
+pub struct Program {
+    /// load address of the first byte
+    pub origin: u32,
+    pub lines: Vec<ListingLine>,
+}
+
+impl Program {
+    /// Get the machine code as a flat binary, whose first byte is loaded at `origin`.
+    pub fn get_bytes(&self) -> Vec<u8> {
+        self.lines.iter().flat_map(|line| line.bytes.to_owned()).collect()
+    }
+
+    /// Format the program as source code, which can be assembled or run again.
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+
+        for line in &self.lines {
+            match &line.statement {
+                Statement::Label(name, _) => source.push_str(&format!("{}:\n", name)),
+                Statement::Instruction(instruction) => source.push_str(&format!("    {}\n", instruction)),
+            }
+        }
+
+        source
+    }
+
+    /// Insert a label before the instruction at `address`, returns `false` if no instruction starts there.
+    pub fn insert_label(&mut self, name: &str, address: u32) -> bool {
+        let position = match self.lines.iter().position(|line| line.address == address) {
+            Some(position) => position,
+            None => return false,
+        };
+
+        let location = match &self.lines[position].statement {
+            Statement::Label(_, location) => location.to_owned(),
+            Statement::Instruction(instruction) => instruction.location.to_owned(),
+        };
+
+        self.lines.insert(position, ListingLine {
+            address,
+            bytes: Vec::new(),
+            statement: Statement::Label(name.to_string(), location),
+        });
+
+        true
+    }
+
+    /// Get the address after the last byte of the program.
+    pub fn get_end(&self) -> u32 {
+        self.lines.last().map_or(self.origin, |line| line.address.wrapping_add(line.bytes.len() as u32))
+    }
+
+    /// Format the map of the program, which lists the section, address, size and source of every label in the order
+    /// of their addresses.
+    ///
+    /// A label followed by data, such as `incbin`, is in `.data`, and any other label is in `.text`. The size of a
+    /// label is the number of bytes up to the next label, or up to the end of the program.
+    pub fn to_map(&self) -> String {
+        let labels: Vec<(u32, &str, &TokenLocation, &str)> = self.lines.iter().enumerate()
+            .filter_map(|(position, line)| match &line.statement {
+                Statement::Label(name, _) if is_location_label(name) => None,
+                Statement::Label(name, location) => {
+                    let mnemonic = self.lines[position..].iter().find_map(|line| match &line.statement {
+                        Statement::Instruction(instruction) if !is_directive(instruction.mnemonic) => {
+                            Some(instruction.mnemonic)
+                        },
+                        _ => None,
+                    });
+                    let section = if mnemonic.is_some_and(is_data) { ".data" } else { ".text" };
+                    Some((line.address, name.as_str(), location, section))
+                },
+                Statement::Instruction(_) => None,
+            })
+            .collect();
+
+        let end = self.get_end();
+        let mut map = format!("Origin: {:#010x}, size: {} bytes\n\n", self.origin, end.wrapping_sub(self.origin));
+        map.push_str(&format!("{:<10}{:<10}{:<10}{:<24}{}\n", "Section", "Address", "Size", "Symbol", "Source"));
+
+        for (i, (address, name, location, section)) in labels.iter().enumerate() {
+            let next = labels.get(i + 1).map_or(end, |label| label.0);
+            map.push_str(&format!("{:<10}{:08X}  {:08X}  {:<24}{}:{}\n", section, address, next.wrapping_sub(*address),
+                                  name, location.get_source_file_name(), location.get_line()));
+        }
+
+        map
+    }
+
+    /// Format the program as a listing of address, machine code and source.
+    pub fn to_listing(&self) -> String {
+        let mut listing = String::new();
+
+        for line in &self.lines {
+            let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            // the rest of long machine code, such as the bytes of `incbin`, goes on the next lines
+            let mut chunks = bytes.chunks(LISTING_BYTES);
+
+            match &line.statement {
+                // the labels of `$` and `$$` are not written in the source
+                Statement::Label(name, _) if is_location_label(name) => {},
+                Statement::Label(name, _) => listing.push_str(&format!("{:08X}  {:<30}{}:\n", line.address, "", name)),
+                // the zeros of a gap are not listed
+                Statement::Instruction(instruction) if instruction.mnemonic == TokenValue::ORG => {
+                    let address = line.address.wrapping_add(line.bytes.len() as u32);
+                    listing.push_str(&format!("{:08X}  {:<30}    {}\n", address, "", instruction));
+                    continue;
+                },
+                Statement::Instruction(instruction) => listing.push_str(&format!("{:08X}  {:<30}    {}\n", line.address,
+                            chunks.next().unwrap_or_default().join(" "), instruction)),
+            }
+
+            for (i, chunk) in chunks.enumerate() {
+                let address = line.address.wrapping_add(((i + 1) * LISTING_BYTES) as u32);
+                listing.push_str(&format!("{:08X}  {}\n", address, chunk.join(" ")));
+            }
+        }
+
+        listing
+    }
+}
+
+/// Read all tokens from a source file with `options`.
+pub fn scan_file(source_file_name: String, options: &SourceOptions) -> Vec<Token> {
+    scan(Scanner::new(source_file_name), options)
+}
+
+/// Read all tokens from source code in memory with `options`, `source_name` is used in locations.
+pub fn scan_source(source_name: String, source: String, options: &SourceOptions) -> Vec<Token> {
+    scan(Scanner::from_source(source_name, source), options)
+}
+
+fn scan(mut scanner: Scanner, options: &SourceOptions) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    scanner.set_options(options);
+
+    loop {
+        let token = scanner.get_next_token();
+
+        if token.get_token_type() == TokenType::END_OF_FILE {
+            break;
+        }
+
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+/// Scan with `options` and parse several source files into the statements of one program, in the given order.
+pub fn parse_files(source_file_names: Vec<String>, options: &SourceOptions) -> Result<Vec<Statement>, String> {
+    let mut statements = Vec::new();
+
+    for source_file_name in source_file_names {
+        let mut tokens = scan_file(source_file_name, options);
+
+        if let Some(msg) = preprocess_tokens(&mut tokens, options).into_iter().next() {
+            return Err(msg);
+        }
+
+        statements.append(&mut Parser::new(&tokens).parse_program()?);
+    }
+
+    if let Some(msg) = link(&statements).into_iter().next() {
+        return Err(msg);
+    }
+
+    Ok(statements)
+}
+
+/// Assemble several source files, read with `options`, into one program loaded at `origin`, which share one label
+/// table, checking their `global` and `extern` declarations.
+pub fn assemble_files(source_file_names: Vec<String>, origin: u32, options: &SourceOptions)
+    -> Result<Program, String> {
+    assemble(parse_files(source_file_names, options)?, origin)
+}
+
+/// Assemble statements into machine code loaded at `origin`.
+///
+/// The first pass gives every label its address, and the second pass encodes every instruction with the labels it
+/// refers to, so a label can be used before it is defined. `call` and jump instructions are always encoded with
+/// 32-bits displacement, except `jecxz` and `jcxz` which only have 8-bits displacement, and a label used as immediate
+/// data with 32-bits immediate data, so the size of every instruction is known before the labels are resolved, and two
+/// passes are enough.
+pub fn assemble(statements: Vec<Statement>, origin: u32) -> Result<Program, String> {
+    let mut labels = HashMap::new();
+    let origin = statements_origin(&statements, origin)?;
+    let mut address = origin;
+
+    for statement in &statements {
+        match statement {
+            Statement::Label(name, location) => {
+                if labels.insert(name.to_owned(), address).is_some() {
+                    return Err(format!("Syntax Error: {} Duplicate label: \"{}\"", location.to_string(), name));
+                }
+            },
+            Statement::Instruction(instruction) if instruction.mnemonic == TokenValue::ORG => {
+                address = org_address(instruction, address)?;
+            },
+            Statement::Instruction(instruction) if is_declaration(instruction.mnemonic) => {},
+            Statement::Instruction(instruction) => {
+                address = next_address(address, instruction_size(instruction)?, &instruction.location)?;
+            },
+        }
+    }
+
+    let mut lines = Vec::new();
+    address = origin;
+
+    for statement in statements {
+        let bytes = match &statement {
+            Statement::Label(..) => Vec::new(),
+            // the gap is filled with zeros in the flat binary
+            Statement::Instruction(instruction) if instruction.mnemonic == TokenValue::ORG => {
+                vec![0; (org_address(instruction, address)? - address) as usize]
+            },
+            Statement::Instruction(instruction) if is_declaration(instruction.mnemonic) => Vec::new(),
+            Statement::Instruction(instruction) => Encoder::new(address, &labels, false).encode(instruction)?,
+        };
+
+        lines.push(ListingLine { address, bytes: bytes.to_owned(), statement });
+        address += bytes.len() as u32;
+    }
+
+    Ok(Program { origin, lines })
+}
+
+/// Get the load address of the statements, which is the address of `org` before every instruction, if any, or
+/// `origin` otherwise.
+pub fn statements_origin(statements: &[Statement], origin: u32) -> Result<u32, String> {
+    let first = statements.iter().find_map(|statement| match statement {
+        Statement::Instruction(instruction) => Some(instruction),
+        Statement::Label(..) => None,
+    });
+
+    match first {
+        Some(instruction) if instruction.mnemonic == TokenValue::ORG => org_address(instruction, 0),
+        _ => Ok(origin),
+    }
+}
+
+/// Get the address `org` sets, which can not be below `address`, the address of the statement before it, as the
+/// bytes there are already laid out.
+///
+/// org &lt;address&gt;
+pub fn org_address(instruction: &Instruction, address: u32) -> Result<u32, String> {
+    let error = |msg: String| format!("Syntax Error: {} {}", instruction.location.to_string(), msg);
+
+    let value = match instruction.operands.as_slice() {
+        [Operand::Immediate(value)] if (0..=u32::MAX as i64).contains(value) => *value as u32,
+        _ => return Err(error("Expected an address, such as org 0x7c00".to_string())),
+    };
+
+    if value < address {
+        return Err(error(format!("org {:#x} is below the current address {:#x}", value, address)));
+    }
+
+    Ok(value)
+}
+
+/// Get the address after the `size` bytes of the statement at `location` laid out at `address`, or an error if they go
+/// beyond the 32-bits address space, such as after an `org` near 0xffffffff.
+pub fn next_address(address: u32, size: u32, location: &TokenLocation) -> Result<u32, String> {
+    address.checked_add(size).ok_or_else(|| format!("Syntax Error: {} The {} bytes at {:#x} end at {:#x}, beyond the \
+                                                     32-bits address space", location.to_string(), size, address,
+                                                    address as u64 + size as u64))
+}
+
+/// Get the size in bytes of the machine code of an instruction, which does not depend on where labels are.
+pub fn instruction_size(instruction: &Instruction) -> Result<u32, String> {
+    Ok(Encoder::new(0, &HashMap::new(), true).encode(instruction)?.len() as u32)
+}
+
+/// Get the condition code of a conditional jump or `setcc`, which is the low 4 bits of its opcode.
+fn condition_code(mnemonic: TokenValue) -> u8 {
+    match mnemonic {
+        TokenValue::JO | TokenValue::SETO => 0x0,
+        TokenValue::JNO | TokenValue::SETNO => 0x1,
+        TokenValue::JB | TokenValue::SETB => 0x2,
+        TokenValue::JAE | TokenValue::SETAE => 0x3,
+        TokenValue::JE | TokenValue::SETE => 0x4,
+        TokenValue::JNE | TokenValue::SETNE => 0x5,
+        TokenValue::JBE | TokenValue::SETBE => 0x6,
+        TokenValue::JA | TokenValue::SETA => 0x7,
+        TokenValue::JS | TokenValue::SETS => 0x8,
+        TokenValue::JNS | TokenValue::SETNS => 0x9,
+        TokenValue::JP | TokenValue::SETP => 0xa,
+        TokenValue::JNP | TokenValue::SETNP => 0xb,
+        TokenValue::JL | TokenValue::SETL => 0xc,
+        TokenValue::JGE | TokenValue::SETGE => 0xd,
+        TokenValue::JLE | TokenValue::SETLE => 0xe,
+        _ => 0xf,
+    }
+}
+
+/// Read the bytes `incbin` includes, which are those of the file from `offset`, at most `length` of them. The file is
+/// searched relative to the directory of the source file first, then to the current directory.
+///
+/// incbin "file" [, offset [, length]]
+pub fn include_binary(instruction: &Instruction) -> Result<Vec<u8>, String> {
+    let error = |msg: String| format!("Syntax Error: {} {}", instruction.location.to_string(), msg);
+
+    let (file_name, offset, length) = match instruction.operands.as_slice() {
+        [Operand::String(file_name)] => (file_name, 0, None),
+        [Operand::String(file_name), Operand::Immediate(offset)] if *offset >= 0 => (file_name, *offset, None),
+        [Operand::String(file_name), Operand::Immediate(offset), Operand::Immediate(length)]
+            if *offset >= 0 && *length >= 0 => (file_name, *offset, Some(*length as usize)),
+        _ => return Err(error("Expected a file name, then an optional offset and length, such as incbin \
+                               \"table.bin\", 16, 256".to_string())),
+    };
+
+    let source_file_name = instruction.location.get_source_file_name();
+    let directory = Path::new(&source_file_name).parent().unwrap_or_else(|| Path::new(""));
+    let path = match directory.join(file_name) {
+        path if !path.exists() && Path::new(file_name).exists() => Path::new(file_name).to_path_buf(),
+        path => path,
+    };
+
+    let bytes = fs::read(&path).map_err(|err| error(format!("Can not read {}, because {}.", path.display(), err)))?;
+    let offset = offset as usize;
+
+    if offset > bytes.len() {
+        return Err(error(format!("Offset {} is beyond the end of {}, which has {} bytes", offset, path.display(),
+                                 bytes.len())));
+    }
+
+    let end = length.map_or(bytes.len(), |length| bytes.len().min(offset + length));
+    Ok(bytes[offset..end].to_vec())
+}
+
+/// Get the size of each item `db`, `dw` or `dd` defines.
+pub fn data_size(mnemonic: TokenValue) -> Option<usize> {
+    match mnemonic {
+        TokenValue::DB => Some(1),
+        TokenValue::DW => Some(2),
+        TokenValue::DD => Some(4),
+        _ => None,
+    }
+}
+
+/// Get the bytes `db`, `dw` or `dd` defines, with the addresses of the labels it uses.
+pub fn define_data(instruction: &Instruction, labels: &HashMap<String, u32>) -> Result<Vec<u8>, String> {
+    Encoder::new(0, labels, false).encode(instruction)
+}
+
+fn fits_i8(value: i64) -> bool {
+    value >= i8::MIN as i64 && value <= i8::MAX as i64
+}
+
+/// Check whether immediate data fits in `size` bytes, as either signed or unsigned integer.
+pub(crate) fn fits_size(value: i64, size: usize) -> bool {
+    let bits = 8 * size as u32;
+    value >= -(1i64 << (bits - 1)) && value < (1i64 << bits)
+}
+
+/// Encoder of one instruction into x86 machine code for 32-bits mode
+struct Encoder<'a> {
+    /// address of the instruction
+    address: u32,
+    labels: &'a HashMap<String, u32>,
+    /// when sizing, labels are not resolved yet
+    sizing: bool,
+    bytes: Vec<u8>,
+    location: String,
+}
+
+impl<'a> Encoder<'a> {
+    fn new(address: u32, labels: &'a HashMap<String, u32>, sizing: bool) -> Self {
+        Encoder {
+            address,
+            labels,
+            sizing,
+            bytes: Vec::new(),
+            location: String::new(),
+        }
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("Encoding Error: {} {}", self.location, msg)
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn emit_immediate(&mut self, value: i64, size: usize) {
+        self.emit(&(value as u32).to_le_bytes()[0..size]);
+    }
+
+    /// Emit the operand size prefix for 16-bits operations.
+    fn emit_size_prefix(&mut self, size: usize) {
+        if size == 2 {
+            self.emit(&[0x66]);
+        }
+    }
+
+    fn register(&self, register: TokenValue) -> Result<(u8, usize), String> {
+        register_info(register).ok_or_else(|| self.error(&format!("Invalid register: {}", register_name(register))))
+    }
+
+    /// Emit ModRM byte, SIB byte and displacement for a register or memory operand.
+    fn emit_modrm(&mut self, reg: u8, operand: &Operand) -> Result<(), String> {
+        match operand {
+            Operand::Register(register) => {
+                let (number, _) = self.register(*register)?;
+                self.emit(&[0xc0 | (reg << 3) | number]);
+            },
+            Operand::Memory(memory) => self.emit_memory(reg, memory)?,
+            _ => return Err(self.error(&format!("Expected register or memory, but find \"{}\"", operand))),
+        }
+
+        Ok(())
+    }
+
+    fn emit_memory(&mut self, reg: u8, memory: &Memory) -> Result<(), String> {
+        let displacement = match &memory.label {
+            Some(name) => memory.displacement.wrapping_add(self.address(name, 4)? as i32),
+            None => memory.displacement,
+        };
+        let base = match memory.base {
+            Some(base) => Some(self.register(base)?.0),
+            None => None,
+        };
+        let index = match memory.index {
+            Some((index, scale)) => Some((self.register(index)?.0, scale.trailing_zeros() as u8)),
+            None => None,
+        };
+
+        let mode = match base {
+            None => 0,
+            // the address of a label is always 32 bits, so that the size does not change once it is resolved
+            Some(_) if memory.label.is_some() => 2,
+            Some(5) if displacement == 0 => 1,
+            Some(_) if displacement == 0 => 0,
+            Some(_) if fits_i8(displacement as i64) => 1,
+            Some(_) => 2,
+        };
+
+        match (base, index) {
+            (None, None) => self.emit(&[(reg << 3) | 5]),
+            (None, Some((index, scale))) => self.emit(&[(reg << 3) | 4, (scale << 6) | (index << 3) | 5]),
+            (Some(base), Some((index, scale))) => self.emit(&[(mode << 6) | (reg << 3) | 4,
+                    (scale << 6) | (index << 3) | base]),
+            (Some(4), None) => self.emit(&[(mode << 6) | (reg << 3) | 4, 0x24]),
+            (Some(base), None) => self.emit(&[(mode << 6) | (reg << 3) | base]),
+        }
+
+        match (base, mode) {
+            (None, _) | (_, 2) => self.emit(&displacement.to_le_bytes()),
+            (_, 1) => self.emit(&[displacement as u8]),
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    fn operand_count(&self, instruction: &Instruction, counts: &[usize]) -> Result<(), String> {
+        if !counts.contains(&instruction.operands.len()) {
+            return Err(self.error(&format!("\"{}\" does not take {} operands", instruction.name,
+                            instruction.operands.len())));
+        }
+
+        Ok(())
+    }
+
+    /// Get the size of a register or memory operand.
+    fn operand_size(&self, operand: &Operand) -> Result<usize, String> {
+        operand.size().ok_or_else(|| self.error(&format!("Expected register or memory, but find \"{}\"", operand)))
+    }
+
+    fn same_size(&self, destination: usize, source: usize) -> Result<(), String> {
+        if destination != source {
+            return Err(self.error(&format!("The destination is {} bytes, but source is {} bytes", destination,
+                            source)));
+        }
+
+        Ok(())
+    }
+
+    fn check_immediate(&self, value: i64, size: usize) -> Result<(), String> {
+        if !fits_size(value, size) {
+            return Err(self.error(&format!("Integer literal \"{}\" does not fit in {} bytes", value, size)));
+        }
+
+        Ok(())
+    }
+
+    /// Get the address of a label used as immediate data, 0 while sizing.
+    ///
+    /// The address is always encoded in 4 bytes, even if it would fit in fewer, so that the size of the instruction
+    /// does not change once the labels are resolved.
+    fn address(&self, name: &str, size: usize) -> Result<i64, String> {
+        if size != 4 {
+            return Err(self.error(&format!("Label \"{}\" is a 32-bits address, but the destination is {} bytes", name,
+                            size)));
+        }
+
+        if self.sizing {
+            return Ok(0);
+        }
+
+        match self.labels.get(name) {
+            Some(address) => Ok(*address as i64),
+            None => Err(self.error(&format!("Unknown label: \"{}\"", name))),
+        }
+    }
+
+    /// Get the displacement from the end of this instruction to a label.
+    fn relative(&self, operand: &Operand, length: u32) -> Result<i32, String> {
+        let name = match operand {
+            Operand::Label(name) => name,
+            _ => return Err(self.error(&format!("Expected \"label\", but find \"{}\"", operand))),
+        };
+
+        if self.sizing {
+            return Ok(0);
+        }
+
+        match self.labels.get(name) {
+            Some(target) => Ok(target.wrapping_sub(self.address + length) as i32),
+            None => Err(self.error(&format!("Unknown label: \"{}\"", name))),
+        }
+    }
+
+    fn encode(mut self, instruction: &Instruction) -> Result<Vec<u8>, String> {
+        self.location = instruction.location.to_string();
+        let operands = &instruction.operands;
+
+        match instruction.mnemonic {
+            TokenValue::MOV => {
+                self.operand_count(instruction, &[2])?;
+                self.encode_mov(&operands[0], &operands[1])?;
+            },
+            TokenValue::MOVZX | TokenValue::MOVSX => {
+                self.operand_count(instruction, &[2])?;
+                let destination = match &operands[0] {
+                    Operand::Register(register) => self.register(*register)?,
+                    _ => return Err(self.error("Destination of movzx and movsx must be a register")),
+                };
+                let source_size = self.operand_size(&operands[1])?;
+
+                if destination.1 <= source_size {
+                    return Err(self.error(&format!("The destination is {} bytes, but source is {} bytes",
+                                    destination.1, source_size)));
+                }
+
+                let opcode = match (instruction.mnemonic, source_size) {
+                    (TokenValue::MOVZX, 1) => 0xb6,
+                    (TokenValue::MOVZX, _) => 0xb7,
+                    (_, 1) => 0xbe,
+                    _ => 0xbf,
+                };
+
+                self.emit_size_prefix(destination.1);
+                self.emit(&[0x0f, opcode]);
+                self.emit_modrm(destination.0, &operands[1])?;
+            },
+            TokenValue::BOUND => {
+                self.operand_count(instruction, &[2])?;
+                let index = match (&operands[0], &operands[1]) {
+                    (Operand::Register(register), Operand::Memory(_)) => self.register(*register)?,
+                    _ => return Err(self.error("bound needs a register as the index and memory as the bounds")),
+                };
+
+                if index.1 == 1 {
+                    return Err(self.error("The index of bound can not be an 8-bits register"));
+                }
+
+                self.emit_size_prefix(index.1);
+                self.emit(&[0x62]);
+                self.emit_modrm(index.0, &operands[1])?;
+            },
+            TokenValue::LEA => {
+                self.operand_count(instruction, &[2])?;
+                let destination = match (&operands[0], &operands[1]) {
+                    (Operand::Register(register), Operand::Memory(_)) => self.register(*register)?,
+                    _ => return Err(self.error("lea needs a register as the destination and memory as the source")),
+                };
+
+                if destination.1 == 1 {
+                    return Err(self.error("The destination of lea can not be an 8-bits register"));
+                }
+
+                self.emit_size_prefix(destination.1);
+                self.emit(&[0x8d]);
+                self.emit_modrm(destination.0, &operands[1])?;
+            },
+            TokenValue::ADD | TokenValue::OR | TokenValue::ADC | TokenValue::SBB | TokenValue::AND | TokenValue::SUB |
+                TokenValue::XOR | TokenValue::CMP => {
+                self.operand_count(instruction, &[2])?;
+                let digit = match instruction.mnemonic {
+                    TokenValue::ADD => 0,
+                    TokenValue::OR => 1,
+                    TokenValue::ADC => 2,
+                    TokenValue::SBB => 3,
+                    TokenValue::AND => 4,
+                    TokenValue::SUB => 5,
+                    TokenValue::XOR => 6,
+                    _ => 7,
+                };
+
+                self.encode_arithmetic(digit, &operands[0], &operands[1])?;
+            },
+            TokenValue::TEST => {
+                self.operand_count(instruction, &[2])?;
+                self.encode_test(&operands[0], &operands[1])?;
+            },
+            TokenValue::INC | TokenValue::DEC => {
+                self.operand_count(instruction, &[1])?;
+                let digit = if instruction.mnemonic == TokenValue::INC { 0 } else { 1 };
+                let size = self.operand_size(&operands[0])?;
+
+                match &operands[0] {
+                    Operand::Register(register) if size != 1 => {
+                        let (number, _) = self.register(*register)?;
+                        self.emit_size_prefix(size);
+                        self.emit(&[0x40 | (digit << 3) | number]);
+                    },
+                    operand => {
+                        self.emit_size_prefix(size);
+                        self.emit(&[if size == 1 { 0xfe } else { 0xff }]);
+                        self.emit_modrm(digit, operand)?;
+                    },
+                }
+            },
+            TokenValue::NOT | TokenValue::NEG | TokenValue::MUL | TokenValue::DIV | TokenValue::IDIV => {
+                self.operand_count(instruction, &[1])?;
+                let digit = match instruction.mnemonic {
+                    TokenValue::NOT => 2,
+                    TokenValue::NEG => 3,
+                    TokenValue::MUL => 4,
+                    TokenValue::DIV => 6,
+                    _ => 7,
+                };
+
+                self.encode_group3(digit, &operands[0])?;
+            },
+            TokenValue::IMUL => {
+                self.operand_count(instruction, &[1, 2, 3])?;
+                self.encode_imul(operands)?;
+            },
+            TokenValue::BSF | TokenValue::BSR => {
+                self.operand_count(instruction, &[2])?;
+                let destination = match &operands[0] {
+                    Operand::Register(register) => self.register(*register)?,
+                    _ => return Err(self.error("Destination of bsf and bsr must be a register")),
+                };
+
+                if destination.1 == 1 {
+                    return Err(self.error("The destination of bsf and bsr can not be an 8-bits register"));
+                }
+
+                self.same_size(destination.1, self.operand_size(&operands[1])?)?;
+                self.emit_size_prefix(destination.1);
+                self.emit(&[0x0f, if instruction.mnemonic == TokenValue::BSF { 0xbc } else { 0xbd }]);
+                self.emit_modrm(destination.0, &operands[1])?;
+            },
+            TokenValue::SHL | TokenValue::SHR | TokenValue::SAR => {
+                self.operand_count(instruction, &[2])?;
+                let digit = match instruction.mnemonic {
+                    TokenValue::SHL => 4,
+                    TokenValue::SHR => 5,
+                    _ => 7,
+                };
+                let size = self.operand_size(&operands[0])?;
+                let wide = if size == 1 { 0 } else { 1 };
+
+                self.emit_size_prefix(size);
+
+                match &operands[1] {
+                    Operand::Immediate(1) => {
+                        self.emit(&[0xd0 | wide]);
+                        self.emit_modrm(digit, &operands[0])?;
+                    },
+                    Operand::Immediate(count) => {
+                        self.check_immediate(*count, 1)?;
+                        self.emit(&[0xc0 | wide]);
+                        self.emit_modrm(digit, &operands[0])?;
+                        self.emit_immediate(*count, 1);
+                    },
+                    Operand::Register(TokenValue::CL) => {
+                        self.emit(&[0xd2 | wide]);
+                        self.emit_modrm(digit, &operands[0])?;
+                    },
+                    _ => return Err(self.error("The count of shift must be immediate data or \"cl\"")),
+                }
+            },
+            TokenValue::PUSH => {
+                self.operand_count(instruction, &[1])?;
+
+                match &operands[0] {
+                    Operand::Immediate(value) if fits_i8(*value) => {
+                        self.emit(&[0x6a]);
+                        self.emit_immediate(*value, 1);
+                    },
+                    Operand::Immediate(value) => {
+                        self.check_immediate(*value, 4)?;
+                        self.emit(&[0x68]);
+                        self.emit_immediate(*value, 4);
+                    },
+                    Operand::Label(name) => {
+                        let value = self.address(name, 4)?;
+                        self.emit(&[0x68]);
+                        self.emit_immediate(value, 4);
+                    },
+                    operand => self.encode_stack(operand, 0x50, 0xff, 6)?,
+                }
+            },
+            TokenValue::POP => {
+                self.operand_count(instruction, &[1])?;
+                self.encode_stack(&operands[0], 0x58, 0x8f, 0)?;
+            },
+            TokenValue::PUSHAD => self.emit(&[0x60]),
+            TokenValue::POPAD => self.emit(&[0x61]),
+            TokenValue::PUSHFD => self.emit(&[0x9c]),
+            TokenValue::POPFD => self.emit(&[0x9d]),
+            TokenValue::SAHF => self.emit(&[0x9e]),
+            TokenValue::LAHF => self.emit(&[0x9f]),
+            TokenValue::CMC => self.emit(&[0xf5]),
+            TokenValue::CLC => self.emit(&[0xf8]),
+            TokenValue::STC => self.emit(&[0xf9]),
+            TokenValue::CLD => self.emit(&[0xfc]),
+            TokenValue::STD => self.emit(&[0xfd]),
+            TokenValue::JMP => {
+                self.operand_count(instruction, &[1])?;
+                let displacement = self.relative(&operands[0], 5)?;
+                self.emit(&[0xe9]);
+                self.emit(&displacement.to_le_bytes());
+            },
+            TokenValue::CALL => {
+                self.operand_count(instruction, &[1])?;
+
+                match &operands[0] {
+                    Operand::Register(_) | Operand::Memory(_) => {
+                        if self.operand_size(&operands[0])? != 4 {
+                            return Err(self.error("The operand of an indirect call must be 32-bits"));
+                        }
+
+                        self.emit(&[0xff]);
+                        self.emit_modrm(2, &operands[0])?;
+                    },
+                    operand => {
+                        let displacement = self.relative(operand, 5)?;
+                        self.emit(&[0xe8]);
+                        self.emit(&displacement.to_le_bytes());
+                    },
+                }
+            },
+            TokenValue::JE | TokenValue::JNE | TokenValue::JG | TokenValue::JGE | TokenValue::JL | TokenValue::JLE |
+                TokenValue::JA | TokenValue::JAE | TokenValue::JB | TokenValue::JBE | TokenValue::JO | TokenValue::JNO |
+                TokenValue::JS | TokenValue::JNS | TokenValue::JP | TokenValue::JNP => {
+                self.operand_count(instruction, &[1])?;
+                let displacement = self.relative(&operands[0], 6)?;
+                self.emit(&[0x0f, 0x80 | condition_code(instruction.mnemonic)]);
+                self.emit(&displacement.to_le_bytes());
+            },
+            TokenValue::SETE | TokenValue::SETNE | TokenValue::SETG | TokenValue::SETGE | TokenValue::SETL |
+                TokenValue::SETLE | TokenValue::SETA | TokenValue::SETAE | TokenValue::SETB | TokenValue::SETBE |
+                TokenValue::SETO | TokenValue::SETNO | TokenValue::SETS | TokenValue::SETNS | TokenValue::SETP |
+                TokenValue::SETNP => {
+                self.operand_count(instruction, &[1])?;
+
+                if self.operand_size(&operands[0])? != 1 {
+                    return Err(self.error(&format!("The operand of {} must be a byte", instruction.name)));
+                }
+
+                self.emit(&[0x0f, 0x90 | condition_code(instruction.mnemonic)]);
+                self.emit_modrm(0, &operands[0])?;
+            },
+            TokenValue::JECXZ | TokenValue::JCXZ => {
+                self.operand_count(instruction, &[1])?;
+
+                // `jcxz` is `jecxz` with the address size prefix
+                let length = if instruction.mnemonic == TokenValue::JCXZ { 3 } else { 2 };
+                let displacement = self.relative(&operands[0], length)?;
+
+                if !fits_i8(displacement as i64) {
+                    return Err(self.error(&format!("The target of {} is {} bytes away, out of -128 to 127 bytes",
+                                    instruction.name, displacement)));
+                }
+
+                if instruction.mnemonic == TokenValue::JCXZ {
+                    self.emit(&[0x67]);
+                }
+
+                self.emit(&[0xe3, displacement as u8]);
+            },
+            TokenValue::RET => self.emit(&[0xc3]),
+            TokenValue::ENTER => {
+                self.operand_count(instruction, &[0, 2])?;
+                let (frame_size, nesting_level) = match operands.as_slice() {
+                    [Operand::Immediate(size), Operand::Immediate(level)] => (*size, *level),
+                    [] => (0, 0),
+                    _ => return Err(self.error("The operands of enter must be immediate data")),
+                };
+
+                if !(0..=0xffff).contains(&frame_size) || !(0..=0xff).contains(&nesting_level) {
+                    return Err(self.error("The frame size of enter must fit in 16 bits and the nesting level in 8 \
+                                           bits"));
+                }
+
+                self.emit(&[0xc8]);
+                self.emit_immediate(frame_size, 2);
+                self.emit_immediate(nesting_level, 1);
+            },
+            TokenValue::LEAVE => self.emit(&[0xc9]),
+            TokenValue::IRET => self.emit(&[0xcf]),
+            TokenValue::HLT => self.emit(&[0xf4]),
+            TokenValue::INTO => self.emit(&[0xce]),
+            TokenValue::CPUID => self.emit(&[0x0f, 0xa2]),
+            TokenValue::NOP if operands.is_empty() => self.emit(&[0x90]),
+            TokenValue::NOP => {
+                self.operand_count(instruction, &[0, 1])?;
+                let size = self.operand_size(&operands[0])?;
+
+                if size == 1 {
+                    return Err(self.error("The operand of nop must be 16 or 32 bits"));
+                }
+
+                self.emit_size_prefix(size);
+                self.emit(&[0x0f, 0x1f]);
+                self.emit_modrm(0, &operands[0])?;
+            },
+            TokenValue::DAA => self.emit(&[0x27]),
+            TokenValue::DAS => self.emit(&[0x2f]),
+            TokenValue::AAA => self.emit(&[0x37]),
+            TokenValue::AAS => self.emit(&[0x3f]),
+            TokenValue::IN | TokenValue::OUT => {
+                self.operand_count(instruction, &[2])?;
+                let (accumulator, port) = match instruction.mnemonic {
+                    TokenValue::IN => (&operands[0], &operands[1]),
+                    _ => (&operands[1], &operands[0]),
+                };
+
+                let size = match accumulator {
+                    Operand::Register(register @ (TokenValue::AL | TokenValue::AX | TokenValue::EAX)) => {
+                        self.register(*register)?.1
+                    },
+                    _ => return Err(self.error(&format!("The accumulator of {} must be al, ax or eax",
+                                                        instruction.name))),
+                };
+
+                // bit 0 selects ax or eax, and bit 1 selects out
+                let opcode = (size != 1) as u8 | if instruction.mnemonic == TokenValue::OUT { 2 } else { 0 };
+                self.emit_size_prefix(size);
+
+                match port {
+                    Operand::Register(TokenValue::DX) => self.emit(&[0xec | opcode]),
+                    Operand::Immediate(value) if (0..=255).contains(value) => {
+                        self.emit(&[0xe4 | opcode]);
+                        self.emit_immediate(*value, 1);
+                    },
+                    _ => return Err(self.error(&format!("The port of {} must be immediate data from 0 to 255 or dx",
+                                                        instruction.name))),
+                }
+            },
+            TokenValue::INCBIN => self.emit(&include_binary(instruction)?),
+            TokenValue::DB | TokenValue::DW | TokenValue::DD => self.encode_data(instruction)?,
+            // a section only changes where the statements after it go
+            TokenValue::DATA | TokenValue::TEXT => self.operand_count(instruction, &[0])?,
+            // the end of a procedure only marks where it ends
+            TokenValue::ENDP => self.operand_count(instruction, &[1])?,
+            TokenValue::INT => {
+                self.operand_count(instruction, &[1])?;
+
+                match operands[0] {
+                    Operand::Immediate(value) => {
+                        self.check_immediate(value, 1)?;
+                        self.emit(&[0xcd]);
+                        self.emit_immediate(value, 1);
+                    },
+                    _ => return Err(self.error("The operand of int must be immediate data")),
+                }
+            },
+            _ => return Err(self.error(&format!("Unsupported instruction: {}", instruction.name))),
+        }
+
+        Ok(self.bytes)
+    }
+
+    /// Encode the items of `db`, `dw` or `dd` in little endian, a label is its 32-bits address, and a string is its
+    /// bytes, padded with zeros to a whole number of items.
+    ///
+    /// db|dw|dd &lt;item&gt; [, &lt;item&gt;]...
+    fn encode_data(&mut self, instruction: &Instruction) -> Result<(), String> {
+        let size = data_size(instruction.mnemonic).unwrap_or(1);
+
+        for operand in &instruction.operands {
+            let value = match operand {
+                Operand::String(string) => {
+                    let length = string.len().div_ceil(size) * size;
+                    self.emit(string.as_bytes());
+                    self.emit(&vec![0; length - string.len()]);
+                    continue;
+                },
+                Operand::Immediate(value) => {
+                    self.check_immediate(*value, size)?;
+                    *value
+                },
+                Operand::Label(name) => self.address(name, size)?,
+                _ => return Err(self.error(&format!("Expected immediate data, \"label\" or string in {}, but find \
+                                                     \"{}\"", instruction.name, operand))),
+            };
+
+            self.emit_immediate(value, size);
+        }
+
+        Ok(())
+    }
+
+    fn encode_mov(&mut self, destination: &Operand, source: &Operand) -> Result<(), String> {
+        let size = self.operand_size(destination)?;
+        let wide = if size == 1 { 0 } else { 1 };
+
+        match (destination, source) {
+            (Operand::Register(register), Operand::Immediate(value)) => {
+                self.check_immediate(*value, size)?;
+                let (number, _) = self.register(*register)?;
+                self.emit_size_prefix(size);
+                self.emit(&[0xb0 | (wide << 3) | number]);
+                self.emit_immediate(*value, size);
+            },
+            (_, Operand::Immediate(value)) => {
+                self.check_immediate(*value, size)?;
+                self.emit_size_prefix(size);
+                self.emit(&[0xc6 | wide]);
+                self.emit_modrm(0, destination)?;
+                self.emit_immediate(*value, size);
+            },
+            (Operand::Register(register), Operand::Label(name)) => {
+                let value = self.address(name, size)?;
+                let (number, _) = self.register(*register)?;
+                self.emit(&[0xb8 | number]);
+                self.emit_immediate(value, 4);
+            },
+            (_, Operand::Label(name)) => {
+                let value = self.address(name, size)?;
+                self.emit(&[0xc7]);
+                self.emit_modrm(0, destination)?;
+                self.emit_immediate(value, 4);
+            },
+            (_, Operand::Register(register)) => {
+                let (number, source_size) = self.register(*register)?;
+                self.same_size(size, source_size)?;
+                self.emit_size_prefix(size);
+                self.emit(&[0x88 | wide]);
+                self.emit_modrm(number, destination)?;
+            },
+            (Operand::Register(register), Operand::Memory(memory)) => {
+                let (number, _) = self.register(*register)?;
+                self.same_size(size, memory.size)?;
+                self.emit_size_prefix(size);
+                self.emit(&[0x8a | wide]);
+                self.emit_modrm(number, source)?;
+            },
+            _ => return Err(self.error(&format!("Invalid operands: \"{}\", \"{}\"", destination, source))),
+        }
+
+        Ok(())
+    }
+
+    /// Encode `add`, `or`, `and`, `sub`, `xor` and `cmp`, whose opcodes differ only by `digit`.
+    fn encode_arithmetic(&mut self, digit: u8, destination: &Operand, source: &Operand) -> Result<(), String> {
+        let size = self.operand_size(destination)?;
+        let wide = if size == 1 { 0 } else { 1 };
+
+        match (destination, source) {
+            (_, Operand::Immediate(value)) => {
+                self.check_immediate(*value, size)?;
+                self.emit_size_prefix(size);
+
+                if size == 1 {
+                    self.emit(&[0x80]);
+                    self.emit_modrm(digit, destination)?;
+                    self.emit_immediate(*value, 1);
+                } else if fits_i8(*value) {
+                    self.emit(&[0x83]);
+                    self.emit_modrm(digit, destination)?;
+                    self.emit_immediate(*value, 1);
+                } else {
+                    self.emit(&[0x81]);
+                    self.emit_modrm(digit, destination)?;
+                    self.emit_immediate(*value, size);
+                }
+            },
+            (_, Operand::Label(name)) => {
+                let value = self.address(name, size)?;
+                self.emit(&[0x81]);
+                self.emit_modrm(digit, destination)?;
+                self.emit_immediate(value, 4);
+            },
+            (_, Operand::Register(register)) => {
+                let (number, source_size) = self.register(*register)?;
+                self.same_size(size, source_size)?;
+                self.emit_size_prefix(size);
+                self.emit(&[(digit << 3) | wide]);
+                self.emit_modrm(number, destination)?;
+            },
+            (Operand::Register(register), Operand::Memory(memory)) => {
+                let (number, _) = self.register(*register)?;
+                self.same_size(size, memory.size)?;
+                self.emit_size_prefix(size);
+                self.emit(&[(digit << 3) | 2 | wide]);
+                self.emit_modrm(number, source)?;
+            },
+            _ => return Err(self.error(&format!("Invalid operands: \"{}\", \"{}\"", destination, source))),
+        }
+
+        Ok(())
+    }
+
+    /// Encode `test`, which has no form with 8-bits immediate data for larger operands, and whose register is the same
+    /// field of ModR/M whichever operand it is.
+    fn encode_test(&mut self, destination: &Operand, source: &Operand) -> Result<(), String> {
+        let size = self.operand_size(destination)?;
+        let wide = if size == 1 { 0 } else { 1 };
+
+        match (destination, source) {
+            (_, Operand::Immediate(value)) => {
+                self.check_immediate(*value, size)?;
+                self.emit_size_prefix(size);
+                self.emit(&[0xf6 | wide]);
+                self.emit_modrm(0, destination)?;
+                self.emit_immediate(*value, size);
+            },
+            (_, Operand::Label(name)) => {
+                let value = self.address(name, size)?;
+                self.emit(&[0xf7]);
+                self.emit_modrm(0, destination)?;
+                self.emit_immediate(value, 4);
+            },
+            (_, Operand::Register(register)) => {
+                let (number, source_size) = self.register(*register)?;
+                self.same_size(size, source_size)?;
+                self.emit_size_prefix(size);
+                self.emit(&[0x84 | wide]);
+                self.emit_modrm(number, destination)?;
+            },
+            (Operand::Register(register), Operand::Memory(memory)) => {
+                let (number, _) = self.register(*register)?;
+                self.same_size(size, memory.size)?;
+                self.emit_size_prefix(size);
+                self.emit(&[0x84 | wide]);
+                self.emit_modrm(number, source)?;
+            },
+            _ => return Err(self.error(&format!("Invalid operands: \"{}\", \"{}\"", destination, source))),
+        }
+
+        Ok(())
+    }
+
+    /// Encode the one operand `not`, `neg`, `mul`, `imul`, `div` and `idiv`.
+    fn encode_group3(&mut self, digit: u8, operand: &Operand) -> Result<(), String> {
+        let size = self.operand_size(operand)?;
+        self.emit_size_prefix(size);
+        self.emit(&[if size == 1 { 0xf6 } else { 0xf7 }]);
+        self.emit_modrm(digit, operand)
+    }
+
+    fn encode_imul(&mut self, operands: &[Operand]) -> Result<(), String> {
+        if operands.len() == 1 {
+            return self.encode_group3(5, &operands[0]);
+        }
+
+        let (number, size) = match &operands[0] {
+            Operand::Register(register) => self.register(*register)?,
+            _ => return Err(self.error("Destination of imul must be a register")),
+        };
+
+        if size == 1 {
+            return Err(self.error("Destination of imul can not be 8-bits register"));
+        }
+
+        self.same_size(size, self.operand_size(&operands[1])?)?;
+        self.emit_size_prefix(size);
+
+        match operands.get(2) {
+            None => {
+                self.emit(&[0x0f, 0xaf]);
+                self.emit_modrm(number, &operands[1])?;
+            },
+            Some(Operand::Immediate(value)) if fits_i8(*value) => {
+                self.emit(&[0x6b]);
+                self.emit_modrm(number, &operands[1])?;
+                self.emit_immediate(*value, 1);
+            },
+            Some(Operand::Immediate(value)) => {
+                self.check_immediate(*value, size)?;
+                self.emit(&[0x69]);
+                self.emit_modrm(number, &operands[1])?;
+                self.emit_immediate(*value, size);
+            },
+            Some(operand) => return Err(self.error(&format!("Expected immediate data, but find \"{}\"", operand))),
+        }
+
+        Ok(())
+    }
+
+    /// Encode `push` and `pop` of register or memory.
+    fn encode_stack(&mut self, operand: &Operand, register_opcode: u8, memory_opcode: u8, digit: u8)
+        -> Result<(), String> {
+        let size = self.operand_size(operand)?;
+
+        if size == 1 {
+            return Err(self.error("8-bits operand can not be pushed or popped"));
+        }
+
+        self.emit_size_prefix(size);
+
+        match operand {
+            Operand::Register(register) => {
+                let (number, _) = self.register(*register)?;
+                self.emit(&[register_opcode | number]);
+            },
+            _ => {
+                self.emit(&[memory_opcode]);
+                self.emit_modrm(digit, operand)?;
+            },
+        }
+
+        Ok(())
+    }
+}
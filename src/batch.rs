@@ -0,0 +1,170 @@
+//! Running many independent programs concurrently, such as the submissions of a class, on a pool of threads with a
+//! virtual machine of its own for every program:
+//!
+//! ```no_run
+//! # use asm_vm::batch::*;
+//! let programs = vec![
+//!     Program::Files(vec!["alice/sum.asm".to_string()]),
+//!     Program::Files(vec!["bob/sum.asm".to_string()]),
+//! ];
+//! let options = RunOptions { max_steps: Some(1_000_000), ..Default::default() };
+//!
+//! for outcome in run_all(programs, &options) {
+//!     println!("{:?} {:?}", outcome.get_register("eax"), outcome.error);
+//! }
+//! ```
+
+use crate::config::*;
+use crate::vm::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// general registers of an outcome, in order
+const REGISTERS: [&str; 8] = ["eax", "ebx", "ecx", "edx", "esi", "edi", "esp", "ebp"];
+
+// a virtual machine belongs to the thread which runs it, so it must be sent to one
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<VM>();
+};
+
+/// Source of one program
+#[derive(Clone)]
+pub enum Program {
+    /// source files which share one label table, as [`VM::load_files`]
+    Files(Vec<String>),
+    /// source code in memory, `name` is only used in diagnostics
+    Source { name: String, source: String },
+}
+
+impl Program {
+    /// Load the program into `vm`, stopping before the first instruction.
+    pub fn load(&self, vm: &mut VM) {
+        match self {
+            Program::Files(source_file_names) => vm.load_files(source_file_names.to_owned()),
+            Program::Source { name, source } => vm.load_source(name.to_owned(), source.to_owned()),
+        }
+    }
+}
+
+/// Settings of every run
+#[derive(Clone, Default)]
+pub struct RunOptions {
+    /// threads of the pool, `None` for one for each core
+    pub threads: Option<usize>,
+    /// settings of every virtual machine, such as the quotas and the policy
+    pub config: Config,
+    /// standard input of every program
+    pub input: Vec<u8>,
+    /// instructions after which a program is stopped with an error, `None` for no limit
+    pub max_steps: Option<u64>,
+}
+
+/// Result of one program
+#[derive(Clone)]
+pub struct RunOutcome {
+    /// the error which stopped the program, such as a syntax error, `None` if it finished
+    pub error: Option<String>,
+    /// the quota which stopped the program, if any, whose message is also the error
+    pub quota_exceeded: Option<QuotaExceeded>,
+    /// status passed to the `exit` system call
+    pub exit_code: Option<u32>,
+    /// general registers when the program stopped, as pairs of the name and the value
+    pub registers: Vec<(&'static str, u32)>,
+    /// `cf`, `zf`, `sf` and `of` when the program stopped
+    pub flags: (bool, bool, bool, bool),
+    /// everything written to the standard output
+    pub output: Vec<u8>,
+    pub steps: u64,
+    /// most bytes on the stack, see [`VM::get_max_stack_usage`]
+    pub max_stack: u32,
+}
+
+impl RunOutcome {
+    /// Whether the program finished without an error, and exited with 0 if it called `exit`.
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none() && matches!(self.exit_code, None | Some(0))
+    }
+
+    pub fn get_register(&self, name: &str) -> Option<u32> {
+        self.registers.iter().find(|(register, _)| *register == name).map(|(_, value)| *value)
+    }
+}
+
+/// Run every program on a virtual machine of its own, on `options.threads` threads at the same time, returns the
+/// outcomes in the order of the programs.
+///
+/// A program which fails, such as with a syntax error, does not stop the others.
+pub fn run_all(programs: Vec<Program>, options: &RunOptions) -> Vec<RunOutcome> {
+    let threads = options.threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |threads| threads.get()))
+        .clamp(1, programs.len().max(1));
+
+    let next = AtomicUsize::new(0);
+    let outcomes = Mutex::new(vec![None; programs.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+
+                let program = match programs.get(index) {
+                    Some(program) => program,
+                    None => break,
+                };
+
+                let outcome = run(program, options);
+                outcomes.lock().unwrap()[index] = Some(outcome);
+            });
+        }
+    });
+
+    outcomes.into_inner().unwrap().into_iter().map(|outcome| outcome.unwrap()).collect()
+}
+
+/// Run one program, as a thread of the pool does.
+pub fn run(program: &Program, options: &RunOptions) -> RunOutcome {
+    let mut vm: VM = Default::default();
+    vm.set_verbosity(Verbosity::QUIET);
+    let mut steps = 0;
+
+    let result = catch_error(|| -> Result<(), String> {
+        options.config.apply(&mut vm)?;
+
+        program.load(&mut vm);
+        vm.set_input(options.input.to_owned());
+        vm.capture_output();
+
+        while !vm.is_finished() {
+            if options.max_steps == Some(steps) {
+                return Err(format!("Runtime Error: not finished after {} steps", steps));
+            }
+
+            vm.step();
+            steps += 1;
+        }
+
+        match (vm.get_quota_exceeded(), vm.get_exception()) {
+            (Some(exceeded), _) => Err(exceeded.to_string()),
+            (None, Some(raised)) => Err(raised.to_string()),
+            (None, None) => Ok(()),
+        }
+    });
+
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(msg)) | Err(msg) => Some(msg),
+    };
+
+    RunOutcome {
+        error,
+        quota_exceeded: vm.get_quota_exceeded().cloned(),
+        exit_code: vm.get_exit_code(),
+        registers: REGISTERS.iter().map(|name| (*name, vm.get_register(name).unwrap_or(0))).collect(),
+        flags: vm.get_flags(),
+        output: vm.get_output().unwrap_or_default().to_vec(),
+        steps,
+        max_stack: vm.get_max_stack_usage(),
+    }
+}
@@ -0,0 +1,70 @@
+//! Statistics of the conditional jumps of a program, and a model of a branch predictor for architecture courses.
+//!
+//! The predictor gives every conditional jump a 2-bit saturating counter of its own, without aliasing, which starts
+//! weakly not taken: it predicts taken from 2 up, and moves up when the jump is taken and down when it is not.
+
+/// What is recorded of every conditional jump
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BranchModel {
+    /// how often it is taken and not taken
+    Count,
+    /// also predict it with a 2-bit counter and count the mispredictions
+    TwoBit,
+}
+
+/// counter of a branch before it is executed, weakly not taken
+const INITIAL_COUNTER: u8 = 1;
+
+/// Record of one conditional jump
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BranchStats {
+    pub taken: u64,
+    pub not_taken: u64,
+    /// executions which the predictor got wrong, 0 without a predictor
+    pub mispredicted: u64,
+    /// 2-bit counter of the predictor, from 0, strongly not taken, to 3, strongly taken
+    counter: u8,
+}
+
+impl Default for BranchStats {
+    fn default() -> Self {
+        BranchStats { taken: 0, not_taken: 0, mispredicted: 0, counter: INITIAL_COUNTER }
+    }
+}
+
+impl BranchStats {
+    pub fn executed(&self) -> u64 {
+        self.taken + self.not_taken
+    }
+
+    /// Get the share of executions which were mispredicted, from 0 to 1, 0 if it was never executed.
+    pub fn misprediction_rate(&self) -> f64 {
+        match self.executed() {
+            0 => 0.0,
+            executed => self.mispredicted as f64 / executed as f64,
+        }
+    }
+
+    /// Record one execution of the jump, which was `taken` or not.
+    pub fn record(&mut self, taken: bool, model: BranchModel) {
+        if taken {
+            self.taken += 1;
+        } else {
+            self.not_taken += 1;
+        }
+
+        if model == BranchModel::TwoBit {
+            if (self.counter >= 2) != taken {
+                self.mispredicted += 1;
+            }
+
+            self.counter = if taken { (self.counter + 1).min(3) } else { self.counter.saturating_sub(1) };
+        }
+    }
+
+    pub fn add(&mut self, other: &BranchStats) {
+        self.taken += other.taken;
+        self.not_taken += other.not_taken;
+        self.mispredicted += other.mispredicted;
+    }
+}
@@ -0,0 +1,137 @@
+//! Model of an L1 data cache for teaching locality, such as why a loop over the rows of a matrix hits where one over
+//! its columns misses. It only counts hits and misses, memory is always read and written directly.
+//!
+//! The cache is set-associative with least recently used replacement, and allocates a line on a write as on a read.
+
+/// Geometry of the cache
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CacheConfig {
+    /// bytes of data
+    pub size: usize,
+    /// lines of each set
+    pub associativity: usize,
+    /// bytes of each line
+    pub line_size: usize,
+}
+
+impl Default for CacheConfig {
+    /// 32 KiB, 8-way with 64-bytes lines, as the L1 data cache of many x86 processors.
+    fn default() -> Self {
+        CacheConfig { size: 32 * 1024, associativity: 8, line_size: 64 }
+    }
+}
+
+impl CacheConfig {
+    /// Check the geometry, every number must be a power of 2 and the size must hold at least one set.
+    pub fn new(size: usize, associativity: usize, line_size: usize) -> Result<CacheConfig, String> {
+        for (name, value) in [("size", size), ("associativity", associativity), ("line size", line_size)] {
+            if !value.is_power_of_two() {
+                return Err(format!("The cache {} must be a power of 2, but it is {}", name, value));
+            }
+        }
+
+        if size < associativity * line_size {
+            return Err(format!("A cache of {} bytes can not hold a set of {} lines of {} bytes", size, associativity,
+                               line_size));
+        }
+
+        Ok(CacheConfig { size, associativity, line_size })
+    }
+
+    pub fn sets(&self) -> usize {
+        self.size / (self.associativity * self.line_size)
+    }
+}
+
+/// Hits and misses of some accesses
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    pub fn accesses(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    /// Get the share of accesses which hit, from 0 to 1, 0 without any access.
+    pub fn hit_rate(&self) -> f64 {
+        match self.accesses() {
+            0 => 0.0,
+            accesses => self.hits as f64 / accesses as f64,
+        }
+    }
+
+    pub fn add(&mut self, other: CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+    }
+}
+
+/// Lines held by the cache
+#[derive(Clone)]
+pub struct Cache {
+    config: CacheConfig,
+    /// tags of the lines of every set, with when each was last used
+    sets: Vec<Vec<(u32, u64)>>,
+    /// accesses so far, which orders the uses of lines
+    clock: u64,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        Cache { config, sets: vec![Vec::with_capacity(config.associativity); config.sets()], clock: 0 }
+    }
+
+    pub fn get_config(&self) -> CacheConfig {
+        self.config
+    }
+
+    /// Empty every line.
+    pub fn clear(&mut self) {
+        self.sets.iter_mut().for_each(|set| set.clear());
+        self.clock = 0;
+    }
+
+    /// Access `size` bytes at `address`, returns a hit or a miss for every line they fall in.
+    pub fn access(&mut self, address: u32, size: usize) -> CacheStats {
+        let line_size = self.config.line_size as u32;
+        let first = address / line_size;
+        let last = address.wrapping_add(size.max(1) as u32 - 1) / line_size;
+        let mut stats = CacheStats::default();
+
+        // an access which wraps around the address space ends at the last line
+        for line in first..=last.max(first) {
+            if self.access_line(line) {
+                stats.hits += 1;
+            } else {
+                stats.misses += 1;
+            }
+        }
+
+        stats
+    }
+
+    /// Access the line with the number `line`, returns whether it hit.
+    fn access_line(&mut self, line: u32) -> bool {
+        self.clock += 1;
+
+        let sets = self.sets.len() as u32;
+        let (index, tag) = ((line % sets) as usize, line / sets);
+        let set = &mut self.sets[index];
+
+        if let Some(entry) = set.iter_mut().find(|(cached, _)| *cached == tag) {
+            entry.1 = self.clock;
+            return true;
+        }
+
+        if set.len() < self.config.associativity {
+            set.push((tag, self.clock));
+        } else if let Some(victim) = set.iter_mut().min_by_key(|(_, used)| *used) {
+            *victim = (tag, self.clock);
+        }
+
+        false
+    }
+}
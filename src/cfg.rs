@@ -0,0 +1,186 @@
+//! Control-flow graph of a program, whose nodes are its instructions, for static analysis.
+
+use crate::instruction::*;
+use crate::token::*;
+use std::collections::HashMap;
+
+/// Control-flow graph of the instructions of a program
+///
+/// The index of an instruction is its position among the instructions only, and `end()`, one past the last
+/// instruction, stands for falling off the end of the text.
+pub struct ControlFlowGraph<'a> {
+    pub instructions: Vec<&'a Instruction>,
+    /// index of the instruction after every label
+    pub labels: HashMap<String, usize>,
+    /// instructions which may be executed next, after `call` it is the instruction after it, as if it returns
+    pub successors: Vec<Vec<usize>>,
+    /// target of every `call`
+    pub calls: Vec<Option<usize>>,
+    /// handler every `ivt` installs, which `int` may call from anywhere after it
+    pub handlers: Vec<Option<usize>>,
+    /// function whose address every instruction takes, such as `mov eax, label`, which an indirect `call` may call
+    /// from anywhere after it
+    pub pointers: Vec<Option<usize>>,
+}
+
+/// Whether the instruction is a jump, which transfers control to its label rather than calls it.
+pub fn is_jump(mnemonic: TokenValue) -> bool {
+    matches!(mnemonic, TokenValue::JMP | TokenValue::JE | TokenValue::JNE | TokenValue::JG | TokenValue::JGE |
+             TokenValue::JL | TokenValue::JLE | TokenValue::JA | TokenValue::JAE | TokenValue::JB | TokenValue::JBE |
+             TokenValue::JO | TokenValue::JNO | TokenValue::JS | TokenValue::JNS | TokenValue::JP | TokenValue::JNP |
+             TokenValue::JECXZ | TokenValue::JCXZ)
+}
+
+/// Whether the instruction may leave the program or the function, which are `ret`, `iret`, `hlt` and `int`, such as
+/// the `exit` system call.
+pub fn is_exit(mnemonic: TokenValue) -> bool {
+    matches!(mnemonic, TokenValue::RET | TokenValue::IRET | TokenValue::HLT | TokenValue::INT)
+}
+
+/// Whether the statement is a directive, which is not executed at all, such as `org`, `global`, `.data` and `endp`.
+pub fn is_directive(mnemonic: TokenValue) -> bool {
+    matches!(mnemonic, TokenValue::ORG | TokenValue::GLOBAL | TokenValue::EXTERN | TokenValue::ENDP) ||
+        is_section(mnemonic)
+}
+
+/// Whether the statement starts a section, which are `.data` and `.text`.
+pub fn is_section(mnemonic: TokenValue) -> bool {
+    matches!(mnemonic, TokenValue::DATA | TokenValue::TEXT)
+}
+
+/// Whether the statement is data rather than an instruction, which are `incbin`, `db`, `dw` and `dd`.
+pub fn is_data(mnemonic: TokenValue) -> bool {
+    matches!(mnemonic, TokenValue::INCBIN | TokenValue::DB | TokenValue::DW | TokenValue::DD)
+}
+
+impl<'a> ControlFlowGraph<'a> {
+    /// Build the graph of statements, a jump to an unknown label has no edge for it.
+    pub fn new(statements: &'a [Statement]) -> Self {
+        let mut instructions = Vec::new();
+        let mut labels = HashMap::new();
+
+        for statement in statements {
+            match statement {
+                Statement::Label(name, _) => {
+                    labels.entry(name.to_owned()).or_insert(instructions.len());
+                },
+                Statement::Instruction(instruction) => instructions.push(instruction),
+            }
+        }
+
+        let target = |instruction: &Instruction| match instruction.operands.first() {
+            Some(Operand::Label(name)) => labels.get(name).copied(),
+            _ => None,
+        };
+
+        let mut successors = Vec::new();
+        let mut calls = Vec::new();
+        let mut handlers = Vec::new();
+        let mut pointers = Vec::new();
+        let indirect = instructions.iter().any(|instruction| instruction.mnemonic == TokenValue::CALL &&
+            !matches!(instruction.operands.first(), Some(Operand::Label(_))));
+
+        for (i, instruction) in instructions.iter().enumerate() {
+            let next = i + 1;
+
+            successors.push(match instruction.mnemonic {
+                TokenValue::RET | TokenValue::IRET | TokenValue::HLT => Vec::new(),
+                mnemonic if is_data(mnemonic) => Vec::new(),
+                TokenValue::JMP => target(instruction).into_iter().collect(),
+                mnemonic if is_jump(mnemonic) => Some(next).into_iter().chain(target(instruction)).collect(),
+                _ => vec![next],
+            });
+
+            calls.push(match instruction.mnemonic {
+                TokenValue::CALL => target(instruction),
+                _ => None,
+            });
+
+            handlers.push(match (instruction.mnemonic, instruction.operands.get(1)) {
+                (TokenValue::IVT, Some(Operand::Label(name))) => labels.get(name).copied(),
+                _ => None,
+            });
+
+            pointers.push(match instruction.mnemonic {
+                mnemonic if !indirect || is_jump(mnemonic) => None,
+                TokenValue::CALL | TokenValue::IVT => None,
+                _ => instruction.operands.iter().find_map(|operand| match operand {
+                    Operand::Label(name) => labels.get(name).copied(),
+                    _ => None,
+                }),
+            });
+        }
+
+        ControlFlowGraph { instructions, labels, successors, calls, handlers, pointers }
+    }
+
+    /// Index standing for falling off the end of the text.
+    pub fn end(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Find the instructions which can be executed from `entries`, following the successors, the targets of `call`,
+    /// the handlers `ivt` installs and the functions whose addresses are taken.
+    pub fn reachable(&self, entries: &[usize]) -> Vec<bool> {
+        let mut reachable = vec![false; self.end()];
+        let mut pending: Vec<usize> = entries.to_vec();
+
+        while let Some(i) = pending.pop() {
+            if i >= self.end() || reachable[i] {
+                continue;
+            }
+
+            reachable[i] = true;
+            pending.extend(&self.successors[i]);
+            pending.extend(self.calls[i]);
+            pending.extend(self.handlers[i]);
+            pending.extend(self.pointers[i]);
+        }
+
+        reachable
+    }
+
+    /// Find the `ret` instructions which can be reached from `entry` through the successors, those of the function
+    /// starting from it.
+    pub fn returns(&self, entry: usize) -> Vec<usize> {
+        let mut visited = vec![false; self.end()];
+        let mut pending = vec![entry];
+        let mut returns = Vec::new();
+
+        while let Some(i) = pending.pop() {
+            if i >= self.end() || visited[i] {
+                continue;
+            }
+
+            visited[i] = true;
+
+            if self.instructions[i].mnemonic == TokenValue::RET {
+                returns.push(i);
+            }
+
+            pending.extend(&self.successors[i]);
+        }
+
+        returns
+    }
+
+    /// Find the instructions from which `ret` or `int` can be reached through the successors.
+    pub fn can_exit(&self) -> Vec<bool> {
+        let mut can_exit: Vec<bool> = self.instructions.iter().map(|instruction| is_exit(instruction.mnemonic))
+            .collect();
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for i in (0..self.end()).rev() {
+                if !can_exit[i] && self.successors[i].iter().any(|next| *next < self.end() && can_exit[*next]) {
+                    can_exit[i] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        can_exit
+    }
+}
@@ -0,0 +1,342 @@
+//! Static analysis of the control flow, which warns about code that is never executed, labels from which the program
+//! can never return or exit, execution falling off the end of the text or of a procedure, and registers read before
+//! they are written.
+
+use crate::assembler::*;
+use crate::scanner::SourceOptions;
+use crate::cfg::*;
+use crate::instruction::*;
+use crate::link::*;
+use crate::token::*;
+
+/// Get the bit of the 32-bits register a register is part of, such as `eax` for `ah`.
+pub(crate) fn register_bit(register: TokenValue) -> u8 {
+    match register_info(register) {
+        Some((number, 1)) if number >= 4 => 1 << (number - 4),
+        Some((number, _)) => 1 << number,
+        None => 0,
+    }
+}
+
+/// Add the registers of the address of a memory operand to `reads`.
+fn read_address(operand: &Operand, reads: &mut Vec<TokenValue>) {
+    if let Operand::Memory(memory) = operand {
+        reads.extend(memory.base);
+        reads.extend(memory.index.map(|(index, _)| index));
+    }
+}
+
+/// Add the registers an operand reads to `reads`, which is the register itself or those of the address.
+fn read(operand: &Operand, reads: &mut Vec<TokenValue>) {
+    match operand {
+        Operand::Register(register) => reads.push(*register),
+        _ => read_address(operand, reads),
+    }
+}
+
+/// Add the register an operand writes to `writes`, the address of a memory operand is read instead.
+fn write(operand: &Operand, reads: &mut Vec<TokenValue>, writes: &mut Vec<TokenValue>) {
+    match operand {
+        Operand::Register(register) => writes.push(*register),
+        _ => read_address(operand, reads),
+    }
+}
+
+/// Find the registers an instruction reads and writes, including those it uses implicitly, such as `eax` of `mul`.
+pub(crate) fn accesses(instruction: &Instruction) -> (Vec<TokenValue>, Vec<TokenValue>) {
+    let operands = instruction.operands.as_slice();
+    let mut reads = Vec::new();
+    let mut writes = Vec::new();
+
+    match (instruction.mnemonic, operands) {
+        (TokenValue::MOV | TokenValue::MOVZX | TokenValue::MOVSX | TokenValue::LEA, [destination, source]) => {
+            read(source, &mut reads);
+            write(destination, &mut reads, &mut writes);
+        },
+        // clearing a register does not depend on its value
+        (TokenValue::XOR | TokenValue::SUB, [Operand::Register(destination), Operand::Register(source)])
+            if destination == source => writes.push(*destination),
+        (TokenValue::CMP | TokenValue::TEST | TokenValue::BOUND, [destination, source]) => {
+            read(destination, &mut reads);
+            read(source, &mut reads);
+        },
+        (TokenValue::ADD | TokenValue::SUB | TokenValue::ADC | TokenValue::SBB | TokenValue::AND | TokenValue::OR |
+         TokenValue::XOR | TokenValue::SHL | TokenValue::SHR | TokenValue::SAR, [destination, source]) => {
+            read(destination, &mut reads);
+            read(source, &mut reads);
+            write(destination, &mut reads, &mut writes);
+        },
+        (TokenValue::INC | TokenValue::DEC | TokenValue::NOT | TokenValue::NEG, [destination]) => {
+            read(destination, &mut reads);
+            write(destination, &mut reads, &mut writes);
+        },
+        (TokenValue::MUL | TokenValue::IMUL, [source]) => {
+            read(source, &mut reads);
+            reads.push(TokenValue::EAX);
+            writes.extend([TokenValue::EAX, TokenValue::EDX]);
+        },
+        (TokenValue::DIV | TokenValue::IDIV, [source]) => {
+            read(source, &mut reads);
+            reads.push(TokenValue::EAX);
+
+            if source.size() != Some(1) {
+                reads.push(TokenValue::EDX);
+            }
+
+            writes.extend([TokenValue::EAX, TokenValue::EDX]);
+        },
+        (TokenValue::IMUL, [destination, source]) => {
+            read(destination, &mut reads);
+            read(source, &mut reads);
+            write(destination, &mut reads, &mut writes);
+        },
+        // the destination is kept if the source is 0
+        (TokenValue::BSF | TokenValue::BSR, [destination, source]) => {
+            read(destination, &mut reads);
+            read(source, &mut reads);
+            write(destination, &mut reads, &mut writes);
+        },
+        (TokenValue::IMUL, [destination, source, _]) => {
+            read(source, &mut reads);
+            write(destination, &mut reads, &mut writes);
+        },
+        (TokenValue::PUSH | TokenValue::CALL, [source]) | (TokenValue::IVT, [_, source]) => read(source, &mut reads),
+        (TokenValue::IN, [accumulator, port]) => {
+            read(port, &mut reads);
+            write(accumulator, &mut reads, &mut writes);
+        },
+        (TokenValue::OUT, [port, accumulator]) => {
+            read(port, &mut reads);
+            read(accumulator, &mut reads);
+        },
+        (TokenValue::JECXZ, _) => reads.push(TokenValue::ECX),
+        (TokenValue::JCXZ, _) => reads.push(TokenValue::CX),
+        (TokenValue::POP, [destination]) => write(destination, &mut reads, &mut writes),
+        (TokenValue::DAA | TokenValue::DAS, []) => {
+            reads.push(TokenValue::AL);
+            writes.push(TokenValue::AL);
+        },
+        (TokenValue::AAA | TokenValue::AAS, []) => {
+            reads.push(TokenValue::AX);
+            writes.push(TokenValue::AX);
+        },
+        (TokenValue::CPUID, []) => {
+            reads.push(TokenValue::EAX);
+            writes.extend([TokenValue::EAX, TokenValue::EBX, TokenValue::ECX, TokenValue::EDX]);
+        },
+        (TokenValue::LAHF, []) => writes.push(TokenValue::AH),
+        (TokenValue::SAHF, []) => reads.push(TokenValue::AH),
+        (TokenValue::PUSHAD, []) => reads.extend((0..8).map(|number| register_from_number(number, 4))),
+        // the value pushed for `esp` is skipped
+        (TokenValue::POPAD, []) => {
+            writes.extend((0..8).map(|number| register_from_number(number, 4))
+                          .filter(|register| *register != TokenValue::ESP));
+        },
+        (TokenValue::SETE | TokenValue::SETNE | TokenValue::SETG | TokenValue::SETGE | TokenValue::SETL |
+         TokenValue::SETLE | TokenValue::SETA | TokenValue::SETAE | TokenValue::SETB | TokenValue::SETBE |
+         TokenValue::SETO | TokenValue::SETNO | TokenValue::SETS | TokenValue::SETNS | TokenValue::SETP |
+         TokenValue::SETNP,
+         [destination]) => write(destination, &mut reads, &mut writes),
+        // the number of the system call or the function is in `eax`, and its result goes back there
+        (TokenValue::INT, [Operand::Immediate(0x80 | 0x81 | 0x83)]) => {
+            reads.push(TokenValue::EAX);
+            writes.push(TokenValue::EAX);
+        },
+        // the arguments of the print service are on the stack
+        (TokenValue::INT, [Operand::Immediate(0x82)]) => writes.push(TokenValue::EAX),
+        // a handler installed by `ivt` may return anything in any register
+        (TokenValue::INT, _) => writes.extend((0..8).map(|number| register_from_number(number, 4))),
+        _ => {},
+    }
+
+    (reads, writes)
+}
+
+/// Get the registers which are surely written before every instruction, as bits of `register_bit`, which is `None`
+/// for the instructions never reached.
+///
+/// A register read before it is written counts as written after the instruction, so each mistake is reported once.
+fn initialized(graph: &ControlFlowGraph, entrance: usize, seeded: u8) -> Vec<Option<u8>> {
+    let mut states: Vec<Option<u8>> = vec![None; graph.end() + 1];
+    let returns: Vec<Vec<usize>> = graph.calls.iter()
+        .map(|target| target.map_or_else(Vec::new, |target| graph.returns(target)))
+        .collect();
+    let mut changed = true;
+
+    states[entrance] = Some(seeded);
+
+    let meet = |states: &mut Vec<Option<u8>>, i: usize, state: u8| -> bool {
+        let new = states[i].map_or(state, |old| old & state);
+        let changed = states[i] != Some(new);
+        states[i] = Some(new);
+        changed
+    };
+
+    while changed {
+        changed = false;
+
+        for i in 0..graph.end() {
+            let state = match states[i] {
+                Some(state) => state,
+                None => continue,
+            };
+
+            let (reads, writes) = accesses(graph.instructions[i]);
+            let after = reads.iter().chain(&writes).fold(state, |state, register| state | register_bit(*register));
+
+            // a handler may be called by `int` anywhere, so the registers are unknown rather than unset
+            if let Some(handler) = graph.handlers[i] {
+                changed |= meet(&mut states, handler, u8::MAX);
+            }
+
+            // so may a function called through a pointer
+            if let Some(function) = graph.pointers[i] {
+                changed |= meet(&mut states, function, u8::MAX);
+            }
+
+            if let Some(target) = graph.calls[i] {
+                changed |= meet(&mut states, target, after);
+
+                // the function returns with what it writes on every path to `ret`
+                let returned = returns[i].iter().filter_map(|ret| states[*ret]).reduce(|first, second| first & second);
+
+                if let Some(returned) = returned {
+                    changed |= meet(&mut states, i + 1, after | returned);
+                }
+            } else {
+                for next in &graph.successors[i] {
+                    changed |= meet(&mut states, *next, after);
+                }
+            }
+        }
+    }
+
+    states
+}
+
+/// Find the index of the instruction the program starts from, which is after the entry label, or after `main`,
+/// `start`, `_main` or `_start` without one, as in the virtual machine.
+fn entrance(graph: &ControlFlowGraph, entry_label: Option<&str>) -> usize {
+    match entry_label {
+        Some(entry_label) => graph.labels.get(entry_label).copied().unwrap_or(0),
+        None => ["main", "start", "_main", "_start"].iter()
+            .filter_map(|name| graph.labels.get(*name).copied())
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+/// Check the control flow of the program, returns a warning for each problem in the order of the source.
+///
+/// `esp` and `ebp` are set by the virtual machine, and the registers of `seeded` are set before the program runs, so
+/// they may be read at once.
+pub fn check(statements: &[Statement], entry_label: Option<&str>, seeded: &[TokenValue]) -> Vec<String> {
+    let graph = ControlFlowGraph::new(statements);
+    let entrance = entrance(&graph, entry_label);
+    let reachable = graph.reachable(&[entrance]);
+    let can_exit = graph.can_exit();
+    let seeded = [TokenValue::ESP, TokenValue::EBP].iter().chain(seeded)
+        .fold(0, |state, register| state | register_bit(*register));
+    let initialized = initialized(&graph, entrance, seeded);
+
+    // data is never executed, on purpose, and directives are not executed at all
+    let dead: Vec<bool> = graph.instructions.iter().zip(&reachable)
+        .map(|(instruction, reachable)| !reachable && !is_data(instruction.mnemonic) &&
+             !is_directive(instruction.mnemonic))
+        .collect();
+
+    let mut diagnostics = Vec::new();
+    let mut i = 0;
+
+    for (position, statement) in statements.iter().enumerate() {
+        match statement {
+            Statement::Label(name, location) => {
+                let data = graph.instructions.get(i).is_some_and(|instruction| is_data(instruction.mnemonic));
+
+                if graph.labels.get(name) == Some(&i) && !data && !is_location_label(name) &&
+                    !can_exit.get(i).copied().unwrap_or(false) {
+                    diagnostics.push(format!("Warning: {} No path from label \"{}\" to ret or int",
+                                             location.to_string(), name));
+                }
+            },
+            Statement::Instruction(instruction) => {
+                if dead[i] && (i == 0 || !dead[i - 1]) {
+                    let count = statements[position..].iter()
+                        .filter(|statement| matches!(statement, Statement::Instruction(_)))
+                        .zip(&dead[i..])
+                        .take_while(|(_, dead)| **dead)
+                        .count();
+                    diagnostics.push(format!("Warning: {} Unreachable code, {} instruction{} never executed",
+                                             instruction.location.to_string(), count,
+                                             if count == 1 { " is" } else { "s are" }));
+                }
+
+                if let Some(state) = initialized[i] {
+                    let (reads, _) = accesses(instruction);
+                    let mut reported = 0;
+
+                    for register in reads {
+                        let bit = register_bit(register);
+
+                        if state & bit == 0 && reported & bit == 0 {
+                            reported |= bit;
+                            diagnostics.push(format!("Warning: {} {}: {} may be read before it is written",
+                                                     instruction.location.to_string(), instruction,
+                                                     register_name(register)));
+                        }
+                    }
+                }
+
+                // `int` may be the `exit` system call, which never comes back, and the end of a procedure is
+                // warned about before it
+                if reachable[i] && !is_exit(instruction.mnemonic) && instruction.mnemonic != TokenValue::ENDP &&
+                    graph.successors[i].contains(&graph.end()) {
+                    diagnostics.push(format!("Warning: {} Execution falls off the end of the text after {}",
+                                             instruction.location.to_string(), instruction));
+                }
+
+                if reachable[i] && !is_exit(instruction.mnemonic) && graph.successors[i].contains(&(i + 1)) &&
+                    graph.instructions.get(i + 1).is_some_and(|next| is_data(next.mnemonic)) {
+                    diagnostics.push(format!("Warning: {} Execution falls into data after {}",
+                                             instruction.location.to_string(), instruction));
+                }
+
+                let procedure = graph.instructions.get(i + 1)
+                    .filter(|next| next.mnemonic == TokenValue::ENDP)
+                    .and_then(|next| next.operands.first());
+
+                if let (true, Some(Operand::Label(name))) = (reachable[i] && !is_exit(instruction.mnemonic) &&
+                                                             graph.successors[i].contains(&(i + 1)), procedure) {
+                    diagnostics.push(format!("Warning: {} Execution falls off the end of procedure {} after {}",
+                                             instruction.location.to_string(), name, instruction));
+                }
+
+                i += 1;
+            },
+        }
+    }
+
+    diagnostics
+}
+
+/// Scan with `options`, parse and check source files as one program, an error of scanning or parsing stops at once.
+pub fn check_files(source_file_names: &[String], entry_label: Option<&str>, seeded: &[TokenValue],
+                   options: &SourceOptions) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+
+    for source_file_name in source_file_names {
+        tokens.append(&mut scan_file(source_file_name.to_owned(), options));
+    }
+
+    if let Some(msg) = preprocess_tokens(&mut tokens, options).into_iter().next() {
+        return Err(msg);
+    }
+
+    let statements = Parser::new(&tokens).parse_program()?;
+
+    if let Some(msg) = link(&statements).into_iter().next() {
+        return Err(msg);
+    }
+
+    Ok(check(&statements, entry_label, seeded))
+}
@@ -0,0 +1,150 @@
+use crate::instruction::*;
+use crate::assembler::*;
+use crate::scanner::SourceOptions;
+use crate::cfg::*;
+use crate::token::TokenValue;
+use capstone::prelude::*;
+use std::collections::HashMap;
+
+/// Disassemble the machine code of every instruction with capstone, and compare the result with the instruction it
+/// was assembled from.
+///
+/// Returns a message for every instruction whose machine code does not decode back to itself, so an empty vector
+/// means that the encoder agrees with capstone on the whole program.
+pub fn verify_encoding(program: &Program) -> Result<Vec<String>, String> {
+    let capstone = Capstone::new()
+        .x86()
+        .mode(arch::x86::ArchMode::Mode32)
+        .syntax(arch::x86::ArchSyntax::Intel)
+        .build()
+        .map_err(|err| format!("Can not initialize capstone, because {}.", err))?;
+
+    let mut labels = HashMap::new();
+
+    for line in &program.lines {
+        if let Statement::Label(name, _) = &line.statement {
+            labels.insert(name.to_owned(), line.address);
+        }
+    }
+
+    let mut mismatches = Vec::new();
+
+    for line in &program.lines {
+        let instruction = match &line.statement {
+            Statement::Label(..) => continue,
+            // data and directives are not decoded
+            Statement::Instruction(instruction) if is_data(instruction.mnemonic) ||
+                is_directive(instruction.mnemonic) => continue,
+            Statement::Instruction(instruction) => instruction,
+        };
+
+        let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let mismatch = |reason: String| {
+            format!("Encoding Mismatch: {} {} is encoded as {}, {}", instruction.location.to_string(), instruction,
+                    bytes.join(" "), reason)
+        };
+
+        let decoded = match capstone.disasm_count(&line.bytes, line.address as u64, 1) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                mismatches.push(mismatch(format!("which capstone can not decode, because {}", err)));
+                continue;
+            },
+        };
+
+        let decoded = match decoded.iter().next() {
+            Some(decoded) => decoded,
+            None => {
+                mismatches.push(mismatch("which capstone can not decode".to_string()));
+                continue;
+            },
+        };
+
+        let text = match (decoded.mnemonic().unwrap_or(""), decoded.op_str().unwrap_or("")) {
+            // capstone prints the operands of `enter`, which may be left out for `enter 0, 0`
+            ("enter", "0, 0") if instruction.operands.is_empty() => "enter".to_string(),
+            // capstone writes the size of both bounds of `bound`
+            ("bound", operands) => format!("bound {}", operands.replace("dword ptr", "word ptr")
+                                           .replace("qword ptr", "dword ptr")),
+            // capstone names `pushad` and `popad` as AT&T does
+            ("pushal", "") => "pushad".to_string(),
+            ("popal", "") => "popad".to_string(),
+            (mnemonic, "") => mnemonic.to_string(),
+            (mnemonic, operands) => format!("{} {}", mnemonic, operands),
+        };
+
+        if decoded.bytes().len() != line.bytes.len() {
+            mismatches.push(mismatch(format!("but capstone decodes only {} bytes as {}", decoded.bytes().len(),
+                            text)));
+            continue;
+        }
+
+        let name = format!("capstone@{:#010x}", line.address);
+        let mut tokens = scan_source(name, text.to_owned(), &SourceOptions::default());
+        // capstone writes no size for the memory operand of `lea`
+        infer_memory_sizes(&mut tokens);
+
+        let actual = match Parser::new(&tokens).parse_statement() {
+            Ok(Some(Statement::Instruction(actual))) => actual,
+            _ => {
+                mismatches.push(mismatch(format!("which decodes to {}, that can not be parsed", text)));
+                continue;
+            },
+        };
+
+        if !same_instruction(instruction, &actual, &labels) {
+            mismatches.push(mismatch(format!("which decodes to {}", text)));
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Compare an instruction with the one decoded by capstone, which has jump targets as addresses.
+fn same_instruction(expected: &Instruction, actual: &Instruction, labels: &HashMap<String, u32>) -> bool {
+    // `test` of a register and memory is encoded with the memory first, whichever order it is written in
+    if let (TokenValue::TEST, [Operand::Register(_), Operand::Memory(_)], [Operand::Memory(_), Operand::Register(_)]) =
+        (expected.mnemonic, expected.operands.as_slice(), actual.operands.as_slice()) {
+        let mut swapped = actual.to_owned();
+        swapped.operands.reverse();
+        return same_instruction(expected, &swapped, labels);
+    }
+
+    if expected.mnemonic != actual.mnemonic || expected.operands.len() != actual.operands.len() {
+        return false;
+    }
+
+    // immediate data is sign-extended or truncated to the size of the destination
+    let size = expected.operands.first().and_then(|operand| operand.size()).unwrap_or(4);
+
+    expected.operands.iter().zip(&actual.operands).all(|(expected, actual)| match (expected, actual) {
+        (Operand::Register(expected), Operand::Register(actual)) => expected == actual,
+        (Operand::Immediate(expected), Operand::Immediate(actual)) => {
+            truncate(*expected, size) == truncate(*actual, size)
+        },
+        (Operand::Memory(expected), Operand::Memory(actual)) => {
+            canonical(expected, labels) == canonical(actual, labels)
+        },
+        (Operand::Label(name), Operand::Immediate(actual)) => labels.get(name).map(|address| *address as i64)
+            == Some(truncate(*actual, 4)),
+        _ => false,
+    })
+}
+
+fn truncate(value: i64, size: usize) -> i64 {
+    value & ((1i64 << (size * 8)) - 1)
+}
+
+/// `[reg * 1]` is encoded as `[reg]`, and the address of a label is added to the displacement.
+fn canonical(memory: &Memory, labels: &HashMap<String, u32>) -> Memory {
+    let displacement = match &memory.label {
+        Some(name) => memory.displacement.wrapping_add(labels.get(name).copied().unwrap_or(0) as i32),
+        None => memory.displacement,
+    };
+    let memory = Memory { displacement, label: None, ..memory.to_owned() };
+
+    match (memory.base, memory.index) {
+        (None, Some((index, 1))) => Memory { base: Some(index), index: None, ..memory },
+        _ => memory,
+    }
+}
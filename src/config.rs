@@ -0,0 +1,366 @@
+//! Project configuration file, `asmvm.toml`, so that a class can share settings without long command lines:
+//!
+//! ```toml
+//! syntax = "intel"
+//! entry = "start"
+//! include_paths = ["lib", "../common"]
+//! devices = ["serial"]
+//! call_depth_limit = 1000
+//! random_seed = 42
+//! detect_loops = true
+//!
+//! [memory]
+//! size = "4MiB"
+//!
+//! [quotas]
+//! instructions = 10000000
+//! memory_written = "16MiB"
+//! stack = "64KiB"
+//! output = "1MiB"
+//! time = "2s"
+//! host_files = false
+//!
+//! [cache]
+//! size = "4KiB"
+//! associativity = 2
+//! line_size = 64
+//!
+//! [aliases]
+//! bra = "jmp"
+//! move = "mov"
+//!
+//! [policy]
+//! deny = ["mul", "imul"]
+//! ```
+//!
+//! It is looked up in the current directory, then in its parents. Relative include paths are relative to the
+//! directory of the file.
+
+use crate::cache::CacheConfig;
+use crate::json::*;
+use crate::policy::Policy;
+use crate::port;
+use crate::scanner::*;
+use crate::toml;
+use crate::vm::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = "asmvm.toml";
+
+/// syntax modes of the source files
+const SYNTAXES: &[&str] = &["intel"];
+
+/// Settings of `asmvm.toml`, every one is optional
+#[derive(Default, Clone)]
+pub struct Config {
+    /// path of the file, `None` if there is no configuration file
+    pub path: Option<PathBuf>,
+    /// syntax mode of the source files, one of [`SYNTAXES`]
+    pub syntax: Option<String>,
+    /// size of memory in bytes
+    pub memory_size: Option<usize>,
+    /// label to start from instead of `main`
+    pub entry: Option<String>,
+    /// directories where source files and included files are searched
+    pub include_paths: Vec<PathBuf>,
+    /// devices attached to the ports of `in` and `out`, by their names of [`port::DEVICES`]
+    pub devices: Vec<String>,
+    /// other names of instructions, as pairs of the alias and the instruction
+    pub aliases: Vec<(String, String)>,
+    /// most calls which may be nested
+    pub call_depth_limit: Option<usize>,
+    /// seed of the random number service of `int 0x81`
+    pub random_seed: Option<u64>,
+    /// stop a program whose state repeats, see [`VM::set_loop_detection`]
+    pub detect_loops: bool,
+    /// hard limits on the resources of a program, such as for untrusted submissions
+    pub quotas: Quotas,
+    /// instructions which programs must not or must use, see [`Policy`]
+    pub policy: Policy,
+    /// geometry of the simulated data cache, see [`crate::cache`]
+    pub cache: Option<CacheConfig>,
+}
+
+/// Parse a duration in milliseconds, such as `2`, `2s`, `500ms` or `1m`; a number without unit is in seconds.
+pub fn parse_duration(text: &str) -> Option<u64> {
+    let text = text.trim();
+
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(position) => text.split_at(position),
+        None => (text, "s"),
+    };
+
+    let scale = match unit.trim() {
+        "ms" => 1,
+        "s" => 1000,
+        "m" | "min" => 60 * 1000,
+        _ => return None,
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+/// Parse the geometry of a data cache, such as `4KiB,2,64`, the size, the associativity and the size of a line, each of
+/// which may be left out for the default one, from [`CacheConfig::default`].
+pub fn parse_cache(text: &str) -> Result<CacheConfig, String> {
+    let default = CacheConfig::default();
+    let parts: Vec<&str> = text.split(',').map(|part| part.trim()).collect();
+
+    if parts.len() > 3 {
+        return Err(format!("Invalid cache: {}, expected <size>,<associativity>,<line size>", text));
+    }
+
+    let part = |i: usize, default: usize| match parts.get(i) {
+        None | Some(&"") => Ok(default),
+        Some(part) => parse_size(&Json::String(part.to_string()))
+            .ok_or_else(|| format!("Invalid cache: {}, expected <size>,<associativity>,<line size>", text)),
+    };
+
+    CacheConfig::new(part(0, default.size)?, part(1, default.associativity)?, part(2, default.line_size)?)
+}
+
+/// Parse a size such as `65536`, `"64KiB"` or `"2MiB"`.
+fn parse_size(value: &Json) -> Option<usize> {
+    if let Some(size) = value.as_u64() {
+        return Some(size as usize);
+    }
+
+    let text = value.as_str()?.trim();
+
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(position) => text.split_at(position),
+        None => (text, ""),
+    };
+
+    let scale = match unit.trim() {
+        "" | "B" => 1,
+        "K" | "KB" | "KiB" => 1 << 10,
+        "M" | "MB" | "MiB" => 1 << 20,
+        "G" | "GB" | "GiB" => 1 << 30,
+        _ => return None,
+    };
+
+    number.parse::<usize>().ok()?.checked_mul(scale)
+}
+
+impl Config {
+    /// Find `asmvm.toml` in `directory` or its parents, and load it. Without one, every setting is the default.
+    pub fn find(directory: &Path) -> Result<Config, String> {
+        for directory in directory.ancestors() {
+            let path = directory.join(CONFIG_FILE_NAME);
+
+            if path.is_file() {
+                return Config::load(&path);
+            }
+        }
+
+        Ok(Default::default())
+    }
+
+    /// Load the configuration file at `path`.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let file_name = path.to_string_lossy();
+        let text = fs::read_to_string(path).map_err(|err| format!("Can not read {}, because {}.", file_name, err))?;
+        let table = toml::parse(&file_name, &text)?;
+        let error = |msg: &str| format!("Config Error: {}: {}", file_name, msg);
+
+        let mut config = Config { path: Some(path.to_path_buf()), ..Default::default() };
+
+        if let Json::Object(members) = &table {
+            for (name, _) in members {
+                if !["syntax", "entry", "include_paths", "devices", "memory", "aliases", "call_depth_limit",
+                     "random_seed", "detect_loops", "quotas", "policy", "cache"].contains(&name.as_str()) {
+                    return Err(error(&format!("Unknown setting: {}", name)));
+                }
+            }
+        }
+
+        if let Some(syntax) = table.get("syntax") {
+            let syntax = syntax.as_str().ok_or_else(|| error("syntax must be a string"))?;
+
+            if !SYNTAXES.contains(&syntax) {
+                return Err(error(&format!("Unsupported syntax: {}, expected one of {}", syntax, SYNTAXES.join(", "))));
+            }
+
+            config.syntax = Some(syntax.to_owned());
+        }
+
+        if let Some(memory) = table.get("memory") {
+            if let Some(size) = memory.get("size") {
+                config.memory_size = Some(parse_size(size).ok_or_else(|| error("memory.size must be a size, such \
+                                                                                as 65536 or \"2MiB\""))?);
+            }
+        }
+
+        if let Some(quotas) = table.get("quotas") {
+            let members = match quotas {
+                Json::Object(members) => members,
+                _ => return Err(error("quotas must be a table")),
+            };
+
+            for (name, value) in members {
+                if name == "host_files" {
+                    let host_files = value.as_bool().ok_or_else(|| error("quotas.host_files must be a boolean"))?;
+                    config.quotas.deny_host_files = !host_files;
+                    continue;
+                }
+
+                let limit = match name.as_str() {
+                    "instructions" => value.as_u64().ok_or_else(|| error("quotas.instructions must be a positive \
+                                                                         integer"))?,
+                    "memory_written" | "stack" | "output" => parse_size(value)
+                        .ok_or_else(|| error(&format!("quotas.{} must be a size, such as 65536 or \"1MiB\"", name)))?
+                        as u64,
+                    "forks" => value.as_u64().ok_or_else(|| error("quotas.forks must be a positive integer"))?,
+                    "time" => value.as_u64().and_then(|seconds| seconds.checked_mul(1000))
+                        .or_else(|| value.as_str().and_then(parse_duration))
+                        .ok_or_else(|| error("quotas.time must be a duration, such as 2 or \"500ms\""))?,
+                    _ => return Err(error(&format!("Unknown quota: {}", name))),
+                };
+
+                match name.as_str() {
+                    "instructions" => config.quotas.instructions = Some(limit),
+                    "memory_written" => config.quotas.memory_written = Some(limit),
+                    "stack" => config.quotas.stack = Some(limit),
+                    "output" => config.quotas.output = Some(limit),
+                    _ => config.quotas.time = Some(limit),
+                }
+            }
+        }
+
+        if let Some(limit) = table.get("call_depth_limit") {
+            config.call_depth_limit = Some(limit.as_u64().ok_or_else(|| error("call_depth_limit must be a positive \
+                                                                             integer"))? as usize);
+        }
+
+        if let Some(seed) = table.get("random_seed") {
+            config.random_seed = Some(seed.as_u64().ok_or_else(|| error("random_seed must be a positive integer"))?);
+        }
+
+        if let Some(detect_loops) = table.get("detect_loops") {
+            config.detect_loops = detect_loops.as_bool().ok_or_else(|| error("detect_loops must be a boolean"))?;
+        }
+
+        if let Some(cache) = table.get("cache") {
+            let members = match cache {
+                Json::Object(members) => members,
+                _ => return Err(error("cache must be a table")),
+            };
+
+            if let Some((name, _)) = members.iter()
+                .find(|(name, _)| !["size", "associativity", "line_size"].contains(&name.as_str())) {
+                return Err(error(&format!("Unknown cache setting: {}", name)));
+            }
+
+            let default = CacheConfig::default();
+            let number = |name: &str, default: usize| match cache.get(name) {
+                Some(value) => parse_size(value).ok_or_else(|| error(&format!("cache.{} must be a positive integer",
+                                                                              name))),
+                None => Ok(default),
+            };
+
+            config.cache = Some(CacheConfig::new(number("size", default.size)?,
+                                                 number("associativity", default.associativity)?,
+                                                 number("line_size", default.line_size)?)
+                .map_err(|msg| error(&msg))?);
+        }
+
+        if let Some(entry) = table.get("entry") {
+            config.entry = Some(entry.as_str().ok_or_else(|| error("entry must be a label"))?.to_owned());
+        }
+
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+        if let Some(include_paths) = table.get("include_paths") {
+            for include_path in include_paths.as_array().ok_or_else(|| error("include_paths must be an array"))? {
+                let include_path = include_path.as_str().ok_or_else(|| error("include_paths must be strings"))?;
+                config.include_paths.push(directory.join(include_path));
+            }
+        }
+
+        if let Some(devices) = table.get("devices") {
+            for device in devices.as_array().ok_or_else(|| error("devices must be an array"))? {
+                let device = device.as_str().ok_or_else(|| error("devices must be strings"))?;
+
+                if !port::DEVICES.contains(&device) {
+                    return Err(error(&format!("Unknown device: {}, expected one of {}", device,
+                                              port::DEVICES.join(", "))));
+                }
+
+                config.devices.push(device.to_owned());
+            }
+        }
+
+        if let Some(aliases) = table.get("aliases") {
+            let aliases = match aliases {
+                Json::Object(aliases) => aliases,
+                _ => return Err(error("aliases must be a table")),
+            };
+
+            for (alias, instruction) in aliases {
+                let instruction = instruction.as_str().ok_or_else(|| error("aliases must be instructions"))?;
+                check_alias(alias, instruction).map_err(|msg| error(&msg))?;
+                config.aliases.push((alias.to_owned(), instruction.to_owned()));
+            }
+        }
+
+        if let Some(policy) = table.get("policy") {
+            config.policy = Policy::parse(policy, &config.aliases).map_err(|msg| error(&msg))?;
+        }
+
+        Ok(config)
+    }
+
+    /// Find a source file, relative to the current directory first, then to each include path in order.
+    pub fn resolve_source_file_name(&self, source_file_name: &str) -> String {
+        if Path::new(source_file_name).exists() || Path::new(source_file_name).is_absolute() {
+            return source_file_name.to_owned();
+        }
+
+        self.include_paths.iter()
+            .map(|include_path| include_path.join(source_file_name))
+            .find(|path| path.exists())
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|| source_file_name.to_owned())
+    }
+
+    /// Get the options to read source files with, the aliases of instructions and where included files are searched.
+    pub fn source_options(&self) -> Result<SourceOptions, String> {
+        let mut options = SourceOptions::default();
+        options.set_aliases(&self.aliases)?;
+        options.set_include_paths(&self.include_paths);
+        Ok(options)
+    }
+
+    /// Apply the settings of the virtual machine and of reading its source files, before loading a program.
+    pub fn apply(&self, vm: &mut VM) -> Result<(), String> {
+        if let Some(memory_size) = self.memory_size {
+            vm.set_memory_size(memory_size)?;
+        }
+
+        if let Some(limit) = self.call_depth_limit {
+            vm.set_call_depth_limit(limit);
+        }
+
+        if let Some(seed) = self.random_seed {
+            vm.set_service_seed(seed);
+        }
+
+        vm.set_loop_detection(self.detect_loops);
+
+        if let Some(cache) = self.cache {
+            vm.set_cache(Some(cache));
+        }
+
+        vm.set_quotas(self.quotas);
+
+        // after the quotas, which limit the output of the devices
+        port::attach(vm, &self.devices)?;
+
+        vm.set_policy(self.policy.clone());
+        vm.set_entry_label(self.entry.to_owned());
+        vm.set_source_options(self.source_options()?);
+        Ok(())
+    }
+}
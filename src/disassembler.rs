@@ -0,0 +1,563 @@
+use crate::token::*;
+use crate::instruction::*;
+use crate::assembler::*;
+use std::collections::BTreeSet;
+
+/// Get the name of the label generated for a jump target.
+fn label_name(address: u32) -> String {
+    format!("L_{:08x}", address)
+}
+
+/// Disassemble x86 machine code for 32-bits mode loaded at `origin`.
+///
+/// The targets of `call` and jump instructions become labels, so that the program can be printed as source code and
+/// run by the virtual machine.
+pub fn disassemble(source_name: &str, bytes: &[u8], origin: u32) -> Result<Program, String> {
+    disassemble_ranges(source_name, &[(origin, bytes)], origin)
+}
+
+/// Disassemble several ranges of machine code, each is a pair of its address and bytes, into one program whose jump
+/// targets may be in any range.
+pub fn disassemble_ranges(source_name: &str, ranges: &[(u32, &[u8])], origin: u32) -> Result<Program, String> {
+    let mut decoded = Vec::new();
+    let mut targets = BTreeSet::new();
+
+    for (address, bytes) in ranges {
+        let mut decoder = Decoder {
+            source_name,
+            bytes,
+            position: 0,
+            origin: *address,
+            start: 0,
+        };
+
+        while decoder.position < bytes.len() {
+            decoder.start = decoder.position;
+            let (instruction, target) = decoder.decode()?;
+
+            if let Some(target) = target {
+                targets.insert(target);
+            }
+
+            decoded.push((address + decoder.start as u32, bytes[decoder.start..decoder.position].to_vec(),
+                        instruction));
+        }
+    }
+
+    for target in &targets {
+        if !decoded.iter().any(|(address, _, _)| address == target) {
+            return Err(format!("Decoding Error: {}: jump target {:#010x} is not the start of an instruction",
+                        source_name, target));
+        }
+    }
+
+    let mut lines = Vec::new();
+
+    for (address, bytes, instruction) in decoded {
+        if targets.contains(&address) {
+            let location = instruction.location.to_owned();
+            lines.push(ListingLine { address, bytes: Vec::new(), statement: Statement::Label(label_name(address),
+                        location) });
+        }
+
+        lines.push(ListingLine { address, bytes, statement: Statement::Instruction(instruction) });
+    }
+
+    Ok(Program { origin, lines })
+}
+
+/// Decoder of x86 machine code
+struct Decoder<'a> {
+    source_name: &'a str,
+    bytes: &'a [u8],
+    position: usize,
+    origin: u32,
+    /// offset of the instruction being decoded
+    start: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn error(&self, msg: &str) -> String {
+        format!("Decoding Error: {}: {:#010x}: {}", self.source_name, self.origin + self.start as u32, msg)
+    }
+
+    fn next_byte(&mut self) -> Result<u8, String> {
+        match self.bytes.get(self.position) {
+            Some(byte) => {
+                self.position += 1;
+                Ok(*byte)
+            },
+            None => Err(self.error("Unexpected end of machine code")),
+        }
+    }
+
+    /// Read immediate data of `size` bytes, sign extended when `signed`.
+    fn immediate(&mut self, size: usize, signed: bool) -> Result<i64, String> {
+        let mut bytes = [0; 4];
+
+        for byte in bytes.iter_mut().take(size) {
+            *byte = self.next_byte()?;
+        }
+
+        let value = u32::from_le_bytes(bytes) as i64;
+
+        if signed && size < 4 && value >= (1i64 << (8 * size - 1)) {
+            Ok(value - (1i64 << (8 * size)))
+        } else if signed && size == 4 {
+            Ok(value as u32 as i32 as i64)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn location(&self) -> TokenLocation {
+        TokenLocation::new(self.source_name.to_string(), 0, (self.origin + self.start as u32) as i32)
+    }
+
+    fn instruction(&self, mnemonic: TokenValue, name: &str, operands: Vec<Operand>) -> Instruction {
+        Instruction {
+            mnemonic,
+            name: name.to_string(),
+            operands,
+            location: self.location(),
+        }
+    }
+
+    /// Decode ModRM byte, SIB byte and displacement into the `reg` field and a register or memory operand.
+    fn modrm(&mut self, size: usize) -> Result<(u8, Operand), String> {
+        let modrm = self.next_byte()?;
+        let mode = modrm >> 6;
+        let reg = (modrm >> 3) & 7;
+        let rm = modrm & 7;
+
+        if mode == 3 {
+            return Ok((reg, Operand::Register(register_from_number(rm, size))));
+        }
+
+        let mut base = None;
+        let mut index = None;
+        let mut no_base = false;
+
+        if rm == 4 {
+            let sib = self.next_byte()?;
+            let scale = 1u32 << (sib >> 6);
+            let index_number = (sib >> 3) & 7;
+            let base_number = sib & 7;
+
+            if index_number != 4 {
+                index = Some((register_from_number(index_number, 4), scale));
+            }
+
+            if base_number == 5 && mode == 0 {
+                no_base = true;
+            } else {
+                base = Some(register_from_number(base_number, 4));
+            }
+        } else if rm == 5 && mode == 0 {
+            no_base = true;
+        } else {
+            base = Some(register_from_number(rm, 4));
+        }
+
+        let displacement = match mode {
+            0 if no_base => self.immediate(4, true)?,
+            1 => self.immediate(1, true)?,
+            2 => self.immediate(4, true)?,
+            _ => 0,
+        };
+
+        Ok((reg, Operand::Memory(Memory { size, base, index, displacement: displacement as i32, label: None })))
+    }
+
+    /// Get the target of a relative jump whose displacement has been read.
+    fn target(&self, displacement: i64) -> u32 {
+        (self.origin + self.position as u32).wrapping_add(displacement as u32)
+    }
+
+    fn decode(&mut self) -> Result<(Instruction, Option<u32>), String> {
+        let mut opcode = self.next_byte()?;
+        let mut size = 4;
+
+        if opcode == 0x66 {
+            size = 2;
+            opcode = self.next_byte()?;
+        }
+
+        // the address size prefix is only supported for `jcxz`
+        if opcode == 0x67 {
+            return match self.next_byte()? {
+                0xe3 => {
+                    let displacement = self.immediate(1, true)?;
+                    let target = self.target(displacement);
+                    Ok((self.instruction(TokenValue::JCXZ, "jcxz", vec![Operand::Label(label_name(target))]),
+                        Some(target)))
+                },
+                opcode => Err(self.error(&format!("Unsupported opcode: 0x67 {:#04x}", opcode))),
+            };
+        }
+
+        let wide = if opcode & 1 == 1 { size } else { 1 };
+
+        let instruction = match opcode {
+            0x00..=0x3f if opcode & 7 < 6 => {
+                let (mnemonic, name) = match opcode >> 3 {
+                    0 => (TokenValue::ADD, "add"),
+                    1 => (TokenValue::OR, "or"),
+                    2 => (TokenValue::ADC, "adc"),
+                    3 => (TokenValue::SBB, "sbb"),
+                    4 => (TokenValue::AND, "and"),
+                    5 => (TokenValue::SUB, "sub"),
+                    6 => (TokenValue::XOR, "xor"),
+                    7 => (TokenValue::CMP, "cmp"),
+                    _ => return Err(self.error(&format!("Unsupported opcode: {:#04x}", opcode))),
+                };
+
+                let operands = match opcode & 7 {
+                    0 | 1 => {
+                        let (reg, rm) = self.modrm(wide)?;
+                        vec![rm, Operand::Register(register_from_number(reg, wide))]
+                    },
+                    2 | 3 => {
+                        let (reg, rm) = self.modrm(wide)?;
+                        vec![Operand::Register(register_from_number(reg, wide)), rm]
+                    },
+                    _ => vec![Operand::Register(register_from_number(0, wide)), Operand::Immediate(self.immediate(wide,
+                                    false)?)],
+                };
+
+                self.instruction(mnemonic, name, operands)
+            },
+            0x0f => return self.decode_two_byte(size),
+            0x27 => self.instruction(TokenValue::DAA, "daa", Vec::new()),
+            0x2f => self.instruction(TokenValue::DAS, "das", Vec::new()),
+            0x37 => self.instruction(TokenValue::AAA, "aaa", Vec::new()),
+            0x3f => self.instruction(TokenValue::AAS, "aas", Vec::new()),
+            0x40..=0x47 => self.instruction(TokenValue::INC, "inc", vec![Operand::Register(register_from_number(opcode,
+                                size))]),
+            0x48..=0x4f => self.instruction(TokenValue::DEC, "dec", vec![Operand::Register(register_from_number(opcode,
+                                size))]),
+            0x50..=0x57 => self.instruction(TokenValue::PUSH, "push", vec![Operand::Register(register_from_number(opcode,
+                                size))]),
+            0x58..=0x5f => self.instruction(TokenValue::POP, "pop", vec![Operand::Register(register_from_number(opcode,
+                                size))]),
+            // the forms of 16 bits, `pushaw` and `popaw`, are not supported
+            0x60 if size == 4 => self.instruction(TokenValue::PUSHAD, "pushad", Vec::new()),
+            0x61 if size == 4 => self.instruction(TokenValue::POPAD, "popad", Vec::new()),
+            0x62 => {
+                let (reg, rm) = self.modrm(size)?;
+
+                if !matches!(rm, Operand::Memory(_)) {
+                    return Err(self.error("The bounds of bound must be memory"));
+                }
+
+                self.instruction(TokenValue::BOUND, "bound", vec![Operand::Register(register_from_number(reg, size)),
+                                 rm])
+            },
+            0x68 => {
+                let value = self.immediate(size, false)?;
+                self.instruction(TokenValue::PUSH, "push", vec![Operand::Immediate(value)])
+            },
+            0x6a => {
+                let value = self.immediate(1, true)?;
+                self.instruction(TokenValue::PUSH, "push", vec![Operand::Immediate(value)])
+            },
+            0x69 | 0x6b => {
+                let (reg, rm) = self.modrm(size)?;
+                let value = if opcode == 0x6b { self.immediate(1, true)? } else { self.immediate(size, true)? };
+                self.instruction(TokenValue::IMUL, "imul", vec![Operand::Register(register_from_number(reg, size)), rm,
+                        Operand::Immediate(value)])
+            },
+            0x70..=0x7f => {
+                let displacement = self.immediate(1, true)?;
+                return self.jump(opcode & 0xf, displacement);
+            },
+            0x80 | 0x81 | 0x83 => {
+                let operand_size = if opcode == 0x80 { 1 } else { size };
+                let (reg, rm) = self.modrm(operand_size)?;
+                let value = match opcode {
+                    0x81 => self.immediate(size, false)?,
+                    _ => self.immediate(1, true)?,
+                };
+
+                let (mnemonic, name) = match reg {
+                    0 => (TokenValue::ADD, "add"),
+                    1 => (TokenValue::OR, "or"),
+                    2 => (TokenValue::ADC, "adc"),
+                    3 => (TokenValue::SBB, "sbb"),
+                    4 => (TokenValue::AND, "and"),
+                    5 => (TokenValue::SUB, "sub"),
+                    6 => (TokenValue::XOR, "xor"),
+                    7 => (TokenValue::CMP, "cmp"),
+                    _ => return Err(self.error(&format!("Unsupported opcode: {:#04x} /{}", opcode, reg))),
+                };
+
+                self.instruction(mnemonic, name, vec![rm, Operand::Immediate(value)])
+            },
+            0x84 | 0x85 => {
+                let (reg, rm) = self.modrm(wide)?;
+                self.instruction(TokenValue::TEST, "test", vec![rm, Operand::Register(register_from_number(reg, wide))])
+            },
+            0x88..=0x8b => {
+                let (reg, rm) = self.modrm(wide)?;
+                let register = Operand::Register(register_from_number(reg, wide));
+
+                if opcode < 0x8a {
+                    self.instruction(TokenValue::MOV, "mov", vec![rm, register])
+                } else {
+                    self.instruction(TokenValue::MOV, "mov", vec![register, rm])
+                }
+            },
+            0x8d => {
+                let (reg, rm) = self.modrm(size)?;
+
+                if !matches!(rm, Operand::Memory(_)) {
+                    return Err(self.error("The source of lea must be memory"));
+                }
+
+                self.instruction(TokenValue::LEA, "lea", vec![Operand::Register(register_from_number(reg, size)), rm])
+            },
+            0x8f => {
+                let (reg, rm) = self.modrm(size)?;
+
+                if reg != 0 {
+                    return Err(self.error(&format!("Unsupported opcode: {:#04x} /{}", opcode, reg)));
+                }
+
+                self.instruction(TokenValue::POP, "pop", vec![rm])
+            },
+            0x90 if size == 4 => self.instruction(TokenValue::NOP, "nop", Vec::new()),
+            0x9c if size == 4 => self.instruction(TokenValue::PUSHFD, "pushfd", Vec::new()),
+            0x9d if size == 4 => self.instruction(TokenValue::POPFD, "popfd", Vec::new()),
+            0x9e => self.instruction(TokenValue::SAHF, "sahf", Vec::new()),
+            0x9f => self.instruction(TokenValue::LAHF, "lahf", Vec::new()),
+            0xb0..=0xbf => {
+                let register_size = if opcode < 0xb8 { 1 } else { size };
+                let value = self.immediate(register_size, false)?;
+                self.instruction(TokenValue::MOV, "mov", vec![Operand::Register(register_from_number(opcode,
+                                register_size)), Operand::Immediate(value)])
+            },
+            0xc0 | 0xc1 | 0xd0 | 0xd1 | 0xd2 | 0xd3 => {
+                let (reg, rm) = self.modrm(wide)?;
+                let (mnemonic, name) = match reg {
+                    4 | 6 => (TokenValue::SHL, "shl"),
+                    5 => (TokenValue::SHR, "shr"),
+                    7 => (TokenValue::SAR, "sar"),
+                    _ => return Err(self.error(&format!("Unsupported opcode: {:#04x} /{}", opcode, reg))),
+                };
+
+                let count = match opcode {
+                    0xc0 | 0xc1 => Operand::Immediate(self.immediate(1, false)?),
+                    0xd0 | 0xd1 => Operand::Immediate(1),
+                    _ => Operand::Register(TokenValue::CL),
+                };
+
+                self.instruction(mnemonic, name, vec![rm, count])
+            },
+            0xc3 => self.instruction(TokenValue::RET, "ret", Vec::new()),
+            0xc6 | 0xc7 => {
+                let (reg, rm) = self.modrm(wide)?;
+
+                if reg != 0 {
+                    return Err(self.error(&format!("Unsupported opcode: {:#04x} /{}", opcode, reg)));
+                }
+
+                let value = self.immediate(wide, false)?;
+                self.instruction(TokenValue::MOV, "mov", vec![rm, Operand::Immediate(value)])
+            },
+            0xc8 => {
+                let frame_size = self.immediate(2, false)?;
+                let nesting_level = self.immediate(1, false)?;
+
+                let operands = match (frame_size, nesting_level) {
+                    (0, 0) => Vec::new(),
+                    _ => vec![Operand::Immediate(frame_size), Operand::Immediate(nesting_level)],
+                };
+
+                self.instruction(TokenValue::ENTER, "enter", operands)
+            },
+            0xc9 => self.instruction(TokenValue::LEAVE, "leave", Vec::new()),
+            0xcd => {
+                let value = self.immediate(1, false)?;
+                self.instruction(TokenValue::INT, "int", vec![Operand::Immediate(value)])
+            },
+            0xce => self.instruction(TokenValue::INTO, "into", Vec::new()),
+            0xcf => self.instruction(TokenValue::IRET, "iret", Vec::new()),
+            0xe3 => {
+                let displacement = self.immediate(1, true)?;
+                let target = self.target(displacement);
+                return Ok((self.instruction(TokenValue::JECXZ, "jecxz", vec![Operand::Label(label_name(target))]),
+                            Some(target)));
+            },
+            0xe4..=0xe7 | 0xec..=0xef => {
+                let accumulator = Operand::Register(register_from_number(0, wide));
+                let port = match opcode & 8 {
+                    0 => Operand::Immediate(self.immediate(1, false)?),
+                    _ => Operand::Register(TokenValue::DX),
+                };
+
+                match opcode & 2 {
+                    0 => self.instruction(TokenValue::IN, "in", vec![accumulator, port]),
+                    _ => self.instruction(TokenValue::OUT, "out", vec![port, accumulator]),
+                }
+            },
+            0xe8 => {
+                let displacement = self.immediate(4, true)?;
+                let target = self.target(displacement);
+                return Ok((self.instruction(TokenValue::CALL, "call", vec![Operand::Label(label_name(target))]),
+                            Some(target)));
+            },
+            0xe9 | 0xeb => {
+                let displacement = if opcode == 0xe9 { self.immediate(4, true)? } else { self.immediate(1, true)? };
+                let target = self.target(displacement);
+                return Ok((self.instruction(TokenValue::JMP, "jmp", vec![Operand::Label(label_name(target))]),
+                            Some(target)));
+            },
+            0xf4 => self.instruction(TokenValue::HLT, "hlt", Vec::new()),
+            0xf5 => self.instruction(TokenValue::CMC, "cmc", Vec::new()),
+            0xf6 | 0xf7 => {
+                let (reg, rm) = self.modrm(wide)?;
+
+                if reg == 0 {
+                    let value = self.immediate(wide, false)?;
+                    return Ok((self.instruction(TokenValue::TEST, "test", vec![rm, Operand::Immediate(value)]), None));
+                }
+
+                let (mnemonic, name) = match reg {
+                    2 => (TokenValue::NOT, "not"),
+                    3 => (TokenValue::NEG, "neg"),
+                    4 => (TokenValue::MUL, "mul"),
+                    5 => (TokenValue::IMUL, "imul"),
+                    6 => (TokenValue::DIV, "div"),
+                    7 => (TokenValue::IDIV, "idiv"),
+                    _ => return Err(self.error(&format!("Unsupported opcode: {:#04x} /{}", opcode, reg))),
+                };
+
+                self.instruction(mnemonic, name, vec![rm])
+            },
+            0xf8 => self.instruction(TokenValue::CLC, "clc", Vec::new()),
+            0xf9 => self.instruction(TokenValue::STC, "stc", Vec::new()),
+            0xfc => self.instruction(TokenValue::CLD, "cld", Vec::new()),
+            0xfd => self.instruction(TokenValue::STD, "std", Vec::new()),
+            0xfe | 0xff => {
+                let (reg, rm) = self.modrm(wide)?;
+                let (mnemonic, name) = match (opcode, reg) {
+                    (_, 0) => (TokenValue::INC, "inc"),
+                    (_, 1) => (TokenValue::DEC, "dec"),
+                    (0xff, 2) if wide == 4 => (TokenValue::CALL, "call"),
+                    (0xff, 6) => (TokenValue::PUSH, "push"),
+                    _ => return Err(self.error(&format!("Unsupported opcode: {:#04x} /{}", opcode, reg))),
+                };
+
+                self.instruction(mnemonic, name, vec![rm])
+            },
+            _ => return Err(self.error(&format!("Unsupported opcode: {:#04x}", opcode))),
+        };
+
+        Ok((instruction, None))
+    }
+
+    /// Decode instructions with `0x0f` escape opcode.
+    fn decode_two_byte(&mut self, size: usize) -> Result<(Instruction, Option<u32>), String> {
+        let opcode = self.next_byte()?;
+
+        let instruction = match opcode {
+            0x80..=0x8f => {
+                let displacement = self.immediate(4, true)?;
+                return self.jump(opcode & 0xf, displacement);
+            },
+            0x90..=0x9f => {
+                let (reg, rm) = self.modrm(1)?;
+
+                if reg != 0 {
+                    return Err(self.error(&format!("Unsupported opcode: 0x0f {:#04x} /{}", opcode, reg)));
+                }
+
+                self.set(opcode & 0xf, rm)?
+            },
+            0x1f => {
+                let (reg, rm) = self.modrm(size)?;
+
+                if reg != 0 {
+                    return Err(self.error(&format!("Unsupported opcode: 0x0f {:#04x} /{}", opcode, reg)));
+                }
+
+                self.instruction(TokenValue::NOP, "nop", vec![rm])
+            },
+            0xa2 => self.instruction(TokenValue::CPUID, "cpuid", Vec::new()),
+            0xaf => {
+                let (reg, rm) = self.modrm(size)?;
+                self.instruction(TokenValue::IMUL, "imul", vec![Operand::Register(register_from_number(reg, size)), rm])
+            },
+            0xbc | 0xbd => {
+                let (reg, rm) = self.modrm(size)?;
+                let (mnemonic, name) = if opcode == 0xbc { (TokenValue::BSF, "bsf") } else { (TokenValue::BSR, "bsr") };
+                self.instruction(mnemonic, name, vec![Operand::Register(register_from_number(reg, size)), rm])
+            },
+            0xb6 | 0xb7 | 0xbe | 0xbf => {
+                let source_size = if opcode & 1 == 0 { 1 } else { 2 };
+                let (reg, rm) = self.modrm(source_size)?;
+                let (mnemonic, name) = if opcode < 0xbe {
+                    (TokenValue::MOVZX, "movzx")
+                } else {
+                    (TokenValue::MOVSX, "movsx")
+                };
+
+                self.instruction(mnemonic, name, vec![Operand::Register(register_from_number(reg, size)), rm])
+            },
+            _ => return Err(self.error(&format!("Unsupported opcode: 0x0f {:#04x}", opcode))),
+        };
+
+        Ok((instruction, None))
+    }
+
+    /// Decode conditional jump of condition code `condition`.
+    fn jump(&mut self, condition: u8, displacement: i64) -> Result<(Instruction, Option<u32>), String> {
+        let (mnemonic, name) = match condition {
+            0x0 => (TokenValue::JO, "jo"),
+            0x1 => (TokenValue::JNO, "jno"),
+            0x2 => (TokenValue::JB, "jb"),
+            0x3 => (TokenValue::JAE, "jae"),
+            0x4 => (TokenValue::JE, "je"),
+            0x5 => (TokenValue::JNE, "jne"),
+            0x6 => (TokenValue::JBE, "jbe"),
+            0x7 => (TokenValue::JA, "ja"),
+            0x8 => (TokenValue::JS, "js"),
+            0x9 => (TokenValue::JNS, "jns"),
+            0xa => (TokenValue::JP, "jp"),
+            0xb => (TokenValue::JNP, "jnp"),
+            0xc => (TokenValue::JL, "jl"),
+            0xd => (TokenValue::JGE, "jge"),
+            0xe => (TokenValue::JLE, "jle"),
+            0xf => (TokenValue::JG, "jg"),
+            _ => return Err(self.error(&format!("Unsupported condition code: {:#x}", condition))),
+        };
+
+        let target = self.target(displacement);
+        Ok((self.instruction(mnemonic, name, vec![Operand::Label(label_name(target))]), Some(target)))
+    }
+
+    /// Decode `setcc` of condition code `condition`.
+    fn set(&mut self, condition: u8, operand: Operand) -> Result<Instruction, String> {
+        let (mnemonic, name) = match condition {
+            0x0 => (TokenValue::SETO, "seto"),
+            0x1 => (TokenValue::SETNO, "setno"),
+            0x2 => (TokenValue::SETB, "setb"),
+            0x3 => (TokenValue::SETAE, "setae"),
+            0x4 => (TokenValue::SETE, "sete"),
+            0x5 => (TokenValue::SETNE, "setne"),
+            0x6 => (TokenValue::SETBE, "setbe"),
+            0x7 => (TokenValue::SETA, "seta"),
+            0x8 => (TokenValue::SETS, "sets"),
+            0x9 => (TokenValue::SETNS, "setns"),
+            0xa => (TokenValue::SETP, "setp"),
+            0xb => (TokenValue::SETNP, "setnp"),
+            0xc => (TokenValue::SETL, "setl"),
+            0xd => (TokenValue::SETGE, "setge"),
+            0xe => (TokenValue::SETLE, "setle"),
+            0xf => (TokenValue::SETG, "setg"),
+            _ => return Err(self.error(&format!("Unsupported condition code: {:#x}", condition))),
+        };
+
+        Ok(self.instruction(mnemonic, name, vec![operand]))
+    }
+}
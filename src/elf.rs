@@ -0,0 +1,129 @@
+use crate::assembler::*;
+use crate::disassembler::*;
+
+/// Program loaded from an executable image, such as a statically linked 32-bits ELF file or an Intel HEX file
+pub struct Image {
+    /// address of the entry point
+    pub entry: u32,
+    /// loadable segments, each is a pair of its address and bytes
+    pub segments: Vec<(u32, Vec<u8>)>,
+    /// disassembled code, the entry point is labeled `_start`
+    pub program: Program,
+}
+
+/// Check whether the bytes start with the magic number of ELF.
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"\x7fELF")
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, String> {
+    match bytes.get(offset..offset + 2) {
+        Some(slice) => Ok(u16::from_le_bytes([slice[0], slice[1]])),
+        None => Err("ELF Error: file is truncated".to_string()),
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    match bytes.get(offset..offset + 4) {
+        Some(slice) => Ok(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]])),
+        None => Err("ELF Error: file is truncated".to_string()),
+    }
+}
+
+/// Load a statically linked 32-bits little endian x86 ELF executable.
+///
+/// Every `PT_LOAD` segment is loaded with its bytes in file, and the rest of its memory size is filled with zero.
+/// The executable sections are disassembled, or the executable segment from the entry point when there is no section
+/// header.
+pub fn load_elf(file_name: &str, bytes: &[u8]) -> Result<Image, String> {
+    if !is_elf(bytes) || bytes.len() < 52 {
+        return Err(format!("ELF Error: {} is not an ELF file", file_name));
+    }
+
+    if bytes[4] != 1 || bytes[5] != 1 || read_u16(bytes, 18)? != 3 {
+        return Err(format!("ELF Error: {} is not a 32-bits little endian x86 ELF file", file_name));
+    }
+
+    if read_u16(bytes, 16)? != 2 {
+        return Err(format!("ELF Error: {} is not a statically linked executable", file_name));
+    }
+
+    let entry = read_u32(bytes, 24)?;
+    let program_header_offset = read_u32(bytes, 28)? as usize;
+    let section_header_offset = read_u32(bytes, 32)? as usize;
+    let program_header_size = read_u16(bytes, 42)? as usize;
+    let program_header_count = read_u16(bytes, 44)? as usize;
+    let section_header_size = read_u16(bytes, 46)? as usize;
+    let section_header_count = read_u16(bytes, 48)? as usize;
+
+    let mut segments = Vec::new();
+    let mut executable_segments = Vec::new();
+
+    for i in 0..program_header_count {
+        let header = program_header_offset + i * program_header_size;
+
+        match read_u32(bytes, header)? {
+            // PT_LOAD
+            1 => {},
+            // PT_DYNAMIC, PT_INTERP
+            2 | 3 => return Err(format!("ELF Error: {} is dynamically linked", file_name)),
+            _ => continue,
+        }
+
+        let offset = read_u32(bytes, header + 4)? as usize;
+        let address = read_u32(bytes, header + 8)?;
+        let file_size = read_u32(bytes, header + 16)? as usize;
+        let memory_size = read_u32(bytes, header + 20)? as usize;
+        let flags = read_u32(bytes, header + 24)?;
+
+        let mut segment = match bytes.get(offset..offset + file_size) {
+            Some(slice) => slice.to_vec(),
+            None => return Err(format!("ELF Error: segment at {:#010x} is truncated", address)),
+        };
+        segment.resize(memory_size.max(file_size), 0);
+
+        // PF_X
+        if flags & 1 != 0 {
+            executable_segments.push((address, segment.to_owned()));
+        }
+
+        segments.push((address, segment));
+    }
+
+    let mut code: Vec<(u32, Vec<u8>)> = Vec::new();
+
+    for i in 0..section_header_count {
+        let header = section_header_offset + i * section_header_size;
+        let section_type = read_u32(bytes, header + 4)?;
+        let flags = read_u32(bytes, header + 8)?;
+
+        // SHT_PROGBITS with SHF_ALLOC and SHF_EXECINSTR
+        if section_type == 1 && flags & 0x6 == 0x6 {
+            let address = read_u32(bytes, header + 12)?;
+            let offset = read_u32(bytes, header + 16)? as usize;
+            let size = read_u32(bytes, header + 20)? as usize;
+
+            match bytes.get(offset..offset + size) {
+                Some(slice) => code.push((address, slice.to_vec())),
+                None => return Err(format!("ELF Error: section at {:#010x} is truncated", address)),
+            }
+        }
+    }
+
+    if code.is_empty() {
+        for (address, segment) in executable_segments {
+            if entry >= address && ((entry - address) as usize) < segment.len() {
+                code.push((entry, segment[(entry - address) as usize..].to_vec()));
+            }
+        }
+    }
+
+    let ranges: Vec<(u32, &[u8])> = code.iter().map(|(address, bytes)| (*address, bytes.as_slice())).collect();
+    let mut program = disassemble_ranges(file_name, &ranges, entry)?;
+
+    if !program.insert_label("_start", entry) {
+        return Err(format!("ELF Error: entry point {:#010x} is not in the code", entry));
+    }
+
+    Ok(Image { entry, segments, program })
+}
@@ -0,0 +1,201 @@
+//! C API of the virtual machine.
+//!
+//! Every function takes the handle returned by [`asmvm_new`], and returns a negative value on failure, whose reason is
+//! kept until the next call and can be read by [`asmvm_last_error`]. Errors of the virtual machine, such as a syntax
+//! error in the source code, are reported in the same way instead of aborting the host program.
+
+use crate::vm::*;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+/// Virtual machine owned by C code
+pub struct AsmVm {
+    vm: VM,
+    last_error: Option<CString>,
+}
+
+impl AsmVm {
+    /// Call `f` on the virtual machine, and keep the error message if it fails.
+    fn call<T>(&mut self, f: impl FnOnce(&mut VM) -> Result<T, String>) -> Result<T, ()> {
+        self.last_error = None;
+
+        let vm = &mut self.vm;
+        catch_error(|| f(vm)).and_then(|result| result).map_err(|msg| {
+            self.last_error = Some(CString::new(msg.replace('\0', "")).unwrap());
+        })
+    }
+}
+
+/// Convert a C string into a `String`.
+///
+/// # Safety
+///
+/// `string` must be null or point to a nul-terminated string.
+unsafe fn to_string(string: *const c_char, name: &str) -> Result<String, String> {
+    if string.is_null() {
+        return Err(format!("{} is null", name));
+    }
+
+    match CStr::from_ptr(string).to_str() {
+        Ok(string) => Ok(string.to_string()),
+        Err(err) => Err(format!("{} is not valid UTF-8, because {}", name, err)),
+    }
+}
+
+/// Create a virtual machine, which must be released by [`asmvm_free`].
+#[no_mangle]
+pub extern "C" fn asmvm_new() -> *mut AsmVm {
+    let vm = AsmVm {
+        vm: Default::default(),
+        last_error: None,
+    };
+
+    Box::into_raw(Box::new(vm))
+}
+
+/// Release a virtual machine created by [`asmvm_new`].
+///
+/// # Safety
+///
+/// `vm` must be null or a handle returned by [`asmvm_new`], which is not used anymore.
+#[no_mangle]
+pub unsafe extern "C" fn asmvm_free(vm: *mut AsmVm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+/// Load assembly source code, and stop before the first instruction of the entry label.
+///
+/// `name` is only used in error messages. Returns 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `vm` must be a handle returned by [`asmvm_new`], `name` and `source` must point to nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn asmvm_load_source(vm: *mut AsmVm, name: *const c_char, source: *const c_char) -> c_int {
+    let vm = match vm.as_mut() {
+        Some(vm) => vm,
+        None => return -1,
+    };
+
+    let result = vm.call(|vm| {
+        let name = to_string(name, "name")?;
+        let source = to_string(source, "source")?;
+        vm.load_source(name, source);
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => 0,
+        Err(()) => -1,
+    }
+}
+
+/// Execute one instruction.
+///
+/// Returns 1 if the program can continue, 0 if it is finished, and -1 on failure.
+///
+/// # Safety
+///
+/// `vm` must be a handle returned by [`asmvm_new`].
+#[no_mangle]
+pub unsafe extern "C" fn asmvm_step(vm: *mut AsmVm) -> c_int {
+    let vm = match vm.as_mut() {
+        Some(vm) => vm,
+        None => return -1,
+    };
+
+    match vm.call(|vm| Ok(vm.step())) {
+        Ok(true) => 1,
+        Ok(false) => 0,
+        Err(()) => -1,
+    }
+}
+
+/// Read a 32-bits general register by its name, such as `"eax"`, into `value`.
+///
+/// Returns 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `vm` must be a handle returned by [`asmvm_new`], `name` must point to a nul-terminated string and `value` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn asmvm_get_register(vm: *mut AsmVm, name: *const c_char, value: *mut u32) -> c_int {
+    let vm = match vm.as_mut() {
+        Some(vm) => vm,
+        None => return -1,
+    };
+
+    let result = vm.call(|vm| {
+        let name = to_string(name, "name")?;
+
+        if value.is_null() {
+            return Err("value is null".to_string());
+        }
+
+        match vm.get_register(&name) {
+            Some(register) => Ok(register),
+            None => Err(format!("Unknown register: {}", name)),
+        }
+    });
+
+    match result {
+        Ok(register) => {
+            *value = register;
+            0
+        },
+        Err(()) => -1,
+    }
+}
+
+/// Copy `size` bytes of memory at `address` into `buffer`.
+///
+/// Returns 0 on success and -1 on failure, such as an address out of the memory of virtual machine.
+///
+/// # Safety
+///
+/// `vm` must be a handle returned by [`asmvm_new`], and `buffer` must be valid for writes of `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn asmvm_read_mem(vm: *mut AsmVm, address: u32, buffer: *mut u8, size: usize) -> c_int {
+    let vm = match vm.as_mut() {
+        Some(vm) => vm,
+        None => return -1,
+    };
+
+    let result = vm.call(|vm| {
+        if buffer.is_null() {
+            return Err("buffer is null".to_string());
+        }
+
+        match vm.read_memory(address, size) {
+            Some(bytes) => {
+                ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, size);
+                Ok(())
+            },
+            None => Err(format!("Invalid memory address: {:#x}", address)),
+        }
+    });
+
+    match result {
+        Ok(()) => 0,
+        Err(()) => -1,
+    }
+}
+
+/// Get the error message of the last failed call, or null if it succeeded.
+///
+/// The message is owned by the virtual machine and valid until the next call with the same handle.
+///
+/// # Safety
+///
+/// `vm` must be null or a handle returned by [`asmvm_new`].
+#[no_mangle]
+pub unsafe extern "C" fn asmvm_last_error(vm: *const AsmVm) -> *const c_char {
+    match vm.as_ref().and_then(|vm| vm.last_error.as_ref()) {
+        Some(msg) => msg.as_ptr(),
+        None => ptr::null(),
+    }
+}
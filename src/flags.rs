@@ -0,0 +1,133 @@
+//! Arithmetic flags of `add`, `adc`, `sub`, `sbb`, `cmp`, `inc`, `dec`, `neg`, `mul` and `imul`, computed as the
+//! processor does for 1, 2 and 4 bytes operands.
+//!
+//! Operands are truncated to `size` bytes first, so a sign-extended value can be passed as is.
+
+/// Result of an arithmetic operation and the flags it sets
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Arithmetic {
+    /// result truncated to the operand size
+    pub result: u32,
+    /// `cf`, carry out of the top bit, or borrow into it
+    pub cf: bool,
+    /// `zf`, the truncated result is zero
+    pub zf: bool,
+    /// `sf`, top bit of the truncated result
+    pub sf: bool,
+    /// `of`, the signed result does not fit in the operand size
+    pub of: bool,
+    /// `af`, carry out of bit 3, or borrow into it, for BCD arithmetic
+    pub af: bool,
+}
+
+/// Get `pf`, whether the low byte of the result has an even number of set bits, whatever the operand size.
+pub fn parity(result: u32) -> bool {
+    (result as u8).count_ones() & 1 == 0
+}
+
+/// Get the mask of an operand of `size` bytes.
+pub fn mask(size: usize) -> u32 {
+    match size {
+        1 => 0xff,
+        2 => 0xffff,
+        4 => 0xffff_ffff,
+        _ => panic!("Invaild length: {}", size),
+    }
+}
+
+/// Get the top bit, which is the sign, of an operand of `size` bytes.
+pub fn sign_bit(size: usize) -> u32 {
+    mask(size) ^ (mask(size) >> 1)
+}
+
+/// Get the signed value of the low `size` bytes of `value`.
+pub fn sign_extend(value: u32, size: usize) -> i64 {
+    let shift = 64 - size * 8;
+    ((value as i64) << shift) >> shift
+}
+
+impl Arithmetic {
+    fn new(first: u32, second: u32, result: u32, cf: bool, of: bool, size: usize) -> Self {
+        // bit 4 of the result differs from the sum of bit 4 of the operands when a carry or a borrow crossed it
+        let af = (first ^ second ^ result) & 0x10 != 0;
+        Arithmetic { result, cf, zf: result == 0, sf: result & sign_bit(size) != 0, of, af }
+    }
+}
+
+/// `first + second`
+pub fn add(first: u32, second: u32, size: usize) -> Arithmetic {
+    let (first, second) = (first & mask(size), second & mask(size));
+    let result = first.wrapping_add(second) & mask(size);
+
+    // carry out when the truncated sum wraps around
+    let cf = result < first;
+    // both operands have the same sign, which differs from the sign of the result
+    let of = (first ^ result) & (second ^ result) & sign_bit(size) != 0;
+
+    Arithmetic::new(first, second, result, cf, of, size)
+}
+
+/// `first - second`, also for `cmp`
+pub fn sub(first: u32, second: u32, size: usize) -> Arithmetic {
+    let (first, second) = (first & mask(size), second & mask(size));
+    let result = first.wrapping_sub(second) & mask(size);
+
+    // borrow when the unsigned subtrahend is larger
+    let cf = first < second;
+    // the operands have different signs, and the sign of the result differs from the minuend
+    let of = (first ^ second) & (first ^ result) & sign_bit(size) != 0;
+
+    Arithmetic::new(first, second, result, cf, of, size)
+}
+
+/// `first + second + carry`, for `adc`
+pub fn adc(first: u32, second: u32, carry: bool, size: usize) -> Arithmetic {
+    let (first, second) = (first & mask(size), second & mask(size));
+    let sum = first as u64 + second as u64 + carry as u64;
+    let result = sum as u32 & mask(size);
+
+    let cf = sum > mask(size) as u64;
+    let of = (first ^ result) & (second ^ result) & sign_bit(size) != 0;
+
+    Arithmetic::new(first, second, result, cf, of, size)
+}
+
+/// `first - second - borrow`, for `sbb`
+pub fn sbb(first: u32, second: u32, borrow: bool, size: usize) -> Arithmetic {
+    let (first, second) = (first & mask(size), second & mask(size));
+    let result = first.wrapping_sub(second).wrapping_sub(borrow as u32) & mask(size);
+
+    let cf = (first as u64) < second as u64 + borrow as u64;
+    let of = (first ^ second) & (first ^ result) & sign_bit(size) != 0;
+
+    Arithmetic::new(first, second, result, cf, of, size)
+}
+
+/// `operand + 1`, whose carry is not kept, as `inc` leaves `cf` unchanged
+pub fn inc(operand: u32, size: usize) -> Arithmetic {
+    add(operand, 1, size)
+}
+
+/// `operand - 1`, whose borrow is not kept, as `dec` leaves `cf` unchanged
+pub fn dec(operand: u32, size: usize) -> Arithmetic {
+    sub(operand, 1, size)
+}
+
+/// `0 - operand`, `cf` is set unless the operand is zero
+pub fn neg(operand: u32, size: usize) -> Arithmetic {
+    sub(0, operand, size)
+}
+
+/// `first * second` of unsigned operands, the product is twice as wide, and `cf` and `of` are set if its upper half is
+/// not 0
+pub fn mul(first: u32, second: u32, size: usize) -> (u64, bool) {
+    let product = (first & mask(size)) as u64 * (second & mask(size)) as u64;
+    (product, product >> (size * 8) != 0)
+}
+
+/// `first * second` of signed operands, the product is twice as wide, and `cf` and `of` are set if it does not fit
+/// in `size` bytes, which is when the truncated product of `imul` with two or three operands is wrong
+pub fn imul(first: u32, second: u32, size: usize) -> (i64, bool) {
+    let product = sign_extend(first, size) * sign_extend(second, size);
+    (product, product != sign_extend(product as u32, size))
+}
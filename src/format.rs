@@ -0,0 +1,286 @@
+//! Canonical formatting of source code, which is reprinted from its token stream:
+//!
+//! * labels start at column 0, on a line of their own, and instructions are indented by 4 spaces, but the name of a
+//!   data definition written without `:`, such as `msg db 1, 2`, stays on the line of its data, as that of a
+//!   constant or a procedure does, such as `SIZE equ 16` and `main proc`,
+//! * the lines starting and ending the definition of a macro, such as `%macro print 2` and `%endmacro`, and includes,
+//!   such as `%include "io.inc"`, start at column 0 too,
+//! * instructions, registers and keywords are in lower case, labels and integer literals are kept as written,
+//! * operands are separated by `, `, and operators have a space on both sides, such as `[ebx + ecx * 4 - 8]`,
+//! * comments after code are aligned within each block of lines between blank lines,
+//! * runs of blank lines become one.
+//!
+//! The formatted source is scanned again and must give the same tokens, so formatting never changes what a program
+//! means.
+
+use crate::assembler::*;
+use crate::scanner::SourceOptions;
+use crate::token::*;
+use crate::vm::catch_error;
+
+/// indentation of instructions, and of comments and directives which are not at column 0
+const INDENT: &str = "    ";
+
+/// One line of formatted source, before comments are aligned
+enum Line {
+    Blank,
+    /// code, such as `mov eax, 1`, and the comment after it, if any
+    Code(String, Option<String>),
+    /// line of only a comment or a directive, such as `.data`, kept as written
+    Verbatim(String),
+}
+
+/// Get the token as written in canonical form.
+fn spelling(token: &Token) -> String {
+    match token.get_token_type() {
+        TokenType::INSTRUCTION | TokenType::REGISTER | TokenType::KEYWORD => token.get_token_name().to_lowercase(),
+        _ => token.get_token_name(),
+    }
+}
+
+/// Get the token as it is printed, integer literals and strings are copied from the source, as their names lose the
+/// prefix of the radix, such as `0x`, and the quotes.
+fn print(token: &Token, source: &str) -> String {
+    let text = source.get(token.get_token_location().get_span());
+
+    match (token.get_token_type(), text) {
+        (TokenType::IMMEDIATE_DATA | TokenType::STRING, Some(text)) => text.to_string(),
+        _ => spelling(token),
+    }
+}
+
+/// Format the operands of an instruction, or the rest of a line which is not an instruction.
+fn format_operands(tokens: &[Token], source: &str) -> String {
+    let mut text = String::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        let separator = match i.checked_sub(1) {
+            None => "",
+            _ if matches!(token.get_token_value(), TokenValue::COMMA | TokenValue::RBRACK | TokenValue::RPAREN) => "",
+            // the items of `dup` are written as `64 dup(0)`
+            Some(previous) if token.get_token_value() == TokenValue::LPAREN &&
+                tokens[previous].get_token_value() == TokenValue::DUP => "",
+            Some(previous) if matches!(tokens[previous].get_token_value(), TokenValue::LBRACK |
+                                       TokenValue::LPAREN) => "",
+            Some(previous) if is_minus_sign(tokens, previous) => "",
+            _ => " ",
+        };
+
+        text += separator;
+        text += &print(token, source);
+    }
+
+    text
+}
+
+/// Whether the `i`th token is a minus sign rather than a subtraction, because it comes first or after an operator, a
+/// comma, `[` or `(`.
+fn is_minus_sign(tokens: &[Token], i: usize) -> bool {
+    if tokens[i].get_token_value() != TokenValue::MINUS {
+        return false;
+    }
+
+    match i.checked_sub(1) {
+        None => true,
+        Some(previous) => matches!(tokens[previous].get_token_value(), TokenValue::PLUS | TokenValue::MINUS |
+                                   TokenValue::TIMES | TokenValue::SLASH | TokenValue::PERCENT |
+                                   TokenValue::LSHIFT | TokenValue::RSHIFT | TokenValue::COMMA |
+                                   TokenValue::LBRACK | TokenValue::LPAREN),
+    }
+}
+
+/// Split the tokens of one line into statements: label definitions, and instructions with their operands.
+fn format_statements(tokens: &[Token], source: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let token = &tokens[i];
+
+        if token.get_token_type() == TokenType::LABEL &&
+            tokens.get(i + 1).map(|token| token.get_token_value()) == Some(TokenValue::COLON) {
+            statements.push(format!("{}:", token.get_token_name()));
+            i += 2;
+            continue;
+        }
+
+        let preprocessor_line = matches!(token.get_token_value(), TokenValue::MACRO | TokenValue::ENDMACRO |
+                                  TokenValue::INCLUDE) ||
+            tokens.get(i + 1).map(|token| token.get_token_value()) == Some(TokenValue::MACRO);
+
+        if preprocessor_line {
+            statements.push(format_operands(&tokens[i..], source));
+            break;
+        }
+
+        // the name of a data definition without `:` or of a constant is kept with the value, as it needs to be on the
+        // same line
+        let named = token.get_token_type() == TokenType::LABEL && tokens.get(i + 1).is_some_and(|next| {
+            data_size(next.get_token_value()).is_some() || matches!(next.get_token_value(), TokenValue::REPEAT |
+                                                                     TokenValue::EQU | TokenValue::PROC |
+                                                                     TokenValue::ENDP)
+        });
+        let mut end = if named { i + 2 } else { i + 1 };
+
+        // `times` and its count are a prefix of the instruction after them, and `section` of the name of the section
+        if matches!(tokens[end - 1].get_token_value(), TokenValue::REPEAT | TokenValue::SECTION) {
+            while end < tokens.len() && tokens[end].get_token_type() != TokenType::INSTRUCTION {
+                end += 1;
+            }
+
+            end = tokens.len().min(end + 1);
+        }
+
+        // the statement goes on until the next instruction or label definition
+
+        while end < tokens.len() && tokens[end].get_token_type() != TokenType::INSTRUCTION &&
+            !(tokens[end].get_token_type() == TokenType::LABEL &&
+              tokens.get(end + 1).map(|token| token.get_token_value()) == Some(TokenValue::COLON)) {
+            end += 1;
+        }
+
+        let statement = if named {
+            format!("{} {}", token.get_token_name(), format_operands(&tokens[i + 1..end], source))
+        } else if token.get_token_type() == TokenType::INSTRUCTION {
+            let operands = format_operands(&tokens[i + 1..end], source);
+
+            if operands.is_empty() {
+                format!("{}{}", INDENT, spelling(token))
+            } else {
+                format!("{}{} {}", INDENT, spelling(token), operands)
+            }
+        } else {
+            format!("{}{}", INDENT, format_operands(&tokens[i..end], source))
+        };
+
+        statements.push(statement);
+        i = end;
+    }
+
+    statements
+}
+
+/// Get the comment at the end of a line, which starts from the first `;` of `rest`, the rest of the line after its
+/// last token.
+fn trailing_comment(rest: &str) -> Option<String> {
+    let rest = rest.split('\n').next().unwrap_or("");
+    let start = rest.find(';')?;
+
+    Some(rest[start..].trim_end().to_string())
+}
+
+/// Keep a line which has no token, such as a comment or a directive, at column 0 or indented.
+fn verbatim(line: &str) -> String {
+    if line.starts_with(|c: char| c.is_ascii_whitespace()) {
+        format!("{}{}", INDENT, line.trim())
+    } else {
+        line.trim_end().to_string()
+    }
+}
+
+/// Compare two token streams, ignoring locations and the case of instructions, registers and keywords.
+fn same_tokens(first: &[Token], second: &[Token]) -> bool {
+    first.len() == second.len() && first.iter().zip(second).all(|(first, second)| {
+        first.get_token_type() == second.get_token_type() && first.get_token_value() == second.get_token_value() &&
+            spelling(first) == spelling(second)
+    })
+}
+
+/// Format source code read with `options`, `source_name` is only used in diagnostics.
+///
+/// # Example
+///
+/// ```no_run
+/// # use asm_vm::format::format_source;
+/// # use asm_vm::scanner::SourceOptions;
+/// let text = format_source("inline", "MAIN:  MOV EAX,[ebx+4] ;load\n RET\n", &SourceOptions::default()).unwrap();
+/// assert_eq!(text, "MAIN:\n    mov eax, [ebx + 4]  ;load\n    ret\n");
+/// ```
+pub fn format_source(source_name: &str, source: &str, options: &SourceOptions) -> Result<String, String> {
+    let tokens = catch_error(|| scan_source(source_name.to_string(), source.to_string(), options))?;
+    let source_lines: Vec<&str> = source.lines().collect();
+    let mut lines = Vec::new();
+    let mut next = 0;
+
+    for (number, line) in source_lines.iter().enumerate() {
+        let start = next;
+
+        while next < tokens.len() && tokens[next].get_token_location().get_line() as usize == number + 1 {
+            next += 1;
+        }
+
+        let line_tokens = &tokens[start..next];
+
+        let last = match line_tokens.last() {
+            Some(last) => last,
+            None if line.trim().is_empty() => {
+                lines.push(Line::Blank);
+                continue;
+            },
+            None => {
+                lines.push(Line::Verbatim(verbatim(line)));
+                continue;
+            },
+        };
+
+        let comment = trailing_comment(source.get(last.get_token_location().get_end()..).unwrap_or(""));
+
+        let statements = format_statements(line_tokens, source);
+        let count = statements.len();
+
+        for (i, statement) in statements.into_iter().enumerate() {
+            let comment = if i + 1 == count { comment.to_owned() } else { None };
+            lines.push(Line::Code(statement, comment));
+        }
+    }
+
+    if next != tokens.len() {
+        return Err(format!("Format Error: {}: tokens do not match the lines of the source", source_name));
+    }
+
+    let text = align(&lines);
+
+    let formatted = catch_error(|| scan_source(source_name.to_string(), text.to_owned(), options))?;
+
+    if !same_tokens(&tokens, &formatted) {
+        return Err(format!("Format Error: {}: formatting would change the program", source_name));
+    }
+
+    Ok(text)
+}
+
+/// Print the lines, with the comments after code aligned within each block between blank lines.
+fn align(lines: &[Line]) -> String {
+    let mut text = String::new();
+    let mut blank = true;
+
+    for block in lines.split(|line| matches!(line, Line::Blank)) {
+        if block.is_empty() {
+            continue;
+        }
+
+        if !blank {
+            text += "\n";
+        }
+
+        let column = block.iter()
+            .filter_map(|line| match line {
+                Line::Code(code, Some(_)) => Some(code.len()),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0) + 2;
+
+        for line in block {
+            match line {
+                Line::Code(code, Some(comment)) => text += &format!("{:width$}{}\n", code, comment, width = column),
+                Line::Code(code, None) | Line::Verbatim(code) => text += &format!("{}\n", code),
+                Line::Blank => {},
+            }
+        }
+
+        blank = false;
+    }
+
+    text
+}
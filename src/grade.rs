@@ -0,0 +1,291 @@
+//! Grading of a program against the cases of a TOML specification, such as:
+//!
+//! ```toml
+//! program = "sum.asm"
+//! max_steps = 100000
+//!
+//! [policy]
+//! deny = ["mul", "imul"]
+//!
+//! [[case]]
+//! name = "ten"
+//! registers = { ecx = 10 }
+//! argv = ["--verbose"]
+//! stdin = "1 2 3\n"
+//!
+//! [case.expect]
+//! registers = { eax = 55 }
+//! memory = [{ address = 0x1ffff0, bytes = [1, 0, 0, 0] }]
+//! stdout = "55\n"
+//! exit_code = 0
+//! max_stack = 64
+//! ```
+
+use crate::batch::Program;
+use crate::config::*;
+use crate::harness::{self, Case, Inputs, DEFAULT_MAX_STEPS};
+use crate::json::*;
+use crate::policy::Policy;
+use crate::toml;
+use std::fs;
+use std::path::Path;
+
+pub use crate::harness::{CaseResult, Check};
+
+/// Get an integer of at most 32 bits, negative ones are in two's complement.
+fn as_word(value: &Json) -> Option<u32> {
+    match value.as_f64() {
+        Some(number) if number.fract() == 0.0 && number >= i32::MIN as f64 && number <= u32::MAX as f64 => {
+            Some(number as i64 as u32)
+        },
+        _ => None,
+    }
+}
+
+fn as_byte(value: &Json) -> Option<u8> {
+    match value.as_u64() {
+        Some(byte) if byte <= 0xff => Some(byte as u8),
+        _ => None,
+    }
+}
+
+/// Iterate over the `(name, value)` members of an optional table.
+fn members<'a>(table: Option<&'a Json>, what: &str) -> Result<&'a [(String, Json)], String> {
+    match table {
+        None => Ok(&[]),
+        Some(Json::Object(members)) => Ok(members),
+        Some(_) => Err(format!("Spec Error: {} must be a table", what)),
+    }
+}
+
+/// Run every case of the specification in `spec_file_name`.
+///
+/// The paths of `program` are relative to the directory of the specification. Each case runs on a fresh virtual
+/// machine with the settings of `config`, whose standard input is `stdin`, empty by default, and whose standard
+/// output is captured. The `policy` of the specification replaces the one of `config`, and a program which breaks it
+/// fails every case with the error of the policy.
+pub fn grade(spec_file_name: &str, config: &Config) -> Result<Vec<CaseResult>, String> {
+    let text = fs::read_to_string(spec_file_name)
+        .map_err(|err| format!("Can not read {}, because {}.", spec_file_name, err))?;
+    let spec = toml::parse(spec_file_name, &text)?;
+
+    let directory = Path::new(spec_file_name).parent().unwrap_or_else(|| Path::new(""));
+
+    let program = match spec.get("program") {
+        Some(Json::String(file_name)) => vec![file_name.to_owned()],
+        Some(Json::Array(file_names)) if !file_names.is_empty() => file_names.iter()
+            .map(|file_name| file_name.as_str().map(|file_name| file_name.to_owned()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| "Spec Error: program must be a list of file names".to_string())?,
+        _ => return Err("Spec Error: program must be a file name or a list of file names".to_string()),
+    };
+
+    let source_file_names: Vec<String> = program.iter()
+        .map(|file_name| directory.join(file_name).to_string_lossy().into_owned())
+        .collect();
+
+    let max_steps = match spec.get("max_steps") {
+        Some(value) => value.as_u64().ok_or_else(|| "Spec Error: max_steps must be a positive integer".to_string())?,
+        None => DEFAULT_MAX_STEPS,
+    };
+
+    let mut config = config.clone();
+
+    if let Some(policy) = spec.get("policy") {
+        config.policy = Policy::parse(policy, &config.aliases).map_err(|msg| format!("Spec Error: {}", msg))?;
+    }
+
+    let cases = match spec.get("case") {
+        Some(Json::Array(cases)) if !cases.is_empty() => cases,
+        _ => return Err("Spec Error: no [[case]] to grade".to_string()),
+    };
+
+    let cases = cases.iter().enumerate()
+        .map(|(i, case)| parse_case(case, i, &source_file_names[0], max_steps))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    harness::run_cases_with_config(&Program::Files(source_file_names), &cases, &config)
+        .map_err(|msg| format!("Spec Error: {}", msg))
+}
+
+fn parse_case(case: &Json, index: usize, program: &str, max_steps: u64) -> Result<Case, String> {
+    let name = match case.get("name") {
+        Some(name) => name.as_str().ok_or_else(|| "Spec Error: name must be a string".to_string())?.to_owned(),
+        None => format!("case {}", index + 1),
+    };
+
+    let max_steps = match case.get("max_steps") {
+        Some(value) => value.as_u64()
+            .ok_or_else(|| format!("Spec Error: {}: max_steps must be a positive integer", name))?,
+        None => max_steps,
+    };
+
+    let mut registers = Vec::new();
+
+    for (register, value) in members(case.get("registers"), &format!("{}: registers", name))? {
+        let value = as_word(value).ok_or_else(|| format!("Spec Error: {}: {} must be a 32-bits integer", name,
+                                                         register))?;
+        registers.push((register.to_owned(), value));
+    }
+
+    // argv[0] is the program, as in a shell
+    let mut arguments = vec![program.to_owned()];
+
+    if let Some(argv) = case.get("argv") {
+        for argument in argv.as_array().ok_or_else(|| format!("Spec Error: {}: argv must be an array", name))? {
+            let argument = argument.as_str()
+                .ok_or_else(|| format!("Spec Error: {}: argv must be an array of strings", name))?;
+            arguments.push(argument.to_owned());
+        }
+    }
+
+    let stdin = match case.get("stdin") {
+        Some(stdin) => stdin.as_str().ok_or_else(|| format!("Spec Error: {}: stdin must be a string", name))?,
+        None => "",
+    };
+
+    let expect = case.get("expect");
+
+    let mut expected_registers = Vec::new();
+
+    for (register, value) in members(expect.and_then(|expect| expect.get("registers")),
+                                     &format!("{}: expect.registers", name))? {
+        let expected = as_word(value).ok_or_else(|| format!("Spec Error: {}: {} must be a 32-bits integer", name,
+                                                            register))?;
+        expected_registers.push((register.to_owned(), expected));
+    }
+
+    let mut expected_memory = Vec::new();
+
+    if let Some(memory) = expect.and_then(|expect| expect.get("memory")) {
+        let memory = memory.as_array()
+            .ok_or_else(|| format!("Spec Error: {}: expect.memory must be an array of tables", name))?;
+
+        for region in memory {
+            let address = region.get("address").and_then(as_word)
+                .ok_or_else(|| format!("Spec Error: {}: expect.memory needs an address", name))?;
+
+            let bytes = match (region.get("bytes"), region.get("string")) {
+                (Some(Json::Array(bytes)), None) => bytes.iter().map(as_byte).collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| format!("Spec Error: {}: bytes must be integers from 0 to 255", name))?,
+                (None, Some(Json::String(string))) => string.as_bytes().to_vec(),
+                _ => return Err(format!("Spec Error: {}: expect.memory needs either bytes or a string", name)),
+            };
+
+            expected_memory.push((address, bytes));
+        }
+    }
+
+    let expected_output = match expect.and_then(|expect| expect.get("stdout")) {
+        Some(stdout) => Some(stdout.as_str()
+            .ok_or_else(|| format!("Spec Error: {}: expect.stdout must be a string", name))?.to_owned()),
+        None => None,
+    };
+
+    let expected_exit_code = match expect.and_then(|expect| expect.get("exit_code")) {
+        Some(exit_code) => Some(as_word(exit_code)
+            .ok_or_else(|| format!("Spec Error: {}: expect.exit_code must be an integer", name))?),
+        None => None,
+    };
+
+    let limit = |limit: &str| match expect.and_then(|expect| expect.get(limit)) {
+        Some(value) => value.as_u64().filter(|value| *value <= u32::MAX as u64).map(|value| Some(value as u32))
+            .ok_or_else(|| format!("Spec Error: {}: expect.{} must be a positive integer", name, limit)),
+        None => Ok(None),
+    };
+    let max_stack = limit("max_stack")?;
+    let max_call_depth = limit("max_call_depth")?;
+
+    Ok(Case {
+        name,
+        inputs: Inputs { registers, arguments, stdin: stdin.as_bytes().to_vec() },
+        expected_registers,
+        expected_memory,
+        expected_output,
+        expected_exit_code,
+        max_stack,
+        max_call_depth,
+        max_steps: Some(max_steps),
+    })
+}
+
+/// Print the pass/fail matrix, a row for each case and a column for each expectation, then the failures.
+pub fn to_matrix(results: &[CaseResult]) -> String {
+    let mut columns: Vec<&str> = Vec::new();
+
+    for check in results.iter().flat_map(|result| &result.checks) {
+        if !columns.contains(&check.name.as_str()) {
+            columns.push(&check.name);
+        }
+    }
+
+    let width = results.iter().map(|result| result.name.len()).chain(Some(4)).max().unwrap();
+    let mut text = format!("{:width$}", "case", width = width);
+
+    for column in &columns {
+        text += &format!("  {:4}", column);
+    }
+
+    text += "  result\n";
+
+    for result in results {
+        text += &format!("{:width$}", result.name, width = width);
+
+        for column in &columns {
+            let cell = match result.checks.iter().find(|check| check.name == *column) {
+                None => "-",
+                Some(_) if result.error.is_some() => "err",
+                Some(check) if check.passed() => "ok",
+                Some(_) => "FAIL",
+            };
+
+            text += &format!("  {:width$}", cell, width = column.len().max(4));
+        }
+
+        text += if result.passed() { "  pass\n" } else { "  FAIL\n" };
+    }
+
+    for result in results.iter().filter(|result| !result.passed()) {
+        text += &format!("\n{}:\n", result.name);
+
+        if let Some(error) = &result.error {
+            text += &format!("    {}\n", error);
+        }
+
+        for check in result.checks.iter().filter(|check| !check.passed()) {
+            let at_most = if check.at_most { "at most " } else { "" };
+            text += &format!("    {}: expected {}{}, got {}\n", check.name, at_most, check.expected, check.actual);
+        }
+    }
+
+    let passed = results.iter().filter(|result| result.passed()).count();
+    text += &format!("\n{} of {} cases passed\n", passed, results.len());
+
+    text
+}
+
+/// Machine-readable results, with every expectation of every case.
+pub fn to_json(results: &[CaseResult]) -> Json {
+    let cases = results.iter().map(|result| Json::object(vec![
+        ("name", result.name.as_str().into()),
+        ("passed", result.passed().into()),
+        ("steps", result.steps.into()),
+        ("max_stack", result.max_stack.into()),
+        ("max_call_depth", result.max_call_depth.into()),
+        ("error", result.error.clone().into()),
+        ("checks", Json::Array(result.checks.iter().map(|check| Json::object(vec![
+            ("name", check.name.as_str().into()),
+            ("passed", check.passed().into()),
+            ("expected", check.expected.clone()),
+            ("actual", check.actual.clone()),
+        ])).collect())),
+    ])).collect();
+
+    let passed = results.iter().filter(|result| result.passed()).count();
+
+    Json::object(vec![
+        ("passed", passed.into()),
+        ("failed", (results.len() - passed).into()),
+        ("cases", Json::Array(cases)),
+    ])
+}
@@ -0,0 +1,197 @@
+//! Test cases of a program, for autograders which set up the inputs of each case and check its results without
+//! driving the virtual machine themselves:
+//!
+//! ```no_run
+//! # use asm_vm::batch::Program;
+//! # use asm_vm::harness::*;
+//! let program = Program::Files(vec!["sum.asm".to_string()]);
+//! let cases = [Case {
+//!     name: "ten".to_string(),
+//!     inputs: Inputs { registers: vec![("ecx".to_string(), 10)], ..Default::default() },
+//!     expected_registers: vec![("eax".to_string(), 55)],
+//!     ..Default::default()
+//! }];
+//!
+//! for result in run_cases(&program, &cases).unwrap() {
+//!     println!("{} {}", result.name, if result.passed() { "passed" } else { "failed" });
+//! }
+//! ```
+
+use crate::batch::Program;
+use crate::config::*;
+use crate::json::*;
+use crate::vm::*;
+
+/// steps of a case, unless `max_steps` is given
+pub const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+/// Inputs of a case, set after the program is loaded
+#[derive(Clone, Default)]
+pub struct Inputs {
+    /// registers set before the first instruction
+    pub registers: Vec<(String, u32)>,
+    /// command line arguments including `argv[0]`, none are pushed on the stack if empty
+    pub arguments: Vec<String>,
+    /// standard input
+    pub stdin: Vec<u8>,
+}
+
+/// One case, every expectation which is `None` or empty is not checked
+#[derive(Clone, Default)]
+pub struct Case {
+    pub name: String,
+    pub inputs: Inputs,
+    pub expected_registers: Vec<(String, u32)>,
+    /// bytes from each address
+    pub expected_memory: Vec<(u32, Vec<u8>)>,
+    /// everything written to the standard output
+    pub expected_output: Option<String>,
+    /// status passed to the `exit` system call
+    pub expected_exit_code: Option<u32>,
+    /// most bytes on the stack
+    pub max_stack: Option<u32>,
+    /// most nested calls
+    pub max_call_depth: Option<u32>,
+    /// instructions after which the case fails, [`DEFAULT_MAX_STEPS`] if `None`
+    pub max_steps: Option<u64>,
+}
+
+/// One expectation of a case, such as the value of a register
+pub struct Check {
+    /// column of the matrix, such as `eax`, `mem[0x1000]`, `stdout` or `exit_code`
+    pub name: String,
+    pub expected: Json,
+    pub actual: Json,
+    /// the actual number may be less than the expected one, as for the limits of the stack
+    pub at_most: bool,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        match (self.at_most, self.expected.as_f64(), self.actual.as_f64()) {
+            (true, Some(expected), Some(actual)) => actual <= expected,
+            _ => self.expected == self.actual,
+        }
+    }
+}
+
+/// Outcome of one case
+pub struct CaseResult {
+    pub name: String,
+    /// one check for each expectation, in the order of the fields of [`Case`]
+    pub checks: Vec<Check>,
+    /// the error which stopped the program, if any, every check fails then
+    pub error: Option<String>,
+    pub steps: u64,
+    /// most bytes on the stack, see [`VM::get_max_stack_usage`]
+    pub max_stack: u32,
+    /// most nested calls, see [`VM::get_max_call_depth`]
+    pub max_call_depth: u32,
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.checks.iter().all(|check| check.passed())
+    }
+}
+
+/// Run every case on a fresh virtual machine with the default settings, returns the results in the order of the cases.
+///
+/// An error of the program, such as a syntax error, fails the case instead, the error is only for a case which can
+/// not be checked, such as one which expects an unknown register.
+pub fn run_cases(program: &Program, cases: &[Case]) -> Result<Vec<CaseResult>, String> {
+    run_cases_with_config(program, cases, &Default::default())
+}
+
+/// Run every case as [`run_cases`], on virtual machines with the settings of `config`.
+pub fn run_cases_with_config(program: &Program, cases: &[Case], config: &Config) -> Result<Vec<CaseResult>, String> {
+    cases.iter().map(|case| run_case(program, case, config)).collect()
+}
+
+/// Run one case on a fresh virtual machine with the settings of `config`, whose standard output is captured.
+pub fn run_case(program: &Program, case: &Case, config: &Config) -> Result<CaseResult, String> {
+    let max_steps = case.max_steps.unwrap_or(DEFAULT_MAX_STEPS);
+
+    let mut vm: VM = Default::default();
+    vm.set_verbosity(Verbosity::QUIET);
+    config.apply(&mut vm)?;
+    let mut steps = 0;
+
+    let run = catch_error(|| -> Result<(), String> {
+        program.load(&mut vm);
+
+        for (register, value) in &case.inputs.registers {
+            vm.set_register(register, *value)?;
+        }
+
+        if !case.inputs.arguments.is_empty() {
+            vm.set_arguments(&case.inputs.arguments);
+        }
+
+        vm.set_input(case.inputs.stdin.to_owned());
+        vm.capture_output();
+
+        while !vm.is_finished() {
+            if steps == max_steps {
+                return Err(format!("Runtime Error: not finished after {} steps", max_steps));
+            }
+
+            vm.step();
+            steps += 1;
+        }
+
+        match (vm.get_quota_exceeded(), vm.get_exception()) {
+            (Some(exceeded), _) => Err(exceeded.to_string()),
+            (None, Some(raised)) => Err(raised.to_string()),
+            (None, None) => Ok(()),
+        }
+    });
+
+    let error = match run {
+        Ok(Ok(())) => None,
+        Ok(Err(msg)) | Err(msg) => Some(msg),
+    };
+
+    let mut checks = Vec::new();
+
+    for (register, expected) in &case.expected_registers {
+        let actual = vm.get_register(register)
+            .ok_or_else(|| format!("{}: unknown register {}", case.name, register))?;
+        checks.push(Check { name: register.to_owned(), expected: (*expected).into(), actual: actual.into(),
+                            at_most: false });
+    }
+
+    for (address, bytes) in &case.expected_memory {
+        let actual = match vm.read_memory(*address, bytes.len()) {
+            Some(actual) => Json::Array(actual.iter().map(|byte| (*byte as u32).into()).collect()),
+            None => Json::Null,
+        };
+
+        let expected = Json::Array(bytes.iter().map(|byte| (*byte as u32).into()).collect());
+        checks.push(Check { name: format!("mem[{:#x}]", address), expected, actual, at_most: false });
+    }
+
+    if let Some(expected) = &case.expected_output {
+        let actual = String::from_utf8_lossy(vm.get_output().unwrap_or(&[])).into_owned();
+        checks.push(Check { name: "stdout".to_string(), expected: expected.as_str().into(), actual: actual.into(),
+                            at_most: false });
+    }
+
+    if let Some(expected) = case.expected_exit_code {
+        let actual = vm.get_exit_code().into();
+        checks.push(Check { name: "exit_code".to_string(), expected: expected.into(), actual, at_most: false });
+    }
+
+    let max_stack = vm.get_max_stack_usage();
+    let max_call_depth = vm.get_max_call_depth();
+
+    for (limit, expected, actual) in [("max_stack", case.max_stack, max_stack),
+                                      ("max_call_depth", case.max_call_depth, max_call_depth)] {
+        if let Some(expected) = expected {
+            checks.push(Check { name: limit.to_string(), expected: expected.into(), actual: actual.into(),
+                                at_most: true });
+        }
+    }
+
+    Ok(CaseResult { name: case.name.to_owned(), checks, error, steps, max_stack, max_call_depth })
+}
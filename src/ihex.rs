@@ -0,0 +1,179 @@
+//! Intel HEX images, the text format of EEPROM programmers and firmware tools.
+
+use crate::disassembler::*;
+use crate::elf::Image;
+
+/// bytes of data in one record written
+const RECORD_SIZE: usize = 16;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Pair of the address and the bytes of a contiguous region of memory
+pub type Segment = (u32, Vec<u8>);
+
+/// Check whether the bytes look like an Intel HEX file, whose lines start with `:`.
+pub fn is_ihex(bytes: &[u8]) -> bool {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(_) => return false,
+    };
+
+    text.trim_start().starts_with(':') && text.lines().all(|line| {
+        let line = line.trim();
+        line.is_empty() || (line.starts_with(':') && line[1..].chars().all(|c| c.is_ascii_hexdigit()))
+    })
+}
+
+fn record(record_type: u8, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+
+    let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+    bytes.push(checksum);
+
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}\n", hex.concat())
+}
+
+/// Write segments as an Intel HEX image with 32-bits linear addresses.
+///
+/// `entry` is written as the start linear address record.
+pub fn write_ihex(segments: &[Segment], entry: Option<u32>) -> String {
+    let mut text = String::new();
+    let mut upper = 0;
+
+    for (address, bytes) in segments {
+        let mut offset = 0;
+
+        while offset < bytes.len() {
+            let current = address.wrapping_add(offset as u32);
+
+            if current >> 16 != upper {
+                upper = current >> 16;
+                text += &record(EXTENDED_LINEAR_ADDRESS, 0, &(upper as u16).to_be_bytes());
+            }
+
+            // a record does not cross a 64 KiB boundary
+            let size = RECORD_SIZE.min(bytes.len() - offset).min(0x10000 - (current & 0xffff) as usize);
+            text += &record(DATA, current as u16, &bytes[offset..offset + size]);
+            offset += size;
+        }
+    }
+
+    if let Some(entry) = entry {
+        text += &record(START_LINEAR_ADDRESS, 0, &entry.to_be_bytes());
+    }
+
+    text + &record(END_OF_FILE, 0, &[])
+}
+
+/// Read an Intel HEX image, returns the start address, if any, and the segments.
+///
+/// Contiguous data records are joined into one segment.
+pub fn read_ihex(file_name: &str, text: &str) -> Result<(Option<u32>, Vec<Segment>), String> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut entry = None;
+    let mut base = 0u32;
+    let mut finished = false;
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let error = |msg: &str| format!("HEX Error: {}:{}: {}", file_name, i + 1, msg);
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if finished {
+            return Err(error("Record after the end of file record"));
+        }
+
+        let digits = match line.strip_prefix(':') {
+            Some(digits) if digits.len() % 2 == 0 && digits.len() >= 10 => digits,
+            _ => return Err(error("Invalid record")),
+        };
+
+        let bytes = (0..digits.len()).step_by(2)
+            .map(|position| u8::from_str_radix(&digits[position..position + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| error("Invalid hexadecimal digit"))?;
+
+        let size = bytes[0] as usize;
+
+        if bytes.len() != size + 5 {
+            return Err(error("Record length does not match its byte count"));
+        }
+
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("Checksum mismatch"));
+        }
+
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..4 + size];
+
+        match (bytes[3], size) {
+            (DATA, _) => {
+                let address = base.wrapping_add(offset);
+
+                match segments.last_mut() {
+                    Some((start, segment)) if start.wrapping_add(segment.len() as u32) == address => {
+                        segment.extend_from_slice(data);
+                    },
+                    _ => segments.push((address, data.to_vec())),
+                }
+            },
+            (END_OF_FILE, 0) => finished = true,
+            (EXTENDED_SEGMENT_ADDRESS, 2) => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            (EXTENDED_LINEAR_ADDRESS, 2) => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            (START_SEGMENT_ADDRESS, 4) => {
+                let segment = u16::from_be_bytes([data[0], data[1]]) as u32;
+                let offset = u16::from_be_bytes([data[2], data[3]]) as u32;
+                entry = Some((segment << 4) + offset);
+            },
+            (START_LINEAR_ADDRESS, 4) => entry = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]])),
+            (record_type, _) => return Err(error(&format!("Invalid record of type {:02X}", record_type))),
+        }
+    }
+
+    if !finished {
+        return Err(format!("HEX Error: {}: missing the end of file record", file_name));
+    }
+
+    segments.sort_by_key(|(address, _)| *address);
+
+    Ok((entry, segments))
+}
+
+/// Load an Intel HEX image as a program.
+///
+/// The code is the segment holding the start address, from the start address to its end, or the lowest segment when
+/// there is no start address.
+pub fn load_ihex(file_name: &str, text: &str) -> Result<Image, String> {
+    let (entry, segments) = read_ihex(file_name, text)?;
+
+    let entry = match (entry, segments.first()) {
+        (Some(entry), _) => entry,
+        (None, Some((address, _))) => *address,
+        (None, None) => return Err(format!("HEX Error: {} has no data", file_name)),
+    };
+
+    let code = segments.iter()
+        .find(|(address, bytes)| entry >= *address && ((entry - address) as usize) < bytes.len())
+        .map(|(address, bytes)| &bytes[(entry - address) as usize..])
+        .ok_or_else(|| format!("HEX Error: start address {:#010x} is not in the image", entry))?;
+
+    let mut program = disassemble(file_name, code, entry)?;
+
+    if !program.insert_label("_start", entry) {
+        return Err(format!("HEX Error: start address {:#010x} is not in the code", entry));
+    }
+
+    Ok(Image { entry, segments, program })
+}
@@ -0,0 +1,1795 @@
+use crate::assembler::{instruction_size, org_address};
+use crate::cfg::is_jump;
+use crate::link::is_declaration;
+use crate::macros::expand_macros;
+use crate::scanner::{expand_includes, SourceOptions};
+use crate::token::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+/// most copies `times` makes of a statement, so that a mistyped count does not exhaust the memory of the host
+pub const MAX_TIMES: i64 = 65536;
+
+/// most times the labels are laid out to evaluate the differences of labels, whose values may change the sizes of
+/// instructions
+pub const MAX_LAYOUT_PASSES: usize = 8;
+
+/// Get the encoding number and the size in bytes of a register.
+pub fn register_info(register: TokenValue) -> Option<(u8, usize)> {
+    match register {
+        TokenValue::EAX => Some((0, 4)),
+        TokenValue::AX => Some((0, 2)),
+        TokenValue::AL => Some((0, 1)),
+        TokenValue::AH => Some((4, 1)),
+        TokenValue::ECX => Some((1, 4)),
+        TokenValue::CX => Some((1, 2)),
+        TokenValue::CL => Some((1, 1)),
+        TokenValue::CH => Some((5, 1)),
+        TokenValue::EDX => Some((2, 4)),
+        TokenValue::DX => Some((2, 2)),
+        TokenValue::DL => Some((2, 1)),
+        TokenValue::DH => Some((6, 1)),
+        TokenValue::EBX => Some((3, 4)),
+        TokenValue::BX => Some((3, 2)),
+        TokenValue::BL => Some((3, 1)),
+        TokenValue::BH => Some((7, 1)),
+        TokenValue::ESP => Some((4, 4)),
+        TokenValue::SP => Some((4, 2)),
+        TokenValue::EBP => Some((5, 4)),
+        TokenValue::BP => Some((5, 2)),
+        TokenValue::ESI => Some((6, 4)),
+        TokenValue::SI => Some((6, 2)),
+        TokenValue::EDI => Some((7, 4)),
+        TokenValue::DI => Some((7, 2)),
+        _ => None,
+    }
+}
+
+/// Get the register of an encoding number and a size in bytes.
+pub fn register_from_number(number: u8, size: usize) -> TokenValue {
+    let registers = match size {
+        1 => [TokenValue::AL, TokenValue::CL, TokenValue::DL, TokenValue::BL, TokenValue::AH, TokenValue::CH,
+                TokenValue::DH, TokenValue::BH],
+        2 => [TokenValue::AX, TokenValue::CX, TokenValue::DX, TokenValue::BX, TokenValue::SP, TokenValue::BP,
+                TokenValue::SI, TokenValue::DI],
+        _ => [TokenValue::EAX, TokenValue::ECX, TokenValue::EDX, TokenValue::EBX, TokenValue::ESP, TokenValue::EBP,
+                TokenValue::ESI, TokenValue::EDI],
+    };
+
+    registers[(number & 7) as usize]
+}
+
+/// Get the name of a register.
+pub fn register_name(register: TokenValue) -> &'static str {
+    match register {
+        TokenValue::EAX => "eax",
+        TokenValue::AX => "ax",
+        TokenValue::AL => "al",
+        TokenValue::AH => "ah",
+        TokenValue::ECX => "ecx",
+        TokenValue::CX => "cx",
+        TokenValue::CL => "cl",
+        TokenValue::CH => "ch",
+        TokenValue::EDX => "edx",
+        TokenValue::DX => "dx",
+        TokenValue::DL => "dl",
+        TokenValue::DH => "dh",
+        TokenValue::EBX => "ebx",
+        TokenValue::BX => "bx",
+        TokenValue::BL => "bl",
+        TokenValue::BH => "bh",
+        TokenValue::ESP => "esp",
+        TokenValue::SP => "sp",
+        TokenValue::EBP => "ebp",
+        TokenValue::BP => "bp",
+        TokenValue::ESI => "esi",
+        TokenValue::SI => "si",
+        TokenValue::EDI => "edi",
+        TokenValue::DI => "di",
+        _ => "?",
+    }
+}
+
+#[derive(Clone, PartialEq)]
+/// Memory operand, `size ptr [base + index * scale + displacement]`
+pub struct Memory {
+    /// size of the operand in bytes
+    pub size: usize,
+    pub base: Option<TokenValue>,
+    /// index register and its scale, which is 1, 2, 4 or 8
+    pub index: Option<(TokenValue, u32)>,
+    pub displacement: i32,
+    /// label whose address is added to the displacement, such as `counter` in `[counter + 4]`
+    pub label: Option<String>,
+}
+
+#[derive(Clone, PartialEq)]
+/// Operand of instruction
+pub enum Operand {
+    Register(TokenValue),
+    Immediate(i64),
+    Memory(Memory),
+    /// target of `call` and jump instructions
+    Label(String),
+    /// file name of `incbin`
+    String(String),
+}
+
+impl Operand {
+    /// Get the size of the operand in bytes, immediate data and label have no size by themselves.
+    pub fn size(&self) -> Option<usize> {
+        match self {
+            Operand::Register(register) => register_info(*register).map(|(_, size)| size),
+            Operand::Memory(memory) => Some(memory.size),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+/// Instruction parsed from the token stream
+pub struct Instruction {
+    pub mnemonic: TokenValue,
+    /// mnemonic as written in source, such as `jz` for `je`
+    pub name: String,
+    pub operands: Vec<Operand>,
+    pub location: TokenLocation,
+}
+
+#[derive(Clone)]
+/// Statement of assembly source
+pub enum Statement {
+    Label(String, TokenLocation),
+    Instruction(Instruction),
+}
+
+impl fmt::Display for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = match self.size {
+            1 => "byte",
+            2 => "word",
+            _ => "dword",
+        };
+
+        let mut expression = self.label.to_owned().unwrap_or_default();
+
+        if let Some(base) = self.base {
+            if !expression.is_empty() {
+                expression.push('+');
+            }
+
+            expression.push_str(register_name(base));
+        }
+
+        if let Some((index, scale)) = self.index {
+            if !expression.is_empty() {
+                expression.push('+');
+            }
+
+            expression.push_str(register_name(index));
+
+            if scale != 1 {
+                expression.push_str(&format!("*{}", scale));
+            }
+        }
+
+        if expression.is_empty() {
+            expression = format!("{}", self.displacement);
+        } else if self.displacement > 0 {
+            expression.push_str(&format!("+{}", self.displacement));
+        } else if self.displacement < 0 {
+            expression.push_str(&format!("-{}", -(self.displacement as i64)));
+        }
+
+        write!(f, "{} ptr [{}]", size, expression)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "{}", register_name(*register)),
+            Operand::Immediate(value) => write!(f, "{}", value),
+            Operand::Memory(memory) => write!(f, "{}", memory),
+            Operand::Label(name) => write!(f, "{}", name),
+            Operand::String(string) if string.contains('"') => write!(f, "'{}'", string),
+            Operand::String(string) => write!(f, "\"{}\"", string),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+
+        for (i, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Linear combination of registers and labels plus a constant, the value of an address expression.
+struct Linear {
+    registers: Vec<(TokenValue, i64)>,
+    labels: Vec<(String, i64)>,
+    constant: i64,
+}
+
+impl Linear {
+    fn add(mut self, rhs: Linear, sign: i64) -> Linear {
+        for (register, coefficient) in rhs.registers {
+            match self.registers.iter_mut().find(|(r, _)| *r == register) {
+                Some((_, c)) => *c += sign * coefficient,
+                None => self.registers.push((register, sign * coefficient)),
+            }
+        }
+
+        for (label, coefficient) in rhs.labels {
+            match self.labels.iter_mut().find(|(l, _)| *l == label) {
+                Some((_, c)) => *c += sign * coefficient,
+                None => self.labels.push((label, sign * coefficient)),
+            }
+        }
+
+        self.constant += sign * rhs.constant;
+        self
+    }
+
+    fn scale(mut self, factor: i64) -> Linear {
+        for (_, coefficient) in self.registers.iter_mut() {
+            *coefficient *= factor;
+        }
+
+        for (_, coefficient) in self.labels.iter_mut() {
+            *coefficient *= factor;
+        }
+
+        self.constant *= factor;
+        self
+    }
+
+    /// Whether the value is only the constant.
+    fn is_constant(&self) -> bool {
+        self.registers.is_empty() && self.labels.is_empty()
+    }
+}
+
+/// Get the index after the last operand of the instruction at `i`, the operands are the rest of the line, up to the
+/// next statement.
+fn operands_end(tokens: &[Token], i: usize) -> usize {
+    let location = tokens[i].get_token_location();
+    let mut end = i + 1;
+
+    while end < tokens.len() {
+        let token = &tokens[end];
+        let same_line = token.get_token_location().get_line() == location.get_line() &&
+            token.get_token_location().get_source_file_name() == location.get_source_file_name();
+        let label = token.get_token_type() == TokenType::LABEL &&
+            tokens.get(end + 1).map(|token| token.get_token_value()) == Some(TokenValue::COLON);
+
+        if !same_line || label || token.get_token_type() == TokenType::INSTRUCTION {
+            break;
+        }
+
+        end += 1;
+    }
+
+    end
+}
+
+/// Rewrite the tokens of a source file, read with `options`, before parsing, returns the errors found:
+///
+/// 1. Insert the tokens of included files, see [`expand_includes`].
+/// 2. Replace the uses of macros by their bodies, see [`expand_macros`].
+/// 3. Make the names of procedures labels, see [`declare_procedures`].
+/// 4. Replace the names of constants by their values, see [`substitute_constants`].
+/// 5. Evaluate the immediate operands which are constant expressions, see [`fold_constant_expressions`].
+/// 6. Repeat the statements after `times`, see [`expand_times`].
+/// 7. Remove `offset` before labels, see [`remove_offsets`].
+/// 8. Remove the distances of jumps, `short` and `near`, see [`remove_distances`].
+/// 9. Expand the operand lists of `push` and `pop`, see [`expand_register_lists`].
+/// 10. Insert the sizes of memory operands, see [`infer_memory_sizes`].
+/// 11. Make the names of data definitions labels, see [`name_data_definitions`].
+/// 12. Expand the `dup` items of data definitions, see [`expand_duplicates`].
+/// 13. Give local and anonymous labels the names of their scopes, see [`scope_local_labels`].
+/// 14. Place the code section before the data section, see [`place_sections`].
+/// 15. Evaluate `$`, `$$` and the differences of labels, see [`resolve_locations`].
+pub fn preprocess_tokens(tokens: &mut Vec<Token>, options: &SourceOptions) -> Vec<String> {
+    let mut errors = expand_includes(tokens, options);
+    errors.extend(expand_macros(tokens));
+    errors.extend(remove_section_keywords(tokens));
+    errors.extend(declare_procedures(tokens));
+    errors.extend(substitute_constants(tokens));
+    errors.extend(fold_constant_expressions(tokens));
+    errors.extend(expand_times(tokens));
+    errors.extend(remove_offsets(tokens));
+    errors.extend(remove_distances(tokens));
+    expand_register_lists(tokens);
+    errors.extend(infer_memory_sizes(tokens));
+    name_data_definitions(tokens);
+    errors.extend(expand_duplicates(tokens));
+    errors.extend(scope_local_labels(tokens));
+    errors.extend(place_sections(tokens));
+    errors.extend(resolve_locations(tokens));
+    errors
+}
+
+/// Make `$`, the address of the statement it is in, and `$$`, the address of the start of its section, labels defined
+/// there, then replace every operand which is a difference of labels, such as `$ - msg` in `mov ecx, $ - msg` or
+/// `end - start` in `dd end - start`, by its value, which does not depend on where the program is loaded, and repeat
+/// the statements of `times` whose count is one, such as `times 510 - ($ - $$) db 0`, see [`expand_times`].
+///
+/// The value may change the size of the instruction using it, and so the addresses of the labels after it, so the
+/// labels are laid out again until the values settle, at most [`MAX_LAYOUT_PASSES`] times. An error is returned for
+/// a difference using a label which is not defined, and for a count of `times` which is not a difference of labels or
+/// is not from 0 to [`MAX_TIMES`].
+pub fn resolve_locations(tokens: &mut Vec<Token>) -> Vec<String> {
+    define_locations(tokens);
+
+    let mut errors = Vec::new();
+    let mut differences = Vec::new();
+    // the statements of `times` left by `expand_times`, with the start of the instruction they repeat
+    let mut repeats = Vec::new();
+
+    for (k, token) in tokens.iter().enumerate() {
+        if token.get_token_value() == TokenValue::REPEAT {
+            let start = k + 1 + tokens[k + 1..].iter()
+                .take_while(|token| token.get_token_type() != TokenType::INSTRUCTION)
+                .count();
+            let mut parser = Parser::new(&tokens[k + 1..start]);
+
+            match parser.parse_expression() {
+                Ok(linear) if parser.position() == start - k - 1 && linear.registers.is_empty() &&
+                    linear.labels.iter().map(|(_, coefficient)| coefficient).sum::<i64>() == 0 => {
+                    repeats.push((k..operands_end(tokens, start), start, linear, token.get_token_location()));
+                },
+                _ => {
+                    errors.push(format!("Syntax Error: {} The count of times must be a constant or a difference of \
+                                         labels, such as 510 - ($ - $$)", token.get_token_location().to_string()));
+                    return errors;
+                },
+            }
+
+            continue;
+        }
+
+        if token.get_token_type() != TokenType::INSTRUCTION {
+            continue;
+        }
+
+        let end = operands_end(tokens, k);
+        let mut start = k + 1;
+
+        for item in split_operands(&tokens[k + 1..end]) {
+            let range = start..start + item.len();
+            start = range.end + 1;
+
+            let memory = matches!(item.first().map(|token| token.get_token_value()),
+                                  Some(TokenValue::LBRACK | TokenValue::BYTE | TokenValue::WORD | TokenValue::DWORD));
+            if memory || item.iter().all(|token| token.get_token_type() != TokenType::LABEL) {
+                continue;
+            }
+
+            let mut parser = Parser::new(item);
+            let linear = match parser.parse_expression() {
+                Ok(linear) if parser.position() == item.len() && linear.registers.is_empty() &&
+                    linear.labels.iter().map(|(_, coefficient)| coefficient).sum::<i64>() == 0 => linear,
+                _ => continue,
+            };
+
+            differences.push((range, linear, token.get_token_location()));
+        }
+    }
+
+    if differences.is_empty() && repeats.is_empty() {
+        return errors;
+    }
+
+    let mut values = vec![0; differences.len()];
+    let mut counts = vec![0; repeats.len()];
+
+    for _ in 0..MAX_LAYOUT_PASSES {
+        let replaced: Vec<(Range<usize>, i64)> = differences.iter().zip(&values)
+            .map(|((range, _, _), value)| (range.to_owned(), *value))
+            .collect();
+        let mut resolved = Vec::with_capacity(tokens.len());
+        let mut next = 0;
+
+        for ((range, start, _, _), count) in repeats.iter().zip(&counts) {
+            resolved.extend(replace_values(tokens, next..range.start, &replaced));
+            let repeated = replace_values(tokens, *start..range.end, &replaced);
+
+            // a count out of range may still settle, it is only reported if it does
+            for _ in 0..(*count).clamp(0, MAX_TIMES) {
+                resolved.extend_from_slice(&repeated);
+            }
+
+            next = range.end;
+        }
+
+        resolved.extend(replace_values(tokens, next..tokens.len(), &replaced));
+
+        // a program which can not be parsed is reported by the parser
+        let Some(addresses) = label_addresses(&resolved) else { return errors; };
+        let mut settled = true;
+
+        let evaluated = differences.iter().map(|(_, linear, location)| (linear, location)).zip(values.iter_mut())
+            .chain(repeats.iter().map(|(_, _, linear, location)| (linear, location)).zip(counts.iter_mut()));
+
+        for ((linear, location), value) in evaluated {
+            let mut resolved = linear.constant;
+
+            for (label, coefficient) in &linear.labels {
+                match addresses.get(label) {
+                    Some(address) => resolved += coefficient * address,
+                    None => {
+                        errors.push(format!("Syntax Error: {} Unknown label: \"{}\"", location.to_string(), label));
+                        return errors;
+                    },
+                }
+            }
+
+            settled &= *value == resolved;
+            *value = resolved;
+        }
+
+        if settled {
+            for ((_, _, _, location), count) in repeats.iter().zip(&counts) {
+                if !(0..=MAX_TIMES).contains(count) {
+                    errors.push(format!("Syntax Error: {} The count of times is {}, but it must be from 0 to {}",
+                                        location.to_string(), count, MAX_TIMES));
+                }
+            }
+
+            *tokens = resolved;
+            return errors;
+        }
+    }
+
+    let location = differences.first().map(|(_, _, location)| location)
+        .or_else(|| repeats.first().map(|(_, _, _, location)| location));
+    errors.push(format!("Syntax Error: {} The differences of labels do not settle after {} layouts",
+                        location.map(|location| location.to_string()).unwrap_or_default(), MAX_LAYOUT_PASSES));
+    errors
+}
+
+/// Get the tokens in `range`, with the operands in it which are differences of labels replaced by their values, given
+/// in the order of the operands.
+fn replace_values(tokens: &[Token], range: Range<usize>, values: &[(Range<usize>, i64)]) -> Vec<Token> {
+    let mut replaced = Vec::with_capacity(range.len());
+    let mut next = range.start;
+
+    for (operand, value) in values.iter().filter(|(operand, _)| range.contains(&operand.start)) {
+        replaced.extend_from_slice(&tokens[next..operand.start]);
+        replaced.extend(value_tokens(*value, tokens[operand.start].get_token_location()));
+        next = operand.end;
+    }
+
+    replaced.extend_from_slice(&tokens[next..range.end]);
+    replaced
+}
+
+/// Split operands at the commas outside brackets and parentheses, such as `[ebx + 4], eax` into `[ebx + 4]` and `eax`.
+fn split_operands(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token.get_token_value() {
+            TokenValue::LBRACK | TokenValue::LPAREN => depth += 1,
+            TokenValue::RBRACK | TokenValue::RPAREN => depth -= 1,
+            TokenValue::COMMA if depth == 0 => {
+                operands.push(&tokens[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+
+    if !tokens.is_empty() {
+        operands.push(&tokens[start..]);
+    }
+
+    operands
+}
+
+/// Get the addresses of the labels from address 0, `None` if the program can not be parsed.
+fn label_addresses(tokens: &[Token]) -> Option<HashMap<String, i64>> {
+    let mut addresses = HashMap::new();
+    let mut address = 0u32;
+
+    for statement in Parser::new(tokens).parse_program().ok()? {
+        match statement {
+            Statement::Label(name, _) => {
+                addresses.entry(name).or_insert(address as i64);
+            },
+            Statement::Instruction(instruction) if instruction.mnemonic == TokenValue::ORG => {
+                address = org_address(&instruction, address).ok()?;
+            },
+            Statement::Instruction(instruction) if is_declaration(instruction.mnemonic) => {},
+            // an instruction without an encoding takes one byte, as in the virtual machine
+            Statement::Instruction(instruction) => {
+                address = address.wrapping_add(instruction_size(&instruction).unwrap_or(1));
+            },
+        }
+    }
+
+    Some(addresses)
+}
+
+/// Whether a label is one which stands for `$` or `$$`, which is not written in the source, see [`resolve_locations`].
+pub fn is_location_label(name: &str) -> bool {
+    name.starts_with("..@$")
+}
+
+/// Replace `$` by a label defined before the statement it is in, and `$$` by a label defined at the start of the code,
+/// which is after the `org` it starts with, if any, or of the data section, which is after the first `.data`.
+fn define_locations(tokens: &mut Vec<Token>) {
+    let location_label = |name: &str, token: &Token| {
+        Token::new_token(TokenType::LABEL, TokenValue::LABEL, token.get_token_location(), name.to_string())
+    };
+    let colon = |token: &Token| {
+        Token::new_symbol_token(TokenValue::COLON, token.get_token_location(), ":".to_string(), -1)
+    };
+
+    let mut defined = Vec::with_capacity(tokens.len());
+    let mut statement = 0;
+    let mut here: Option<String> = None;
+    let mut count = 0;
+    let mut data_start = None;
+    let mut starts: Vec<(usize, String, Token)> = Vec::new();
+    let text_start = match tokens.first() {
+        Some(token) if token.get_token_value() == TokenValue::ORG => operands_end(tokens, 0),
+        _ => 0,
+    };
+
+    for token in tokens.iter() {
+        // `$` in the count of `times` is the address of the statement of `times`
+        if token.get_token_type() == TokenType::INSTRUCTION || token.get_token_value() == TokenValue::REPEAT {
+            statement = defined.len();
+            here = None;
+
+            if token.get_token_value() == TokenValue::DATA && data_start.is_none() {
+                data_start = Some(defined.len() + 1);
+            }
+        }
+
+        match token.get_token_name().as_str() {
+            "$" if token.get_token_type() == TokenType::LABEL => {
+                let name = here.get_or_insert_with(|| {
+                    count += 1;
+                    format!("..@${}", count)
+                }).to_owned();
+
+                if defined.get(statement).map(|token: &Token| token.get_token_name()) != Some(name.to_owned()) {
+                    defined.splice(statement..statement, [location_label(&name, token), colon(token)]);
+                }
+
+                defined.push(location_label(&name, token));
+            },
+            "$$" if token.get_token_type() == TokenType::LABEL => {
+                let (start, name) = match data_start {
+                    Some(start) => (start, "..@$$.data"),
+                    None => (text_start, "..@$$.text"),
+                };
+
+                if !starts.iter().any(|(_, defined, _)| defined == name) {
+                    starts.push((start, name.to_string(), token.to_owned()));
+                }
+
+                defined.push(location_label(name, token));
+            },
+            _ => defined.push(token.to_owned()),
+        }
+    }
+
+    // from the last one, so that the positions of the others stay the same
+    starts.sort_by_key(|(start, _, _)| std::cmp::Reverse(*start));
+
+    for (start, name, token) in starts {
+        defined.splice(start..start, [location_label(&name, &token), colon(&token)]);
+    }
+
+    *tokens = defined;
+}
+
+/// Move the statements of the data section, those after `.data` up to `.text` or `.code`, after the statements of
+/// the code section, in the order they are written, so that the code starts at the origin wherever the data is
+/// written, as in `.data` before `.code` of MASM. The statements of a source file before any section are in the code
+/// section.
+///
+/// `.stack 4096` of MASM is removed, as the virtual machine has a stack of its own, but an error is returned for
+/// anything other than the size after `.stack`.
+pub fn place_sections(tokens: &mut Vec<Token>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut text = Vec::with_capacity(tokens.len());
+    let mut data = Vec::new();
+    let mut in_data = false;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let token = &tokens[i];
+
+        if i > 0 && tokens[i - 1].get_token_location().get_source_file_name() !=
+            token.get_token_location().get_source_file_name() {
+            in_data = false;
+        }
+
+        match token.get_token_value() {
+            TokenValue::STACK => {
+                let end = operands_end(tokens, i);
+
+                match &tokens[i + 1..end] {
+                    [] => {},
+                    [size] if size.get_token_type() == TokenType::IMMEDIATE_DATA => {},
+                    _ => errors.push(format!("Syntax Error: {} Expected the size of the stack after .stack, such as \
+                                              .stack 4096", token.get_token_location().to_string())),
+                }
+
+                i = end;
+                continue;
+            },
+            TokenValue::DATA => in_data = true,
+            TokenValue::TEXT => in_data = false,
+            _ => {},
+        }
+
+        if in_data { &mut data } else { &mut text }.push(token.to_owned());
+        i += 1;
+    }
+
+    text.append(&mut data);
+    *tokens = text;
+    errors
+}
+
+/// Rewrite the procedures of MASM, `square proc` up to `square endp`, so that `square proc` defines the label
+/// `square:`, which `call square` targets, and `square endp` is the directive `endp square`, which marks where the
+/// procedure ends. An error is returned for a procedure inside another one, a procedure without `endp`, or an `endp`
+/// which does not end the procedure it names.
+pub fn declare_procedures(tokens: &mut Vec<Token>) -> Vec<String> {
+    let same_line = |first: &Token, second: &Token| {
+        first.get_token_location().get_line() == second.get_token_location().get_line() &&
+            first.get_token_location().get_source_file_name() == second.get_token_location().get_source_file_name()
+    };
+
+    let mut errors = Vec::new();
+    let mut rewritten: Vec<Token> = Vec::with_capacity(tokens.len());
+    // the name and the location of the procedure which has not ended yet
+    let mut open: Option<(String, String)> = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let value = token.get_token_value();
+
+        if !matches!(value, TokenValue::PROC | TokenValue::ENDP) {
+            rewritten.push(token.to_owned());
+            continue;
+        }
+
+        let location = token.get_token_location().to_string();
+        let keyword = token.get_token_name().to_lowercase();
+        let named = i >= 1 && tokens[i - 1].get_token_type() == TokenType::LABEL && same_line(&tokens[i - 1], token) &&
+            (i == 1 || !same_line(&tokens[i - 2], token));
+
+        if !named {
+            errors.push(format!("Syntax Error: {} Expected the name of the procedure before {}, such as main {}",
+                                location, keyword, keyword));
+            continue;
+        }
+
+        if tokens.get(i + 1).is_some_and(|next| same_line(next, token)) {
+            errors.push(format!("Syntax Error: {} Expected nothing after {}, such as main {}", location, keyword,
+                                keyword));
+        }
+
+        let name = tokens[i - 1].get_token_name();
+
+        if value == TokenValue::PROC {
+            if let Some((outer, _)) = &open {
+                errors.push(format!("Syntax Error: {} The procedure {} is inside the procedure {}, which has not \
+                                     ended", location, name, outer));
+            }
+
+            open = Some((name, location));
+            rewritten.push(Token::new_symbol_token(TokenValue::COLON, token.get_token_location(), ":".to_string(),
+                                                   -1));
+        } else {
+            match open.take() {
+                Some((procedure, _)) if procedure == name => {},
+                Some((procedure, _)) => {
+                    errors.push(format!("Syntax Error: {} Expected {} endp, which ends the procedure {} first",
+                                        location, procedure, procedure));
+                },
+                None => errors.push(format!("Syntax Error: {} Unexpected endp outside a procedure", location)),
+            }
+
+            let name = rewritten.pop().unwrap();
+            rewritten.push(token.to_owned());
+            rewritten.push(name);
+        }
+    }
+
+    if let Some((procedure, start)) = open {
+        errors.push(format!("Syntax Error: {} Expected {} endp after the procedure {}", start, procedure, procedure));
+    }
+
+    *tokens = rewritten;
+    errors
+}
+
+/// Replace the names of constants defined by `equ`, such as `SIZE equ 4 * 1024`, by their values wherever they are
+/// used, before or after the definition, which is removed. The value is a constant expression, which may use the
+/// constants defined before it, or an expression of labels, such as `$ - msg`, which is put in parentheses where it
+/// is used and evaluated by [`resolve_locations`], where its `$` is a label at the definition. An error is returned
+/// for each definition whose value is neither, such as one with a register.
+pub fn substitute_constants(tokens: &mut Vec<Token>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut constants = HashMap::new();
+    let mut rest = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        if tokens[i].get_token_type() != TokenType::LABEL ||
+            tokens.get(i + 1).map(|token| token.get_token_value()) != Some(TokenValue::EQU) {
+            rest.push(tokens[i].to_owned());
+            i += 1;
+            continue;
+        }
+
+        let name = tokens[i].get_token_name();
+        let location = tokens[i].get_token_location().to_string();
+        let end = operands_end(tokens, i + 1);
+        let value: Vec<Token> = tokens[i + 2..end].iter()
+            .flat_map(|token| constant_tokens(token, &constants))
+            .collect();
+        let mut parser = Parser::new(&value);
+
+        let definition = match parser.parse_expression() {
+            _ if value.is_empty() => {
+                Err(format!("Syntax Error: {} Expected a value after equ, such as {} equ 4 * 1024", location, name))
+            },
+            Ok(linear) if linear.is_constant() && parser.position() == value.len() &&
+                (i32::MIN as i64..=u32::MAX as i64).contains(&linear.constant) => {
+                Ok(value_tokens(linear.constant, tokens[i].get_token_location()))
+            },
+            // an expression of labels, such as `$ - msg`, is evaluated where it is used, once labels have addresses
+            Ok(linear) if !linear.is_constant() && linear.registers.is_empty() && parser.position() == value.len() => {
+                let location = tokens[i].get_token_location();
+                let here = Token::new_token(TokenType::LABEL, TokenValue::LABEL, location.to_owned(),
+                                            format!("..@$.{}", name));
+                let is_here = |token: &Token| {
+                    token.get_token_type() == TokenType::LABEL && token.get_token_name() == "$"
+                };
+
+                // `$` is where the constant is defined, which is a label there
+                if value.iter().any(is_here) {
+                    rest.push(here.to_owned());
+                    rest.push(Token::new_symbol_token(TokenValue::COLON, location.to_owned(), ":".to_string(), -1));
+                }
+
+                let value = value.into_iter().map(|token| if is_here(&token) { here.to_owned() } else { token });
+                let lparen = Token::new_symbol_token(TokenValue::LPAREN, location.to_owned(), "(".to_string(), -1);
+                let rparen = Token::new_symbol_token(TokenValue::RPAREN, location, ")".to_string(), -1);
+
+                Ok(std::iter::once(lparen).chain(value).chain(std::iter::once(rparen)).collect())
+            },
+            Ok(_) => Err(format!("Syntax Error: {} The value of {} must be a constant expression of 32 bits, or an \
+                                  expression of labels, such as $ - msg", location, name)),
+            Err(error) => Err(error),
+        };
+
+        match definition {
+            Ok(value) => {
+                if constants.insert(name.to_owned(), value).is_some() {
+                    errors.push(format!("Syntax Error: {} Duplicate constant: \"{}\"", location, name));
+                }
+            },
+            Err(error) => errors.push(error),
+        }
+
+        i = end;
+    }
+
+    *tokens = rest.iter().flat_map(|token| constant_tokens(token, &constants)).collect();
+    errors
+}
+
+/// Replace every operand which is a constant expression, such as `3 * 4 + 1` or `(1 << 8) - 1` in `mov eax, 3 * 4 + 1`,
+/// by its value, so that immediate data is a single integer, with `-` before it if it is negative. An operand which
+/// can not be evaluated is left as written. An error is returned for each operand of only integers which can not be
+/// evaluated, such as `1 / 0`, or whose value does not fit in 32 bits.
+pub fn fold_constant_expressions(tokens: &mut Vec<Token>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut folded = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        folded.push(tokens[i].to_owned());
+
+        if tokens[i].get_token_type() != TokenType::INSTRUCTION {
+            i += 1;
+            continue;
+        }
+
+        let end = operands_end(tokens, i);
+        let mut start = i + 1;
+
+        while start < end {
+            let operand_end = (start..end).find(|j| tokens[*j].get_token_value() == TokenValue::COMMA).unwrap_or(end);
+            let operand = &tokens[start..operand_end];
+
+            let constant = operand.len() > 1 && operand.iter().all(|token| {
+                token.get_token_type() == TokenType::IMMEDIATE_DATA ||
+                    matches!(token.get_token_value(), TokenValue::PLUS | TokenValue::MINUS | TokenValue::TIMES |
+                             TokenValue::SLASH | TokenValue::PERCENT | TokenValue::LSHIFT | TokenValue::RSHIFT |
+                             TokenValue::LPAREN | TokenValue::RPAREN)
+            });
+
+            let mut parser = Parser::new(operand);
+
+            match parser.parse_expression() {
+                Ok(linear) if constant && parser.position() == operand.len() => {
+                    let location = operand[0].get_token_location();
+
+                    if !(i32::MIN as i64..=u32::MAX as i64).contains(&linear.constant) {
+                        errors.push(format!("Syntax Error: {} The value of the expression is {}, which does not fit in \
+                                             32 bits", location.to_string(), linear.constant));
+                    }
+
+                    folded.extend(value_tokens(linear.constant, location));
+                },
+                // such as a division by zero
+                Err(error) if constant => {
+                    errors.push(error);
+                    folded.extend_from_slice(operand);
+                },
+                _ => folded.extend_from_slice(operand),
+            }
+
+            folded.extend(tokens.get(operand_end).filter(|_| operand_end < end).cloned());
+            start = operand_end + 1;
+        }
+
+        i = end;
+    }
+
+    *tokens = folded;
+    errors
+}
+
+/// Get the tokens of a value at `location`, such as `-` and `1` for `-1`.
+fn value_tokens(value: i64, location: TokenLocation) -> Vec<Token> {
+    let magnitude = value.unsigned_abs();
+    let number = Token::new_int_token(location.to_owned(), magnitude.to_string(), magnitude as u32);
+
+    if value < 0 {
+        vec![Token::new_symbol_token(TokenValue::MINUS, location, "-".to_string(), 10), number]
+    } else {
+        vec![number]
+    }
+}
+
+/// Get the tokens of the value of a constant where its name is used, such as `-` and `1` for `-1`, or the token
+/// itself if it is not the name of a constant.
+fn constant_tokens(token: &Token, constants: &HashMap<String, Vec<Token>>) -> Vec<Token> {
+    let value = match constants.get(&token.get_token_name()) {
+        Some(value) if token.get_token_type() == TokenType::LABEL => value,
+        _ => return vec![token.to_owned()],
+    };
+
+    value.iter()
+        .map(|value| {
+            let mut value = value.to_owned();
+            value.set_token_location(token.get_token_location());
+            value
+        })
+        .collect()
+}
+
+/// Rename local, anonymous and numeric labels, where they are defined and where they are used, returns an error for
+/// each use of an anonymous or numeric label which does not exist:
+///
+/// * a label starting with `.`, such as `.loop`, is local to the last label defined before it which does not, as in
+///   NASM, so `.loop` after `main:` is `main.loop`, and every function can have its own `.loop`,
+/// * `@@:` of MASM defines an anonymous label, `@F` is the next one and `@B` the last one, which are numbered in the
+///   order they are defined, such as `@@1` for the first one, which is their name in listings,
+/// * `1:` of GNU as defines a numeric label, which may be defined again, `1f` is the next `1` and `1b` the last one,
+///   and they are numbered the same way, such as `1@2` for the second `1`.
+///
+/// The local labels of macros, such as `..@1.again`, are left as they are, and are not scopes themselves, as in NASM.
+pub fn scope_local_labels(tokens: &mut [Token]) -> Vec<String> {
+    let is_definition = |tokens: &[Token], i: usize| {
+        tokens[i].get_token_type() == TokenType::LABEL &&
+            tokens.get(i + 1).map(|token| token.get_token_value()) == Some(TokenValue::COLON)
+    };
+
+    // the positions where every anonymous and numeric label is defined, in order
+    let mut definitions: HashMap<String, Vec<usize>> = HashMap::new();
+
+    for i in 0..tokens.len() {
+        let name = tokens[i].get_token_name();
+
+        if is_definition(tokens, i) && (name == "@@" || is_number(&name)) {
+            definitions.entry(name).or_default().push(i);
+        }
+    }
+
+    let numbered = |key: &str, n: usize| {
+        if key == "@@" { format!("@@{}", n + 1) } else { format!("{}@{}", key, n + 1) }
+    };
+
+    let mut errors = Vec::new();
+    let mut scope: Option<String> = None;
+
+    for i in 0..tokens.len() {
+        if tokens[i].get_token_type() != TokenType::LABEL {
+            continue;
+        }
+
+        let name = tokens[i].get_token_name();
+        let location = tokens[i].get_token_location();
+
+        let renamed = if let Some(n) = definitions.get(&name).and_then(|positions| {
+            positions.iter().position(|position| *position == i)
+        }) {
+            Some(numbered(&name, n))
+        } else if let Some((key, forward)) = anonymous_reference(&name) {
+            let positions = definitions.get(&key).map_or(&[][..], |positions| positions.as_slice());
+            let found = if forward {
+                positions.iter().position(|position| *position > i)
+            } else {
+                positions.iter().rposition(|position| *position < i)
+            };
+
+            if found.is_none() {
+                errors.push(format!("Syntax Error: {} No {}: label {} {}", location.to_string(), key,
+                                    if forward { "after" } else { "before" }, name));
+            }
+
+            found.map(|n| numbered(&key, n))
+        } else if name.starts_with('.') && !name.starts_with("..@") {
+            scope.as_ref().map(|scope| format!("{}{}", scope, name))
+        } else {
+            if is_definition(tokens, i) && !name.starts_with('@') && !name.starts_with("..@") {
+                scope = Some(name.to_owned());
+            }
+
+            None
+        };
+
+        if let Some(renamed) = renamed {
+            tokens[i] = Token::new_token(TokenType::LABEL, TokenValue::LABEL, location, renamed);
+        }
+    }
+
+    errors
+}
+
+/// Whether the name is only digits, such as that of a numeric label.
+fn is_number(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Get the label a use of an anonymous or numeric label refers to, `@@` or the number, and whether it is the next one,
+/// such as `@@` and `true` for `@F`, or `1` and `false` for `1b`.
+fn anonymous_reference(name: &str) -> Option<(String, bool)> {
+    let lower = name.to_lowercase();
+    let forward = match lower.chars().last() {
+        Some('f') => true,
+        Some('b') => false,
+        _ => return None,
+    };
+
+    match &lower[..lower.len() - 1] {
+        "@" => Some(("@@".to_string(), forward)),
+        key if is_number(key) => Some((key.to_string(), forward)),
+        _ => None,
+    }
+}
+
+/// Expand `times 4 db 0` into 4 copies of the statement after the count, which may be any instruction, such as
+/// `times 2 nop`, or none if the count is 0.
+///
+/// The count must be immediate data up to [`MAX_TIMES`]. A count using labels, `$` or `$$`, such as the padding of a
+/// boot sector, `times 510 - ($ - $$) db 0`, is left as it is for [`resolve_locations`], which knows the addresses.
+/// When the count is neither, an error is returned for it and the statement is kept once, so that parsing can go on.
+pub fn expand_times(tokens: &mut Vec<Token>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut expanded = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        if tokens[i].get_token_value() != TokenValue::REPEAT {
+            expanded.push(tokens[i].to_owned());
+            i += 1;
+            continue;
+        }
+
+        let location = tokens[i].get_token_location().to_string();
+        let line = tokens[i].get_token_location().get_line();
+        let start = i + 1 + tokens[i + 1..].iter()
+            .take_while(|token| token.get_token_type() != TokenType::INSTRUCTION &&
+                        token.get_token_location().get_line() == line)
+            .count();
+        let count = match &tokens[i + 1..start] {
+            [count] if count.get_token_type() == TokenType::IMMEDIATE_DATA => Some(count.get_int_value() as i64),
+            _ => None,
+        };
+
+        let end = match tokens.get(start) {
+            Some(token) if token.get_token_type() == TokenType::INSTRUCTION => operands_end(tokens, start),
+            _ => {
+                errors.push(format!("Syntax Error: {} Expected a count, then an instruction, such as times 4 db 0",
+                                    location));
+                i = start;
+                continue;
+            },
+        };
+
+        if count.is_none() && tokens[i + 1..start].iter().any(|token| token.get_token_type() == TokenType::LABEL) {
+            expanded.extend_from_slice(&tokens[i..end]);
+            i = end;
+            continue;
+        }
+
+        let count = match count {
+            Some(count) if (0..=MAX_TIMES).contains(&count) => count,
+            Some(count) => {
+                errors.push(format!("Syntax Error: {} The count of times is {}, but it must be from 0 to {}", location,
+                                    count, MAX_TIMES));
+                1
+            },
+            None => {
+                errors.push(format!("Syntax Error: {} Expected a count, then an instruction, such as times 4 db 0",
+                                    location));
+                1
+            },
+        };
+
+        for _ in 0..count {
+            expanded.extend_from_slice(&tokens[start..end]);
+        }
+
+        i = end;
+    }
+
+    *tokens = expanded;
+    errors
+}
+
+/// Insert `:` after the name of a data definition written without it, such as `msg` in `msg db 1, 2, 3`, so that the
+/// name is a label at the address of the data. The name is the first token of its line, so that `x` in
+/// `dd x dd x`, which `times 2 dd x` expands into, is not taken for one.
+pub fn name_data_definitions(tokens: &mut Vec<Token>) {
+    let same_line = |first: &Token, second: &Token| {
+        first.get_token_location().get_line() == second.get_token_location().get_line() &&
+            first.get_token_location().get_source_file_name() == second.get_token_location().get_source_file_name()
+    };
+
+    let mut insertions = Vec::new();
+
+    for i in 0..tokens.len().saturating_sub(1) {
+        let first = i == 0 || !same_line(&tokens[i - 1], &tokens[i]);
+
+        if first && tokens[i].get_token_type() == TokenType::LABEL && same_line(&tokens[i], &tokens[i + 1]) &&
+            matches!(tokens[i + 1].get_token_value(), TokenValue::DB | TokenValue::DW | TokenValue::DD) {
+            insertions.push(i + 1);
+        }
+    }
+
+    for position in insertions.into_iter().rev() {
+        let location = tokens[position - 1].get_token_location();
+        tokens.insert(position, Token::new_symbol_token(TokenValue::COLON, location, ":".to_string(), -1));
+    }
+}
+
+/// Remove `offset` before a label, as in `mov esi, offset msg` of MASM, since a label is its address wherever
+/// immediate data is, as in `mov esi, msg`. An error is returned for `offset` before anything else.
+pub fn remove_offsets(tokens: &mut Vec<Token>) -> Vec<String> {
+    let errors = tokens.iter().enumerate()
+        .filter(|(i, token)| token.get_token_value() == TokenValue::OFFSET &&
+                tokens.get(i + 1).map(|token| token.get_token_type()) != Some(TokenType::LABEL))
+        .map(|(_, token)| format!("Syntax Error: {} Expected a label after offset, such as offset msg",
+                                  token.get_token_location().to_string()))
+        .collect();
+
+    tokens.retain(|token| token.get_token_value() != TokenValue::OFFSET);
+    errors
+}
+
+/// Remove `section` or `segment` of NASM before the name of a section, as in `section .data`, which then starts the
+/// section as `.data` does, see [`place_sections`]. An error is returned for one which is not followed by `.text`,
+/// `.data`, `.code` or `.stack`, such as `section .bss`.
+pub fn remove_section_keywords(tokens: &mut Vec<Token>) -> Vec<String> {
+    let errors = tokens.iter().enumerate()
+        .filter(|(i, token)| token.get_token_value() == TokenValue::SECTION &&
+                !matches!(tokens.get(i + 1).map(|token| token.get_token_value()),
+                          Some(TokenValue::TEXT | TokenValue::DATA | TokenValue::STACK)))
+        .map(|(i, token)| format!("Syntax Error: {} Expected .text, .data, .code or .stack after {}, but find {}",
+                                  token.get_token_location().to_string(), token.get_token_name().to_lowercase(),
+                                  tokens.get(i + 1).map_or("the end".to_string(), |token| token.get_token_name())))
+        .collect();
+
+    tokens.retain(|token| token.get_token_value() != TokenValue::SECTION);
+    errors
+}
+
+/// Remove the distance of a jump or call before its label, `short` as in `jmp short done`, or `near` as in
+/// `call near ptr square` of MASM, since every target is in the one segment of the program. An error is returned for
+/// a distance anywhere else, before anything but a label, or for a `short` call, which does not exist.
+pub fn remove_distances(tokens: &mut Vec<Token>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut removed = Vec::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        let distance = token.get_token_value();
+
+        if !matches!(distance, TokenValue::SHORT | TokenValue::NEAR) {
+            continue;
+        }
+
+        let location = token.get_token_location().to_string();
+        let name = token.get_token_name().to_lowercase();
+        let mnemonic = i.checked_sub(1).map(|i| tokens[i].get_token_value());
+        let ptr = distance == TokenValue::NEAR && tokens.get(i + 1).map(|token| token.get_token_value()) ==
+            Some(TokenValue::PTR);
+        let label = tokens.get(i + 1 + ptr as usize).map(|token| token.get_token_type()) == Some(TokenType::LABEL);
+
+        match mnemonic {
+            Some(mnemonic) if is_jump(mnemonic) || mnemonic == TokenValue::CALL => {},
+            _ => errors.push(format!("Syntax Error: {} Unexpected {}, which is written after a jump or call, such as \
+                                      jmp {} done", location, name, name)),
+        }
+
+        if mnemonic == Some(TokenValue::CALL) && distance == TokenValue::SHORT {
+            errors.push(format!("Syntax Error: {} A call is not short, use call or call near", location));
+        } else if !label {
+            errors.push(format!("Syntax Error: {} Expected a label after {}, such as jmp {} done", location, name,
+                                name));
+        }
+
+        removed.push(i);
+
+        if ptr {
+            removed.push(i + 1);
+        }
+    }
+
+    let mut position = 0;
+    tokens.retain(|_| {
+        position += 1;
+        removed.binary_search(&(position - 1)).is_err()
+    });
+
+    errors
+}
+
+/// Expand the `dup` items of `db`, `dw` and `dd` into the items they repeat, such as `64 dup(0)` into 64 zeros, which
+/// may be `dup` items themselves, as in `2 dup(1, 2 dup(3))`. The item `?`, whose value does not matter, is 0.
+///
+/// A definition has at most [`MAX_TIMES`] items after the expansion. When an item can not be expanded, an error is
+/// returned for it and the definition is left as written.
+pub fn expand_duplicates(tokens: &mut Vec<Token>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let definition = tokens[i].get_token_type() == TokenType::INSTRUCTION &&
+            matches!(tokens[i].get_token_value(), TokenValue::DB | TokenValue::DW | TokenValue::DD);
+        let end = if definition { operands_end(tokens, i) } else { i + 1 };
+
+        if !tokens[i + 1..end].iter().any(|token| matches!(token.get_token_value(), TokenValue::DUP |
+                                                               TokenValue::QUESTION)) {
+            i = end;
+            continue;
+        }
+
+        let mut position = i + 1;
+
+        match duplicate_items(&tokens[..end], &mut position) {
+            Ok(_) if position < end => {
+                errors.push(format!("Syntax Error: {} Unexpected token: {}",
+                                    tokens[position].get_token_location().to_string(),
+                                    tokens[position].get_token_name()));
+                i = end;
+            },
+            Ok((_, 0)) => {
+                errors.push(format!("Syntax Error: {} {} has no items after dup is expanded",
+                                    tokens[i].get_token_location().to_string(), tokens[i].get_token_name()));
+                i = end;
+            },
+            Ok((items, _)) => {
+                i += 1 + items.len();
+                tokens.splice(i - items.len()..end, items);
+            },
+            Err(msg) => {
+                errors.push(msg);
+                i = end;
+            },
+        }
+    }
+
+    errors
+}
+
+/// Expand the items from `position` up to `)` or the end of `tokens`, which are separated by commas, and move
+/// `position` after them. Returns the tokens of the items and their number.
+fn duplicate_items(tokens: &[Token], position: &mut usize) -> Result<(Vec<Token>, i64), String> {
+    let error = |token: &Token, msg: String| {
+        format!("Syntax Error: {} {}", token.get_token_location().to_string(), msg)
+    };
+    let mut items = Vec::new();
+    let mut count = 0;
+
+    loop {
+        let start = *position;
+        let token = match tokens.get(start) {
+            Some(token) => token,
+            None => return Err(error(&tokens[start - 1], "Expected an item after it".to_string())),
+        };
+
+        if token.get_token_type() == TokenType::IMMEDIATE_DATA &&
+            tokens.get(start + 1).map(|token| token.get_token_value()) == Some(TokenValue::DUP) {
+            if tokens.get(start + 2).map(|token| token.get_token_value()) != Some(TokenValue::LPAREN) {
+                return Err(error(&tokens[start + 1], "Expected \"(\" after dup, such as 64 dup(0)".to_string()));
+            }
+
+            *position += 3;
+            let (repeated, length) = duplicate_items(tokens, position)?;
+
+            if tokens.get(*position).map(|token| token.get_token_value()) != Some(TokenValue::RPAREN) {
+                return Err(error(&tokens[*position - 1], "Expected \")\" after the items of dup".to_string()));
+            }
+
+            *position += 1;
+
+            let times = token.get_int_value() as i64;
+
+            if count + times * length > MAX_TIMES {
+                return Err(error(token, format!("The definition has more than {} items", MAX_TIMES)));
+            }
+
+            for _ in 0..times {
+                items.push(repeated.to_owned());
+            }
+
+            count += times * length;
+        } else {
+            while tokens.get(*position).is_some_and(|token| !matches!(token.get_token_value(), TokenValue::COMMA |
+                                                                       TokenValue::RPAREN)) {
+                *position += 1;
+            }
+
+            if *position == start {
+                return Err(error(token, format!("Expected an item, but find \"{}\"", token.get_token_name())));
+            }
+
+            let item = tokens[start..*position].iter()
+                .map(|token| match token.get_token_value() {
+                    TokenValue::QUESTION => Token::new_int_token(token.get_token_location(), "0".to_string(), 0),
+                    _ => token.to_owned(),
+                })
+                .collect();
+
+            items.push(item);
+            count += 1;
+        }
+
+        if tokens.get(*position).map(|token| token.get_token_value()) != Some(TokenValue::COMMA) {
+            break;
+        }
+
+        *position += 1;
+    }
+
+    let mut expanded: Vec<Token> = Vec::new();
+
+    for item in items.into_iter().filter(|item: &Vec<Token>| !item.is_empty()) {
+        if let Some(last) = expanded.last() {
+            expanded.push(Token::new_symbol_token(TokenValue::COMMA, last.get_token_location(), ",".to_string(), -1));
+        }
+
+        expanded.extend(item);
+    }
+
+    Ok((expanded, count))
+}
+
+/// Expand `push eax, ebx, ecx` into `push eax`, `push ebx` and `push ecx`, and the same for `pop`, in the order they
+/// are written.
+///
+/// Each comma between the operands is replaced with the mnemonic again, so every operation keeps the location of its
+/// operand.
+pub fn expand_register_lists(tokens: &mut [Token]) {
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let mnemonic = tokens[i].get_token_value();
+
+        if tokens[i].get_token_type() != TokenType::INSTRUCTION ||
+            !matches!(mnemonic, TokenValue::PUSH | TokenValue::POP) {
+            i += 1;
+            continue;
+        }
+
+        let name = tokens[i].get_token_name();
+        let end = operands_end(tokens, i);
+
+        for token in &mut tokens[i + 1..end] {
+            if token.get_token_value() == TokenValue::COMMA {
+                *token = Token::new_token(TokenType::INSTRUCTION, mnemonic, token.get_token_location(), name.to_owned());
+            }
+        }
+
+        i = end;
+    }
+}
+
+/// Insert `size ptr` before every memory operand written without its size, such as `[ebx]` in `mov [ebx], eax`, and
+/// `ptr` after a size written without it, as in NASM, such as `byte [ebx]` in `mov byte [ebx], 1`.
+///
+/// The size is the size of a register operand of the same instruction, or 4 bytes for `push` and `pop`. When it is
+/// ambiguous, such as in `mov [ebx], 1`, an error is returned for the operand and `dword ptr` is inserted, so that
+/// parsing can go on.
+pub fn infer_memory_sizes(tokens: &mut Vec<Token>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut insertions = Vec::new();
+    let mut i = 0;
+
+    while i < tokens.len() {
+        if tokens[i].get_token_type() != TokenType::INSTRUCTION {
+            i += 1;
+            continue;
+        }
+
+        let mnemonic = tokens[i].get_token_value();
+        let end = operands_end(tokens, i);
+        let operands: Vec<&[Token]> = tokens[i + 1..end].split(|token| token.get_token_value() == TokenValue::COMMA)
+            .collect();
+
+        let register_size = operands.iter()
+            .filter_map(|operand| match operand {
+                [token] if token.get_token_type() == TokenType::REGISTER => register_info(token.get_token_value()),
+                _ => None,
+            })
+            .map(|(_, size)| size)
+            .next();
+
+        let size = match mnemonic {
+            TokenValue::PUSH | TokenValue::POP => Some(4),
+            TokenValue::SETE | TokenValue::SETNE | TokenValue::SETG | TokenValue::SETGE | TokenValue::SETL |
+            TokenValue::SETLE | TokenValue::SETA | TokenValue::SETAE | TokenValue::SETB | TokenValue::SETBE |
+            TokenValue::SETO | TokenValue::SETNO | TokenValue::SETS | TokenValue::SETNS | TokenValue::SETP |
+            TokenValue::SETNP => Some(1),
+            // the size of the source can not be told from the destination
+            TokenValue::MOVSX | TokenValue::MOVZX => None,
+            // the count in `cl` has nothing to do with the size
+            TokenValue::SHL | TokenValue::SHR | TokenValue::SAR => None,
+            _ => register_size,
+        };
+
+        let mut position = i + 1;
+
+        for operand in &operands {
+            match operand {
+                [first, ..] if first.get_token_value() == TokenValue::LBRACK => {
+                    if size.is_none() {
+                        errors.push(format!("Syntax Error: {} Operation size not specified, use byte ptr, word ptr or \
+                                            dword ptr", operand[0].get_token_location().to_string()));
+                    }
+
+                    insertions.push((position, Some(size.unwrap_or(4))));
+                },
+                [first, second, ..] if second.get_token_value() == TokenValue::LBRACK &&
+                    matches!(first.get_token_value(), TokenValue::BYTE | TokenValue::WORD | TokenValue::DWORD) => {
+                    insertions.push((position + 1, None));
+                },
+                _ => {},
+            }
+
+            position += operand.len() + 1;
+        }
+
+        i = end;
+    }
+
+    // a size written without `ptr` only gets `ptr`
+    for (position, size) in insertions.into_iter().rev() {
+        let location = tokens[position].get_token_location();
+
+        tokens.insert(position, Token::new_token(TokenType::KEYWORD, TokenValue::PTR, location.to_owned(),
+                                                 "ptr".to_string()));
+
+        let (value, name) = match size {
+            Some(1) => (TokenValue::BYTE, "byte"),
+            Some(2) => (TokenValue::WORD, "word"),
+            Some(_) => (TokenValue::DWORD, "dword"),
+            None => continue,
+        };
+
+        tokens.insert(position, Token::new_token(TokenType::KEYWORD, value, location, name.to_string()));
+    }
+
+    errors
+}
+
+/// Parser from token stream to statements
+pub struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    /// position of the first token of the statement being parsed
+    start: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [Token]) -> Self {
+        Parser {
+            tokens,
+            position: 0,
+            start: 0,
+        }
+    }
+
+    /// Index of the next token to parse.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Skip the rest of the line where the last statement starts, so that parsing can go on after a syntax error.
+    pub fn skip_line(&mut self) {
+        let line = match self.tokens.get(self.start) {
+            Some(token) => token.get_token_location().get_line(),
+            None => return,
+        };
+
+        self.position = self.position.max(self.start + 1);
+
+        while let Some(token) = self.peek() {
+            if token.get_token_location().get_line() != line {
+                break;
+            }
+
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_value(&self) -> Option<TokenValue> {
+        self.peek().map(|token| token.get_token_value())
+    }
+
+    fn error(&self, msg: &str) -> String {
+        match self.peek().or_else(|| self.tokens.last()) {
+            Some(token) => format!("Syntax Error: {} {}", token.get_token_location().to_string(), msg),
+            None => format!("Syntax Error: {}", msg),
+        }
+    }
+
+    fn unexpected(&self) -> String {
+        match self.peek() {
+            Some(token) => self.error(&format!("Unexpected token: {}", token.get_token_name())),
+            None => self.error("Unexpected end of file"),
+        }
+    }
+
+    fn expect_value(&mut self, token_value: TokenValue, token_name: &str) -> Result<(), String> {
+        if self.peek_value() != Some(token_value) {
+            let found = self.peek().map(|token| token.get_token_name()).unwrap_or_else(|| "END_OF_FILE".to_string());
+            return Err(self.error(&format!("Expected \"{}\", but find \"{}\"", token_name, found)));
+        }
+
+        self.position += 1;
+        Ok(())
+    }
+
+    /// Parse all statements of the token stream.
+    pub fn parse_program(&mut self) -> Result<Vec<Statement>, String> {
+        let mut statements = Vec::new();
+
+        while let Some(statement) = self.parse_statement()? {
+            statements.push(statement);
+        }
+
+        Ok(statements)
+    }
+
+    /// Parse the next statement, `None` at the end of token stream.
+    pub fn parse_statement(&mut self) -> Result<Option<Statement>, String> {
+        self.start = self.position;
+
+        let token = match self.peek() {
+            Some(token) => token.to_owned(),
+            None => return Ok(None),
+        };
+
+        match token.get_token_type() {
+            TokenType::END_OF_FILE => Ok(None),
+            TokenType::LABEL => {
+                self.position += 1;
+                self.expect_value(TokenValue::COLON, ":")?;
+                Ok(Some(Statement::Label(token.get_token_name(), token.get_token_location())))
+            },
+            TokenType::INSTRUCTION => Ok(Some(Statement::Instruction(self.parse_instruction()?))),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn parse_instruction(&mut self) -> Result<Instruction, String> {
+        let token = self.tokens[self.position].to_owned();
+        self.position += 1;
+
+        let mut operands = Vec::new();
+
+        match token.get_token_value() {
+            // the operands of `enter` may be left out for `enter 0, 0`
+            TokenValue::ENTER if self.peek().map(|token| token.get_token_type()) !=
+                Some(TokenType::IMMEDIATE_DATA) => {},
+            // the operand of `nop` only pads it, the instruction may be bare
+            TokenValue::NOP if self.peek().map(|token| token.get_token_type()) != Some(TokenType::REGISTER) &&
+                !matches!(self.peek_value(), Some(TokenValue::BYTE | TokenValue::WORD | TokenValue::DWORD)) => {},
+            TokenValue::RET | TokenValue::LEAVE | TokenValue::IRET | TokenValue::PUSHAD |
+                TokenValue::POPAD | TokenValue::PUSHFD | TokenValue::POPFD | TokenValue::LAHF | TokenValue::SAHF |
+                TokenValue::STC | TokenValue::CLC | TokenValue::CMC | TokenValue::STD | TokenValue::CLD |
+                TokenValue::HLT | TokenValue::DAA | TokenValue::DAS | TokenValue::AAA | TokenValue::AAS |
+                TokenValue::INTO | TokenValue::CPUID | TokenValue::DATA | TokenValue::TEXT => {},
+            _ => {
+                operands.push(self.parse_operand()?);
+
+                while self.peek_value() == Some(TokenValue::COMMA) {
+                    self.position += 1;
+                    operands.push(self.parse_operand()?);
+                }
+            },
+        }
+
+        Ok(Instruction {
+            mnemonic: token.get_token_value(),
+            name: token.get_token_name().to_lowercase(),
+            operands,
+            location: token.get_token_location(),
+        })
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, String> {
+        let token = match self.peek() {
+            Some(token) => token.to_owned(),
+            None => return Err(self.unexpected()),
+        };
+
+        match token.get_token_value() {
+            TokenValue::BYTE | TokenValue::WORD | TokenValue::DWORD => {
+                return Ok(Operand::Memory(self.parse_memory()?));
+            },
+            TokenValue::MINUS | TokenValue::LPAREN => return self.parse_immediate(),
+            _ => {},
+        }
+
+        match token.get_token_type() {
+            TokenType::REGISTER => {
+                self.position += 1;
+                Ok(Operand::Register(token.get_token_value()))
+            },
+            TokenType::IMMEDIATE_DATA => self.parse_immediate(),
+            TokenType::LABEL => {
+                self.position += 1;
+                Ok(Operand::Label(token.get_token_name()))
+            },
+            TokenType::STRING => {
+                self.position += 1;
+                Ok(Operand::String(token.get_token_name()))
+            },
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Parse immediate data, which is a constant expression, such as `3 * 4 + 1` or `(1 << 8) - 1`.
+    fn parse_immediate(&mut self) -> Result<Operand, String> {
+        let location = self.tokens[self.position].get_token_location().to_string();
+        let linear = self.parse_expression()?;
+
+        if !linear.is_constant() {
+            return Err(format!("Syntax Error: {} Immediate data must be a constant expression, without registers or \
+                                labels", location));
+        }
+
+        Ok(Operand::Immediate(linear.constant))
+    }
+
+    fn parse_memory(&mut self) -> Result<Memory, String> {
+        let size = match self.peek_value() {
+            Some(TokenValue::BYTE) => 1,
+            Some(TokenValue::WORD) => 2,
+            _ => 4,
+        };
+
+        self.position += 1;
+        self.expect_value(TokenValue::PTR, "ptr")?;
+        self.expect_value(TokenValue::LBRACK, "[")?;
+
+        let location = self.position;
+        let linear = self.parse_expression()?;
+        let memory = self.to_memory(size, linear, location)?;
+
+        self.expect_value(TokenValue::RBRACK, "]")?;
+
+        Ok(memory)
+    }
+
+    /// expression := sum (('<<' | '>>') sum)*, where the count of a shift is a constant
+    fn parse_expression(&mut self) -> Result<Linear, String> {
+        let mut lhs = self.parse_sum()?;
+
+        loop {
+            let left = match self.peek_value() {
+                Some(TokenValue::LSHIFT) => true,
+                Some(TokenValue::RSHIFT) => false,
+                _ => return Ok(lhs),
+            };
+
+            let location = self.tokens[self.position].get_token_location().to_string();
+            self.position += 1;
+            let rhs = self.parse_sum()?;
+
+            if !rhs.is_constant() {
+                return Err(format!("Syntax Error: {} The count of a shift must be a constant", location));
+            }
+
+            if !(0..64).contains(&rhs.constant) {
+                return Err(format!("Syntax Error: {} The count of the shift is {}, but it must be from 0 to 63",
+                                   location, rhs.constant));
+            }
+
+            // a register shifted left is scaled, as `ebx << 2` is `ebx * 4`
+            lhs = match (lhs.is_constant(), left) {
+                (true, true) => Linear { constant: lhs.constant << rhs.constant, ..lhs },
+                (true, false) => Linear { constant: lhs.constant >> rhs.constant, ..lhs },
+                (false, true) if rhs.constant < 32 => lhs.scale(1 << rhs.constant),
+                _ => return Err(format!("Syntax Error: {} Only constants can be shifted right, or by more than 31, \
+                                         not registers or labels", location)),
+            };
+        }
+    }
+
+    /// sum := term (('+' | '-') term)*
+    fn parse_sum(&mut self) -> Result<Linear, String> {
+        let mut lhs = self.parse_term()?;
+
+        loop {
+            let sign = match self.peek_value() {
+                Some(TokenValue::PLUS) => 1,
+                Some(TokenValue::MINUS) => -1,
+                _ => return Ok(lhs),
+            };
+
+            self.position += 1;
+            let rhs = self.parse_term()?;
+            lhs = lhs.add(rhs, sign);
+        }
+    }
+
+    /// term := factor (('*' | '/' | '%') factor)*, where only constants are divided
+    fn parse_term(&mut self) -> Result<Linear, String> {
+        let mut lhs = self.parse_factor()?;
+
+        while let Some(operation @ (TokenValue::TIMES | TokenValue::SLASH | TokenValue::PERCENT)) = self.peek_value() {
+            let location = self.tokens[self.position].get_token_location().to_string();
+            self.position += 1;
+            let rhs = self.parse_factor()?;
+
+            lhs = match operation {
+                TokenValue::TIMES if lhs.is_constant() => rhs.scale(lhs.constant),
+                TokenValue::TIMES if rhs.is_constant() => lhs.scale(rhs.constant),
+                TokenValue::TIMES => {
+                    return Err(self.error("Two registers or labels can not be multiplied in address expression"));
+                },
+                _ if !lhs.is_constant() || !rhs.is_constant() => {
+                    return Err(format!("Syntax Error: {} Only constants can be divided, not registers or labels",
+                                       location));
+                },
+                _ if rhs.constant == 0 => {
+                    return Err(format!("Syntax Error: {} Division by zero in expression", location));
+                },
+                TokenValue::SLASH => Linear { constant: lhs.constant.wrapping_div(rhs.constant), ..lhs },
+                _ => Linear { constant: lhs.constant.wrapping_rem(rhs.constant), ..lhs },
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    /// factor := '-' factor | '(' expression ')' | register | immediate data | label
+    fn parse_factor(&mut self) -> Result<Linear, String> {
+        if self.peek_value() == Some(TokenValue::MINUS) {
+            self.position += 1;
+            return Ok(self.parse_factor()?.scale(-1));
+        }
+
+        if self.peek_value() == Some(TokenValue::LPAREN) {
+            self.position += 1;
+            let linear = self.parse_expression()?;
+            self.expect_value(TokenValue::RPAREN, ")")?;
+            return Ok(linear);
+        }
+
+        let token = match self.peek() {
+            Some(token) => token.to_owned(),
+            None => return Err(self.unexpected()),
+        };
+
+        match token.get_token_type() {
+            TokenType::REGISTER => {
+                if register_info(token.get_token_value()).map(|(_, size)| size) != Some(4) {
+                    return Err(self.error(&format!("Only 32-bits register can be used in address: {}",
+                                    token.get_token_name())));
+                }
+
+                self.position += 1;
+                Ok(Linear { registers: vec![(token.get_token_value(), 1)], labels: Vec::new(), constant: 0 })
+            },
+            TokenType::IMMEDIATE_DATA => {
+                self.position += 1;
+                Ok(Linear { registers: Vec::new(), labels: Vec::new(), constant: token.get_int_value() as i64 })
+            },
+            TokenType::LABEL => {
+                self.position += 1;
+                Ok(Linear { registers: Vec::new(), labels: vec![(token.get_token_name(), 1)], constant: 0 })
+            },
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Normalize the address expression into `[base + index * scale + displacement]`.
+    fn to_memory(&self, size: usize, linear: Linear, location: usize) -> Result<Memory, String> {
+        let error = |msg: &str| {
+            format!("Syntax Error: {} {}", self.tokens[location].get_token_location().to_string(), msg)
+        };
+
+        let mut base = None;
+        let mut index = None;
+
+        for (register, coefficient) in linear.registers.into_iter().filter(|(_, c)| *c != 0) {
+            match coefficient {
+                1 if base.is_none() => base = Some(register),
+                1 | 2 | 4 | 8 if index.is_none() => index = Some((register, coefficient as u32)),
+                _ => return Err(error("Address expression can not be encoded as base + index * scale")),
+            }
+        }
+
+        // `esp` can not be an index register, swap it with the base when possible.
+        if let Some((TokenValue::ESP, 1)) = index {
+            index = base.map(|register| (register, 1));
+            base = Some(TokenValue::ESP);
+        }
+
+        if let Some((TokenValue::ESP, _)) = index {
+            return Err(error("\"esp\" can not be used as index register"));
+        }
+
+        if linear.constant < i32::MIN as i64 || linear.constant > u32::MAX as i64 {
+            return Err(error("Displacement is out of range"));
+        }
+
+        let mut labels = linear.labels.into_iter().filter(|(_, c)| *c != 0);
+
+        let label = match (labels.next(), labels.next()) {
+            (None, _) => None,
+            (Some((label, 1)), None) => Some(label),
+            _ => return Err(error("A label in an address can only be added once, not subtracted or multiplied")),
+        };
+
+        Ok(Memory {
+            size,
+            base,
+            index,
+            displacement: linear.constant as i32,
+            label,
+        })
+    }
+}
@@ -0,0 +1,742 @@
+//! Instruction set of the virtual machine, a table of every instruction it executes with its operand forms, the flags
+//! it writes and how it differs from x86.
+//!
+//! The table is what `asm-vm explain` prints, and what `--strict` validates the operands against, see
+//! [`crate::validate::check_instruction`], so an operand form is accepted only if it is listed here.
+
+use crate::assembler::fits_size;
+use crate::instruction::*;
+use crate::json::*;
+use crate::scanner::instruction_names;
+use crate::token::*;
+use std::fmt;
+use self::OperandKind::*;
+
+/// Kind of operand an operand form takes, with its size in bytes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OperandKind {
+    Register(usize),
+    Memory(usize),
+    RegisterOrMemory(usize),
+    /// immediate data which fits in the size as either signed or unsigned integer
+    Immediate(usize),
+    /// immediate data from 0 up to the largest unsigned integer of the size
+    UnsignedImmediate(usize),
+    Label,
+    StringLiteral,
+    /// item of a data definition, immediate data which fits in the size, a string, or a label if the size is 32 bits
+    Data(usize),
+}
+
+impl OperandKind {
+    /// Whether the operand is of this kind, an operand whose size is not known is 32 bits.
+    pub fn matches(&self, operand: &Operand) -> bool {
+        let size = operand.size().unwrap_or(4);
+
+        match (self, operand) {
+            (OperandKind::Register(expected), Operand::Register(_)) |
+                (OperandKind::Memory(expected), Operand::Memory(_)) |
+                (OperandKind::RegisterOrMemory(expected), Operand::Register(_) | Operand::Memory(_)) => {
+                size == *expected
+            },
+            (OperandKind::Immediate(size), Operand::Immediate(value)) => fits_size(*value, *size),
+            (OperandKind::UnsignedImmediate(size), Operand::Immediate(value)) => {
+                *value >= 0 && (*size >= 8 || *value >> (size * 8) == 0)
+            },
+            (OperandKind::Label, Operand::Label(_)) => true,
+            (OperandKind::StringLiteral, Operand::String(_)) => true,
+            (OperandKind::Data(size), Operand::Immediate(value)) => fits_size(*value, *size),
+            (OperandKind::Data(size), Operand::Label(_)) => *size == 4,
+            (OperandKind::Data(_), Operand::String(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for OperandKind {
+    /// Format the kind as in the manuals of Intel, such as `r/m32`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OperandKind::Register(size) => write!(f, "r{}", size * 8),
+            OperandKind::Memory(size) => write!(f, "m{}", size * 8),
+            OperandKind::RegisterOrMemory(size) => write!(f, "r/m{}", size * 8),
+            OperandKind::Immediate(size) => write!(f, "imm{}", size * 8),
+            OperandKind::UnsignedImmediate(size) if *size >= 8 => write!(f, "imm"),
+            OperandKind::UnsignedImmediate(size) => write!(f, "imm{}", size * 8),
+            OperandKind::Label => write!(f, "label"),
+            OperandKind::StringLiteral => write!(f, "string"),
+            OperandKind::Data(4) => write!(f, "imm32/label/string"),
+            OperandKind::Data(size) => write!(f, "imm{}/string", size * 8),
+        }
+    }
+}
+
+/// One instruction of the table
+pub struct InstructionInfo {
+    pub mnemonic: TokenValue,
+    /// name of the instruction, the other names are aliases, such as `jz` of `je`
+    pub name: &'static str,
+    pub summary: &'static str,
+    /// operand forms, one of which the operands must match
+    pub forms: &'static [&'static [OperandKind]],
+    /// whether the last operand of a form may be repeated, as in `global a, b`
+    pub repeats: bool,
+    /// flags the instruction writes, of `cf`, `zf`, `sf`, `of` and `if`
+    pub flags: &'static [&'static str],
+    /// how the virtual machine differs from x86, or what it adds to it
+    pub notes: &'static str,
+}
+
+impl InstructionInfo {
+    /// Get the names of the instruction, its own name first, then its aliases.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec![self.name.to_string()];
+        names.extend(instruction_names(self.mnemonic).into_iter().filter(|name| name != self.name));
+        names
+    }
+
+    /// Whether the operands match one of the forms.
+    pub fn matches(&self, operands: &[Operand]) -> bool {
+        self.forms.iter().any(|form| match form.split_last() {
+            Some((last, _)) if self.repeats && operands.len() >= form.len() => {
+                form.iter().zip(operands).all(|(kind, operand)| kind.matches(operand)) &&
+                    operands[form.len()..].iter().all(|operand| last.matches(operand))
+            },
+            _ => form.len() == operands.len() && form.iter().zip(operands).all(|(kind, operand)| kind.matches(operand)),
+        })
+    }
+
+    /// Get the numbers of operands the forms take, in increasing order. If the last operand repeats, any number from
+    /// the least one is taken.
+    pub fn operand_counts(&self) -> Vec<usize> {
+        let mut counts: Vec<usize> = self.forms.iter().map(|form| form.len()).collect();
+        counts.sort_unstable();
+        counts.dedup();
+        counts
+    }
+
+    /// Format a form with the name of the instruction, such as `mov r/m32, imm32`.
+    pub fn format_form(&self, form: &[OperandKind]) -> String {
+        let operands: Vec<String> = form.iter().map(|kind| kind.to_string()).collect();
+        let repeat = if self.repeats { ", ..." } else { "" };
+
+        if operands.is_empty() {
+            self.name.to_string()
+        } else {
+            format!("{} {}{}", self.name, operands.join(", "), repeat)
+        }
+    }
+
+    /// Describe the instruction for `asm-vm explain`, with its names, forms, flags and notes.
+    pub fn explain(&self) -> String {
+        let mut text = format!("{}: {}\n", self.names().join(", "), self.summary);
+
+        text.push_str("\nForms:\n");
+
+        for form in self.forms {
+            text.push_str(&format!("    {}\n", self.format_form(form)));
+        }
+
+        let flags = if self.flags.is_empty() { "none".to_string() } else { self.flags.join(" ") };
+        text.push_str(&format!("\nFlags: {}\n", flags));
+
+        if !self.notes.is_empty() {
+            text.push_str(&format!("\nNotes: {}\n", self.notes));
+        }
+
+        text
+    }
+
+    /// Get the instruction as JSON for tools, with its `names`, `summary`, `forms`, `flags` and `notes`.
+    pub fn to_json(&self) -> Json {
+        let forms = self.forms.iter()
+            .map(|form| Json::Array(form.iter().map(|kind| kind.to_string().into()).collect()))
+            .collect();
+
+        Json::object(vec![
+            ("names", Json::Array(self.names().into_iter().map(|name| name.into()).collect())),
+            ("summary", self.summary.into()),
+            ("forms", Json::Array(forms)),
+            ("repeats", self.repeats.into()),
+            ("flags", Json::Array(self.flags.iter().map(|flag| (*flag).into()).collect())),
+            ("notes", self.notes.into()),
+        ])
+    }
+}
+
+const ARITHMETIC_FLAGS: &[&str] = &["cf", "zf", "sf", "of", "pf", "af"];
+
+/// flags of the bitwise instructions and shifts, which leave `af` unchanged
+const LOGIC_FLAGS: &[&str] = &["cf", "zf", "sf", "of", "pf"];
+
+/// forms of `mov`, `add`, `sub`, `and`, `or`, `xor`, `cmp` and `test`
+const BINARY: &[&[OperandKind]] = &[
+    &[RegisterOrMemory(1), Register(1)], &[RegisterOrMemory(2), Register(2)], &[RegisterOrMemory(4), Register(4)],
+    &[Register(1), Memory(1)], &[Register(2), Memory(2)], &[Register(4), Memory(4)],
+    &[RegisterOrMemory(1), Immediate(1)], &[RegisterOrMemory(2), Immediate(2)], &[RegisterOrMemory(4), Immediate(4)],
+    &[RegisterOrMemory(4), Label],
+];
+
+/// forms of `movsx` and `movzx`
+const EXTEND: &[&[OperandKind]] = &[
+    &[Register(2), RegisterOrMemory(1)], &[Register(4), RegisterOrMemory(1)], &[Register(4), RegisterOrMemory(2)],
+];
+
+/// forms of the instructions of one operand, such as `inc`
+const UNARY: &[&[OperandKind]] = &[&[RegisterOrMemory(1)], &[RegisterOrMemory(2)], &[RegisterOrMemory(4)]];
+
+/// forms of `imul`
+const IMUL: &[&[OperandKind]] = &[
+    &[RegisterOrMemory(1)], &[RegisterOrMemory(2)], &[RegisterOrMemory(4)],
+    &[Register(2), RegisterOrMemory(2)], &[Register(4), RegisterOrMemory(4)],
+    &[Register(2), RegisterOrMemory(2), Immediate(2)], &[Register(4), RegisterOrMemory(4), Immediate(4)],
+];
+
+/// forms of `in`
+const IN: &[&[OperandKind]] = &[
+    &[Register(1), UnsignedImmediate(1)], &[Register(2), UnsignedImmediate(1)], &[Register(4), UnsignedImmediate(1)],
+    &[Register(1), Register(2)], &[Register(2), Register(2)], &[Register(4), Register(2)],
+];
+
+/// forms of `out`
+const OUT: &[&[OperandKind]] = &[
+    &[UnsignedImmediate(1), Register(1)], &[UnsignedImmediate(1), Register(2)], &[UnsignedImmediate(1), Register(4)],
+    &[Register(2), Register(1)], &[Register(2), Register(2)], &[Register(2), Register(4)],
+];
+
+/// forms of `bsf` and `bsr`
+const BIT_SCAN: &[&[OperandKind]] = &[&[Register(2), RegisterOrMemory(2)], &[Register(4), RegisterOrMemory(4)]];
+
+/// forms of `shl`, `shr` and `sar`
+const SHIFT: &[&[OperandKind]] = &[
+    &[RegisterOrMemory(1), UnsignedImmediate(1)], &[RegisterOrMemory(2), UnsignedImmediate(1)],
+    &[RegisterOrMemory(4), UnsignedImmediate(1)],
+];
+
+/// forms of `call` and jumps
+const TARGET: &[&[OperandKind]] = &[&[Label]];
+
+/// forms of `setcc`
+const SET: &[&[OperandKind]] = &[&[RegisterOrMemory(1)]];
+
+const NO_OPERANDS: &[&[OperandKind]] = &[&[]];
+
+const JUMP_NOTES: &str = "The target must be a label.";
+
+const PARITY_NOTES: &str = "The target must be a label. pf is set if the low byte of the last result has an even \
+                            number of set bits.";
+
+const SHORT_JUMP_NOTES: &str = "The target must be a label, which x86 encodes within -128 to 127 bytes of the next \
+                                instruction.";
+
+const SET_NOTES: &str = "The byte is 1 if the condition holds, or 0 if it does not.";
+
+const BIT_SCAN_NOTES: &str = "zf is set and the destination is kept if the source is 0, and cleared otherwise. \
+                              The other flags are kept, which x86 leaves undefined.";
+
+const SHIFT_NOTES: &str = "The count must be immediate data, shifting by cl is not supported.";
+
+/// Every instruction the virtual machine executes, and the directives of the assembler
+pub const INSTRUCTIONS: &[InstructionInfo] = &[
+    InstructionInfo {
+        mnemonic: TokenValue::MOV, name: "mov", summary: "Move the source to the destination", forms: BINARY,
+        repeats: false, flags: &[],
+        notes: "Moving from memory to memory runs, but has no x86 encoding, so --strict rejects it.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::MOVSX, name: "movsx",
+        summary: "Move the source extended with its sign to the larger destination", forms: EXTEND, repeats: false,
+        flags: &[], notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::MOVZX, name: "movzx",
+        summary: "Move the source extended with zeros to the larger destination", forms: EXTEND, repeats: false,
+        flags: &[], notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::LEA, name: "lea", summary: "Load the address of the source into the destination",
+        forms: &[&[Register(2), Memory(2)], &[Register(4), Memory(4)]], repeats: false, flags: &[],
+        notes: "The memory is not read, so the address may be outside the stack.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::ADD, name: "add", summary: "Add the source to the destination", forms: BINARY,
+        repeats: false, flags: ARITHMETIC_FLAGS, notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SUB, name: "sub", summary: "Subtract the source from the destination", forms: BINARY,
+        repeats: false, flags: ARITHMETIC_FLAGS, notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::ADC, name: "adc", summary: "Add the source and cf to the destination", forms: BINARY,
+        repeats: false, flags: ARITHMETIC_FLAGS, notes: "Adds the upper parts of numbers wider than a register.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SBB, name: "sbb", summary: "Subtract the source and cf from the destination",
+        forms: BINARY, repeats: false, flags: ARITHMETIC_FLAGS,
+        notes: "Subtracts the upper parts of numbers wider than a register.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::CMP, name: "cmp",
+        summary: "Compare by subtracting the source from the destination, keeping both", forms: BINARY, repeats: false,
+        flags: ARITHMETIC_FLAGS, notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::AND, name: "and", summary: "Bitwise and of the destination and the source", forms: BINARY,
+        repeats: false, flags: LOGIC_FLAGS, notes: "cf and of are cleared.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::TEST, name: "test",
+        summary: "Compare by a bitwise and of the destination and the source, keeping both", forms: BINARY,
+        repeats: false, flags: LOGIC_FLAGS, notes: "cf and of are cleared.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::OR, name: "or", summary: "Bitwise or of the destination and the source", forms: BINARY,
+        repeats: false, flags: LOGIC_FLAGS, notes: "cf and of are cleared.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::XOR, name: "xor", summary: "Bitwise exclusive or of the destination and the source",
+        forms: BINARY, repeats: false, flags: LOGIC_FLAGS, notes: "cf and of are cleared.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::INC, name: "inc", summary: "Add 1 to the operand", forms: UNARY, repeats: false,
+        flags: &["zf", "sf", "of", "pf", "af"], notes: "cf is unchanged.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::DEC, name: "dec", summary: "Subtract 1 from the operand", forms: UNARY, repeats: false,
+        flags: &["zf", "sf", "of", "pf", "af"], notes: "cf is unchanged.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::NOT, name: "not", summary: "Invert every bit of the operand", forms: UNARY,
+        repeats: false, flags: &[], notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::NEG, name: "neg", summary: "Negate the operand in two's complement", forms: UNARY,
+        repeats: false, flags: ARITHMETIC_FLAGS, notes: "cf is set unless the operand is 0.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::MUL, name: "mul",
+        summary: "Multiply al, ax or eax by the unsigned operand into ax, dx:ax or edx:eax", forms: UNARY,
+        repeats: false, flags: &["cf", "of"],
+        notes: "cf and of are set if the upper half of the product is not 0. zf, sf and pf are also set from the \
+                product, which x86 leaves undefined.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::IMUL, name: "imul", summary: "Multiply signed integers into the destination",
+        forms: IMUL, repeats: false, flags: &["cf", "of"],
+        notes: "The form of one operand multiplies al, ax or eax into ax, dx:ax or edx:eax. cf and of are set if the \
+                signed product does not fit in the operand size, and the other flags are kept, which x86 leaves \
+                undefined.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::DIV, name: "div",
+        summary: "Divide ax, dx:ax or edx:eax by the unsigned operand into the quotient in al, ax or eax and the \
+                  remainder in ah, dx or edx",
+        forms: UNARY, repeats: false, flags: &[],
+        notes: "Division by zero, or a quotient which does not fit in the operand size, stops the program.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::IDIV, name: "idiv",
+        summary: "Divide ax, dx:ax or edx:eax by the signed operand into the quotient in al, ax or eax and the \
+                  remainder in ah, dx or edx",
+        forms: UNARY, repeats: false, flags: &[],
+        notes: "The quotient is truncated toward zero and the remainder has the sign of the dividend. Division by \
+                zero, or a quotient which does not fit in the operand size, stops the program.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::BSF, name: "bsf", summary: "Find the index of the lowest set bit of the source",
+        forms: BIT_SCAN, repeats: false, flags: &["zf"], notes: BIT_SCAN_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::BSR, name: "bsr", summary: "Find the index of the highest set bit of the source",
+        forms: BIT_SCAN, repeats: false, flags: &["zf"], notes: BIT_SCAN_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SHL, name: "shl", summary: "Shift the destination left by the count", forms: SHIFT,
+        repeats: false, flags: LOGIC_FLAGS, notes: SHIFT_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SHR, name: "shr", summary: "Shift the destination right by the count, filling with zeros",
+        forms: SHIFT, repeats: false, flags: LOGIC_FLAGS, notes: SHIFT_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SAR, name: "sar", summary: "Shift the destination right by the count, keeping the sign",
+        forms: SHIFT, repeats: false, flags: LOGIC_FLAGS, notes: SHIFT_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::PUSH, name: "push", summary: "Push the operand onto the stack",
+        forms: &[&[RegisterOrMemory(2)], &[RegisterOrMemory(4)], &[Immediate(4)], &[Label]], repeats: false, flags: &[],
+        notes: "A list of operands, as in push eax, ebx, is one push for each operand in the order they are written. \
+                A 16-bits operand moves esp by 2, and immediate data is pushed as 32 bits.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::POP, name: "pop", summary: "Pop the top of the stack into the operand",
+        forms: &[&[RegisterOrMemory(2)], &[RegisterOrMemory(4)]], repeats: false, flags: &[],
+        notes: "A list of operands, as in pop ebx, eax, is one pop for each operand in the order they are written. \
+                A 16-bits operand moves esp by 2, and memory is addressed after esp is moved, as in pop dword ptr \
+                [esp].",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::PUSHAD, name: "pushad",
+        summary: "Push eax, ecx, edx, ebx, esp as it was before, ebp, esi and edi", forms: NO_OPERANDS, repeats: false,
+        flags: &[], notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::POPAD, name: "popad",
+        summary: "Pop edi, esi, ebp, ebx, edx, ecx and eax pushed by pushad", forms: NO_OPERANDS, repeats: false,
+        flags: &[], notes: "The value pushed for esp is skipped.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::PUSHFD, name: "pushfd", summary: "Push the flags as the bits of eflags",
+        forms: NO_OPERANDS, repeats: false, flags: &[],
+        notes: "cf is bit 0, pf bit 2, af bit 4, zf bit 6, sf bit 7, if bit 9, df bit 10 and of bit 11, bit 1 is \
+                always set and the others are clear.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::POPFD, name: "popfd", summary: "Pop the bits of eflags into the flags",
+        forms: NO_OPERANDS, repeats: false, flags: &["cf", "zf", "sf", "of", "pf", "af", "if", "df"],
+        notes: "Only the bits of cf, pf, af, zf, sf, if, df and of are used, see pushfd.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::STC, name: "stc", summary: "Set cf", forms: NO_OPERANDS, repeats: false, flags: &["cf"],
+        notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::CLC, name: "clc", summary: "Clear cf", forms: NO_OPERANDS, repeats: false,
+        flags: &["cf"], notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::CMC, name: "cmc", summary: "Complement cf", forms: NO_OPERANDS, repeats: false,
+        flags: &["cf"], notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::STD, name: "std", summary: "Set df, so that string instructions step down",
+        forms: NO_OPERANDS, repeats: false, flags: &["df"], notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::CLD, name: "cld", summary: "Clear df, so that string instructions step up",
+        forms: NO_OPERANDS, repeats: false, flags: &["df"], notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::LAHF, name: "lahf", summary: "Load the low byte of eflags into ah", forms: NO_OPERANDS,
+        repeats: false, flags: &[],
+        notes: "cf is bit 0, pf bit 2, af bit 4, zf bit 6 and sf bit 7, bit 1 is always set and the others are \
+                clear.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SAHF, name: "sahf", summary: "Store ah into the low byte of eflags", forms: NO_OPERANDS,
+        repeats: false, flags: &["cf", "zf", "sf", "pf", "af"],
+        notes: "Only the bits of cf, pf, af, zf and sf are used, see lahf.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::DAA, name: "daa", summary: "Adjust al after adding packed BCD bytes", forms: NO_OPERANDS,
+        repeats: false, flags: &["cf", "zf", "sf", "pf", "af"],
+        notes: "cf is set on a decimal carry out of the two digits, and of is unchanged, which x86 leaves undefined.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::DAS, name: "das", summary: "Adjust al after subtracting packed BCD bytes",
+        forms: NO_OPERANDS, repeats: false, flags: &["cf", "zf", "sf", "pf", "af"],
+        notes: "cf is set on a decimal borrow out of the two digits, and of is unchanged, which x86 leaves undefined.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::AAA, name: "aaa", summary: "Adjust al after adding unpacked BCD digits, carrying into ah",
+        forms: NO_OPERANDS, repeats: false, flags: &["cf", "af"],
+        notes: "The high half of al is cleared. zf, sf, of and pf are unchanged, which x86 leaves undefined.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::AAS, name: "aas",
+        summary: "Adjust al after subtracting unpacked BCD digits, borrowing from ah", forms: NO_OPERANDS,
+        repeats: false, flags: &["cf", "af"],
+        notes: "The high half of al is cleared. zf, sf, of and pf are unchanged, which x86 leaves undefined.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JMP, name: "jmp", summary: "Jump to the target", forms: TARGET, repeats: false,
+        flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JE, name: "je", summary: "Jump if equal, zf = 1", forms: TARGET, repeats: false,
+        flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JNE, name: "jne", summary: "Jump if not equal, zf = 0", forms: TARGET, repeats: false,
+        flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JG, name: "jg", summary: "Jump if greater, signed, zf = 0 and sf = of", forms: TARGET,
+        repeats: false, flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JGE, name: "jge", summary: "Jump if greater or equal, signed, sf = of", forms: TARGET,
+        repeats: false, flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JL, name: "jl", summary: "Jump if less, signed, sf != of", forms: TARGET, repeats: false,
+        flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JLE, name: "jle", summary: "Jump if less or equal, signed, zf = 1 or sf != of",
+        forms: TARGET, repeats: false, flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JA, name: "ja", summary: "Jump if above, unsigned, cf = 0 and zf = 0", forms: TARGET,
+        repeats: false, flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JAE, name: "jae", summary: "Jump if above or equal, unsigned, cf = 0", forms: TARGET,
+        repeats: false, flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JB, name: "jb", summary: "Jump if below, unsigned, cf = 1", forms: TARGET, repeats: false,
+        flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JBE, name: "jbe", summary: "Jump if below or equal, unsigned, cf = 1 or zf = 1",
+        forms: TARGET, repeats: false, flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JO, name: "jo", summary: "Jump if overflow, of = 1", forms: TARGET, repeats: false,
+        flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JNO, name: "jno", summary: "Jump if not overflow, of = 0", forms: TARGET, repeats: false,
+        flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JS, name: "js", summary: "Jump if sign, sf = 1", forms: TARGET, repeats: false,
+        flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JNS, name: "jns", summary: "Jump if not sign, sf = 0", forms: TARGET, repeats: false,
+        flags: &[], notes: JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JP, name: "jp", summary: "Jump if parity even, pf = 1", forms: TARGET, repeats: false,
+        flags: &[], notes: PARITY_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JNP, name: "jnp", summary: "Jump if parity odd, pf = 0", forms: TARGET, repeats: false,
+        flags: &[], notes: PARITY_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JECXZ, name: "jecxz", summary: "Jump if ecx = 0", forms: TARGET, repeats: false,
+        flags: &[], notes: SHORT_JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::JCXZ, name: "jcxz", summary: "Jump if cx = 0", forms: TARGET, repeats: false, flags: &[],
+        notes: SHORT_JUMP_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETE, name: "sete", summary: "Set the byte if equal, zf = 1", forms: SET, repeats: false,
+        flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETNE, name: "setne", summary: "Set the byte if not equal, zf = 0", forms: SET,
+        repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETG, name: "setg", summary: "Set the byte if greater, signed, zf = 0 and sf = of",
+        forms: SET, repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETGE, name: "setge", summary: "Set the byte if greater or equal, signed, sf = of",
+        forms: SET, repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETL, name: "setl", summary: "Set the byte if less, signed, sf != of", forms: SET,
+        repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETLE, name: "setle",
+        summary: "Set the byte if less or equal, signed, zf = 1 or sf != of", forms: SET, repeats: false, flags: &[],
+        notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETA, name: "seta", summary: "Set the byte if above, unsigned, cf = 0 and zf = 0",
+        forms: SET, repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETAE, name: "setae", summary: "Set the byte if above or equal, unsigned, cf = 0",
+        forms: SET, repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETB, name: "setb", summary: "Set the byte if below, unsigned, cf = 1", forms: SET,
+        repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETBE, name: "setbe",
+        summary: "Set the byte if below or equal, unsigned, cf = 1 or zf = 1", forms: SET, repeats: false, flags: &[],
+        notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETO, name: "seto", summary: "Set the byte if overflow, of = 1", forms: SET,
+        repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETNO, name: "setno", summary: "Set the byte if not overflow, of = 0", forms: SET,
+        repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETS, name: "sets", summary: "Set the byte if sign, sf = 1", forms: SET, repeats: false,
+        flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETNS, name: "setns", summary: "Set the byte if not sign, sf = 0", forms: SET,
+        repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETP, name: "setp", summary: "Set the byte if parity even, pf = 1", forms: SET,
+        repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::SETNP, name: "setnp", summary: "Set the byte if parity odd, pf = 0", forms: SET,
+        repeats: false, flags: &[], notes: SET_NOTES,
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::CALL, name: "call", summary: "Push the return address and jump to the function",
+        forms: &[&[Label], &[RegisterOrMemory(4)]], repeats: false, flags: &[],
+        notes: "The target is a label, or an address held in a 32-bits register or memory, such as one loaded by \
+                mov eax, label.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::RET, name: "ret", summary: "Pop the return address and jump to it", forms: NO_OPERANDS,
+        repeats: false, flags: &[], notes: "Returning from the entry label finishes the program.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::ENTER, name: "enter", summary: "Make a stack frame, push ebp and mov ebp, esp",
+        forms: &[&[], &[UnsignedImmediate(2), UnsignedImmediate(1)]], repeats: false, flags: &[],
+        notes: "enter size, level also copies level - 1 frame pointers of the enclosing frames, pushes the new one if \
+                level is not 0, and allocates size bytes for the locals. Only the low 5 bits of level count, and \
+                enter without operands is enter 0, 0.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::LEAVE, name: "leave", summary: "Release the stack frame, mov esp, ebp and pop ebp",
+        forms: NO_OPERANDS, repeats: false, flags: &[], notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::INT, name: "int", summary: "Call the handler of the interrupt vector",
+        forms: &[&[UnsignedImmediate(1)]], repeats: false, flags: &["if"],
+        notes: "Without a handler installed by ivt, int 0x80 is a Linux system call, int 0x81 the random number \
+                service, int 0x82 the print service and int 0x83 the input service.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::INTO, name: "into", summary: "Raise the overflow exception if of is set",
+        forms: NO_OPERANDS, repeats: false, flags: &["if"],
+        notes: "The exception calls the handler ivt installed for vector 4, which returns to the instruction after \
+                into, or stops the program without one.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::CPUID, name: "cpuid", summary: "Load eax, ebx, ecx and edx with the leaf in eax",
+        forms: NO_OPERANDS, repeats: false, flags: &[],
+        notes: "The leaves are set by the host, leaf 0 has the vendor AsmVMVirtual and leaf 1 no feature by default. \
+                A leaf which is not set is all zeros, and the subleaf in ecx is ignored.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::BOUND, name: "bound",
+        summary: "Raise the bound range exception if the index is outside the bounds in memory",
+        forms: &[&[Register(2), Memory(2)], &[Register(4), Memory(4)]], repeats: false, flags: &["if"],
+        notes: "The memory holds the signed lower bound, then the upper bound, each of the size of the index, so \
+                capstone and MASM write it as dword ptr or qword ptr. The exception calls the handler ivt installed \
+                for vector 5, which returns to bound to check again, or stops the program without one.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::IVT, name: "ivt", summary: "Install the handler of an interrupt vector, which int calls",
+        forms: &[
+            &[UnsignedImmediate(1), Label],
+            &[UnsignedImmediate(1), Register(4)],
+            &[UnsignedImmediate(1), Immediate(4)],
+        ],
+        repeats: false, flags: &[], notes: "It is not an x86 instruction.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::IRET, name: "iret",
+        summary: "Return from an interrupt handler, popping the return address and the flags", forms: NO_OPERANDS,
+        repeats: false, flags: &["cf", "zf", "sf", "of", "pf", "af", "if", "df"], notes: "",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::IN, name: "in", summary: "Read the port into the accumulator", forms: IN, repeats: false,
+        flags: &[],
+        notes: "The register is al, ax or eax, and the port is immediate data from 0 to 255 or dx. A port without a \
+                device the host attached reads as all ones.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::OUT, name: "out", summary: "Write the accumulator to the port", forms: OUT,
+        repeats: false, flags: &[],
+        notes: "The port is immediate data from 0 to 255 or dx, and the register is al, ax or eax. What is written to \
+                a port without a device the host attached is dropped.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::HLT, name: "hlt", summary: "Stop the program", forms: NO_OPERANDS, repeats: false,
+        flags: &[], notes: "The program is finished and reported as halted, from any call depth.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::NOP, name: "nop", summary: "Do nothing",
+        forms: &[&[], &[RegisterOrMemory(2)], &[RegisterOrMemory(4)]], repeats: false, flags: &[],
+        notes: "The operand only makes the instruction longer for padding, memory is not accessed. Any other \
+                operands are ignored when the program runs, but they cannot be assembled.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::INCBIN, name: "incbin",
+        summary: "Include the bytes of a file, from the offset and up to the length",
+        forms: &[
+            &[StringLiteral],
+            &[StringLiteral, UnsignedImmediate(8)],
+            &[StringLiteral, UnsignedImmediate(8), UnsignedImmediate(8)],
+        ],
+        repeats: false, flags: &[], notes: "It is a directive of the assembler, executing it is an error.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::DB, name: "db", summary: "Define bytes", forms: &[&[Data(1)]], repeats: true,
+        flags: &[], notes: "It is a directive of the assembler, executing it is an error. A string is its bytes, \
+                            without a terminator, so a string terminated by a zero byte is written as db \"hi\", 0. A \
+                            label before it may be written without the colon, as in msg db 1, 2, 3.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::DW, name: "dw", summary: "Define words", forms: &[&[Data(2)]], repeats: true,
+        flags: &[],
+        notes: "It is a directive of the assembler, executing it is an error. A string is padded with zeros to a \
+                whole number of words.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::DD, name: "dd", summary: "Define double words", forms: &[&[Data(4)]], repeats: true,
+        flags: &[], notes: "It is a directive of the assembler, executing it is an error. A label is its address, \
+                            and a string is padded with zeros to a whole number of double words.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::DATA, name: ".data", summary: "Start the data section", forms: NO_OPERANDS,
+        repeats: false, flags: &[],
+        notes: "It is a directive of the assembler. The statements after it, up to `.text`, are placed after those of \
+                the code section.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::TEXT, name: ".text", summary: "Start the code section", forms: NO_OPERANDS,
+        repeats: false, flags: &[], notes: "It is a directive of the assembler, which is also written `.code`, as in \
+                                            MASM. The statements before any section are in the code section.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::ENDP, name: "endp", summary: "End the procedure of MASM named by the label",
+        forms: TARGET, repeats: false, flags: &[],
+        notes: "It is a directive of the assembler. `name proc` starts the procedure, which is the label `name`, and \
+                `name endp` ends it.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::ORG, name: "org", summary: "Set the address of the next instruction",
+        forms: &[&[Immediate(4)]], repeats: false, flags: &[], notes: "It is a directive of the assembler.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::GLOBAL, name: "global", summary: "Make labels visible to the other source files",
+        forms: TARGET, repeats: true, flags: &[], notes: "It is a directive of the assembler.",
+    },
+    InstructionInfo {
+        mnemonic: TokenValue::EXTERN, name: "extern", summary: "Use labels another source file makes visible",
+        forms: TARGET, repeats: true, flags: &[], notes: "It is a directive of the assembler.",
+    },
+];
+
+/// Get the instruction of a mnemonic from the table.
+pub fn instruction_info(mnemonic: TokenValue) -> Option<&'static InstructionInfo> {
+    INSTRUCTIONS.iter().find(|info| info.mnemonic == mnemonic)
+}
+
+/// Get the table as JSON for tools, an array of [`InstructionInfo::to_json`].
+pub fn to_json() -> Json {
+    Json::Array(INSTRUCTIONS.iter().map(|info| info.to_json()).collect())
+}
@@ -0,0 +1,43 @@
+//! Toy virtual machine for 32-bits assembly.
+//!
+//! Besides the `asm-vm` command, the virtual machine can be embedded as a Rust library, or from C and other languages
+//! through the C API in [`ffi`], which is declared in `include/asmvm.h`.
+
+pub mod vm;
+pub mod token;
+pub mod scanner;
+pub mod instruction;
+pub mod isa;
+pub mod assembler;
+pub mod batch;
+pub mod branch;
+pub mod cache;
+pub mod cfg;
+pub mod check;
+pub mod config;
+pub mod disassembler;
+pub mod elf;
+pub mod flags;
+pub mod format;
+pub mod ffi;
+pub mod grade;
+pub mod harness;
+pub mod ihex;
+pub mod json;
+pub mod link;
+pub mod lsp;
+pub mod macros;
+pub mod optimize;
+pub mod pipeline;
+pub mod policy;
+pub mod port;
+pub mod printf;
+pub mod random;
+pub mod selftest;
+pub mod server;
+pub mod toml;
+pub mod trace;
+pub mod validate;
+pub mod websocket;
+#[cfg(feature = "check-encoding")]
+pub mod check_encoding;
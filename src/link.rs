@@ -0,0 +1,131 @@
+//! Linking of several source files into one program, which checks the `global` and `extern` declarations:
+//!
+//! ```asm
+//! ; main.asm
+//!     extern square
+//! main:
+//!     mov eax, 7
+//!     call square
+//!     ret
+//!
+//! ; square.asm
+//!     global square
+//! square:
+//!     imul eax, eax
+//!     ret
+//! ```
+//!
+//! The labels of all source files share one label table, so a file without any declaration may use the labels of
+//! every other one, as before. A file with a declaration is a module: it may only use a label of another file if it
+//! declares it `extern`, and the other file may only be used through the labels it declares `global`.
+
+use crate::instruction::*;
+use crate::token::*;
+use std::collections::{HashMap, HashSet};
+
+/// Whether the instruction is a declaration of the visibility of labels, which is `global` or `extern`.
+pub fn is_declaration(mnemonic: TokenValue) -> bool {
+    matches!(mnemonic, TokenValue::GLOBAL | TokenValue::EXTERN)
+}
+
+/// Labels a source file declares
+#[derive(Default)]
+struct Module {
+    globals: HashSet<String>,
+    externs: HashSet<String>,
+}
+
+/// Check the declarations of the statements of several source files, which are told apart by the file names of
+/// their locations, returns an error for each problem in the order of the source.
+///
+/// # Example
+///
+/// ```no_run
+/// # use asm_vm::assembler::parse_files;
+/// # use asm_vm::link::link;
+/// # use asm_vm::scanner::SourceOptions;
+/// let names = vec!["main.asm".to_string(), "square.asm".to_string()];
+/// let statements = parse_files(names, &SourceOptions::default()).unwrap();
+/// assert!(link(&statements).is_empty());
+/// ```
+pub fn link(statements: &[Statement]) -> Vec<String> {
+    let mut definitions: HashMap<&str, String> = HashMap::new();
+    let mut modules: HashMap<String, Module> = HashMap::new();
+
+    for statement in statements {
+        match statement {
+            Statement::Label(name, location) => {
+                definitions.entry(name).or_insert_with(|| location.get_source_file_name());
+            },
+            Statement::Instruction(instruction) => {
+                let module = modules.entry(instruction.location.get_source_file_name()).or_default();
+
+                for operand in &instruction.operands {
+                    if let Operand::Label(name) = operand {
+                        match instruction.mnemonic {
+                            TokenValue::GLOBAL => module.globals.insert(name.to_owned()),
+                            TokenValue::EXTERN => module.externs.insert(name.to_owned()),
+                            _ => false,
+                        };
+                    }
+                }
+            },
+        }
+    }
+
+    let is_module = |file_name: &str| {
+        modules.get(file_name).is_some_and(|module| !module.globals.is_empty() || !module.externs.is_empty())
+    };
+    let is_global = |file_name: &str, name: &str| {
+        modules.get(file_name).is_some_and(|module| module.globals.contains(name))
+    };
+
+    let mut errors = Vec::new();
+
+    for statement in statements {
+        let instruction = match statement {
+            Statement::Instruction(instruction) => instruction,
+            Statement::Label(..) => continue,
+        };
+
+        let file_name = instruction.location.get_source_file_name();
+        let error = |msg: String| format!("Link Error: {} {}", instruction.location.to_string(), msg);
+
+        for operand in &instruction.operands {
+            let name = match operand {
+                Operand::Label(name) => name,
+                _ => continue,
+            };
+
+            let defined = definitions.get(name.as_str());
+
+            match (instruction.mnemonic, defined) {
+                (TokenValue::GLOBAL, Some(defined)) if *defined == file_name => {},
+                (TokenValue::GLOBAL, _) => {
+                    errors.push(error(format!("Global symbol \"{}\" is not defined in this file", name)));
+                },
+                (TokenValue::EXTERN, None) => errors.push(error(format!("Undefined external symbol: \"{}\"", name))),
+                (TokenValue::EXTERN, Some(defined)) if *defined == file_name => {
+                    errors.push(error(format!("External symbol \"{}\" is defined in this file", name)));
+                },
+                (TokenValue::EXTERN, Some(defined)) if !is_global(defined, name) => {
+                    errors.push(error(format!("Undefined external symbol: \"{}\", which {} defines without \
+                                               declaring it global", name, defined)));
+                },
+                (TokenValue::EXTERN, Some(_)) => {},
+                (_, Some(defined)) if *defined != file_name && (is_module(&file_name) || is_module(defined)) => {
+                    let externs = modules.get(&file_name).is_some_and(|module| module.externs.contains(name));
+
+                    if !externs {
+                        errors.push(error(format!("\"{}\" is defined in {}, declare it with extern", name, defined)));
+                    } else if !is_global(defined, name) {
+                        errors.push(error(format!("\"{}\" is not declared global in {}", name, defined)));
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    errors
+}
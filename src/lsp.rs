@@ -0,0 +1,402 @@
+//! Language server of the assembly dialect, speaking the Language Server Protocol over the standard input and output.
+//!
+//! It publishes the errors of the scanner, the parser and the assembler as diagnostics, finds the definition of labels,
+//! and shows the address of labels and the form, address and machine code of instructions on hover. Every document is
+//! analyzed on its own, so a label defined in another source file is reported as undefined.
+
+use crate::token::*;
+use crate::scanner::*;
+use crate::instruction::*;
+use crate::assembler::*;
+use crate::json::*;
+use crate::vm::catch_error;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+/// Serve one client on the standard input and output until it sends `exit`, reading documents with `options`.
+///
+/// Returns the exit status, 0 if the client has sent `shutdown` before `exit`.
+pub fn serve_stdio(options: SourceOptions) -> Result<i32, String> {
+    let stdin = io::stdin();
+    let mut reader = BufReader::new(stdin.lock());
+    let stdout = io::stdout();
+    let mut writer = stdout.lock();
+    let mut server = LanguageServer { documents: HashMap::new(), options, shutdown: false };
+
+    loop {
+        let message = match read_message(&mut reader)? {
+            Some(message) => message,
+            None => return Ok(1),
+        };
+
+        let method = message.get("method").and_then(|method| method.as_str()).unwrap_or("").to_string();
+
+        if method == "exit" {
+            return Ok(if server.shutdown { 0 } else { 1 });
+        }
+
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+
+        for notification in server.notify(&method, &params) {
+            write_message(&mut writer, &notification)?;
+        }
+
+        // a request has an `id`, a notification does not
+        if let Some(id) = message.get("id").cloned() {
+            if method.is_empty() {
+                continue;
+            }
+
+            let response = match server.request(&method, &params) {
+                Ok(result) => Json::object(vec![("jsonrpc", "2.0".into()), ("id", id), ("result", result)]),
+                Err((code, msg)) => Json::object(vec![
+                    ("jsonrpc", "2.0".into()),
+                    ("id", id),
+                    ("error", Json::object(vec![("code", code.into()), ("message", msg.into())])),
+                ]),
+            };
+
+            write_message(&mut writer, &response)?;
+        }
+    }
+}
+
+/// Read one message with its `Content-Length` header, `None` at the end of input.
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Json>, String> {
+    let mut size = None;
+
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim();
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                size = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let size = size.ok_or_else(|| "Missing Content-Length".to_string())?;
+    let mut body = vec![0; size];
+    reader.read_exact(&mut body).map_err(|err| err.to_string())?;
+
+    let body = String::from_utf8(body).map_err(|_| "Message is not valid UTF-8".to_string())?;
+    Json::parse(&body).map(Some)
+}
+
+fn write_message(writer: &mut impl Write, message: &Json) -> Result<(), String> {
+    let body = message.to_string();
+
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).map_err(|err| err.to_string())?;
+    writer.flush().map_err(|err| err.to_string())
+}
+
+/// Error found in a document, at a 1-based line and column like `TokenLocation`
+struct Diagnostic {
+    line: i32,
+    column: i32,
+    length: usize,
+    message: String,
+}
+
+/// Everything known about a document
+struct Analysis {
+    tokens: Vec<Token>,
+    statements: Vec<Statement>,
+    /// machine code of the document, if it has no error
+    program: Option<Program>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Scan with `options`, parse and assemble a document, collecting the errors.
+fn analyze(uri: &str, text: &str, options: &SourceOptions) -> Analysis {
+    let mut analysis = Analysis { tokens: Vec::new(), statements: Vec::new(), program: None, diagnostics: Vec::new() };
+    let mut scanner = Scanner::from_source(uri.to_string(), text.to_string());
+    scanner.set_options(options);
+
+    loop {
+        match catch_error(|| scanner.get_next_token()) {
+            Ok(token) if token.get_token_type() == TokenType::END_OF_FILE => break,
+            Ok(token) => analysis.tokens.push(token),
+            Err(msg) => {
+                // the scanner can not go on after an error
+                analysis.diagnostics.push(diagnostic(uri, &analysis.tokens, &msg));
+                break;
+            },
+        }
+    }
+
+    for msg in preprocess_tokens(&mut analysis.tokens, options) {
+        analysis.diagnostics.push(diagnostic(uri, &analysis.tokens, &msg));
+    }
+
+    let mut parser = Parser::new(&analysis.tokens);
+
+    loop {
+        match parser.parse_statement() {
+            Ok(Some(statement)) => analysis.statements.push(statement),
+            Ok(None) => break,
+            Err(msg) => {
+                analysis.diagnostics.push(diagnostic(uri, &analysis.tokens, &msg));
+                parser.skip_line();
+            },
+        }
+    }
+
+    if analysis.diagnostics.is_empty() {
+        match assemble(analysis.statements.to_owned(), 0) {
+            Ok(program) => analysis.program = Some(program),
+            Err(msg) => analysis.diagnostics.push(diagnostic(uri, &analysis.tokens, &msg)),
+        }
+    }
+
+    analysis
+}
+
+/// Turn an error message such as `Syntax Error: file:3:9: Unexpected token: ,` into a diagnostic.
+fn diagnostic(uri: &str, tokens: &[Token], msg: &str) -> Diagnostic {
+    let prefix = format!("{}:", uri);
+
+    let located = msg.find(&prefix).and_then(|start| {
+        let mut fields = msg[start + prefix.len()..].splitn(3, ':');
+        let line = fields.next()?.parse::<i32>().ok()?;
+        let column = fields.next()?.parse::<i32>().ok()?;
+        let message = format!("{}{}", &msg[..start], fields.next().unwrap_or("").trim_start());
+        Some((line, column, message))
+    });
+
+    let (line, column, message) = located.unwrap_or((1, 1, msg.to_string()));
+
+    let length = tokens.iter().find(|token| {
+        let location = token.get_token_location();
+        location.get_line() == line && location.get_column() == column
+    }).map_or(1, |token| token.get_token_location().get_length().max(1));
+
+    Diagnostic { line, column, length, message }
+}
+
+/// LSP range of `length` characters at a 1-based line and column.
+fn range(line: i32, column: i32, length: usize) -> Json {
+    let position = |column: i32| Json::object(vec![("line", (line - 1).into()), ("character", (column - 1).into())]);
+
+    Json::object(vec![("start", position(column)), ("end", position(column + length as i32))])
+}
+
+/// Get the token at a 0-based LSP position.
+fn token_at<'a>(tokens: &'a [Token], params: &Json) -> Option<&'a Token> {
+    let position = params.get("position")?;
+    let line = position.get("line")?.as_u64()? as i32 + 1;
+    let column = position.get("character")?.as_u64()? as i32 + 1;
+
+    tokens.iter().find(|token| {
+        let location = token.get_token_location();
+        let length = location.get_length() as i32;
+
+        location.get_line() == line && location.get_column() <= column && column <= location.get_column() + length
+    })
+}
+
+/// Describe the form of an instruction, such as `add r32, r/m32`.
+fn instruction_form(instruction: &Instruction) -> String {
+    let operands: Vec<String> = instruction.operands.iter().map(|operand| match operand {
+        Operand::Register(register) => format!("r{}", register_info(*register).map_or(32, |(_, size)| size * 8)),
+        Operand::Memory(memory) => format!("m{}", memory.size * 8),
+        Operand::Immediate(_) => "imm".to_string(),
+        Operand::Label(_) => "rel32".to_string(),
+        Operand::String(_) => "string".to_string(),
+    }).collect();
+
+    if operands.is_empty() {
+        instruction.name.to_owned()
+    } else {
+        format!("{} {}", instruction.name, operands.join(", "))
+    }
+}
+
+fn statement_location(statement: &Statement) -> TokenLocation {
+    match statement {
+        Statement::Label(_, location) => location.to_owned(),
+        Statement::Instruction(instruction) => instruction.location.to_owned(),
+    }
+}
+
+struct LanguageServer {
+    /// text of the open documents, by URI
+    documents: HashMap<String, String>,
+    /// options the documents are read with
+    options: SourceOptions,
+    shutdown: bool,
+}
+
+impl LanguageServer {
+    /// Handle a notification, or the notification part of a request, and return the notifications to send.
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let document = params.get("textDocument");
+        let uri = document.and_then(|document| document.get("uri")).and_then(|uri| uri.as_str());
+
+        let uri = match (method, uri) {
+            ("textDocument/didOpen", Some(uri)) | ("textDocument/didChange", Some(uri)) |
+                ("textDocument/didClose", Some(uri)) => uri.to_string(),
+            _ => return Vec::new(),
+        };
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = document.and_then(|document| document.get("text")).and_then(|text| text.as_str());
+                self.documents.insert(uri.to_owned(), text.unwrap_or("").to_string());
+            },
+            "textDocument/didChange" => {
+                // full synchronization, the last change is the whole text
+                let changes = params.get("contentChanges").and_then(|changes| changes.as_array());
+                let text = changes.and_then(|changes| changes.last()).and_then(|change| change.get("text"))
+                    .and_then(|text| text.as_str());
+
+                if let Some(text) = text {
+                    self.documents.insert(uri.to_owned(), text.to_string());
+                }
+            },
+            _ => {
+                self.documents.remove(&uri);
+            },
+        }
+
+        let diagnostics = match self.documents.get(&uri) {
+            Some(text) => analyze(&uri, text, &self.options).diagnostics.iter().map(|diagnostic| Json::object(vec![
+                ("range", range(diagnostic.line, diagnostic.column, diagnostic.length)),
+                ("severity", 1u32.into()),
+                ("source", "asm-vm".into()),
+                ("message", diagnostic.message.to_owned().into()),
+            ])).collect(),
+            None => Vec::new(),
+        };
+
+        vec![Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            ("params", Json::object(vec![("uri", uri.into()), ("diagnostics", Json::Array(diagnostics))])),
+        ])]
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i32, String)> {
+        match method {
+            "initialize" => Ok(Json::object(vec![
+                ("capabilities", Json::object(vec![
+                    ("textDocumentSync", 1u32.into()),
+                    ("definitionProvider", true.into()),
+                    ("hoverProvider", true.into()),
+                ])),
+                ("serverInfo", Json::object(vec![
+                    ("name", "asm-vm".into()),
+                    ("version", env!("CARGO_PKG_VERSION").into()),
+                ])),
+            ])),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            },
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+        }
+    }
+
+    fn analysis(&self, params: &Json) -> Result<(String, Analysis), (i32, String)> {
+        let uri = params.get("textDocument").and_then(|document| document.get("uri")).and_then(|uri| uri.as_str())
+            .ok_or((INVALID_PARAMS, "Missing textDocument".to_string()))?;
+
+        match self.documents.get(uri) {
+            Some(text) => Ok((uri.to_string(), analyze(uri, text, &self.options))),
+            None => Err((INVALID_PARAMS, format!("Document is not open: {}", uri))),
+        }
+    }
+
+    /// Find the definition of the label under the cursor.
+    fn definition(&self, params: &Json) -> Result<Json, (i32, String)> {
+        let (uri, analysis) = self.analysis(params)?;
+
+        let name = match token_at(&analysis.tokens, params) {
+            Some(token) => token.get_token_name(),
+            None => return Ok(Json::Null),
+        };
+
+        let location = analysis.statements.iter().find_map(|statement| match statement {
+            Statement::Label(label, location) if *label == name => Some(location),
+            _ => None,
+        });
+
+        Ok(match location {
+            Some(location) => Json::object(vec![
+                ("uri", uri.into()),
+                ("range", range(location.get_line(), location.get_column(), location.get_length())),
+            ]),
+            None => Json::Null,
+        })
+    }
+
+    /// Show the address of the label, or the form, address and machine code of the instruction under the cursor.
+    fn hover(&self, params: &Json) -> Result<Json, (i32, String)> {
+        let (_, analysis) = self.analysis(params)?;
+
+        let token = match token_at(&analysis.tokens, params) {
+            Some(token) => token,
+            None => return Ok(Json::Null),
+        };
+
+        let name = token.get_token_name();
+        let line = token.get_token_location().get_line();
+        let is_label = analysis.statements.iter().any(|statement| matches!(statement, Statement::Label(label, _)
+                    if *label == name));
+
+        // the listing line of the statement, or the label, under the cursor
+        let listing = analysis.program.as_ref().and_then(|program| program.lines.iter().find(|listing| {
+            match &listing.statement {
+                Statement::Label(label, _) if is_label => *label == name,
+                Statement::Instruction(instruction) if !is_label => instruction.location.get_line() == line,
+                _ => false,
+            }
+        }));
+
+        let contents = if is_label {
+            match listing {
+                Some(listing) => format!("label `{}` at `{:#010x}`", name, listing.address),
+                None => format!("label `{}`", name),
+            }
+        } else {
+            let statement = analysis.statements.iter()
+                .find(|statement| statement_location(statement).get_line() == line);
+
+            let instruction = match statement {
+                Some(Statement::Instruction(instruction)) => instruction,
+                _ => return Ok(Json::Null),
+            };
+
+            let mut contents = format!("```asm\n{}\n```\n\nform: `{}`", instruction, instruction_form(instruction));
+
+            if let Some(listing) = listing {
+                let bytes: Vec<String> = listing.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                contents.push_str(&format!("  \naddress: `{:#010x}`  \nmachine code: `{}`", listing.address,
+                            bytes.join(" ")));
+            }
+
+            contents
+        };
+
+        Ok(Json::object(vec![
+            ("contents", Json::object(vec![("kind", "markdown".into()), ("value", contents.into())])),
+            ("range", range(line, token.get_token_location().get_column(), token.get_token_location().get_length())),
+        ]))
+    }
+}
@@ -0,0 +1,258 @@
+//! Macros, which factor out a repetitive sequence of instructions, in the syntax of NASM or of MASM:
+//!
+//! ```text
+//! %macro print 2              print MACRO fmt, value
+//!     push %2                     LOCAL again
+//!     push %1                 again:
+//!     int 0x82                    push value
+//!     add esp, 8                  push fmt
+//! %endmacro                       int 0x82
+//!                                 add esp, 8
+//!                             ENDM
+//! ```
+//!
+//! A macro is used as an instruction, `print fmt, eax`, and is replaced by its body where every parameter, `%1` or
+//! `fmt`, is the argument given for it. Each use gives the local labels of the body, `%%again` or those after
+//! `LOCAL`, a name of its own, such as `..@1.again`, so that a macro with a loop can be used more than once.
+
+use crate::token::*;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+/// most times a macro is used inside itself, through the macros it uses, so that a macro using itself stops
+pub const MAX_MACRO_DEPTH: usize = 64;
+
+/// A macro defined by `%macro` or `MACRO`.
+struct Macro {
+    /// the names of the parameters, `%1`, `%2` and so on for NASM, or the names after `MACRO` for MASM
+    parameters: Vec<String>,
+    /// the names after `LOCAL`, for MASM, besides the names starting with `%%`
+    locals: Vec<String>,
+    body: Vec<Token>,
+}
+
+fn same_line(a: &Token, b: &Token) -> bool {
+    let (a, b) = (a.get_token_location(), b.get_token_location());
+    a.get_line() == b.get_line() && a.get_source_file_name() == b.get_source_file_name()
+}
+
+/// The end of the line of the token at `i`, which is the position after its last token.
+fn line_end(tokens: &[Token], i: usize) -> usize {
+    let mut end = i + 1;
+
+    while end < tokens.len() && same_line(&tokens[i], &tokens[end]) {
+        end += 1;
+    }
+
+    end
+}
+
+/// Split the tokens of a line at the commas outside brackets and parentheses, such as `[ebx + 4], eax` into
+/// `[ebx + 4]` and `eax`. No tokens are no items.
+fn split_items(tokens: &[Token]) -> Vec<Vec<Token>> {
+    let mut items = vec![Vec::new()];
+    let mut depth = 0;
+
+    for token in tokens {
+        match token.get_token_value() {
+            TokenValue::LBRACK | TokenValue::LPAREN => depth += 1,
+            TokenValue::RBRACK | TokenValue::RPAREN => depth -= 1,
+            TokenValue::COMMA if depth == 0 => {
+                items.push(Vec::new());
+                continue;
+            },
+            _ => {},
+        }
+
+        items.last_mut().unwrap().push(token.to_owned());
+    }
+
+    if tokens.is_empty() { Vec::new() } else { items }
+}
+
+/// Remove the definitions of macros from the tokens and replace every use of a macro by its body, returns the errors
+/// found, such as a macro without `%endmacro`, or a use with the wrong number of arguments, which is left as written.
+///
+/// A macro is used where an instruction would be, at the start of a line or after the definition of a label, and
+/// may use other macros, which are expanded too, up to [`MAX_MACRO_DEPTH`] deep.
+///
+/// The tokens of the body keep their locations in the definition, so that every line of the body is a line of its
+/// own, and the tokens of an argument take the location of the parameter they replace.
+pub fn expand_macros(tokens: &mut Vec<Token>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut macros = HashMap::new();
+    let mut rest = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        match tokens[i].get_token_value() {
+            TokenValue::MACRO => i = define_macro(tokens, i, &mut rest, &mut macros, &mut errors),
+            TokenValue::ENDMACRO | TokenValue::LOCAL => {
+                errors.push(format!("Syntax Error: {} Unexpected {} outside a macro",
+                                    tokens[i].get_token_location().to_string(), tokens[i].get_token_name()));
+                i = line_end(tokens, i);
+            },
+            _ => {
+                rest.push(tokens[i].to_owned());
+                i += 1;
+            },
+        }
+    }
+
+    let mut count = 0;
+    *tokens = expand(&rest, &macros, 0, &mut count, &mut errors);
+    errors
+}
+
+/// Read the definition of a macro at the `MACRO` token at `i`, whose name for MASM is the last token of `rest`, and
+/// returns the position after its `ENDM`.
+fn define_macro(tokens: &[Token], i: usize, rest: &mut Vec<Token>, macros: &mut HashMap<String, Macro>,
+                errors: &mut Vec<String>) -> usize {
+    let location = tokens[i].get_token_location().to_string();
+    let header_end = line_end(tokens, i);
+    let header = &tokens[i + 1..header_end];
+
+    let masm = rest.last().is_some_and(|last| last.get_token_type() == TokenType::LABEL && same_line(last, &tokens[i]));
+    let definition = if masm {
+        let name = rest.pop().unwrap().get_token_name();
+        let names: Option<Vec<String>> = split_items(header).into_iter()
+            .map(|item| match item.as_slice() {
+                [token] if token.get_token_type() == TokenType::LABEL => Some(token.get_token_name()),
+                _ => None,
+            })
+            .collect();
+
+        names.map(|names| (name, names)).ok_or_else(|| {
+            format!("Syntax Error: {} Expected the names of the parameters of the macro, such as print MACRO fmt, value",
+                    location)
+        })
+    } else {
+        match header {
+            [name] if name.get_token_type() == TokenType::LABEL => Ok((name.get_token_name(), Vec::new())),
+            [name, count] if name.get_token_type() == TokenType::LABEL &&
+                count.get_token_type() == TokenType::IMMEDIATE_DATA && count.get_int_value() <= 9 => {
+                Ok((name.get_token_name(), (1..=count.get_int_value()).map(|n| format!("%{}", n)).collect()))
+            },
+            _ => Err(format!("Syntax Error: {} Expected the name and the number of parameters, from 0 to 9, after \
+                              %macro, such as %macro print 2", location)),
+        }
+    };
+
+    let body_end = (header_end..tokens.len()).find(|j| {
+        matches!(tokens[*j].get_token_value(), TokenValue::ENDMACRO | TokenValue::MACRO)
+    });
+
+    let body_end = match body_end {
+        Some(j) if tokens[j].get_token_value() == TokenValue::ENDMACRO => j,
+        _ => {
+            errors.push(format!("Syntax Error: {} Expected %endmacro or ENDM after the body of the macro", location));
+            return body_end.unwrap_or(tokens.len());
+        },
+    };
+
+    let (name, parameters) = match definition {
+        Ok(definition) => definition,
+        Err(error) => {
+            errors.push(error);
+            return line_end(tokens, body_end);
+        },
+    };
+
+    let mut body = Vec::new();
+    let mut locals = Vec::new();
+    let mut j = header_end;
+
+    while j < body_end {
+        if tokens[j].get_token_value() == TokenValue::LOCAL {
+            let end = line_end(tokens, j).min(body_end);
+            locals.extend(tokens[j + 1..end].iter()
+                .filter(|token| token.get_token_type() == TokenType::LABEL)
+                .map(|token| token.get_token_name()));
+            j = end;
+        } else {
+            body.push(tokens[j].to_owned());
+            j += 1;
+        }
+    }
+
+    match macros.entry(name) {
+        Entry::Occupied(entry) => errors.push(format!("Syntax Error: {} Duplicate macro: \"{}\"", location, entry.key())),
+        Entry::Vacant(entry) => {
+            entry.insert(Macro { parameters, locals, body });
+        },
+    }
+
+    line_end(tokens, body_end)
+}
+
+/// Replace the uses of macros in the tokens by their bodies, `depth` deep inside other macros, where `count` is the
+/// number of uses expanded so far, which numbers the local labels.
+fn expand(tokens: &[Token], macros: &HashMap<String, Macro>, depth: usize, count: &mut usize,
+          errors: &mut Vec<String>) -> Vec<Token> {
+    let mut expanded = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let name = tokens[i].get_token_name();
+        let instruction = i == 0 || !same_line(&tokens[i - 1], &tokens[i]) ||
+            tokens[i - 1].get_token_value() == TokenValue::COLON;
+        let definition = tokens.get(i + 1).map(|token| token.get_token_value()) == Some(TokenValue::COLON);
+
+        let Some(definition) = macros.get(&name).filter(|_| {
+            tokens[i].get_token_type() == TokenType::LABEL && instruction && !definition
+        }) else {
+            expanded.push(tokens[i].to_owned());
+            i += 1;
+            continue;
+        };
+
+        let location = tokens[i].get_token_location().to_string();
+        let end = line_end(tokens, i);
+        let arguments = split_items(&tokens[i + 1..end]);
+
+        if arguments.len() != definition.parameters.len() {
+            errors.push(format!("Syntax Error: {} The macro {} takes {} arguments, but {} are given", location, name,
+                                definition.parameters.len(), arguments.len()));
+            expanded.extend_from_slice(&tokens[i..end]);
+        } else if depth >= MAX_MACRO_DEPTH {
+            errors.push(format!("Syntax Error: {} The macro {} is used more than {} deep inside macros", location, name,
+                                MAX_MACRO_DEPTH));
+        } else {
+            *count += 1;
+            let body = substitute(definition, &arguments, *count);
+            expanded.extend(expand(&body, macros, depth + 1, count, errors));
+        }
+
+        i = end;
+    }
+
+    expanded
+}
+
+/// The body of a macro for its `n`th use, with the arguments in place of the parameters and the local labels named
+/// `..@n.name`, which are not local to another label.
+fn substitute(definition: &Macro, arguments: &[Vec<Token>], n: usize) -> Vec<Token> {
+    let mut body = Vec::with_capacity(definition.body.len());
+
+    for token in &definition.body {
+        let name = token.get_token_name();
+
+        if token.get_token_type() != TokenType::LABEL {
+            body.push(token.to_owned());
+        } else if let Some(k) = definition.parameters.iter().position(|parameter| parameter.eq_ignore_ascii_case(&name)) {
+            body.extend(arguments[k].iter().map(|argument| {
+                let mut argument = argument.to_owned();
+                argument.set_token_location(token.get_token_location());
+                argument
+            }));
+        } else if let Some(local) = name.strip_prefix("%%")
+            .or_else(|| definition.locals.iter().find(|local| local.eq_ignore_ascii_case(&name)).map(|_| name.as_str())) {
+            body.push(Token::new_token(TokenType::LABEL, TokenValue::LABEL, token.get_token_location(),
+                                       format!("..@{}.{}", n, local)));
+        } else {
+            body.push(token.to_owned());
+        }
+    }
+
+    body
+}
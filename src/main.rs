@@ -1,56 +1,1254 @@
-mod vm;
-mod token;
-mod scanner;
-use crate::vm::*;
+use asm_vm::vm::*;
+use asm_vm::config::*;
+use asm_vm::assembler::*;
+use asm_vm::branch::{BranchModel, BranchStats};
+use asm_vm::cache::{CacheConfig, CacheStats};
+use asm_vm::pipeline::{Pipeline, PipelineStats};
+use asm_vm::scanner::{instruction_value, SourceOptions};
+use asm_vm::disassembler::*;
+use asm_vm::instruction::{Statement, register_from_number, register_name};
+use asm_vm::elf::*;
+use asm_vm::format::format_source;
+use asm_vm::ihex::*;
+use asm_vm::validate::*;
+use asm_vm::check::*;
+use asm_vm::isa::{instruction_info, INSTRUCTIONS};
+use asm_vm::json::Json;
+use asm_vm::optimize::{optimize, to_source};
+use asm_vm::token::TokenValue;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
 use std::io::prelude::*;
+use std::io::BufWriter;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+/// bytes printed by `--hexdump` without a size
+const DEFAULT_HEXDUMP_SIZE: u32 = 64;
+/// faults skipped by `--keep-going` without a limit
+const DEFAULT_FAULT_LIMIT: usize = 100;
+/// time after the timeout when the watchdog stops a program the virtual machine could not, in milliseconds
+const WATCHDOG_GRACE: u64 = 500;
+
+/// Options parsed from the command line
+struct Options {
+    source_file_names: Vec<String>,
+    /// where to write the token dump, if requested
+    token_file_name: Option<String>,
+    /// where to write the flat binary, if requested
+    output_file_name: Option<String>,
+    /// where to write the map of the labels, if requested
+    map_file_name: Option<String>,
+    /// load address of the flat binary
+    origin: u32,
+    /// run the disassembled program
+    execute: bool,
+    /// address of the JSON-RPC server
+    listen_address: String,
+    /// serve WebSocket connections
+    websocket: bool,
+    /// run again whenever a source file changes
+    watch: bool,
+    /// Intel HEX image loaded into memory before running
+    load_hex_file_name: Option<String>,
+    /// where to write the memory as an Intel HEX image after running, if requested
+    dump_hex_file_name: Option<String>,
+    /// memory printed as a hexdump after running, from an address or label, with the number of bytes
+    hexdump: Option<(String, usize)>,
+    /// where to write a record of every instruction, if requested
+    trace_file_name: Option<String>,
+    /// where to write the state after running, if requested
+    snapshot_file_name: Option<String>,
+    /// specification of the cases to grade
+    spec_file_name: Option<String>,
+    /// print machine-readable results
+    json: bool,
+    /// reject operand combinations which real x86 does not allow
+    strict: bool,
+    /// run or assemble the program after the peephole optimization
+    optimize: bool,
+    /// seed of the garbage in the registers and memory before running, instead of zeros
+    random_seed: Option<u64>,
+    /// return the time of the host from the time system calls instead of the virtual time
+    wall_clock: bool,
+    /// faults skipped before the next one stops the program, `None` to stop at the first one
+    fault_limit: Option<usize>,
+    /// wall-clock time after which the program is stopped, in milliseconds
+    timeout: Option<u64>,
+    /// stop the program when its state repeats
+    detect_loops: bool,
+    /// geometry of the simulated data cache, whose hits and misses are printed after running
+    cache: Option<CacheConfig>,
+    /// what is recorded of every conditional jump, which is printed after running
+    branches: Option<BranchModel>,
+    /// whether the executed instructions go through a pipeline with forwarding, whose stalls are printed after running
+    pipeline: Option<bool>,
+    /// rewrite the source files in place
+    write: bool,
+    /// only tell whether the source files are formatted
+    check: bool,
+    /// registers set before the program runs, which `check` does not warn about
+    seeded: Vec<TokenValue>,
+    /// settings of `asmvm.toml`
+    config: Config,
+    /// how the source files are read, after `config`
+    source_options: SourceOptions,
+    verbosity: Verbosity,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: asm-vm [run] [-q | -v] [--emit-tokens <path>] [--load-hex <path>] [--dump-hex <path>] \
+               [--hexdump <address>[,<size>]] [--watch] [--strict] [--optimize] [--randomize-state [<seed>]] \
+               [--wall-clock] [--keep-going [<limit>]] [--timeout <duration>] [--detect-loops] [--cache [<geometry>]] \
+               [--branches [2bit]] [--pipeline [noforward]] [--trace <path>] [--snapshot <path>] <file.asm>...");
+    eprintln!("       asm-vm assemble [-o <file.bin | file.hex>] [--map <file.map>] [--origin <address>] [--strict] \
+               [--optimize] <file.asm>...");
+    eprintln!("       asm-vm disasm [-o <file.asm>] [--origin <address>] [--run] <file.bin | file.hex>");
+    eprintln!("       asm-vm check-encoding [-q] [--origin <address>] <file.asm>...");
+    eprintln!("       asm-vm fmt [--write | --check] <file.asm>...");
+    eprintln!("       asm-vm check [--seed <registers>] <file.asm>...");
+    eprintln!("       asm-vm serve [-q | -v] [--listen <address>] [--websocket]");
+    eprintln!("       asm-vm lsp");
+    eprintln!("       asm-vm grade [--json] --spec <spec.toml>");
+    eprintln!("       asm-vm selftest [-q]");
+    eprintln!("       asm-vm explain [--json] [<instruction>...]");
+    eprintln!("       asm-vm diff <snapshot | trace> <snapshot | trace>");
+    eprintln!();
+    eprintln!("Commands:");
+    eprintln!("    run                      Run the program, the default command");
+    eprintln!("    assemble                 Encode the program into x86 machine code and print the listing");
+    eprintln!("    disasm                   Decode x86 machine code into instructions and print the listing");
+    eprintln!("    check-encoding           Verify the machine code of the program with the capstone disassembler");
+    eprintln!("    fmt                      Print the source files in canonical form");
+    eprintln!("    check                    Warn about unreachable code, labels which never return, falling off the");
+    eprintln!("                             end and registers read before they are written");
+    eprintln!("    serve                    Control virtual machines with JSON-RPC requests over TCP");
+    eprintln!("    lsp                      Run the language server on the standard input and output");
+    eprintln!("    grade                    Run the program against the cases of a specification");
+    eprintln!("    explain                  Print the operand forms, the flags and the notes of instructions, or list");
+    eprintln!("                             every instruction");
+    eprintln!("    diff                     Print the first divergence between two snapshots or two traces");
+    eprintln!("    selftest                 Check the flags, the stack and the jumps of this build with programs");
+    eprintln!("                             embedded in it");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("    -q, --quiet              Print nothing but fatal errors");
+    eprintln!("    -v, --verbose            Print all registers, flags and preprocess details");
+    eprintln!("    --emit-tokens <path>     Write the token stream of the source files to <path>");
+    eprintln!("    --load-hex <path>        Load an Intel HEX image into memory before running");
+    eprintln!("    --dump-hex <path>        Write the memory in use to <path> as an Intel HEX image after running");
+    eprintln!("    --hexdump <address>[,<size>]");
+    eprintln!("                             Print <size> bytes of memory from <address> or a label after running, 64");
+    eprintln!("                             by default, with the region every line falls in");
+    eprintln!("    --trace <path>           Write the registers, the flags and the memory written after every");
+    eprintln!("                             instruction to <path>, a line of JSON for each");
+    eprintln!("    --snapshot <path>        Write the registers, the flags and the memory to <path> as JSON after");
+    eprintln!("                             running");
+    eprintln!("    --watch                  Run the program again whenever a source file changes");
+    eprintln!("    -o <file>                Write the machine code (assemble) or the source code (disasm) to <file>");
+    eprintln!("                             Machine code is written as an Intel HEX image if <file> ends with .hex");
+    eprintln!("    --map <file>             Write the section, address, size and source of every label to <file>");
+    eprintln!("    --origin <address>       Load address of the flat binary, 0 by default");
+    eprintln!("    --run                    Run the disassembled program");
+    eprintln!("    --listen <address>       Address of the server, 127.0.0.1:4000 by default");
+    eprintln!("    --websocket              Serve WebSocket connections, which receive the changes of every step");
+    eprintln!("    --spec <path>            TOML specification of the program and the cases to grade");
+    eprintln!("    --json                   Print the results of grading as JSON");
+    eprintln!("    --config <path>          Read the settings from <path> instead of the nearest asmvm.toml");
+    eprintln!("    --strict                 Reject operand combinations which real x86 does not allow");
+    eprintln!("    --optimize               Fold constants, remove mov from a register to itself and replace imul by");
+    eprintln!("                             a power of 2 with shl, printing what changed");
+    eprintln!("    --write                  Rewrite the source files in canonical form instead of printing them");
+    eprintln!("    --check                  Fail if a source file is not in canonical form, without changing it");
+    eprintln!("    --randomize-state [<seed>]");
+    eprintln!("                             Fill the registers and memory with garbage from <seed>, or from a seed");
+    eprintln!("                             which is printed, instead of zeros");
+    eprintln!("    --wall-clock             Return the time of the host from the time system calls instead of the");
+    eprintln!("                             virtual time");
+    eprintln!("    --keep-going [<limit>]");
+    eprintln!("                             Skip an instruction which faults, such as one with a bad operand, and go");
+    eprintln!("                             on, up to <limit> faults, 100 by default");
+    eprintln!("    --timeout <duration>     Stop the program after <duration> of wall-clock time, such as 2s or");
+    eprintln!("                             500ms, even if it waits for input");
+    eprintln!("    --detect-loops           Stop the program when it repeats the same state without changing memory,");
+    eprintln!("                             input or output, so it would never finish");
+    eprintln!("    --cache [<size>,<associativity>,<line size>]");
+    eprintln!("                             Simulate a data cache, 32KiB,8,64 by default, and print its hits and");
+    eprintln!("                             misses by label and by instruction after running");
+    eprintln!("    --branches [2bit]        Print how often every conditional jump was taken after running, and with");
+    eprintln!("                             2bit how often a 2-bit predictor mispredicted it");
+    eprintln!("    --pipeline [noforward]   Put the instructions through a 5-stage pipeline, with forwarding unless");
+    eprintln!("                             noforward, and print its stalls by instruction after running");
+    eprintln!("    --seed <registers>       Registers set before the program runs, such as eax,ebx");
+    process::exit(2);
+}
 
-    if args.len() < 2 {
-        panic!("Please enter file name!");
+/// Parse a decimal or `0x` prefixed hexadecimal number.
+fn parse_number(text: &str) -> Option<u32> {
+    if text.starts_with("0x") || text.starts_with("0X") {
+        u32::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse().ok()
     }
+}
 
-    if args.len() > 3 {
-        panic!("Many argument!");
+/// Get the value following an option.
+fn option_value<'a>(iter: &mut impl Iterator<Item = &'a String>, option: &str) -> String {
+    match iter.next() {
+        Some(value) => value.to_owned(),
+        None => {
+            eprintln!("Missing value after {}!", option);
+            usage();
+        },
     }
+}
 
-    let mut file;
-    let file_name;
+fn parse_options(args: &[String], source_files_required: bool) -> Options {
+    let mut source_file_names = Vec::new();
+    let mut token_file_name = None;
+    let mut output_file_name = None;
+    let mut map_file_name = None;
+    let mut origin = 0;
+    let mut execute = false;
+    let mut listen_address = "127.0.0.1:4000".to_string();
+    let mut websocket = false;
+    let mut watch = false;
+    let mut load_hex_file_name = None;
+    let mut dump_hex_file_name = None;
+    let mut hexdump = None;
+    let mut trace_file_name = None;
+    let mut snapshot_file_name = None;
+    let mut spec_file_name = None;
+    let mut json = false;
+    let mut config_file_name = None;
+    let mut strict = false;
+    let mut optimize = false;
+    let mut random_seed = None;
+    let mut new_seed = false;
+    let mut wall_clock = false;
+    let mut fault_limit = None;
+    let mut timeout = None;
+    let mut detect_loops = false;
+    let mut cache = None;
+    let mut branches = None;
+    let mut pipeline = None;
+    let mut write = false;
+    let mut check = false;
+    let mut seeded = Vec::new();
+    let mut verbosity = Verbosity::NORMAL;
+    let mut iter = args.iter().peekable();
 
-    if args.len() == 3 {
-        file_name = args[2].to_owned();
-    } else {
-        file_name = "./TokenOut.txt".to_string();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-q" | "--quiet" => verbosity = Verbosity::QUIET,
+            "-v" | "--verbose" => verbosity = Verbosity::VERBOSE,
+            "--emit-tokens" => token_file_name = Some(option_value(&mut iter, arg)),
+            "-o" | "--output" => output_file_name = Some(option_value(&mut iter, arg)),
+            "--map" => map_file_name = Some(option_value(&mut iter, arg)),
+            "--origin" => {
+                let value = option_value(&mut iter, arg);
+                origin = match parse_number(&value) {
+                    Some(origin) => origin,
+                    None => {
+                        eprintln!("Invalid origin: {}", value);
+                        usage();
+                    },
+                };
+            },
+            "--run" => execute = true,
+            "--listen" => listen_address = option_value(&mut iter, arg),
+            "--websocket" => websocket = true,
+            "--load-hex" => load_hex_file_name = Some(option_value(&mut iter, arg)),
+            "--hexdump" => {
+                let value = option_value(&mut iter, arg);
+                let (start, size) = match value.split_once(',') {
+                    Some((start, size)) => (start, parse_number(size)),
+                    None => (value.as_str(), Some(DEFAULT_HEXDUMP_SIZE)),
+                };
+
+                hexdump = match size {
+                    Some(size) if !start.is_empty() => Some((start.to_string(), size as usize)),
+                    _ => {
+                        eprintln!("Invalid hexdump: {}", value);
+                        usage();
+                    },
+                };
+            },
+            "--dump-hex" => dump_hex_file_name = Some(option_value(&mut iter, arg)),
+            "--trace" => trace_file_name = Some(option_value(&mut iter, arg)),
+            "--snapshot" => snapshot_file_name = Some(option_value(&mut iter, arg)),
+            "--watch" => watch = true,
+            "--spec" => spec_file_name = Some(option_value(&mut iter, arg)),
+            "--json" => json = true,
+            "--config" => config_file_name = Some(option_value(&mut iter, arg)),
+            "--strict" => strict = true,
+            "--optimize" => optimize = true,
+            "--randomize-state" => {
+                random_seed = match iter.peek().and_then(|value| parse_number(value)) {
+                    Some(seed) => {
+                        iter.next();
+                        Some(seed as u64)
+                    },
+                    None => {
+                        // printed below, so that the run can be repeated
+                        new_seed = true;
+                        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+                            .map_or(Some(0), |duration| Some(duration.as_nanos() as u64 & 0xffff_ffff))
+                    },
+                };
+            },
+            "--wall-clock" => wall_clock = true,
+            "--detect-loops" => detect_loops = true,
+            "--branches" => {
+                branches = match iter.peek().map(|value| value.as_str()) {
+                    Some("2bit") => {
+                        iter.next();
+                        Some(BranchModel::TwoBit)
+                    },
+                    _ => Some(BranchModel::Count),
+                };
+            },
+            "--pipeline" => {
+                pipeline = match iter.peek().map(|value| value.as_str()) {
+                    Some("noforward") => {
+                        iter.next();
+                        Some(false)
+                    },
+                    _ => Some(true),
+                };
+            },
+            "--cache" => {
+                let geometry = match iter.peek() {
+                    Some(value) if value.starts_with(|c: char| c.is_ascii_digit() || c == ',') => iter.next().unwrap(),
+                    _ => "",
+                };
+
+                cache = match parse_cache(geometry) {
+                    Ok(config) => Some(config),
+                    Err(msg) => {
+                        eprintln!("{}", msg);
+                        usage();
+                    },
+                };
+            },
+            "--timeout" => {
+                let duration = iter.next().unwrap_or_else(|| usage());
+
+                timeout = match parse_duration(duration) {
+                    Some(timeout) if timeout > 0 => Some(timeout),
+                    _ => {
+                        eprintln!("Invalid timeout: {}, expected a duration such as 2s or 500ms", duration);
+                        usage();
+                    },
+                };
+            },
+            "--keep-going" => {
+                fault_limit = match iter.peek().and_then(|value| parse_number(value)) {
+                    Some(limit) => {
+                        iter.next();
+                        Some(limit as usize)
+                    },
+                    None => Some(DEFAULT_FAULT_LIMIT),
+                };
+            },
+            "--write" => write = true,
+            "--check" => check = true,
+            "--seed" => {
+                for name in option_value(&mut iter, arg).split(',') {
+                    match (0..8).map(|number| register_from_number(number, 4))
+                        .find(|register| register_name(*register) == name.trim()) {
+                        Some(register) => seeded.push(register),
+                        None => {
+                            eprintln!("Invalid register: {}", name);
+                            usage();
+                        },
+                    }
+                }
+            },
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown option: {}", arg);
+                usage();
+            },
+            _ => source_file_names.push(arg.to_owned()),
+        }
+    }
+
+    if let (Some(seed), true, true) = (random_seed, new_seed, verbosity >= Verbosity::NORMAL) {
+        eprintln!("Random state seed: {}", seed);
+    }
+
+    if source_files_required && source_file_names.is_empty() {
+        eprintln!("Please enter file name!");
+        usage();
+    }
+
+    let config = match config_file_name {
+        Some(config_file_name) => Config::load(Path::new(&config_file_name)),
+        None => env::current_dir().map_err(|err| err.to_string()).and_then(|directory| Config::find(&directory)),
+    };
+
+    let config = match config {
+        Ok(config) => config,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        },
+    };
+
+    if let (Some(path), true) = (&config.path, verbosity >= Verbosity::VERBOSE) {
+        eprintln!("Config: {}", path.display());
     }
 
-    file = match File::create(file_name) {
-        Err(err) => panic!("Can not create {}, because {}.", args[2], err.to_string()),
+    let source_options = match config.source_options() {
+        Ok(source_options) => source_options,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        },
+    };
+
+    let source_file_names = source_file_names.iter()
+        .map(|source_file_name| config.resolve_source_file_name(source_file_name))
+        .collect();
+
+    Options { source_file_names, token_file_name, output_file_name, map_file_name, origin, execute, listen_address,
+        websocket, watch, load_hex_file_name, dump_hex_file_name, hexdump, trace_file_name, snapshot_file_name,
+        spec_file_name, json, strict, optimize, random_seed, wall_clock, fault_limit, timeout, detect_loops, cache,
+        branches, pipeline, write, check,
+        seeded, config, source_options,
+        verbosity }
+}
+
+fn emit_tokens(vm: &VM, token_file_name: &str) {
+    let mut file = match File::create(token_file_name) {
+        Err(err) => panic!("Can not create {}, because {}.", token_file_name, err),
         Ok(file) => file,
     };
 
+    for token in vm.get_text() {
+        file.write_all(format!("{}\n", token.to_string()).as_bytes()).unwrap();
+    }
+}
+
+fn print_summary(vm: &VM, verbosity: Verbosity) {
+    match verbosity {
+        Verbosity::QUIET => {},
+        Verbosity::NORMAL => println!("eax: {}", vm.get_eax()),
+        Verbosity::VERBOSE => {
+            let (cf, zf, sf, of) = vm.get_flags();
+            println!("eax: {}", vm.get_eax());
+            println!("ebx: {}", vm.get_ebx());
+            println!("ecx: {}", vm.get_ecx());
+            println!("edx: {}", vm.get_edx());
+            println!("esi: {}", vm.get_esi());
+            println!("edi: {}", vm.get_edi());
+            println!("esp: {:#x}", vm.get_esp());
+            println!("ebp: {:#x}", vm.get_ebp());
+            println!("eip: {:#x}", vm.get_eip());
+            println!("cf: {} zf: {} sf: {} of: {}", cf as u8, zf as u8, sf as u8, of as u8);
+            println!("max stack: {} bytes, max call depth: {}", vm.get_max_stack_usage(), vm.get_max_call_depth());
+
+            if vm.is_halted() {
+                println!("halted by hlt");
+            }
+        },
+    }
+
+    if !vm.get_faults().is_empty() && verbosity >= Verbosity::NORMAL {
+        eprintln!("{} faults skipped", vm.get_faults().len());
+    }
+}
+
+/// Format the hits and misses of the data cache, in total, by label and by instruction.
+fn cache_report(vm: &VM) -> String {
+    let config = match vm.get_cache_config() {
+        Some(config) => config,
+        None => return String::new(),
+    };
+
+    let instructions: Vec<(String, CacheStats)> = vm.get_cache_stats().into_iter()
+        .map(|(location, name, stats)| (format!("{} {}", location.to_string(), name), stats))
+        .collect();
+    let labels = vm.get_cache_stats_by_label();
+
+    let mut total = CacheStats::default();
+    instructions.iter().for_each(|(_, stats)| total.add(*stats));
+
+    let mut text = format!("Data cache: {} bytes, {}-way, {}-bytes lines, {} sets\n", config.size,
+                           config.associativity, config.line_size, config.sets());
+    text += &format!("hits: {}, misses: {}, hit rate: {:.1}%\n", total.hits, total.misses, total.hit_rate() * 100.0);
+
+    for (title, rows) in [("label", &labels), ("instruction", &instructions)] {
+        let width = rows.iter().map(|(name, _)| name.len()).chain(Some(title.len())).max().unwrap();
+        text += &format!("\n{:width$}  {:>10}  {:>10}  {:>8}\n", title, "hits", "misses", "hit rate", width = width);
+
+        for (name, stats) in rows.iter() {
+            text += &format!("{:width$}  {:>10}  {:>10}  {:>7.1}%\n", name, stats.hits, stats.misses,
+                             stats.hit_rate() * 100.0, width = width);
+        }
+    }
+
+    text
+}
+
+/// Format how often every conditional jump was taken, and mispredicted if a predictor was simulated.
+fn branch_report(vm: &VM) -> String {
+    let predicted = vm.get_branch_model() == Some(BranchModel::TwoBit);
+    let branches: Vec<(String, BranchStats)> = vm.get_branch_stats().into_iter()
+        .map(|(location, name, stats)| (format!("{} {}", location.to_string(), name), stats))
+        .collect();
+
+    let mut total = BranchStats::default();
+    branches.iter().for_each(|(_, stats)| total.add(stats));
+
+    let width = branches.iter().map(|(name, _)| name.len()).chain(Some("branch".len())).max().unwrap();
+    let mut text = format!("{:width$}  {:>10}  {:>10}", "branch", "taken", "not taken", width = width);
+
+    if predicted {
+        text += &format!("  {:>12}  {:>8}", "mispredicted", "rate");
+    }
+
+    text += "\n";
+
+    for (name, stats) in branches.iter().chain(Some(&("total".to_string(), total))) {
+        text += &format!("{:width$}  {:>10}  {:>10}", name, stats.taken, stats.not_taken, width = width);
+
+        if predicted {
+            text += &format!("  {:>12}  {:>7.1}%", stats.mispredicted, stats.misprediction_rate() * 100.0);
+        }
+
+        text += "\n";
+    }
+
+    text
+}
+
+/// Format the stages of the first instructions through the pipeline, and the stalls of every instruction.
+fn pipeline_report(vm: &VM) -> String {
+    let pipeline = match vm.get_pipeline() {
+        Some(pipeline) => pipeline,
+        None => return String::new(),
+    };
+
+    let instructions: Vec<(String, PipelineStats)> = vm.get_pipeline_stats().into_iter()
+        .map(|(location, text, stats)| (format!("{} {}", location.to_string(), text), stats))
+        .collect();
+
+    let mut total = PipelineStats::default();
+    instructions.iter().for_each(|(_, stats)| total.add(stats));
+
+    let forwarding = if pipeline.has_forwarding() { "with" } else { "without" };
+    let mut text = format!("Pipeline: 5 stages {} forwarding, {} cycles for {} instructions", forwarding,
+                           pipeline.cycles(), total.instructions);
+
+    if total.instructions > 0 {
+        text += &format!(", {:.2} cycles per instruction", pipeline.cycles() as f64 / total.instructions as f64);
+    }
+
+    text += "\n";
+
+    // every cycle of the diagram is a column, from the fetch of the first instruction
+    let diagram = vm.get_pipeline_diagram();
+
+    if let Some((_, first)) = diagram.first() {
+        let width = diagram.iter().map(|(text, _)| text.len()).max().unwrap();
+        let origin = first.decode - 1;
+        let cycles = diagram.iter().map(|(_, issue)| issue.decode + 3 - origin).max().unwrap();
+
+        text += &format!("\n{:width$}", "cycle", width = width);
+        text += (1..=cycles + 1).map(|cycle| format!(" {:<3}", cycle)).collect::<String>().trim_end();
+        text += "\n";
+
+        for (instruction, issue) in &diagram {
+            let fetch = issue.decode - 1 - issue.data_stalls - origin;
+            let stages = ["IF"].iter().chain(vec!["--"; issue.data_stalls as usize].iter())
+                .chain(["ID", "EX", "MEM", "WB"].iter())
+                .map(|stage| format!(" {:<3}", stage))
+                .collect::<String>();
+
+            let line = format!("{:width$}{}{}", instruction, " ".repeat(4 * fetch as usize), stages, width = width);
+            text += line.trim_end();
+            text += "\n";
+        }
+    }
+
+    let width = instructions.iter().map(|(name, _)| name.len()).chain(Some("instruction".len())).max().unwrap();
+    text += &format!("\n{:width$}  {:>10}  {:>11}  {:>14}\n", "instruction", "executed", "data stalls",
+                     "control stalls", width = width);
+
+    for (name, stats) in instructions.iter().chain(Some(&("total".to_string(), total))) {
+        text += &format!("{:width$}  {:>10}  {:>11}  {:>14}\n", name, stats.instructions, stats.data_stalls,
+                         stats.control_stalls, width = width);
+    }
+
+    text
+}
+
+fn assemble(args: &[String]) {
+    let options = parse_options(args, true);
+
+    if let Err(msg) = check_strict(&options) {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+
+    let program = if options.optimize {
+        optimize_files(&options).and_then(|statements| asm_vm::assembler::assemble(statements, options.origin))
+    } else {
+        assemble_files(options.source_file_names.to_owned(), options.origin, &options.source_options)
+    };
+
+    match program {
+        Ok(program) => {
+            if let Some(output_file_name) = &options.output_file_name {
+                let mut file = match File::create(output_file_name) {
+                    Err(err) => panic!("Can not create {}, because {}.", output_file_name, err),
+                    Ok(file) => file,
+                };
+
+                let bytes = program.get_bytes();
+
+                if is_hex_file_name(output_file_name) {
+                    let entry = program.lines.iter()
+                        .find(|line| matches!(&line.statement, Statement::Label(name, _)
+                                              if is_entrance(name, &options.config)))
+                        .map(|line| line.address);
+                    file.write_all(write_ihex(&[(program.origin, bytes.to_owned())], entry).as_bytes()).unwrap();
+                } else {
+                    file.write_all(&bytes).unwrap();
+                }
+
+                if options.verbosity >= Verbosity::VERBOSE {
+                    eprintln!("{} bytes at origin {:#x} written to {}", bytes.len(), program.origin, output_file_name);
+                }
+            }
+
+            if let Some(map_file_name) = &options.map_file_name {
+                if let Err(err) = fs::write(map_file_name, program.to_map()) {
+                    panic!("Can not create {}, because {}.", map_file_name, err);
+                }
+            }
+
+            if options.verbosity >= Verbosity::NORMAL {
+                print!("{}", program.to_listing());
+            }
+        },
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        },
+    }
+}
+
+fn disasm(args: &[String]) {
+    let options = parse_options(args, true);
+
+    if options.source_file_names.len() != 1 {
+        eprintln!("Please enter one binary file!");
+        usage();
+    }
+
+    let binary_file_name = &options.source_file_names[0];
+    let mut bytes = Vec::new();
+
+    match File::open(binary_file_name) {
+        Err(err) => panic!("When trying to open file {}, because {}, an error occurred.", binary_file_name, err),
+        Ok(mut file) => file.read_to_end(&mut bytes).unwrap(),
+    };
+
+    let result = if is_ihex(&bytes) {
+        load_ihex(binary_file_name, &String::from_utf8_lossy(&bytes)).map(|image| (image.program, image.segments))
+    } else {
+        disassemble(binary_file_name, &bytes, options.origin).map(|program| (program, Vec::new()))
+    };
+
+    let (program, segments) = match result {
+        Ok(result) => result,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        },
+    };
+
+    if let Some(output_file_name) = &options.output_file_name {
+        let mut file = match File::create(output_file_name) {
+            Err(err) => panic!("Can not create {}, because {}.", output_file_name, err),
+            Ok(file) => file,
+        };
+
+        file.write_all(program.to_source().as_bytes()).unwrap();
+    }
+
+    if options.execute {
+        let mut vm: VM = Default::default();
+        vm.set_verbosity(options.verbosity);
+        vm.set_random_state(options.random_seed);
+        vm.set_wall_clock(options.wall_clock);
+        vm.set_fault_limit(options.fault_limit);
+        vm.set_loop_detection(options.detect_loops);
+        vm.set_origin(program.origin);
+        vm.run_image(binary_file_name.to_owned(), program.to_source(), &segments);
+        print_summary(&vm, options.verbosity);
+    } else if options.verbosity >= Verbosity::NORMAL {
+        print!("{}", program.to_listing());
+    }
+}
+
+#[cfg(feature = "check-encoding")]
+fn check_encoding(args: &[String]) {
+    let options = parse_options(args, true);
+
+    let result = assemble_files(options.source_file_names, options.origin, &options.source_options)
+        .and_then(|program| asm_vm::check_encoding::verify_encoding(&program).map(|mismatches| (program, mismatches)));
+
+    match result {
+        Ok((program, mismatches)) => {
+            for mismatch in &mismatches {
+                println!("{}", mismatch);
+            }
+
+            if options.verbosity >= Verbosity::NORMAL {
+                let count = program.lines.iter().filter(|line| !line.bytes.is_empty()).count();
+                println!("{} instructions checked, {} mismatches", count, mismatches.len());
+            }
+
+            if !mismatches.is_empty() {
+                process::exit(1);
+            }
+        },
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        },
+    }
+}
+
+#[cfg(not(feature = "check-encoding"))]
+fn check_encoding(_args: &[String]) {
+    eprintln!("asm-vm is built without check-encoding, please rebuild it with `--features check-encoding`!");
+    process::exit(2);
+}
+
+fn serve(args: &[String]) {
+    let options = parse_options(args, false);
+
+    if !options.source_file_names.is_empty() {
+        eprintln!("serve does not take source files, please load them with the load method!");
+        usage();
+    }
+
+    if let Err(msg) = asm_vm::server::serve(&options.listen_address, options.websocket, options.config.quotas,
+                                                 options.verbosity) {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+}
+
+fn lsp(args: &[String]) {
+    if !args.is_empty() {
+        usage();
+    }
+
+    // the aliases of the nearest asmvm.toml, the diagnostics tell nothing about the configuration itself
+    let options = env::current_dir().map_err(|err| err.to_string())
+        .and_then(|directory| Config::find(&directory))
+        .and_then(|config| config.source_options())
+        .unwrap_or_default();
+
+    match asm_vm::lsp::serve_stdio(options) {
+        Ok(status) => process::exit(status),
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        },
+    }
+}
+
+fn grade(args: &[String]) {
+    let options = parse_options(args, false);
+
+    let spec_file_name = match (&options.spec_file_name, options.source_file_names.is_empty()) {
+        (Some(spec_file_name), true) => spec_file_name,
+        (None, _) => {
+            eprintln!("Please enter the specification with --spec!");
+            usage();
+        },
+        (Some(_), false) => {
+            eprintln!("grade does not take source files, please list them as program in the specification!");
+            usage();
+        },
+    };
+
+    match asm_vm::grade::grade(spec_file_name, &options.config) {
+        Ok(results) => {
+            if options.json {
+                println!("{}", asm_vm::grade::to_json(&results));
+            } else if options.verbosity >= Verbosity::NORMAL {
+                print!("{}", asm_vm::grade::to_matrix(&results));
+            }
+
+            if !results.iter().all(|result| result.passed()) {
+                process::exit(1);
+            }
+        },
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        },
+    }
+}
+
+/// Check whether the file name has the extension of Intel HEX images.
+fn is_hex_file_name(file_name: &str) -> bool {
+    file_name.ends_with(".hex") || file_name.ends_with(".ihex")
+}
+
+/// Check whether the label is the entry label of the virtual machine.
+fn is_entrance(name: &str, config: &Config) -> bool {
+    match &config.entry {
+        Some(entry) => name == entry,
+        None => matches!(name, "main" | "start" | "_main" | "_start"),
+    }
+}
+
+/// Read the whole file, `None` if it is not an ELF file or an Intel HEX image.
+fn read_image(file_name: &str) -> Option<Result<Image, String>> {
+    let mut bytes = Vec::new();
+    File::open(file_name).ok()?.read_to_end(&mut bytes).ok()?;
+
+    if is_elf(&bytes) {
+        Some(load_elf(file_name, &bytes))
+    } else if is_ihex(&bytes) {
+        Some(load_ihex(file_name, &String::from_utf8_lossy(&bytes)))
+    } else {
+        None
+    }
+}
+
+/// Read the segments of an Intel HEX image.
+fn read_hex_file(file_name: &str) -> Result<Vec<Segment>, String> {
+    let text = fs::read_to_string(file_name).map_err(|err| format!("Can not read {}, because {}.", file_name, err))?;
+    read_ihex(file_name, &text).map(|(_, segments)| segments)
+}
+
+/// Parse and optimize the source files with `--optimize`, the report is printed unless quiet.
+fn optimize_files(options: &Options) -> Result<Vec<Statement>, String> {
+    let (statements, report) = optimize(parse_files(options.source_file_names.to_owned(), &options.source_options)?);
+
+    if options.verbosity >= Verbosity::NORMAL {
+        for line in report {
+            eprintln!("{}", line);
+        }
+    }
+
+    Ok(statements)
+}
+
+/// Validate the operands of the source files with `--strict`, the error has every diagnostic on its own line.
+fn check_strict(options: &Options) -> Result<(), String> {
+    if !options.strict {
+        return Ok(());
+    }
+
+    let diagnostics = validate_files(&options.source_file_names, &options.source_options)?;
+
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(diagnostics.join("\n"))
+    }
+}
+
+/// Run the program once and print the summary, returns the status passed to the `exit` system call.
+fn run_once(options: &Options) -> Result<Option<u32>, String> {
     let mut vm: VM = Default::default();
+    vm.set_verbosity(options.verbosity);
+    options.config.apply(&mut vm)?;
+    vm.set_random_state(options.random_seed);
+    vm.set_wall_clock(options.wall_clock);
+    vm.set_fault_limit(options.fault_limit);
+
+    if options.detect_loops {
+        vm.set_loop_detection(true);
+    }
+
+    if let Some(cache) = options.cache {
+        vm.set_cache(Some(cache));
+    }
+
+    vm.set_branch_model(options.branches);
+    vm.set_pipeline(options.pipeline.map(Pipeline::new));
+
+    if let Some(timeout) = options.timeout {
+        vm.set_quotas(Quotas { time: Some(timeout), ..vm.get_quotas() });
+    }
+
+    let _watchdog = vm.get_quotas().time.map(Watchdog::start);
+
+    if let Some(trace_file_name) = &options.trace_file_name {
+        let trace = File::create(trace_file_name)
+            .map_err(|err| format!("Can not create {}, because {}.", trace_file_name, err))?;
+        vm.set_trace(Some(Box::new(BufWriter::new(trace))));
+    }
+
+    let image = match options.source_file_names.as_slice() {
+        [file_name] => read_image(file_name).map(|image| (file_name, image)),
+        _ => None,
+    };
+
+    let data = match &options.load_hex_file_name {
+        Some(load_hex_file_name) => read_hex_file(load_hex_file_name)?,
+        None => Vec::new(),
+    };
+
+    match image {
+        Some((file_name, image)) => {
+            let mut image = image?;
+
+            if options.verbosity >= Verbosity::VERBOSE {
+                eprintln!("Image: {} segments, entry at {:#010x}", image.segments.len(), image.entry);
+            }
+
+            image.segments.extend(data);
+            // the image starts from its own entry point, and its code from where it was disassembled
+            vm.set_entry_label(None);
+            vm.set_origin(image.program.lines.first().map_or(image.entry, |line| line.address));
+            vm.run_image(file_name.to_owned(), image.program.to_source(), &image.segments);
+        },
+        None => {
+            check_strict(options)?;
+
+            if options.optimize {
+                let source_name = format!("{} (optimized)", options.source_file_names.join(", "));
+                vm.load_source(source_name, to_source(&optimize_files(options)?));
+            } else {
+                vm.load_files(options.source_file_names.to_owned());
+            }
+
+            for (address, bytes) in &data {
+                vm.write_memory(*address, bytes)?;
+            }
+
+            vm.execute();
+        },
+    }
+
+    if let Some(exceeded) = vm.get_quota_exceeded() {
+        return Err(exceeded.to_string());
+    }
+
+    if let Some(raised) = vm.get_exception() {
+        return Err(raised.to_string());
+    }
+
+    if let Some(dump_hex_file_name) = &options.dump_hex_file_name {
+        fs::write(dump_hex_file_name, write_ihex(&vm.get_memory_segments(), None))
+            .map_err(|err| format!("Can not create {}, because {}.", dump_hex_file_name, err))?;
+    }
+
+    if let Some(snapshot_file_name) = &options.snapshot_file_name {
+        fs::write(snapshot_file_name, format!("{}\n", asm_vm::trace::snapshot(&vm)))
+            .map_err(|err| format!("Can not create {}, because {}.", snapshot_file_name, err))?;
+    }
+
+    if let Some((start, size)) = &options.hexdump {
+        let address = match parse_number(start).or_else(|| vm.get_symbol(start)) {
+            Some(address) => address,
+            None => return Err(format!("Unknown label: \"{}\"", start)),
+        };
+
+        print!("{}", vm.hexdump(address, *size)?);
+    }
+
+    if vm.get_cache_config().is_some() {
+        print!("{}", cache_report(&vm));
+    }
+
+    if vm.get_branch_model().is_some() {
+        print!("{}", branch_report(&vm));
+    }
+
+    if vm.get_pipeline().is_some() {
+        print!("{}", pipeline_report(&vm));
+    }
+
+    if let Some(token_file_name) = &options.token_file_name {
+        emit_tokens(&vm, token_file_name);
+
+        if options.verbosity >= Verbosity::VERBOSE {
+            eprintln!("Tokens written to {}", token_file_name);
+        }
+    }
+
+    print_summary(&vm, options.verbosity);
+
+    // a program whose faults were skipped did not succeed, even if it finished
+    match vm.get_exit_code() {
+        None | Some(0) if !vm.get_faults().is_empty() => Ok(Some(1)),
+        exit_code => Ok(exit_code),
+    }
+}
+
+/// Stops the process when a run goes on past its timeout, such as while it waits for the standard input, which the
+/// virtual machine can not interrupt; dropping it when the run finishes disarms it.
+struct Watchdog(Arc<AtomicBool>);
+
+impl Watchdog {
+    fn start(timeout: u64) -> Watchdog {
+        let finished = Arc::new(AtomicBool::new(false));
+        let watched = finished.clone();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(timeout + WATCHDOG_GRACE));
+
+            if !watched.load(Ordering::SeqCst) {
+                eprintln!("{}", QuotaExceeded { quota: Quota::Time, limit: timeout, location: None });
+                process::exit(1);
+            }
+        });
+
+        Watchdog(finished)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Get the modification time of every source file, `None` for a file which can not be read, such as one being saved.
+fn modification_times(source_file_names: &[String]) -> Vec<Option<SystemTime>> {
+    source_file_names.iter()
+        .map(|source_file_name| fs::metadata(source_file_name).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+/// Run the program again whenever a source file changes, until interrupted.
+fn watch(options: &Options) -> ! {
+    let mut last_times = Vec::new();
+
+    if options.verbosity >= Verbosity::NORMAL {
+        eprintln!("Watching {}, press Ctrl-C to stop.", options.source_file_names.join(", "));
+    }
 
-    /*
-    vm.run();
     loop {
-        match scanner.get_token().get_token_type() {
-            TokenType::END_OF_FILE => break,
-            _ => {},
+        let times = modification_times(&options.source_file_names);
+
+        if times != last_times && times.iter().all(|time| time.is_some()) {
+            // let the editor finish writing
+            thread::sleep(Duration::from_millis(100));
+            last_times = modification_times(&options.source_file_names);
+
+            if options.verbosity >= Verbosity::NORMAL {
+                eprintln!("==> Running {}", options.source_file_names.join(", "));
+            }
+
+            match catch_error(|| run_once(options)) {
+                Ok(Ok(Some(exit_code))) if options.verbosity >= Verbosity::NORMAL => {
+                    println!("exit code: {}", exit_code);
+                },
+                Ok(Ok(_)) => {},
+                Ok(Err(msg)) | Err(msg) => eprintln!("{}", msg),
+            }
+        }
+
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+fn run(args: &[String]) {
+    let options = parse_options(args, true);
+
+    if options.watch {
+        watch(&options);
+    }
+
+    match run_once(&options) {
+        Ok(Some(exit_code)) => process::exit(exit_code as i32),
+        Ok(None) => {},
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        },
+    }
+}
+
+fn fmt(args: &[String]) {
+    let options = parse_options(args, true);
+
+    if options.write && options.check {
+        eprintln!("Please give either --write or --check!");
+        usage();
+    }
+
+    let mut unformatted = false;
+
+    for source_file_name in &options.source_file_names {
+        let result = fs::read_to_string(source_file_name)
+            .map_err(|err| format!("Can not read {}, because {}.", source_file_name, err))
+            .and_then(|source| {
+                format_source(source_file_name, &source, &options.source_options).map(|text| (source, text))
+            });
+
+        let (source, text) = match result {
+            Ok(result) => result,
+            Err(msg) => {
+                eprintln!("{}", msg);
+                process::exit(1);
+            },
+        };
+
+        if options.check {
+            if source != text {
+                unformatted = true;
+                println!("{}", source_file_name);
+            }
+        } else if options.write {
+            if source != text {
+                if let Err(err) = fs::write(source_file_name, &text) {
+                    eprintln!("Can not write {}, because {}.", source_file_name, err);
+                    process::exit(1);
+                }
+            }
+        } else {
+            print!("{}", text);
         }
+    }
+
+    if unformatted {
+        process::exit(1);
+    }
+}
+
+fn check(args: &[String]) {
+    let options = parse_options(args, true);
+
+    let result = check_files(&options.source_file_names, options.config.entry.as_deref(), &options.seeded,
+                             &options.source_options)
+        .and_then(|mut diagnostics| {
+            if !options.config.policy.is_empty() {
+                let statements = parse_files(options.source_file_names.to_owned(), &options.source_options)?;
+                diagnostics.extend(options.config.policy.check(&statements));
+            }
+
+            Ok(diagnostics)
+        });
+
+    match result {
+        Ok(diagnostics) => {
+            for diagnostic in &diagnostics {
+                println!("{}", diagnostic);
+            }
+
+            if !diagnostics.is_empty() {
+                process::exit(1);
+            }
+        },
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        },
+    }
+}
+
+fn explain(args: &[String]) {
+    let json = args.iter().any(|arg| arg == "--json");
+    let names: Vec<&String> = args.iter().filter(|arg| *arg != "--json").collect();
 
-        file.write_all(format!("{}\n",scanner.get_next_token().to_string()).as_bytes()).unwrap();
+    if names.iter().any(|name| name.starts_with('-')) {
+        usage();
     }
-    */
 
-    vm.run_file(args[1].to_string());
+    let mut infos = Vec::new();
 
-    let tokens = vm.get_text();
-    for token in tokens {
-        file.write_all(format!("{}\n",token.to_string()).as_bytes()).unwrap();
+    for name in &names {
+        match instruction_value(name).and_then(instruction_info) {
+            Some(info) => infos.push(info),
+            None => {
+                eprintln!("Unknown instruction: {}", name);
+                process::exit(1);
+            },
+        }
     }
 
-    println!("eax: {}", vm.get_eax());
+    if json {
+        match infos.as_slice() {
+            [] => println!("{}", asm_vm::isa::to_json()),
+            [info] => println!("{}", info.to_json()),
+            _ => println!("{}", Json::Array(infos.iter().map(|info| info.to_json()).collect())),
+        }
+    } else if infos.is_empty() {
+        let names: Vec<String> = INSTRUCTIONS.iter().map(|info| info.names().join(", ")).collect();
+        let width = names.iter().map(|name| name.len()).max().unwrap_or(0) + 2;
+
+        for (name, info) in names.iter().zip(INSTRUCTIONS) {
+            println!("{:<width$}{}", name, info.summary, width = width);
+        }
+    } else {
+        let texts: Vec<String> = infos.iter().map(|info| info.explain()).collect();
+        print!("{}", texts.join("\n"));
+    }
+}
+
+fn diff(args: &[String]) {
+    let (left_name, right_name) = match args {
+        [left, right] if !left.starts_with('-') && !right.starts_with('-') => (left, right),
+        _ => usage(),
+    };
+
+    let read = |file_name: &str| fs::read_to_string(file_name).unwrap_or_else(|err| {
+        eprintln!("Can not read {}, because {}.", file_name, err);
+        process::exit(2);
+    });
+
+    match asm_vm::trace::diff(left_name, &read(left_name), right_name, &read(right_name)) {
+        Ok(None) => println!("No divergence"),
+        Ok(Some(report)) => {
+            print!("{}", report);
+            process::exit(1);
+        },
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        },
+    }
+}
+
+fn selftest(args: &[String]) {
+    let options = parse_options(args, false);
+    let results = asm_vm::selftest::selftest();
+
+    if options.verbosity >= Verbosity::NORMAL {
+        print!("{}", asm_vm::selftest::to_report(&results));
+    }
+
+    if results.iter().any(|result| result.failure.is_some()) {
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(|arg| arg.as_str()) {
+        Some("run") => run(&args[1..]),
+        Some("assemble") => assemble(&args[1..]),
+        Some("disasm") => disasm(&args[1..]),
+        Some("check-encoding") => check_encoding(&args[1..]),
+        Some("fmt") => fmt(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("serve") => serve(&args[1..]),
+        Some("lsp") => lsp(&args[1..]),
+        Some("grade") => grade(&args[1..]),
+        Some("selftest") => selftest(&args[1..]),
+        Some("explain") => explain(&args[1..]),
+        Some("diff") => diff(&args[1..]),
+        _ => run(&args),
+    }
 }
@@ -0,0 +1,216 @@
+//! Peephole optimization of the statements of a program, which rewrites short sequences of instructions into fewer or
+//! cheaper ones:
+//!
+//! * constant folding, `mov eax, 2` followed by `add eax, 3` becomes `mov eax, 5`,
+//! * `mov` from a register to itself is removed,
+//! * strength reduction, `imul eax, 8` becomes `shl eax, 3`.
+//!
+//! `mov` does not set the flags, and `shl` sets them differently from `imul`, so an instruction setting the flags is
+//! only folded or reduced when they are set again before anything could read them. Labels are kept, and nothing is
+//! rewritten across them, as a jump may arrive there.
+
+use crate::instruction::*;
+use crate::token::*;
+
+/// Whether the instruction sets `cf`, `zf`, `sf` and `of` whatever the flags were before, a shift by 0 does not.
+fn sets_flags(instruction: &Instruction) -> bool {
+    match (instruction.mnemonic, instruction.operands.as_slice()) {
+        (TokenValue::SHL | TokenValue::SHR | TokenValue::SAR, [_, Operand::Immediate(count)]) => *count != 0,
+        (mnemonic, _) => matches!(mnemonic, TokenValue::ADD | TokenValue::SUB | TokenValue::AND | TokenValue::OR |
+                                  TokenValue::XOR | TokenValue::CMP | TokenValue::TEST | TokenValue::NEG |
+                                  TokenValue::POPFD),
+    }
+}
+
+/// Whether the flags after the `i`th statement are set again, or the program returns, before they could be read.
+fn flags_unused_after(statements: &[Statement], i: usize) -> bool {
+    for statement in &statements[i + 1..] {
+        let instruction = match statement {
+            Statement::Label(..) => return false,
+            Statement::Instruction(instruction) => instruction,
+        };
+
+        match instruction.mnemonic {
+            _ if sets_flags(instruction) => return true,
+            // neither the caller nor a system call expects the flags, `iret` restores them and `hlt` stops
+            TokenValue::RET | TokenValue::INT | TokenValue::IRET | TokenValue::HLT => return true,
+            TokenValue::MOV | TokenValue::MOVZX | TokenValue::MOVSX | TokenValue::LEA | TokenValue::PUSH |
+                TokenValue::POP | TokenValue::NOT | TokenValue::ENTER | TokenValue::LEAVE | TokenValue::PUSHAD |
+                TokenValue::POPAD | TokenValue::STD | TokenValue::CLD | TokenValue::IN | TokenValue::OUT |
+                TokenValue::NOP | TokenValue::CPUID => {},
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// Sign extend the lowest `bits` bits of `value`.
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    ((value << (64 - bits)) as i64) >> (64 - bits)
+}
+
+/// Compute an instruction whose destination is a register of `size` bytes holding `constant`, and whose source is
+/// `source`, if any, as the virtual machine does. `None` if the instruction can not be folded.
+fn fold(mnemonic: TokenValue, constant: i64, source: Option<i64>, size: usize) -> Option<i64> {
+    let bits = size as u32 * 8;
+    let value = constant as u64 & ((1 << bits) - 1);
+
+    let result = match (mnemonic, source) {
+        (TokenValue::ADD, Some(source)) => value.wrapping_add(source as u64),
+        (TokenValue::SUB, Some(source)) => value.wrapping_sub(source as u64),
+        (TokenValue::AND, Some(source)) => value & source as u64,
+        (TokenValue::OR, Some(source)) => value | source as u64,
+        (TokenValue::XOR, Some(source)) => value ^ source as u64,
+        (TokenValue::IMUL, Some(source)) => value.wrapping_mul(source as u64),
+        (TokenValue::SHL, Some(count)) if (0..bits as i64).contains(&count) => value << count,
+        (TokenValue::SHR, Some(count)) if (0..bits as i64).contains(&count) => value >> count,
+        (TokenValue::SAR, Some(count)) if size == 4 && (0..32).contains(&count) => {
+            (sign_extend(value, bits) >> count) as u64
+        },
+        (TokenValue::INC, None) => value.wrapping_add(1),
+        (TokenValue::DEC, None) => value.wrapping_sub(1),
+        (TokenValue::NEG, None) => value.wrapping_neg(),
+        (TokenValue::NOT, None) => !value,
+        _ => return None,
+    };
+
+    Some(sign_extend(result, bits))
+}
+
+/// Fold `mov reg, imm` and the instruction after it, which changes the same register by immediate data, into one
+/// `mov`.
+fn fold_constant(statements: &[Statement], i: usize) -> Option<Instruction> {
+    let (first, second) = match (&statements[i], statements.get(i + 1)) {
+        (Statement::Instruction(first), Some(Statement::Instruction(second))) => (first, second),
+        _ => return None,
+    };
+
+    let (register, constant) = match (first.mnemonic, first.operands.as_slice()) {
+        (TokenValue::MOV, [Operand::Register(register), Operand::Immediate(constant)]) => (*register, *constant),
+        _ => return None,
+    };
+
+    let source = match second.operands.as_slice() {
+        [Operand::Register(destination)] if *destination == register => None,
+        [Operand::Register(destination), Operand::Immediate(source)] if *destination == register => Some(*source),
+        _ => return None,
+    };
+
+    if second.mnemonic != TokenValue::NOT && !flags_unused_after(statements, i + 1) {
+        return None;
+    }
+
+    let (_, size) = register_info(register)?;
+    let value = fold(second.mnemonic, constant, source, size)?;
+
+    Some(Instruction { operands: vec![Operand::Register(register), Operand::Immediate(value)], ..first.to_owned() })
+}
+
+/// Replace `imul reg, 2^k` with `shl reg, k`.
+fn reduce_strength(statements: &[Statement], i: usize) -> Option<Instruction> {
+    let instruction = match &statements[i] {
+        Statement::Instruction(instruction) if instruction.mnemonic == TokenValue::IMUL => instruction,
+        _ => return None,
+    };
+
+    let (register, factor) = match instruction.operands.as_slice() {
+        [Operand::Register(register), Operand::Immediate(factor)] => (*register, *factor),
+        [Operand::Register(register), Operand::Register(source), Operand::Immediate(factor)]
+            if register == source => (*register, *factor),
+        _ => return None,
+    };
+
+    if factor < 2 || factor.count_ones() != 1 || !flags_unused_after(statements, i) {
+        return None;
+    }
+
+    Some(Instruction {
+        mnemonic: TokenValue::SHL,
+        name: "shl".to_string(),
+        operands: vec![Operand::Register(register), Operand::Immediate(factor.trailing_zeros() as i64)],
+        location: instruction.location.to_owned(),
+    })
+}
+
+/// Count the instructions of the statements.
+fn count_instructions(statements: &[Statement]) -> usize {
+    statements.iter().filter(|statement| matches!(statement, Statement::Instruction(_))).count()
+}
+
+/// Optimize the statements of a program, returns the optimized statements and a report of every change, followed by
+/// the number of instructions before and after.
+///
+/// # Example
+///
+/// ```no_run
+/// # use asm_vm::instruction::Parser;
+/// # use asm_vm::optimize::*;
+/// # use asm_vm::assembler::scan_source;
+/// # use asm_vm::scanner::SourceOptions;
+/// let source = "main:\n    mov eax, 2\n    add eax, 3\n    ret\n".to_string();
+/// let tokens = scan_source("inline".to_string(), source, &SourceOptions::default());
+/// let (statements, _) = optimize(Parser::new(&tokens).parse_program().unwrap());
+/// assert_eq!(to_source(&statements), "main:\n    mov eax, 5\n    ret\n");
+/// ```
+pub fn optimize(mut statements: Vec<Statement>) -> (Vec<Statement>, Vec<String>) {
+    let before = count_instructions(&statements);
+    let mut report = Vec::new();
+    let mut i = 0;
+
+    while i < statements.len() {
+        let instruction = match &statements[i] {
+            Statement::Instruction(instruction) => instruction.to_owned(),
+            Statement::Label(..) => {
+                i += 1;
+                continue;
+            },
+        };
+
+        if let (TokenValue::MOV, [Operand::Register(destination), Operand::Register(source)]) =
+            (instruction.mnemonic, instruction.operands.as_slice()) {
+            if destination == source {
+                report.push(format!("Optimize: {} {}: removed", instruction.location.to_string(), instruction));
+                statements.remove(i);
+                continue;
+            }
+        }
+
+        if let Some(reduced) = reduce_strength(&statements, i) {
+            report.push(format!("Optimize: {} {}: replaced by {}", instruction.location.to_string(), instruction,
+                                reduced));
+            statements[i] = Statement::Instruction(reduced);
+            continue;
+        }
+
+        if let Some(folded) = fold_constant(&statements, i) {
+            if let Statement::Instruction(second) = statements.remove(i + 1) {
+                report.push(format!("Optimize: {} {}; {}: folded into {}", instruction.location.to_string(),
+                                    instruction, second, folded));
+            }
+
+            statements[i] = Statement::Instruction(folded);
+            continue;
+        }
+
+        i += 1;
+    }
+
+    report.push(format!("Optimize: {} instructions before, {} after", before, count_instructions(&statements)));
+
+    (statements, report)
+}
+
+/// Format statements as source code, which can be assembled or run again.
+pub fn to_source(statements: &[Statement]) -> String {
+    let mut source = String::new();
+
+    for statement in statements {
+        match statement {
+            Statement::Label(name, _) => source.push_str(&format!("{}:\n", name)),
+            Statement::Instruction(instruction) => source.push_str(&format!("    {}\n", instruction)),
+        }
+    }
+
+    source
+}
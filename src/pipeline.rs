@@ -0,0 +1,197 @@
+//! Model of the classic 5-stage pipeline, fetch (IF), decode (ID), execute (EX), memory (MEM) and write back (WB), for
+//! architecture courses which connect a program to the stalls it causes. It only counts cycles, every instruction is
+//! still executed at once by the virtual machine.
+//!
+//! Every instruction takes one cycle in each stage and reads its registers and flags in ID:
+//!
+//! - a data hazard stalls an instruction in ID until the registers and flags it reads are ready. With forwarding, a
+//!   result is ready for the next instruction, except one loaded from memory, which is ready a cycle later; without
+//!   it, a result is only ready once it is written back, as the register file is written before it is read.
+//! - a control hazard flushes the 2 instructions fetched after a jump, call or return which is taken, as it is
+//!   resolved in EX and the pipeline always fetches the next instruction.
+
+use crate::check::*;
+use crate::instruction::*;
+use crate::token::TokenValue;
+
+/// bit of the flags among the registers of `register_bit`
+const FLAGS: u16 = 1 << 8;
+
+/// cycles lost to a taken jump, call or return
+pub const CONTROL_PENALTY: u64 = 2;
+
+/// How an instruction uses the pipeline
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Usage {
+    /// registers and flags read, as bits
+    reads: u16,
+    /// registers and flags written, as bits
+    writes: u16,
+    /// whether it reads memory, whose value is only ready after MEM
+    load: bool,
+}
+
+impl Usage {
+    pub fn new(instruction: &Instruction) -> Self {
+        let (reads, writes) = accesses(instruction);
+        let bits = |registers: Vec<TokenValue>| {
+            registers.iter().fold(0, |bits, register| bits | register_bit(*register) as u16)
+        };
+        let mut usage = Usage { reads: bits(reads), writes: bits(writes), load: false };
+        let esp = register_bit(TokenValue::ESP) as u16;
+
+        match instruction.mnemonic {
+            TokenValue::PUSH | TokenValue::CALL | TokenValue::PUSHAD => {
+                usage.reads |= esp;
+                usage.writes |= esp;
+            },
+            TokenValue::POP | TokenValue::RET | TokenValue::IRET | TokenValue::POPAD => {
+                usage.reads |= esp;
+                usage.writes |= esp;
+                usage.load = true;
+            },
+            TokenValue::LAHF | TokenValue::INTO => usage.reads |= FLAGS,
+            TokenValue::SAHF | TokenValue::STC | TokenValue::CLC | TokenValue::STD | TokenValue::CLD => {
+                usage.writes |= FLAGS;
+            },
+            TokenValue::CMC | TokenValue::DAA | TokenValue::DAS | TokenValue::AAA | TokenValue::AAS => {
+                usage.reads |= FLAGS;
+                usage.writes |= FLAGS;
+            },
+            TokenValue::PUSHFD => {
+                usage.reads |= esp | FLAGS;
+                usage.writes |= esp;
+            },
+            TokenValue::POPFD => {
+                usage.reads |= esp;
+                usage.writes |= esp | FLAGS;
+                usage.load = true;
+            },
+            TokenValue::ENTER => {
+                usage.reads |= esp | register_bit(TokenValue::EBP) as u16;
+                usage.writes |= esp | register_bit(TokenValue::EBP) as u16;
+                // the frame pointers of the enclosing frames are copied from memory
+                usage.load = matches!(instruction.operands.get(1), Some(Operand::Immediate(level)) if level % 32 > 1);
+            },
+            TokenValue::LEAVE => {
+                usage.reads |= register_bit(TokenValue::EBP) as u16;
+                usage.writes |= esp | register_bit(TokenValue::EBP) as u16;
+                usage.load = true;
+            },
+            TokenValue::ADD | TokenValue::SUB | TokenValue::AND | TokenValue::OR | TokenValue::XOR | TokenValue::SHL |
+            TokenValue::SHR | TokenValue::SAR | TokenValue::INC | TokenValue::DEC | TokenValue::NEG | TokenValue::CMP |
+            TokenValue::TEST | TokenValue::BSF | TokenValue::BSR | TokenValue::MUL | TokenValue::IMUL |
+            TokenValue::DIV | TokenValue::IDIV => usage.writes |= FLAGS,
+            TokenValue::ADC | TokenValue::SBB => {
+                usage.reads |= FLAGS;
+                usage.writes |= FLAGS;
+            },
+            TokenValue::JE | TokenValue::JNE | TokenValue::JG | TokenValue::JGE | TokenValue::JL | TokenValue::JLE |
+            TokenValue::JA | TokenValue::JAE | TokenValue::JB | TokenValue::JBE | TokenValue::JO | TokenValue::JNO |
+            TokenValue::JS | TokenValue::JNS | TokenValue::JP | TokenValue::JNP | TokenValue::SETE | TokenValue::SETNE |
+            TokenValue::SETG | TokenValue::SETGE | TokenValue::SETL | TokenValue::SETLE | TokenValue::SETA |
+            TokenValue::SETAE | TokenValue::SETB | TokenValue::SETBE | TokenValue::SETO | TokenValue::SETNO |
+            TokenValue::SETS | TokenValue::SETNS | TokenValue::SETP | TokenValue::SETNP => usage.reads |= FLAGS,
+            _ => {},
+        }
+
+        // the destination of `mov` is only written, `lea` only computes the address of its source and `nop` pads
+        let written = match instruction.mnemonic {
+            TokenValue::MOV => 1,
+            TokenValue::LEA | TokenValue::NOP => instruction.operands.len(),
+            _ => 0,
+        };
+        usage.load |= instruction.operands.iter().skip(written).any(|operand| matches!(operand, Operand::Memory(_)));
+
+        usage
+    }
+}
+
+/// Cycles of some instructions
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PipelineStats {
+    pub instructions: u64,
+    /// cycles stalled in ID for a register or flags not ready
+    pub data_stalls: u64,
+    /// cycles of the instructions flushed after a taken jump, call or return
+    pub control_stalls: u64,
+}
+
+impl PipelineStats {
+    pub fn stalls(&self) -> u64 {
+        self.data_stalls + self.control_stalls
+    }
+
+    pub fn add(&mut self, other: &PipelineStats) {
+        self.instructions += other.instructions;
+        self.data_stalls += other.data_stalls;
+        self.control_stalls += other.control_stalls;
+    }
+}
+
+/// Stages of one instruction, by the cycle it is decoded in
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Issue {
+    /// cycle of ID, from 1, IF is the cycle before the stalls and EX, MEM and WB are the 3 after
+    pub decode: u64,
+    /// cycles stalled in ID
+    pub data_stalls: u64,
+    /// cycles lost after it, as it is a taken jump, call or return
+    pub control_stalls: u64,
+}
+
+/// State of the pipeline between instructions
+#[derive(Clone)]
+pub struct Pipeline {
+    forwarding: bool,
+    /// earliest cycle the next instruction can be decoded in
+    next: u64,
+    /// cycle the last instruction was decoded in, 0 before the first one
+    decoded: u64,
+    /// cycle from which the next instruction can read each register and the flags
+    ready: [u64; 9],
+}
+
+impl Pipeline {
+    pub fn new(forwarding: bool) -> Self {
+        Pipeline { forwarding, next: 1, decoded: 0, ready: [0; 9] }
+    }
+
+    /// Empty the pipeline, for the first instruction of a program.
+    pub fn clear(&mut self) {
+        *self = Pipeline::new(self.forwarding);
+    }
+
+    pub fn has_forwarding(&self) -> bool {
+        self.forwarding
+    }
+
+    /// Get the cycles from the fetch of the first instruction to the write back of the last one.
+    pub fn cycles(&self) -> u64 {
+        if self.decoded == 0 { 0 } else { self.decoded + 4 }
+    }
+
+    /// Issue the next instruction, which uses `usage` and transfers control if `taken`.
+    pub fn issue(&mut self, usage: &Usage, taken: bool) -> Issue {
+        let ready = (0..9).filter(|i| usage.reads & 1 << i != 0).map(|i| self.ready[i]).max().unwrap_or(0);
+        let earliest = self.next;
+        let decode = earliest.max(ready);
+
+        // the cycles after ID from which the result can be read
+        let latency = match (self.forwarding, usage.load) {
+            (true, false) => 1,
+            (true, true) => 2,
+            (false, _) => 3,
+        };
+
+        for i in (0..9).filter(|i| usage.writes & 1 << i != 0) {
+            self.ready[i] = decode + latency;
+        }
+
+        let control_stalls = if taken { CONTROL_PENALTY } else { 0 };
+        self.next = decode + 1 + control_stalls;
+        self.decoded = decode;
+
+        Issue { decode, data_stalls: decode - earliest, control_stalls }
+    }
+}
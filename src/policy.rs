@@ -0,0 +1,113 @@
+//! Instruction policy of an assignment, which forbids or requires instructions, such as:
+//!
+//! ```toml
+//! [policy]
+//! deny = ["mul", "imul"]
+//! require = ["call", "ret"]
+//! ```
+//!
+//! A denied instruction is also denied under its other names, such as `sal` for `shl`, and under the aliases of the
+//! configuration. The policy is checked when a program is loaded, before it runs.
+
+use crate::instruction::*;
+use crate::json::*;
+use crate::scanner::instruction_value;
+use crate::token::*;
+
+/// Instructions a program must not or must use
+#[derive(Default, Clone, PartialEq)]
+pub struct Policy {
+    /// instructions which must not be used, as they are named in the policy
+    pub denied: Vec<(String, TokenValue)>,
+    /// instructions which must be used at least once, as they are named in the policy
+    pub required: Vec<(String, TokenValue)>,
+}
+
+impl Policy {
+    /// Parse the `policy` table of a configuration or a specification, whose `deny` and `require` are arrays of
+    /// instruction names, which may be the `aliases` of the configuration.
+    pub fn parse(table: &Json, aliases: &[(String, String)]) -> Result<Policy, String> {
+        let members = match table {
+            Json::Object(members) => members,
+            _ => return Err("policy must be a table".to_string()),
+        };
+
+        let mut policy = Policy::default();
+
+        for (name, value) in members {
+            let instructions = match name.as_str() {
+                "deny" => &mut policy.denied,
+                "require" => &mut policy.required,
+                _ => return Err(format!("Unknown policy: {}", name)),
+            };
+
+            let names = value.as_array().ok_or_else(|| format!("policy.{} must be an array of instructions", name))?;
+
+            for instruction in names {
+                let instruction = instruction.as_str()
+                    .ok_or_else(|| format!("policy.{} must be an array of instructions", name))?;
+                let resolved = aliases.iter().find(|(alias, _)| alias.eq_ignore_ascii_case(instruction))
+                    .map_or(instruction, |(_, resolved)| resolved.as_str());
+                let mnemonic = instruction_value(resolved)
+                    .ok_or_else(|| format!("Unknown instruction in policy.{}: {}", name, instruction))?;
+                instructions.push((instruction.to_lowercase(), mnemonic));
+            }
+        }
+
+        if let Some((name, _)) = policy.denied.iter().find(|(_, denied)| {
+            policy.required.iter().any(|(_, required)| required == denied)
+        }) {
+            return Err(format!("{} is both denied and required by the policy", name));
+        }
+
+        Ok(policy)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.denied.is_empty() && self.required.is_empty()
+    }
+
+    /// Check the statements of a program, returns an error for each use of a denied instruction in the order of the
+    /// source, followed by one for each required instruction which is not used.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::assembler::parse_files;
+    /// # use asm_vm::json::Json;
+    /// # use asm_vm::policy::Policy;
+    /// # use asm_vm::scanner::SourceOptions;
+    /// let deny = Json::object(vec![("deny", Json::Array(vec!["imul".into()]))]);
+    /// let policy = Policy::parse(&deny, &[]).unwrap();
+    /// let statements = parse_files(vec!["./square.asm".to_string()], &SourceOptions::default()).unwrap();
+    /// for msg in policy.check(&statements) {
+    ///     println!("{}", msg);
+    /// }
+    /// ```
+    pub fn check(&self, statements: &[Statement]) -> Vec<String> {
+        let instructions: Vec<&Instruction> = statements.iter()
+            .filter_map(|statement| match statement {
+                Statement::Instruction(instruction) => Some(instruction),
+                Statement::Label(..) => None,
+            })
+            .collect();
+
+        let mut errors = Vec::new();
+
+        for instruction in &instructions {
+            if let Some((name, _)) = self.denied.iter().find(|(_, mnemonic)| *mnemonic == instruction.mnemonic) {
+                let also = if *name == instruction.name { String::new() } else { format!(", as {} is denied", name) };
+                errors.push(format!("Policy Error: {} {} is not allowed in this assignment{}",
+                                    instruction.location.to_string(), instruction.name, also));
+            }
+        }
+
+        for (name, mnemonic) in &self.required {
+            if !instructions.iter().any(|instruction| instruction.mnemonic == *mnemonic) {
+                errors.push(format!("Policy Error: The program must use {}, but it does not", name));
+            }
+        }
+
+        errors
+    }
+}
@@ -0,0 +1,127 @@
+//! Ports of `in` and `out`, which the host emulates by attaching a device to each port the program uses, such as a
+//! keyboard controller or a serial line for exercises of device drivers.
+//!
+//! A port without a device reads as all ones, as an unconnected bus of x86 does, and what is written to it is dropped.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use asm_vm::port::PortDevice;
+//! # use asm_vm::vm::VM;
+//! # use std::sync::{Arc, Mutex};
+//! /// Serial line which keeps the bytes written to it
+//! #[derive(Default)]
+//! struct Serial(Vec<u8>);
+//!
+//! impl PortDevice for Serial {
+//!     fn read(&mut self, _port: u16, _size: usize) -> u32 {
+//!         0
+//!     }
+//!
+//!     fn write(&mut self, _port: u16, _size: usize, value: u32) {
+//!         self.0.push(value as u8);
+//!     }
+//! }
+//!
+//! let serial = Arc::new(Mutex::new(Serial::default()));
+//! let mut vm: VM = Default::default();
+//! vm.set_port_device(0x3f8, Some(serial.clone()));
+//! vm.load_source("inline".to_string(), "main:\n    mov al, 65\n    mov dx, 0x3f8\n    out dx, al\n    ret\n"
+//!                .to_string());
+//! vm.execute();
+//! assert_eq!(serial.lock().unwrap().0, b"A");
+//! ```
+
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Devices which can be attached by name, such as by `devices` of `asmvm.toml`, see [`Console::named`]
+pub const DEVICES: &[&str] = &["serial", "debugcon"];
+
+/// Device attached to a port
+pub trait PortDevice {
+    /// Read `size` bytes, 1, 2 or 4, from `port` for `in`.
+    fn read(&mut self, port: u16, size: usize) -> u32;
+
+    /// Write the low `size` bytes of `value`, 1, 2 or 4, to `port` for `out`.
+    fn write(&mut self, port: u16, size: usize, value: u32);
+}
+
+/// Device shared by the host, a virtual machine and its forks, which may be attached to several ports
+pub type SharedPortDevice = Arc<Mutex<dyn PortDevice + Send>>;
+
+/// Console which writes the bytes `out` sends to its data port to the standard output of the host, as `-serial stdio`
+/// and `-debugcon stdio` of QEMU do
+pub struct Console {
+    /// port of the bytes written, the first of the ports of the device
+    data: u16,
+    /// whether it is the serial line COM1, whose other ports are the registers of its UART, rather than the debug
+    /// console of Bochs and QEMU
+    serial: bool,
+    /// bytes written so far, by every console sharing the limit
+    written: Arc<AtomicU64>,
+    /// most bytes which may be written, such as the output quota of the program
+    limit: Option<u64>,
+}
+
+impl Console {
+    /// Get the device named `name`, one of [`DEVICES`], with the ports it is attached to, which writes at most `limit`
+    /// bytes, counted in `written` with the other consoles given the same counter:
+    ///
+    /// - `serial`: the serial line COM1, at ports 0x3f8 to 0x3ff. Its line status register, 0x3fd, always tells that
+    ///   the transmitter is empty and that no byte was received, so a driver which polls it goes on at once, and the
+    ///   other registers, which set up the line, read as 0 and ignore what is written to them.
+    /// - `debugcon`: the debug console of Bochs and QEMU, at port 0xe9, which reads as 0xe9 so that a program can
+    ///   detect it.
+    pub fn named(name: &str, written: Arc<AtomicU64>, limit: Option<u64>) -> Option<(Console, RangeInclusive<u16>)> {
+        let (data, serial, ports) = match name {
+            "serial" => (0x3f8, true, 0x3f8..=0x3ff),
+            "debugcon" => (0xe9, false, 0xe9..=0xe9),
+            _ => return None,
+        };
+
+        Some((Console { data, serial, written, limit }, ports))
+    }
+}
+
+impl PortDevice for Console {
+    fn read(&mut self, port: u16, _size: usize) -> u32 {
+        match (self.serial, port - self.data) {
+            // line status: the transmitter holding register and the transmitter are empty, and no data is ready
+            (true, 5) => 0x60,
+            (true, _) => 0,
+            (false, _) => 0xe9,
+        }
+    }
+
+    fn write(&mut self, port: u16, _size: usize, value: u32) {
+        if port != self.data || self.limit.is_some_and(|limit| self.written.fetch_add(1, Ordering::Relaxed) >= limit) {
+            return;
+        }
+
+        let mut stdout = std::io::stdout();
+        // the program can not tell that the host failed to write, as with a real line
+        let _ = stdout.write_all(&[value as u8]).and_then(|_| stdout.flush());
+    }
+}
+
+/// Attach the devices named `names`, each one of [`DEVICES`], to their ports of `vm`, see [`Console::named`]. The
+/// bytes they write together are limited by the output quota of `vm`.
+pub fn attach<S: AsRef<str>>(vm: &mut crate::vm::VM, names: &[S]) -> Result<(), String> {
+    let written = Arc::new(AtomicU64::new(0));
+
+    for name in names {
+        let name = name.as_ref();
+        let (console, ports) = Console::named(name, written.clone(), vm.get_quotas().output)
+            .ok_or_else(|| format!("Unknown device: {}, expected one of {}", name, DEVICES.join(", ")))?;
+        let device: SharedPortDevice = Arc::new(Mutex::new(console));
+
+        for port in ports {
+            vm.set_port_device(port, Some(device.clone()));
+        }
+    }
+
+    Ok(())
+}
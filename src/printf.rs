@@ -0,0 +1,92 @@
+//! Formatting of the print service of the virtual machine, a subset of `printf` of C.
+//!
+//! A conversion is `%`, then optional flags `-` (left-justify) and `0` (pad with zeros), an optional width, and one
+//! of:
+//!
+//! * `d` or `i`, a signed decimal integer,
+//! * `u`, an unsigned decimal integer,
+//! * `x` or `X`, an unsigned hexadecimal integer in lowercase or uppercase,
+//! * `b`, an unsigned binary integer,
+//! * `c`, a character,
+//! * `s`, a string terminated by a zero byte,
+//! * `%`, a `%` itself, which takes no argument.
+
+/// Format `format`, taking the 32-bits argument of each conversion from `argument`, and the bytes of the string at an
+/// address from `string`.
+///
+/// Returns the unsupported conversion as an error, such as `%f`.
+pub fn printf(format: &[u8], mut argument: impl FnMut() -> u32, mut string: impl FnMut(u32) -> Vec<u8>)
+    -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    let mut i = 0;
+
+    while i < format.len() {
+        if format[i] != b'%' {
+            output.push(format[i]);
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        i += 1;
+
+        let (mut left, mut zero) = (false, false);
+
+        while let Some(flag) = format.get(i) {
+            match flag {
+                b'-' => left = true,
+                b'0' => zero = true,
+                _ => break,
+            }
+
+            i += 1;
+        }
+
+        let mut width = 0;
+
+        while let Some(digit) = format.get(i).filter(|digit| digit.is_ascii_digit()) {
+            width = width * 10 + (digit - b'0') as usize;
+            i += 1;
+        }
+
+        let conversion = match format.get(i) {
+            Some(conversion) => *conversion,
+            None => return Err(format!("incomplete conversion {}", String::from_utf8_lossy(&format[start..]))),
+        };
+
+        i += 1;
+
+        let converted = match conversion {
+            b'd' | b'i' => (argument() as i32).to_string().into_bytes(),
+            b'u' => argument().to_string().into_bytes(),
+            b'x' => format!("{:x}", argument()).into_bytes(),
+            b'X' => format!("{:X}", argument()).into_bytes(),
+            b'b' => format!("{:b}", argument()).into_bytes(),
+            b'c' => vec![argument() as u8],
+            b's' => {
+                let address = argument();
+                string(address)
+            },
+            b'%' => vec![b'%'],
+            _ => return Err(format!("unsupported conversion {}", String::from_utf8_lossy(&format[start..i]))),
+        };
+
+        let padding = width.saturating_sub(converted.len());
+
+        if left {
+            output.extend(&converted);
+            output.resize(output.len() + padding, b' ');
+        } else if zero && !matches!(conversion, b'c' | b's') {
+            // the zeros go after the sign
+            let sign = converted.first().filter(|sign| **sign == b'-').map_or(0, |_| 1);
+            output.extend(&converted[..sign]);
+            output.resize(output.len() + padding, b'0');
+            output.extend(&converted[sign..]);
+        } else {
+            output.resize(output.len() + padding, b' ');
+            output.extend(&converted);
+        }
+    }
+
+    Ok(output)
+}
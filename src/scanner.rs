@@ -1,8 +1,10 @@
+use crate::cfg::is_jump;
 use crate::token::*;
-use std::fs::File;
+use crate::vm::catch_error;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::SeekFrom;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 #[allow(non_camel_case_types)]
 /// State of lexical analysis
@@ -11,13 +13,408 @@ enum State {
     END_OF_FILE,
     IDENTIFIER,
     IMMEDIATE_DATA,
+    STRING_LITERAL,
     SYMBOL,
 }
 
+/// Settings of reading the source of a program, which its scanners and preprocessing share, such as those of
+/// `asmvm.toml`. Every program, such as a case of a batch or a session of a server, is read with its own.
+#[derive(Clone)]
+pub struct SourceOptions {
+    /// mnemonics defined by the user, as pairs of the alias and the instruction in lower case, see
+    /// [`SourceOptions::set_aliases`]
+    aliases: Vec<(String, String)>,
+    /// directories where included files missing from the directory of the file including them and from the current
+    /// directory are searched, in order
+    include_paths: Vec<PathBuf>,
+    /// whether the source may read files of the host, with `%include` and `incbin`, see
+    /// [`SourceOptions::set_host_files`]
+    host_files: bool,
+}
+
+impl Default for SourceOptions {
+    fn default() -> Self {
+        SourceOptions { aliases: Vec::new(), include_paths: Vec::new(), host_files: true }
+    }
+}
+
+impl SourceOptions {
+    /// Read every alias as its instruction, such as `bra` as `jmp`, in place of the aliases set before. Aliases are
+    /// case insensitive, as instructions are.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::scanner::SourceOptions;
+    /// let mut options = SourceOptions::default();
+    /// options.set_aliases(&[("bra".to_string(), "jmp".to_string()), ("move".to_string(), "mov".to_string())])
+    ///     .unwrap();
+    /// ```
+    pub fn set_aliases(&mut self, aliases: &[(String, String)]) -> Result<(), String> {
+        for (alias, instruction) in aliases {
+            check_alias(alias, instruction)?;
+        }
+
+        self.aliases = aliases.iter()
+            .map(|(alias, instruction)| (alias.to_lowercase(), instruction.to_lowercase()))
+            .collect();
+
+        Ok(())
+    }
+
+    /// Search included files in `include_paths` too, in order, after the directory of the file including them and the
+    /// current directory.
+    pub fn set_include_paths(&mut self, include_paths: &[PathBuf]) {
+        self.include_paths = include_paths.to_vec();
+    }
+
+    /// Let the source read files of the host, with `%include`, `include` and `incbin`, or not, such as a source sent
+    /// over the network, for which each of them is an error.
+    pub fn set_host_files(&mut self, host_files: bool) {
+        self.host_files = host_files;
+    }
+
+    /// Whether the source may read files of the host, see [`SourceOptions::set_host_files`].
+    pub fn get_host_files(&self) -> bool {
+        self.host_files
+    }
+}
+
+/// Get the built-in words, which are instructions, registers and keywords.
+fn dictionary() -> HashMap<String, (TokenType, TokenValue)> {
+    let mut dictionary = HashMap::new();
+    dictionary.insert("mov".to_string(), (TokenType::INSTRUCTION, TokenValue::MOV));
+    dictionary.insert("movzx".to_string(), (TokenType::INSTRUCTION, TokenValue::MOVZX));
+    dictionary.insert("movsx".to_string(), (TokenType::INSTRUCTION, TokenValue::MOVSX));
+    dictionary.insert("lea".to_string(), (TokenType::INSTRUCTION, TokenValue::LEA));
+    dictionary.insert("add".to_string(), (TokenType::INSTRUCTION, TokenValue::ADD));
+    dictionary.insert("sub".to_string(), (TokenType::INSTRUCTION, TokenValue::SUB));
+    dictionary.insert("adc".to_string(), (TokenType::INSTRUCTION, TokenValue::ADC));
+    dictionary.insert("sbb".to_string(), (TokenType::INSTRUCTION, TokenValue::SBB));
+    dictionary.insert("inc".to_string(), (TokenType::INSTRUCTION, TokenValue::INC));
+    dictionary.insert("dec".to_string(), (TokenType::INSTRUCTION, TokenValue::DEC));
+    dictionary.insert("mul".to_string(), (TokenType::INSTRUCTION, TokenValue::MUL));
+    dictionary.insert("imul".to_string(), (TokenType::INSTRUCTION, TokenValue::IMUL));
+    dictionary.insert("div".to_string(), (TokenType::INSTRUCTION, TokenValue::DIV));
+    dictionary.insert("idiv".to_string(), (TokenType::INSTRUCTION, TokenValue::IDIV));
+    dictionary.insert("and".to_string(), (TokenType::INSTRUCTION, TokenValue::AND));
+    dictionary.insert("or".to_string(), (TokenType::INSTRUCTION, TokenValue::OR));
+    dictionary.insert("xor".to_string(), (TokenType::INSTRUCTION, TokenValue::XOR));
+    dictionary.insert("not".to_string(), (TokenType::INSTRUCTION, TokenValue::NOT));
+    dictionary.insert("neg".to_string(), (TokenType::INSTRUCTION, TokenValue::NEG));
+    dictionary.insert("push".to_string(), (TokenType::INSTRUCTION, TokenValue::PUSH));
+    dictionary.insert("pop".to_string(), (TokenType::INSTRUCTION, TokenValue::POP));
+    dictionary.insert("pushad".to_string(), (TokenType::INSTRUCTION, TokenValue::PUSHAD));
+    dictionary.insert("popad".to_string(), (TokenType::INSTRUCTION, TokenValue::POPAD));
+    dictionary.insert("pushfd".to_string(), (TokenType::INSTRUCTION, TokenValue::PUSHFD));
+    dictionary.insert("popfd".to_string(), (TokenType::INSTRUCTION, TokenValue::POPFD));
+    dictionary.insert("lahf".to_string(), (TokenType::INSTRUCTION, TokenValue::LAHF));
+    dictionary.insert("sahf".to_string(), (TokenType::INSTRUCTION, TokenValue::SAHF));
+    dictionary.insert("stc".to_string(), (TokenType::INSTRUCTION, TokenValue::STC));
+    dictionary.insert("clc".to_string(), (TokenType::INSTRUCTION, TokenValue::CLC));
+    dictionary.insert("cmc".to_string(), (TokenType::INSTRUCTION, TokenValue::CMC));
+    dictionary.insert("std".to_string(), (TokenType::INSTRUCTION, TokenValue::STD));
+    dictionary.insert("cld".to_string(), (TokenType::INSTRUCTION, TokenValue::CLD));
+    dictionary.insert("daa".to_string(), (TokenType::INSTRUCTION, TokenValue::DAA));
+    dictionary.insert("das".to_string(), (TokenType::INSTRUCTION, TokenValue::DAS));
+    dictionary.insert("aaa".to_string(), (TokenType::INSTRUCTION, TokenValue::AAA));
+    dictionary.insert("aas".to_string(), (TokenType::INSTRUCTION, TokenValue::AAS));
+    dictionary.insert("shl".to_string(), (TokenType::INSTRUCTION, TokenValue::SHL));
+    dictionary.insert("sal".to_string(), (TokenType::INSTRUCTION, TokenValue::SHL));
+    dictionary.insert("shr".to_string(), (TokenType::INSTRUCTION, TokenValue::SHR));
+    dictionary.insert("sar".to_string(), (TokenType::INSTRUCTION, TokenValue::SAR));
+    dictionary.insert("cmp".to_string(), (TokenType::INSTRUCTION, TokenValue::CMP));
+    dictionary.insert("test".to_string(), (TokenType::INSTRUCTION, TokenValue::TEST));
+    dictionary.insert("bsf".to_string(), (TokenType::INSTRUCTION, TokenValue::BSF));
+    dictionary.insert("bsr".to_string(), (TokenType::INSTRUCTION, TokenValue::BSR));
+    dictionary.insert("jmp".to_string(), (TokenType::INSTRUCTION, TokenValue::JMP));
+    dictionary.insert("je".to_string(), (TokenType::INSTRUCTION, TokenValue::JE));
+    dictionary.insert("jz".to_string(), (TokenType::INSTRUCTION, TokenValue::JE));
+    dictionary.insert("jne".to_string(), (TokenType::INSTRUCTION, TokenValue::JNE));
+    dictionary.insert("jnz".to_string(), (TokenType::INSTRUCTION, TokenValue::JNE));
+    dictionary.insert("jg".to_string(), (TokenType::INSTRUCTION, TokenValue::JG));
+    dictionary.insert("jnle".to_string(), (TokenType::INSTRUCTION, TokenValue::JG));
+    dictionary.insert("jge".to_string(), (TokenType::INSTRUCTION, TokenValue::JGE));
+    dictionary.insert("jnl".to_string(), (TokenType::INSTRUCTION, TokenValue::JGE));
+    dictionary.insert("jl".to_string(), (TokenType::INSTRUCTION, TokenValue::JL));
+    dictionary.insert("jnge".to_string(), (TokenType::INSTRUCTION, TokenValue::JL));
+    dictionary.insert("jle".to_string(), (TokenType::INSTRUCTION, TokenValue::JLE));
+    dictionary.insert("jng".to_string(), (TokenType::INSTRUCTION, TokenValue::JLE));
+    dictionary.insert("ja".to_string(), (TokenType::INSTRUCTION, TokenValue::JA));
+    dictionary.insert("jnbe".to_string(), (TokenType::INSTRUCTION, TokenValue::JA));
+    dictionary.insert("jae".to_string(), (TokenType::INSTRUCTION, TokenValue::JAE));
+    dictionary.insert("jnb".to_string(), (TokenType::INSTRUCTION, TokenValue::JAE));
+    dictionary.insert("jb".to_string(), (TokenType::INSTRUCTION, TokenValue::JB));
+    dictionary.insert("jnae".to_string(), (TokenType::INSTRUCTION, TokenValue::JB));
+    dictionary.insert("jbe".to_string(), (TokenType::INSTRUCTION, TokenValue::JBE));
+    dictionary.insert("jna".to_string(), (TokenType::INSTRUCTION, TokenValue::JBE));
+    dictionary.insert("jc".to_string(), (TokenType::INSTRUCTION, TokenValue::JB));
+    dictionary.insert("jnc".to_string(), (TokenType::INSTRUCTION, TokenValue::JAE));
+    dictionary.insert("jo".to_string(), (TokenType::INSTRUCTION, TokenValue::JO));
+    dictionary.insert("jno".to_string(), (TokenType::INSTRUCTION, TokenValue::JNO));
+    dictionary.insert("js".to_string(), (TokenType::INSTRUCTION, TokenValue::JS));
+    dictionary.insert("jns".to_string(), (TokenType::INSTRUCTION, TokenValue::JNS));
+    dictionary.insert("jp".to_string(), (TokenType::INSTRUCTION, TokenValue::JP));
+    dictionary.insert("jpe".to_string(), (TokenType::INSTRUCTION, TokenValue::JP));
+    dictionary.insert("jnp".to_string(), (TokenType::INSTRUCTION, TokenValue::JNP));
+    dictionary.insert("jpo".to_string(), (TokenType::INSTRUCTION, TokenValue::JNP));
+    dictionary.insert("jecxz".to_string(), (TokenType::INSTRUCTION, TokenValue::JECXZ));
+    dictionary.insert("jcxz".to_string(), (TokenType::INSTRUCTION, TokenValue::JCXZ));
+    dictionary.insert("sete".to_string(), (TokenType::INSTRUCTION, TokenValue::SETE));
+    dictionary.insert("setz".to_string(), (TokenType::INSTRUCTION, TokenValue::SETE));
+    dictionary.insert("setne".to_string(), (TokenType::INSTRUCTION, TokenValue::SETNE));
+    dictionary.insert("setnz".to_string(), (TokenType::INSTRUCTION, TokenValue::SETNE));
+    dictionary.insert("setg".to_string(), (TokenType::INSTRUCTION, TokenValue::SETG));
+    dictionary.insert("setnle".to_string(), (TokenType::INSTRUCTION, TokenValue::SETG));
+    dictionary.insert("setge".to_string(), (TokenType::INSTRUCTION, TokenValue::SETGE));
+    dictionary.insert("setnl".to_string(), (TokenType::INSTRUCTION, TokenValue::SETGE));
+    dictionary.insert("setl".to_string(), (TokenType::INSTRUCTION, TokenValue::SETL));
+    dictionary.insert("setnge".to_string(), (TokenType::INSTRUCTION, TokenValue::SETL));
+    dictionary.insert("setle".to_string(), (TokenType::INSTRUCTION, TokenValue::SETLE));
+    dictionary.insert("setng".to_string(), (TokenType::INSTRUCTION, TokenValue::SETLE));
+    dictionary.insert("seta".to_string(), (TokenType::INSTRUCTION, TokenValue::SETA));
+    dictionary.insert("setnbe".to_string(), (TokenType::INSTRUCTION, TokenValue::SETA));
+    dictionary.insert("setae".to_string(), (TokenType::INSTRUCTION, TokenValue::SETAE));
+    dictionary.insert("setnb".to_string(), (TokenType::INSTRUCTION, TokenValue::SETAE));
+    dictionary.insert("setb".to_string(), (TokenType::INSTRUCTION, TokenValue::SETB));
+    dictionary.insert("setnae".to_string(), (TokenType::INSTRUCTION, TokenValue::SETB));
+    dictionary.insert("setbe".to_string(), (TokenType::INSTRUCTION, TokenValue::SETBE));
+    dictionary.insert("setna".to_string(), (TokenType::INSTRUCTION, TokenValue::SETBE));
+    dictionary.insert("setc".to_string(), (TokenType::INSTRUCTION, TokenValue::SETB));
+    dictionary.insert("setnc".to_string(), (TokenType::INSTRUCTION, TokenValue::SETAE));
+    dictionary.insert("seto".to_string(), (TokenType::INSTRUCTION, TokenValue::SETO));
+    dictionary.insert("setno".to_string(), (TokenType::INSTRUCTION, TokenValue::SETNO));
+    dictionary.insert("sets".to_string(), (TokenType::INSTRUCTION, TokenValue::SETS));
+    dictionary.insert("setns".to_string(), (TokenType::INSTRUCTION, TokenValue::SETNS));
+    dictionary.insert("setp".to_string(), (TokenType::INSTRUCTION, TokenValue::SETP));
+    dictionary.insert("setpe".to_string(), (TokenType::INSTRUCTION, TokenValue::SETP));
+    dictionary.insert("setnp".to_string(), (TokenType::INSTRUCTION, TokenValue::SETNP));
+    dictionary.insert("setpo".to_string(), (TokenType::INSTRUCTION, TokenValue::SETNP));
+    dictionary.insert("call".to_string(), (TokenType::INSTRUCTION, TokenValue::CALL));
+    dictionary.insert("ret".to_string(), (TokenType::INSTRUCTION, TokenValue::RET));
+    dictionary.insert("enter".to_string(), (TokenType::INSTRUCTION, TokenValue::ENTER));
+    dictionary.insert("leave".to_string(), (TokenType::INSTRUCTION, TokenValue::LEAVE));
+    dictionary.insert("int".to_string(), (TokenType::INSTRUCTION, TokenValue::INT));
+    dictionary.insert("into".to_string(), (TokenType::INSTRUCTION, TokenValue::INTO));
+    dictionary.insert("bound".to_string(), (TokenType::INSTRUCTION, TokenValue::BOUND));
+    dictionary.insert("cpuid".to_string(), (TokenType::INSTRUCTION, TokenValue::CPUID));
+    dictionary.insert("ivt".to_string(), (TokenType::INSTRUCTION, TokenValue::IVT));
+    dictionary.insert("iret".to_string(), (TokenType::INSTRUCTION, TokenValue::IRET));
+    dictionary.insert("iretd".to_string(), (TokenType::INSTRUCTION, TokenValue::IRET));
+    dictionary.insert("hlt".to_string(), (TokenType::INSTRUCTION, TokenValue::HLT));
+    dictionary.insert("nop".to_string(), (TokenType::INSTRUCTION, TokenValue::NOP));
+    dictionary.insert("in".to_string(), (TokenType::INSTRUCTION, TokenValue::IN));
+    dictionary.insert("out".to_string(), (TokenType::INSTRUCTION, TokenValue::OUT));
+    dictionary.insert("incbin".to_string(), (TokenType::INSTRUCTION, TokenValue::INCBIN));
+    dictionary.insert("org".to_string(), (TokenType::INSTRUCTION, TokenValue::ORG));
+    dictionary.insert("global".to_string(), (TokenType::INSTRUCTION, TokenValue::GLOBAL));
+    dictionary.insert("extern".to_string(), (TokenType::INSTRUCTION, TokenValue::EXTERN));
+    dictionary.insert("db".to_string(), (TokenType::INSTRUCTION, TokenValue::DB));
+    dictionary.insert("dw".to_string(), (TokenType::INSTRUCTION, TokenValue::DW));
+    dictionary.insert("dd".to_string(), (TokenType::INSTRUCTION, TokenValue::DD));
+    dictionary.insert(".data".to_string(), (TokenType::INSTRUCTION, TokenValue::DATA));
+    dictionary.insert(".text".to_string(), (TokenType::INSTRUCTION, TokenValue::TEXT));
+    dictionary.insert(".code".to_string(), (TokenType::INSTRUCTION, TokenValue::TEXT));
+    dictionary.insert(".stack".to_string(), (TokenType::INSTRUCTION, TokenValue::STACK));
+    dictionary.insert("endp".to_string(), (TokenType::INSTRUCTION, TokenValue::ENDP));
+    dictionary.insert("eax".to_string(), (TokenType::REGISTER, TokenValue::EAX));
+    dictionary.insert("ax".to_string(), (TokenType::REGISTER, TokenValue::AX));
+    dictionary.insert("ah".to_string(), (TokenType::REGISTER, TokenValue::AH));
+    dictionary.insert("al".to_string(), (TokenType::REGISTER, TokenValue::AL));
+    dictionary.insert("ebx".to_string(), (TokenType::REGISTER, TokenValue::EBX));
+    dictionary.insert("bx".to_string(), (TokenType::REGISTER, TokenValue::BX));
+    dictionary.insert("bh".to_string(), (TokenType::REGISTER, TokenValue::BH));
+    dictionary.insert("bl".to_string(), (TokenType::REGISTER, TokenValue::BL));
+    dictionary.insert("ecx".to_string(), (TokenType::REGISTER, TokenValue::ECX));
+    dictionary.insert("cx".to_string(), (TokenType::REGISTER, TokenValue::CX));
+    dictionary.insert("ch".to_string(), (TokenType::REGISTER, TokenValue::CH));
+    dictionary.insert("cl".to_string(), (TokenType::REGISTER, TokenValue::CL));
+    dictionary.insert("edx".to_string(), (TokenType::REGISTER, TokenValue::EDX));
+    dictionary.insert("dx".to_string(), (TokenType::REGISTER, TokenValue::DX));
+    dictionary.insert("dh".to_string(), (TokenType::REGISTER, TokenValue::DH));
+    dictionary.insert("dl".to_string(), (TokenType::REGISTER, TokenValue::DL));
+    dictionary.insert("esi".to_string(), (TokenType::REGISTER, TokenValue::ESI));
+    dictionary.insert("si".to_string(), (TokenType::REGISTER, TokenValue::SI));
+    dictionary.insert("edi".to_string(), (TokenType::REGISTER, TokenValue::EDI));
+    dictionary.insert("di".to_string(), (TokenType::REGISTER, TokenValue::DI));
+    dictionary.insert("esp".to_string(), (TokenType::REGISTER, TokenValue::ESP));
+    dictionary.insert("sp".to_string(), (TokenType::REGISTER, TokenValue::SP));
+    dictionary.insert("ebp".to_string(), (TokenType::REGISTER, TokenValue::EBP));
+    dictionary.insert("bp".to_string(), (TokenType::REGISTER, TokenValue::BP));
+    dictionary.insert("ptr".to_string(), (TokenType::KEYWORD, TokenValue::PTR));
+    dictionary.insert("byte".to_string(), (TokenType::KEYWORD, TokenValue::BYTE));
+    dictionary.insert("word".to_string(), (TokenType::KEYWORD, TokenValue::WORD));
+    dictionary.insert("dword".to_string(), (TokenType::KEYWORD, TokenValue::DWORD));
+    dictionary.insert("times".to_string(), (TokenType::KEYWORD, TokenValue::REPEAT));
+    dictionary.insert("dup".to_string(), (TokenType::KEYWORD, TokenValue::DUP));
+    dictionary.insert("offset".to_string(), (TokenType::KEYWORD, TokenValue::OFFSET));
+    dictionary.insert("short".to_string(), (TokenType::KEYWORD, TokenValue::SHORT));
+    dictionary.insert("near".to_string(), (TokenType::KEYWORD, TokenValue::NEAR));
+    dictionary.insert("section".to_string(), (TokenType::KEYWORD, TokenValue::SECTION));
+    dictionary.insert("segment".to_string(), (TokenType::KEYWORD, TokenValue::SECTION));
+    dictionary.insert("%macro".to_string(), (TokenType::KEYWORD, TokenValue::MACRO));
+    dictionary.insert("macro".to_string(), (TokenType::KEYWORD, TokenValue::MACRO));
+    dictionary.insert("%endmacro".to_string(), (TokenType::KEYWORD, TokenValue::ENDMACRO));
+    dictionary.insert("endm".to_string(), (TokenType::KEYWORD, TokenValue::ENDMACRO));
+    dictionary.insert("local".to_string(), (TokenType::KEYWORD, TokenValue::LOCAL));
+    dictionary.insert("%include".to_string(), (TokenType::KEYWORD, TokenValue::INCLUDE));
+    dictionary.insert("include".to_string(), (TokenType::KEYWORD, TokenValue::INCLUDE));
+    dictionary.insert("equ".to_string(), (TokenType::KEYWORD, TokenValue::EQU));
+    dictionary.insert("proc".to_string(), (TokenType::KEYWORD, TokenValue::PROC));
+
+    dictionary
+}
+
+/// Get the instruction of a built-in name, such as `jz`, case insensitive.
+pub fn instruction_value(name: &str) -> Option<TokenValue> {
+    match dictionary().get(&name.to_lowercase()) {
+        Some((TokenType::INSTRUCTION, value)) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Get the built-in names of an instruction in alphabetical order, such as `je` and `jz`.
+pub fn instruction_names(mnemonic: TokenValue) -> Vec<String> {
+    let mut names: Vec<String> = dictionary().into_iter()
+        .filter(|(_, (token_type, value))| *token_type == TokenType::INSTRUCTION && *value == mnemonic)
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    names
+}
+
+/// Check that `alias` can be another name of `instruction`, such as `bra` of `jmp`.
+pub fn check_alias(alias: &str, instruction: &str) -> Result<(), String> {
+    let dictionary = dictionary();
+
+    let identifier = alias.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') &&
+        alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !identifier {
+        return Err(format!("Alias \"{}\" is not a valid mnemonic", alias));
+    }
+
+    if dictionary.contains_key(&alias.to_lowercase()) {
+        return Err(format!("Alias \"{}\" is already an instruction, a register or a keyword", alias));
+    }
+
+    match dictionary.get(&instruction.to_lowercase()) {
+        Some((TokenType::INSTRUCTION, _)) => Ok(()),
+        _ => Err(format!("Alias \"{}\" is for \"{}\", which is not an instruction", alias, instruction)),
+    }
+}
+
+/// Replace every `%include "file"` of NASM and `include file` of MASM by the tokens of the file, which keep their
+/// locations in that file, so that diagnostics point into it. Included files may include others, but not themselves.
+/// The file is searched relative to the directory of the file including it first, then to the current directory, as
+/// that of `incbin`, then to the include paths of `options`, and is read with `options`. Returns an error for each file
+/// which can not be included, whose line is left out, and, if `options` do not let the source read files of the host,
+/// for every include and `incbin`.
+pub fn expand_includes(tokens: &mut Vec<Token>, options: &SourceOptions) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut including: Vec<PathBuf> = tokens.first()
+        .and_then(|token| fs::canonicalize(token.get_token_location().get_source_file_name()).ok())
+        .into_iter()
+        .collect();
+
+    *tokens = include_files(tokens, options, &mut including, &mut errors);
+    errors
+}
+
+/// Replace the includes of the tokens, where `including` are the files being scanned, innermost last.
+fn include_files(tokens: &[Token], options: &SourceOptions, including: &mut Vec<PathBuf>, errors: &mut Vec<String>)
+    -> Vec<Token> {
+    let mut included = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let location = tokens[i].get_token_location();
+        let error = |msg: String| format!("Syntax Error: {} {}", location.to_string(), msg);
+
+        if !options.host_files && matches!(tokens[i].get_token_value(), TokenValue::INCLUDE | TokenValue::INCBIN) {
+            errors.push(error(format!("{} can not read files of the host here", tokens[i].get_token_name())));
+        }
+
+        if tokens[i].get_token_value() != TokenValue::INCLUDE {
+            included.push(tokens[i].to_owned());
+            i += 1;
+            continue;
+        }
+        let file_name = tokens.get(i + 1)
+            .filter(|token| token.get_token_location().get_line() == location.get_line() &&
+                    matches!(token.get_token_type(), TokenType::STRING | TokenType::LABEL))
+            .map(|token| token.get_token_name());
+        i += if file_name.is_some() { 2 } else { 1 };
+
+        let Some(file_name) = file_name else {
+            errors.push(error("Expected a file name after include, such as %include \"macros.inc\"".to_string()));
+            continue;
+        };
+
+        if !options.host_files {
+            continue;
+        }
+
+        let source_file_name = location.get_source_file_name();
+        let directory = Path::new(&source_file_name).parent().unwrap_or_else(|| Path::new(""));
+        let path = [directory.to_path_buf(), PathBuf::new()].iter()
+            .chain(&options.include_paths)
+            .map(|include_path| include_path.join(&file_name))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| directory.join(&file_name));
+
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.to_owned());
+
+        if including.contains(&canonical) {
+            errors.push(error(format!("{} includes itself", path.display())));
+            continue;
+        }
+
+        let source = match fs::read(&path) {
+            Ok(source) => source,
+            Err(err) => {
+                errors.push(error(format!("Can not read {}, because {}.", path.display(), err)));
+                continue;
+            },
+        };
+
+        let path_name = path.to_string_lossy().into_owned();
+        let scanned = catch_error(|| {
+            let mut scanner = Scanner::from_bytes(path_name, source);
+            scanner.set_options(options);
+            let mut tokens = Vec::new();
+
+            loop {
+                let token = scanner.get_next_token();
+
+                if token.get_token_type() == TokenType::END_OF_FILE {
+                    break tokens;
+                }
+
+                tokens.push(token);
+            }
+        });
+
+        match scanned {
+            Ok(scanned) => {
+                including.push(canonical);
+                included.extend(include_files(&scanned, options, including, errors));
+                including.pop();
+            },
+            Err(msg) => errors.push(msg),
+        }
+    }
+
+    included
+}
+
 /// Lexical scanner
 pub struct Scanner {
     source_file_name_: String,
-    file_: Option<File>,
+    /// whole source, read at once
+    source_: Option<Vec<u8>>,
+    /// index of the next byte of the source
+    offset_: usize,
     line_: i32,
     column_: i32,
     loc_: TokenLocation,
@@ -34,7 +431,8 @@ impl Default for Scanner {
     fn default() -> Self {
         Scanner {
             source_file_name_: Default::default(),
-            file_: Default::default(),
+            source_: Default::default(),
+            offset_: 0,
             line_: 1,
             column_: 0,
             loc_: Default::default(),
@@ -58,92 +456,43 @@ impl Scanner {
             Ok(file) => file,
         };
 
-        let mut dictionary = HashMap::new();
-        dictionary.insert("mov".to_string(), (TokenType::INSTRUCTION, TokenValue::MOV));
-        dictionary.insert("movzx".to_string(), (TokenType::INSTRUCTION, TokenValue::MOVZX));
-        dictionary.insert("movsx".to_string(), (TokenType::INSTRUCTION, TokenValue::MOVSX));
-        dictionary.insert("add".to_string(), (TokenType::INSTRUCTION, TokenValue::ADD));
-        dictionary.insert("sub".to_string(), (TokenType::INSTRUCTION, TokenValue::SUB));
-        dictionary.insert("inc".to_string(), (TokenType::INSTRUCTION, TokenValue::INC));
-        dictionary.insert("dec".to_string(), (TokenType::INSTRUCTION, TokenValue::DEC));
-        dictionary.insert("mul".to_string(), (TokenType::INSTRUCTION, TokenValue::MUL));
-        dictionary.insert("imul".to_string(), (TokenType::INSTRUCTION, TokenValue::IMUL));
-        dictionary.insert("div".to_string(), (TokenType::INSTRUCTION, TokenValue::DIV));
-        dictionary.insert("idiv".to_string(), (TokenType::INSTRUCTION, TokenValue::IDIV));
-        dictionary.insert("and".to_string(), (TokenType::INSTRUCTION, TokenValue::AND));
-        dictionary.insert("or".to_string(), (TokenType::INSTRUCTION, TokenValue::OR));
-        dictionary.insert("xor".to_string(), (TokenType::INSTRUCTION, TokenValue::XOR));
-        dictionary.insert("not".to_string(), (TokenType::INSTRUCTION, TokenValue::NOT));
-        dictionary.insert("neg".to_string(), (TokenType::INSTRUCTION, TokenValue::NEG));
-        dictionary.insert("push".to_string(), (TokenType::INSTRUCTION, TokenValue::PUSH));
-        dictionary.insert("pop".to_string(), (TokenType::INSTRUCTION, TokenValue::POP));
-        dictionary.insert("shl".to_string(), (TokenType::INSTRUCTION, TokenValue::SHL));
-        dictionary.insert("sal".to_string(), (TokenType::INSTRUCTION, TokenValue::SHL));
-        dictionary.insert("shr".to_string(), (TokenType::INSTRUCTION, TokenValue::SHR));
-        dictionary.insert("sar".to_string(), (TokenType::INSTRUCTION, TokenValue::SAR));
-        dictionary.insert("cmp".to_string(), (TokenType::INSTRUCTION, TokenValue::CMP));
-        dictionary.insert("jmp".to_string(), (TokenType::INSTRUCTION, TokenValue::JMP));
-        dictionary.insert("je".to_string(), (TokenType::INSTRUCTION, TokenValue::JE));
-        dictionary.insert("jz".to_string(), (TokenType::INSTRUCTION, TokenValue::JE));
-        dictionary.insert("jne".to_string(), (TokenType::INSTRUCTION, TokenValue::JNE));
-        dictionary.insert("jnz".to_string(), (TokenType::INSTRUCTION, TokenValue::JNE));
-        dictionary.insert("jg".to_string(), (TokenType::INSTRUCTION, TokenValue::JG));
-        dictionary.insert("jnle".to_string(), (TokenType::INSTRUCTION, TokenValue::JG));
-        dictionary.insert("jge".to_string(), (TokenType::INSTRUCTION, TokenValue::JGE));
-        dictionary.insert("jnl".to_string(), (TokenType::INSTRUCTION, TokenValue::JGE));
-        dictionary.insert("jl".to_string(), (TokenType::INSTRUCTION, TokenValue::JL));
-        dictionary.insert("jnge".to_string(), (TokenType::INSTRUCTION, TokenValue::JL));
-        dictionary.insert("jle".to_string(), (TokenType::INSTRUCTION, TokenValue::JLE));
-        dictionary.insert("jng".to_string(), (TokenType::INSTRUCTION, TokenValue::JLE));
-        dictionary.insert("ja".to_string(), (TokenType::INSTRUCTION, TokenValue::JA));
-        dictionary.insert("jnbe".to_string(), (TokenType::INSTRUCTION, TokenValue::JA));
-        dictionary.insert("jae".to_string(), (TokenType::INSTRUCTION, TokenValue::JAE));
-        dictionary.insert("jnb".to_string(), (TokenType::INSTRUCTION, TokenValue::JAE));
-        dictionary.insert("jb".to_string(), (TokenType::INSTRUCTION, TokenValue::JB));
-        dictionary.insert("jnae".to_string(), (TokenType::INSTRUCTION, TokenValue::JB));
-        dictionary.insert("jbe".to_string(), (TokenType::INSTRUCTION, TokenValue::JBE));
-        dictionary.insert("jna".to_string(), (TokenType::INSTRUCTION, TokenValue::JBE));
-        dictionary.insert("call".to_string(), (TokenType::INSTRUCTION, TokenValue::CALL));
-        dictionary.insert("ret".to_string(), (TokenType::INSTRUCTION, TokenValue::RET));
-        dictionary.insert("enter".to_string(), (TokenType::INSTRUCTION, TokenValue::ENTER));
-        dictionary.insert("leave".to_string(), (TokenType::INSTRUCTION, TokenValue::LEAVE));
-        dictionary.insert("eax".to_string(), (TokenType::REGISTER, TokenValue::EAX));
-        dictionary.insert("ax".to_string(), (TokenType::REGISTER, TokenValue::AX));
-        dictionary.insert("ah".to_string(), (TokenType::REGISTER, TokenValue::AH));
-        dictionary.insert("al".to_string(), (TokenType::REGISTER, TokenValue::AL));
-        dictionary.insert("ebx".to_string(), (TokenType::REGISTER, TokenValue::EBX));
-        dictionary.insert("bx".to_string(), (TokenType::REGISTER, TokenValue::BX));
-        dictionary.insert("bh".to_string(), (TokenType::REGISTER, TokenValue::BH));
-        dictionary.insert("bl".to_string(), (TokenType::REGISTER, TokenValue::BL));
-        dictionary.insert("ecx".to_string(), (TokenType::REGISTER, TokenValue::ECX));
-        dictionary.insert("cx".to_string(), (TokenType::REGISTER, TokenValue::CX));
-        dictionary.insert("ch".to_string(), (TokenType::REGISTER, TokenValue::CH));
-        dictionary.insert("cl".to_string(), (TokenType::REGISTER, TokenValue::CL));
-        dictionary.insert("edx".to_string(), (TokenType::REGISTER, TokenValue::EDX));
-        dictionary.insert("dx".to_string(), (TokenType::REGISTER, TokenValue::DX));
-        dictionary.insert("dh".to_string(), (TokenType::REGISTER, TokenValue::DH));
-        dictionary.insert("dl".to_string(), (TokenType::REGISTER, TokenValue::DL));
-        dictionary.insert("esi".to_string(), (TokenType::REGISTER, TokenValue::ESI));
-        dictionary.insert("si".to_string(), (TokenType::REGISTER, TokenValue::SI));
-        dictionary.insert("edi".to_string(), (TokenType::REGISTER, TokenValue::EDI));
-        dictionary.insert("di".to_string(), (TokenType::REGISTER, TokenValue::DI));
-        dictionary.insert("esp".to_string(), (TokenType::REGISTER, TokenValue::ESP));
-        dictionary.insert("sp".to_string(), (TokenType::REGISTER, TokenValue::SP));
-        dictionary.insert("ebp".to_string(), (TokenType::REGISTER, TokenValue::EBP));
-        dictionary.insert("bp".to_string(), (TokenType::REGISTER, TokenValue::BP));
-        dictionary.insert("ptr".to_string(), (TokenType::KEYWORD, TokenValue::PTR));
-        dictionary.insert("byte".to_string(), (TokenType::KEYWORD, TokenValue::BYTE));
-        dictionary.insert("word".to_string(), (TokenType::KEYWORD, TokenValue::WORD));
-        dictionary.insert("dword".to_string(), (TokenType::KEYWORD, TokenValue::DWORD));
+        Scanner::from_reader(source_file_name, file)
+    }
+
+    /// New scanner from source code in memory, `source_name` is only used in token location.
+    pub fn from_source(source_name: String, source: String) -> Self {
+        Scanner::from_bytes(source_name, source.into_bytes())
+    }
+
+    /// New scanner from everything `reader` reads, which needs not be seekable, such as the standard input.
+    /// `source_name` is only used in token location.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::scanner::Scanner;
+    /// let scanner = Scanner::from_reader("<stdin>".to_string(), std::io::stdin());
+    /// ```
+    pub fn from_reader(source_name: String, mut reader: impl Read) -> Self {
+        let mut source = Vec::new();
+
+        if let Err(err) = reader.read_to_end(&mut source) {
+            panic!("When trying to read {}, because {}, an error occurred.", source_name, err);
+        }
+
+        Scanner::from_bytes(source_name, source)
+    }
 
+    fn from_bytes(source_file_name: String, source: Vec<u8>) -> Self {
         Scanner {
             source_file_name_: source_file_name.to_owned(),
-            file_: Some(file),
+            source_: Some(source),
+            offset_: 0,
             line_: 1,
             column_: 0,
             loc_: TokenLocation::new(source_file_name, 1, 0),
             current_char_: Default::default(),
-            dictionary_: dictionary,
+            dictionary_: dictionary(),
             state_: State::NONE,
             token_: Default::default(),
             buffer_: Default::default(),
@@ -152,40 +501,63 @@ impl Scanner {
         }
     }
 
+    /// Read the source with `options`, such as its aliases, before the first token is read.
+    pub fn set_options(&mut self, options: &SourceOptions) {
+        for (alias, instruction) in &options.aliases {
+            let word = self.dictionary_[instruction];
+            self.dictionary_.insert(alias.to_owned(), word);
+        }
+    }
+
+    /// Get the offset of the current char, or of the end of the source after the last one.
+    fn get_offset(&self) -> usize {
+        if self.eof_flag_ { self.offset_ } else { self.offset_.saturating_sub(1) }
+    }
+
+    /// Get the location of the current char, which is where a token starts.
     fn get_token_location(&self) -> TokenLocation {
-        TokenLocation::new(self.source_file_name_.to_owned(), self.line_, self.column_)
+        let offset = self.get_offset();
+        TokenLocation::new(self.source_file_name_.to_owned(), self.line_, self.column_).with_span(offset, offset)
+    }
+
+    /// Get the span of a token which starts at `loc` and ends before the current char.
+    fn get_token_span(&self, loc: TokenLocation) -> TokenLocation {
+        let start = loc.get_start();
+        loc.with_span(start, self.get_offset())
     }
 
     /// Make a `instruction`, `register` or `label` token and reset scanner.
     fn make_token(&mut self, token_type: TokenType, token_value: TokenValue, loc: TokenLocation, name: String) {
-        self.token_ = Token::new_token(token_type, token_value, loc, name);
+        self.token_ = Token::new_token(token_type, token_value, self.get_token_span(loc), name);
         self.buffer_.clear();
         self.state_ = State::NONE;
     }
 
     /// Make a `immediate data` token and reset scanner.
     fn make_int_token(&mut self, loc: TokenLocation, name: String, int_value: u32) {
-        self.token_ = Token::new_int_token(loc, name, int_value);
+        self.token_ = Token::new_int_token(self.get_token_span(loc), name, int_value);
         self.buffer_.clear();
         self.state_ = State::NONE;
     }
 
     /// Make a `symbol` token and reset scanner.
     fn make_symbol_token(&mut self, token_value: TokenValue, loc: TokenLocation, name: String, int_value: i32) {
-        self.token_ = Token::new_symbol_token(token_value, loc, name, int_value);
+        self.token_ = Token::new_symbol_token(token_value, self.get_token_span(loc), name, int_value);
         self.buffer_.clear();
         self.state_ = State::NONE;
     }
 
     /// Get one char from source file and advance the sequence.
     fn get_next_char(&mut self) {
-        let mut buffer = [0; 1];
-        match self.file_.as_ref().unwrap().read_exact(&mut buffer) {
-            Err(_e) => {
+        match self.source_.as_ref().unwrap().get(self.offset_) {
+            None => {
                 self.eof_flag_ = true;
                 self.current_char_ = std::char::MAX;
             },
-            Ok(()) => self.current_char_ = buffer[0].into(),
+            Some(byte) => {
+                self.current_char_ = (*byte).into();
+                self.offset_ += 1;
+            },
         }
 
         if self.current_char_ == '\n' {
@@ -198,13 +570,10 @@ impl Scanner {
 
     /// Get one char from source file without advancing the sequence.
     fn get_peek_char(&mut self) -> char {
-        let mut buffer = [0; 1];
-        match self.file_.as_ref().unwrap().read_exact(&mut buffer) {
-            Err(_e) => self.eof_flag_ = true,
-            Ok(()) => buffer[0] = std::u8::MAX,
-        };
-        self.file_.as_ref().unwrap().seek(SeekFrom::Current(-1)).unwrap();
-        buffer[0].into()
+        match self.source_.as_ref().unwrap().get(self.offset_) {
+            None => std::char::MAX,
+            Some(byte) => (*byte).into(),
+        }
     }
 
     /// Add current char to buffer.
@@ -237,10 +606,40 @@ impl Scanner {
         }
     }
 
+    /// Whether the current `.` starts a line to skip, which is a directive the scanner does not know, such as
+    /// `.model small`, rather than a directive it knows, such as `.data`, the definition of a local label, such as
+    /// `.loop:` and `.table db 1, 2`, or a local label used as an operand, as in `jnz .loop`.
+    fn is_skipped_directive(&self) -> bool {
+        if self.current_char_ != '.' {
+            return false;
+        }
+
+        let rest = &self.source_.as_ref().unwrap()[self.offset_..];
+        let length = rest.iter().take_while(|byte| byte.is_ascii_alphanumeric() || **byte == b'_' || **byte == b'.')
+            .count();
+        let word = String::from_utf8_lossy(&rest[..length]).to_lowercase();
+
+        // the word after the spaces, such as `db` in `.table db 1, 2`
+        let next: String = rest[length..].iter().map(|byte| char::from(*byte))
+            .skip_while(|c| *c == ' ' || *c == '\t')
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+
+        let known = self.dictionary_.contains_key(&format!(".{}", word));
+        let label = length > 0 && (rest.get(length) == Some(&b':') ||
+            matches!(self.dictionary_.get(&next.to_lowercase()), Some((_, TokenValue::DB | TokenValue::DW |
+                                                                          TokenValue::DD | TokenValue::REPEAT))));
+        let operand = self.token_.get_token_location().get_line() == self.line_ &&
+            self.token_.get_token_location().get_source_file_name() == self.source_file_name_;
+
+        !(known || label || operand)
+    }
+
+    /// Skip a line starting with a directive the scanner does not know, such as `.model small`.
     fn handle_directive(&mut self) {
         self.loc_ = self.get_token_location();
 
-        if self.current_char_ == '.' {
+        if self.is_skipped_directive() {
             self.get_next_char();
 
             while self.current_char_ != '\n' && !self.eof_flag_ {
@@ -262,7 +661,10 @@ impl Scanner {
             self.handle_directive();
             self.handle_comment();
 
-            if !(self.current_char_.is_ascii_whitespace() || self.current_char_ == ';' || self.current_char_ == '.') || self.eof_flag_ {
+            let skipped = self.current_char_.is_ascii_whitespace() || self.current_char_ == ';' ||
+                self.is_skipped_directive();
+
+            if !skipped || self.eof_flag_ {
                 break;
             }
         }
@@ -272,12 +674,13 @@ impl Scanner {
     ///
     /// # Examples
     ///
-    /// ```
-    /// let scanner = Scanner::new("/test.asm");
+    /// ```no_run
+    /// # use asm_vm::scanner::Scanner;
+    /// let scanner = Scanner::new("./test.asm".to_string());
     /// let token = scanner.get_token();
     /// ```
     pub fn get_token(&self) -> Token {
-        if self.file_.is_some() {
+        if self.source_.is_some() {
             self.token_.to_owned()
         } else {
             panic!("Source File has not been set!");
@@ -287,12 +690,13 @@ impl Scanner {
     /// Get the next token.
     ///
     /// # Examples
-    /// ```
-    /// let scanner = Scanner::new("./test.asm");
+    /// ```no_run
+    /// # use asm_vm::scanner::Scanner;
+    /// let mut scanner = Scanner::new("./test.asm".to_string());
     /// let token = scanner.get_next_token();
     /// ```
     pub fn get_next_token(&mut self) -> Token {
-        if self.file_.is_none() {
+        if self.source_.is_none() {
             panic!("Source file has not been set!");
         }
 
@@ -311,6 +715,7 @@ impl Scanner {
                 State::END_OF_FILE => self.handle_eof_state(),
                 State::IDENTIFIER => self.handle_identifier_state(),
                 State::IMMEDIATE_DATA => self.handle_immedidate_data_state(),
+                State::STRING_LITERAL => self.handle_string_state(),
                 State::SYMBOL => self.handle_symbol_state(),
             }
 
@@ -321,10 +726,16 @@ impl Scanner {
                     if self.eof_flag_ {
                         self.state_ = State::END_OF_FILE;
                     } else {
-                        if self.current_char_.is_ascii_alphabetic() || self.current_char_ == '_' {
+                        let macro_syntax = self.current_char_ == '%' &&
+                            (self.get_peek_char().is_ascii_alphanumeric() || self.get_peek_char() == '%');
+
+                        if self.current_char_.is_ascii_alphabetic() ||
+                            matches!(self.current_char_, '_' | '.' | '@' | '$') || macro_syntax {
                             self.state_ = State::IDENTIFIER;
                         } else if self.current_char_.is_ascii_digit() {
                             self.state_ = State::IMMEDIATE_DATA;
+                        } else if self.current_char_ == '"' || self.current_char_ == '\'' {
+                            self.state_ = State::STRING_LITERAL;
                         } else {
                             self.state_ = State::SYMBOL;
                         }
@@ -385,19 +796,59 @@ impl Scanner {
         }
     }
 
+    /// Whether the token being scanned is the operand of a jump or `call`, where `1b` is a numeric label rather than
+    /// the binary integer 1.
+    fn is_jump_operand(&self) -> bool {
+        let location = self.token_.get_token_location();
+
+        // the distance comes between the jump and its label, as in `jmp short 1b` and `call near ptr 1f`
+        location.get_line() == self.line_ && location.get_source_file_name() == self.source_file_name_ &&
+            (is_jump(self.token_.get_token_value()) || matches!(self.token_.get_token_value(), TokenValue::CALL |
+                TokenValue::SHORT | TokenValue::NEAR | TokenValue::PTR))
+    }
+
+    fn handle_bdigit(&mut self) {
+        while self.current_char_ == '0' || self.current_char_ == '1' {
+            self.add_to_buffer(self.current_char_);
+            self.get_next_char();
+        }
+    }
+
     fn handle_immedidate_data_state(&mut self) {
         self.loc_ = self.get_token_location();
 
+        // the whole word of the number, such as `0FFh`
+        let word: String = self.source_.as_ref().unwrap()[self.get_offset()..].iter()
+            .map(|byte| char::from(*byte))
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect::<String>()
+            .to_lowercase();
+        let all_digits = |digits: &str, radix| !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix));
+
+        // the radix written after the digits, as in MASM, such as `0FFh`, `777o` or `777q`, `1010b` and `42d`
+        let suffix = match word.chars().last() {
+            Some('h') => Some(16),
+            Some('o' | 'q') => Some(8),
+            Some('b') if !self.is_jump_operand() => Some(2),
+            Some('d') => Some(10),
+            _ => None,
+        }.filter(|radix| all_digits(&word[..word.len() - 1], *radix));
+
         let mut number_base = 10;
 
-        if self.current_char_ == '0' && (self.get_peek_char() == 'x' || self.get_peek_char() == 'X') {
-            number_base = 16;
+        if let Some(radix) = suffix {
+            number_base = radix;
+        } else if word.strip_prefix("0b").is_some_and(|digits| all_digits(digits, 2)) {
+            number_base = 2;
 
             self.get_next_char();
             self.get_next_char();
-        }
+        } else if self.current_char_ == '0' && (self.get_peek_char() == 'x' || self.get_peek_char() == 'X') {
+            number_base = 16;
 
-        if self.current_char_ == '0' && self.get_peek_char() >= '0' && self.get_peek_char() <= '7' {
+            self.get_next_char();
+            self.get_next_char();
+        } else if self.current_char_ == '0' && self.get_peek_char() >= '0' && self.get_peek_char() <= '7' {
             number_base = 8;
 
             self.get_next_char();
@@ -407,9 +858,29 @@ impl Scanner {
             10 => self.handle_digit(),
             16 => self.handle_xdigit(),
             8 => self.handle_odigit(),
+            2 => self.handle_bdigit(),
             _ => {},
         }
 
+        if suffix.is_some() {
+            self.get_next_char();
+        }
+
+        // the numeric labels of GNU as: `1:` defines one, and `1f` and `1b` use the next and the last one
+        let next = self.get_peek_char();
+        let reference = matches!(self.current_char_, 'f' | 'F' | 'b' | 'B') &&
+            !(next.is_ascii_alphanumeric() || next == '_' || next == '.');
+
+        if number_base == 10 && suffix.is_none() && (reference || self.current_char_ == ':') {
+            if reference {
+                self.add_to_buffer(self.current_char_);
+                self.get_next_char();
+            }
+
+            self.make_token(TokenType::LABEL, TokenValue::LABEL, self.loc_.to_owned(), self.buffer_.to_owned());
+            return;
+        }
+
         if !self.error_flag_ {
             let int_value: u32 = match u32::from_str_radix(&self.buffer_.clone(), number_base) {
                 Err(err) => {
@@ -426,18 +897,53 @@ impl Scanner {
         }
     }
 
-    /// handle `instruction`, `register` and `label`.
+    /// handle `instruction`, `register` and `label`, including local labels, such as `.loop` and `main.loop`, and the
+    /// anonymous labels of MASM, `@@`, `@F` and `@B`, and the macro syntax of NASM, `%macro`, `%endmacro`, the
+    /// parameters, such as `%1`, and the local labels, such as `%%loop`.
     fn handle_identifier_state(&mut self) {
         self.loc_ = self.get_token_location();
 
+        let anonymous = self.current_char_ == '@';
+        let macro_syntax = self.current_char_ == '%';
         self.add_to_buffer(self.current_char_);
         self.get_next_char();
 
-        while self.current_char_.is_ascii_alphanumeric() || self.current_char_ == '_'{
+        // `$` is the address of the statement and `$$` that of its section, which are labels of their own
+        if self.buffer_ == "$" {
+            if self.current_char_ == '$' {
+                self.add_to_buffer(self.current_char_);
+                self.get_next_char();
+            }
+
+            self.make_token(TokenType::LABEL, TokenValue::LABEL, self.loc_.to_owned(), self.buffer_.to_owned());
+            return;
+        }
+
+        while self.current_char_.is_ascii_alphanumeric() || self.current_char_ == '_' || self.current_char_ == '.' ||
+            (anonymous && self.current_char_ == '@') || (macro_syntax && self.current_char_ == '%') {
             self.add_to_buffer(self.current_char_);
             self.get_next_char();
         }
 
+        if macro_syntax && !self.dictionary_.contains_key(&self.buffer_.to_lowercase()) {
+            let parameter = self.buffer_[1..].chars().all(|c| c.is_ascii_digit());
+            let local = self.buffer_.len() > 2 && self.buffer_.starts_with("%%") && !self.buffer_[2..].contains('%') &&
+                !self.buffer_[2..].starts_with(|c: char| c.is_ascii_digit());
+
+            if !parameter && !local {
+                self.error_report(&format!("Unknown macro syntax: {}, which is %macro, %endmacro, %include, %1 or \
+                                            %%label", self.buffer_));
+            }
+        }
+
+        // `@@1` is the name of the first anonymous label in listings
+        let numbered = self.buffer_.len() > 2 && self.buffer_.starts_with("@@") &&
+            self.buffer_[2..].chars().all(|c| c.is_ascii_digit());
+
+        if anonymous && !numbered && !matches!(self.buffer_.to_lowercase().as_str(), "@@" | "@f" | "@b") {
+            self.error_report(&format!("Unknown anonymous label: {}, which is @@, @F or @B", self.buffer_));
+        }
+
         let (token_type, token_value) = match self.dictionary_.get(&self.buffer_.to_lowercase()) {
             Some(info) => *info,
             None => (TokenType::LABEL, TokenValue::LABEL),
@@ -446,27 +952,66 @@ impl Scanner {
         self.make_token(token_type, token_value, self.loc_.to_owned(), self.buffer_.to_owned());
     }
 
+    /// handle a string between double or single quotes, which ends on the same line.
+    fn handle_string_state(&mut self) {
+        self.loc_ = self.get_token_location();
+
+        let quote = self.current_char_;
+        self.get_next_char();
+
+        while self.current_char_ != quote {
+            if self.current_char_ == '\n' || self.eof_flag_ {
+                self.error_report(&"Unterminated string.".to_string());
+                return;
+            }
+
+            self.add_to_buffer(self.current_char_);
+            self.get_next_char();
+        }
+
+        self.get_next_char();
+
+        // every char is a byte of the source file
+        let bytes: Vec<u8> = self.buffer_.chars().map(|c| c as u8).collect();
+        let string = String::from_utf8_lossy(&bytes).into_owned();
+        self.make_token(TokenType::STRING, TokenValue::STRING_LITERAL, self.loc_.to_owned(), string);
+    }
+
     fn handle_symbol_state(&mut self) {
         self.loc_ = self.get_token_location();
 
         self.add_to_buffer(self.current_char_);
 
+        // `<<` and `>>` are the only symbols of two chars
+        if matches!(self.current_char_, '<' | '>') && self.get_peek_char() == self.current_char_ {
+            self.get_next_char();
+            self.add_to_buffer(self.current_char_);
+        }
+
         let (token_value, precedence) =  match self.buffer_.as_str() {
             "+" => (TokenValue::PLUS, 10),
             "-" => (TokenValue::MINUS, 10),
             "*" => (TokenValue::TIMES, 20),
+            "/" => (TokenValue::SLASH, 20),
+            "%" => (TokenValue::PERCENT, 20),
             "," => (TokenValue::COMMA, -1),
             "[" => (TokenValue::LBRACK, -1),
             "]" => (TokenValue::RBRACK, -1),
             ":" => (TokenValue::COLON, -1),
+            "(" => (TokenValue::LPAREN, -1),
+            ")" => (TokenValue::RPAREN, -1),
+            "?" => (TokenValue::QUESTION, -1),
+            "<<" => (TokenValue::LSHIFT, 5),
+            ">>" => (TokenValue::RSHIFT, 5),
             _ => {
                 self.error_report(&format!("Unknown symbol: {}", &self.buffer_));
                 (TokenValue::UNKNOWN, -1)
             },
         };
 
-        self.make_symbol_token(token_value, self.loc_.to_owned(), self.buffer_.to_owned(), precedence);
-
+        // the token ends before the next char, as every other token
         self.get_next_char();
+
+        self.make_symbol_token(token_value, self.loc_.to_owned(), self.buffer_.to_owned(), precedence);
     }
 }
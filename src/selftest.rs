@@ -0,0 +1,549 @@
+//! Conformance self-test of the virtual machine, a suite of small programs embedded in the crate with the registers
+//! and flags each one must end with, or the runtime error it must stop with, which checks the flags of arithmetic, the
+//! stack and jumps of the current build, such as a port to another platform.
+
+use crate::vm::*;
+
+/// steps after which a case is stopped, as a broken jump may loop forever
+const MAX_STEPS: u64 = 100_000;
+
+/// One program of the suite
+struct Case {
+    name: &'static str,
+    source: &'static str,
+    /// registers, such as `eax`, and flags, such as `cf`, when the program finishes
+    expected: &'static [(&'static str, u32)],
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "add sets of and sf on signed overflow",
+        source: "main:\n mov eax, 0x7fffffff\n add eax, 1\n ret\n",
+        expected: &[("eax", 0x8000_0000), ("cf", 0), ("zf", 0), ("sf", 1), ("of", 1)],
+    },
+    Case {
+        name: "add sets cf and zf on unsigned overflow",
+        source: "main:\n mov eax, 0xffffffff\n add eax, 1\n ret\n",
+        expected: &[("eax", 0), ("cf", 1), ("zf", 1), ("sf", 0), ("of", 0)],
+    },
+    Case {
+        name: "sub sets cf on borrow",
+        source: "main:\n mov eax, 0\n sub eax, 1\n ret\n",
+        expected: &[("eax", 0xffff_ffff), ("cf", 1), ("zf", 0), ("sf", 1), ("of", 0)],
+    },
+    Case {
+        name: "sub sets of on signed overflow",
+        source: "main:\n mov eax, 0x80000000\n sub eax, 1\n ret\n",
+        expected: &[("eax", 0x7fff_ffff), ("cf", 0), ("sf", 0), ("of", 1)],
+    },
+    Case {
+        name: "cmp of equal values sets zf only",
+        source: "main:\n mov eax, 5\n cmp eax, 5\n ret\n",
+        expected: &[("eax", 5), ("cf", 0), ("zf", 1), ("sf", 0), ("of", 0)],
+    },
+    Case {
+        name: "inc and dec keep cf",
+        source: "main:\n mov eax, 0xffffffff\n add eax, 1\n inc ebx\n dec ecx\n ret\n",
+        expected: &[("ebx", 1), ("ecx", 0xffff_ffff), ("cf", 1), ("zf", 0), ("sf", 1)],
+    },
+    Case {
+        name: "neg sets cf unless the operand is zero",
+        source: "main:\n mov eax, 5\n neg eax\n ret\n",
+        expected: &[("eax", 0xffff_fffb), ("cf", 1), ("sf", 1)],
+    },
+    Case {
+        name: "and clears cf and of",
+        source: "main:\n mov eax, 0xffffffff\n add eax, 1\n mov eax, 0xf0\n and eax, 0x3c\n ret\n",
+        expected: &[("eax", 0x30), ("cf", 0), ("zf", 0), ("of", 0)],
+    },
+    Case {
+        name: "test sets zf and sf, clears cf and of and keeps the operands",
+        source: "main:\n mov eax, 0xffffffff\n add eax, 1\n mov eax, 0x80000001\n test eax, 0x80000000\n \
+                 mov ebx, 2\n test ebx, 1\n ret\n",
+        expected: &[("eax", 0x8000_0001), ("ebx", 2), ("cf", 0), ("zf", 1), ("sf", 0), ("of", 0)],
+    },
+    Case {
+        name: "lea stores the address without reading memory and keeps the flags",
+        source: "main:\n mov eax, 0xffffffff\n add eax, 1\n mov ebx, 0x100\n mov ecx, 3\n \
+                 lea eax, [ebx + ecx * 4 + 8]\n lea dx, [ebx - 1]\n ret\n",
+        expected: &[("eax", 0x114), ("edx", 0xff), ("cf", 1), ("zf", 1)],
+    },
+    Case {
+        name: "setcc writes the signed and unsigned conditions as bytes",
+        source: "main:\n mov ebx, 0xffff\n mov edx, 0x100\n mov eax, 3\n cmp eax, 5\n setl bl\n setg bh\n \
+                 mov ecx, 0xffffffff\n cmp ecx, 1\n seta cl\n setl dl\n ret\n",
+        expected: &[("ebx", 1), ("ecx", 0xffff_ff01), ("edx", 0x101)],
+    },
+    Case {
+        name: "popfd and popad restore what pushfd and pushad saved",
+        source: "main:\n mov ecx, 2\n mov esi, 3\n pushad\n mov ecx, 8\n mov esi, 9\n popad\n \
+                 mov edx, [esp - 20]\n sub edx, esp\n mov eax, 0xffffffff\n add eax, 1\n pushfd\n mov ebx, 5\n \
+                 cmp ebx, 3\n popfd\n ret\n",
+        expected: &[("eax", 0), ("ebx", 5), ("ecx", 2), ("edx", 0), ("esi", 3), ("cf", 1), ("zf", 1)],
+    },
+    Case {
+        name: "lahf loads cf, pf, af, zf and sf into ah and sahf stores them back",
+        source: "main:\n mov eax, 0xffffffff\n add eax, 1\n lahf\n mov ebx, eax\n mov ah, 0x80\n sahf\n ret\n",
+        expected: &[("ebx", 0x5700), ("cf", 0), ("zf", 0), ("sf", 1), ("pf", 0), ("af", 0)],
+    },
+    Case {
+        name: "bsf and bsr find the lowest and highest set bits and keep the destination of 0",
+        source: "main:\n mov ebx, 0xf00100\n bsf eax, ebx\n bsr ecx, ebx\n mov edx, 7\n mov esi, 0\n \
+                 bsf edx, esi\n ret\n",
+        expected: &[("eax", 8), ("ecx", 23), ("edx", 7), ("zf", 1)],
+    },
+    Case {
+        name: "stc, clc and cmc set, clear and complement cf, std and cld set and clear df",
+        source: "main:\n stc\n cmc\n std\n cld\n std\n stc\n clc\n cmc\n ret\n",
+        expected: &[("cf", 1), ("df", 1)],
+    },
+    Case {
+        name: "jecxz and jcxz jump if ecx and cx are 0",
+        source: "main:\n mov eax, 0\n mov ebx, 5\n mov ecx, 3\nagain:\n jecxz done\n add eax, 2\n sub ecx, 1\n \
+                 jmp again\ndone:\n mov ecx, 0x10000\n jcxz skip\n mov ebx, 1\nskip:\n ret\n",
+        expected: &[("eax", 6), ("ebx", 5), ("ecx", 0x10000)],
+    },
+    Case {
+        name: "jo, js, jc and jp jump by of, sf, cf and pf",
+        source: "main:\n mov ecx, 0\n mov edx, 0\n mov edi, 0\n mov ebx, 0x7fffffff\n add ebx, 1\n jno skip_o\n \
+                 mov ecx, 1\nskip_o:\n js sign\n ret\nsign:\n stc\n jnc skip_c\n mov edx, 1\nskip_c:\n mov esi, 3\n \
+                 and esi, 0xff\n jnp skip_p\n mov edi, 1\nskip_p:\n ret\n",
+        expected: &[("ecx", 1), ("edx", 1), ("edi", 1), ("pf", 1)],
+    },
+    Case {
+        name: "call through a register or memory jumps to the address it holds",
+        source: "main:\n mov ebx, 0\n mov eax, bump\n call eax\n push twice\n push bump\n call dword ptr [esp]\n \
+                 mov ecx, esp\n call dword ptr [ecx + 4]\n add esp, 8\n ret\nbump:\n inc ebx\n ret\ntwice:\n \
+                 add ebx, 2\n ret\n",
+        expected: &[("ebx", 4)],
+    },
+    Case {
+        name: "enter allocates the locals and copies the frame pointers of the enclosing frames",
+        source: "main:\n enter 8, 0\n mov esi, ebp\n sub esi, esp\n call outer\n leave\n ret\nouter:\n \
+                 enter 4, 1\n mov dword ptr [ebp - 8], 22\n call inner\n leave\n ret\ninner:\n enter 0, 2\n \
+                 mov eax, dword ptr [ebp - 4]\n mov eax, dword ptr [eax - 8]\n leave\n ret\n",
+        expected: &[("eax", 22), ("esi", 8)],
+    },
+    Case {
+        name: "imul sign-extends the product into dx:ax and sets cf and of when it does not fit",
+        source: "main:\n mov eax, 0x8000\n mov cx, 2\n imul cx\n mov esi, edx\n mov ebx, 3\n imul bx, bx, -300\n \
+                 mov al, 0xff\n mov dl, 0xff\n mul dl\n mov edi, eax\n mov ecx, 0x10000\n imul ecx, ecx, 0x10000\n \
+                 ret\n",
+        expected: &[("esi", 0xffff), ("ebx", 0xfc7c), ("edi", 0xfe01), ("ecx", 0), ("cf", 1), ("of", 1)],
+    },
+    Case {
+        name: "div divides unsigned and idiv truncates toward zero, also for the most negative dividend",
+        source: "main:\n mov eax, 100\n mov edx, 0\n mov ecx, 7\n div ecx\n mov esi, eax\n mov edi, edx\n \
+                 mov eax, -7\n mov edx, -1\n mov ecx, 2\n idiv ecx\n mov ebx, eax\n mov ebp, edx\n \
+                 mov eax, 0x80000000\n mov edx, -1\n mov ecx, -2\n idiv ecx\n mov ecx, eax\n mov ax, -300\n \
+                 mov dl, 7\n idiv dl\n ret\n",
+        expected: &[("esi", 14), ("edi", 2), ("ebx", 0xffff_fffd), ("ebp", 0xffff_ffff), ("ecx", 0x4000_0000),
+                    ("eax", 0x4000_fad6)],
+    },
+    Case {
+        name: "hlt stops the program from inside a call",
+        source: "main:\n mov eax, 1\n call stop\n mov eax, 2\n ret\nstop:\n mov ebx, 3\n hlt\n mov ebx, 4\n ret\n",
+        expected: &[("eax", 1), ("ebx", 3)],
+    },
+    Case {
+        name: "in reads all ones from a port without a device, and out drops what it writes",
+        source: "main:\n mov eax, 0\n in al, 0x60\n mov ebx, eax\n mov dx, 0x3f8\n out dx, ax\n in ax, dx\n \
+                 ret\n",
+        expected: &[("eax", 0xffff), ("ebx", 0xff)],
+    },
+    Case {
+        name: "daa, das, aaa and aas adjust BCD sums and differences by af and cf",
+        source: "main:\n mov eax, 0x38\n add al, 0x45\n daa\n mov ebx, eax\n sub al, 0x45\n das\n mov ecx, eax\n \
+                 mov eax, 8\n add al, 9\n aaa\n mov esi, eax\n mov eax, 0x102\n sub al, 5\n aas\n mov edi, eax\n \
+                 mov al, 0x99\n add al, 1\n daa\n ret\n",
+        expected: &[("ebx", 0x83), ("ecx", 0x38), ("esi", 0x107), ("edi", 7), ("eax", 0), ("cf", 1), ("af", 1)],
+    },
+    Case {
+        name: "nop skips its operands without accessing memory",
+        source: "main:\n mov eax, 0xfffffff0\n nop\n nop dword ptr [eax + eax]\n nop ax\n nop eax, ebx\n \
+                 mov ebx, 1\n ret\n",
+        expected: &[("eax", 0xffff_fff0), ("ebx", 1)],
+    },
+    Case {
+        name: "into calls the handler of vector 4 only if of is set",
+        source: "main:\n ivt 4, overflow\n mov eax, 1\n add eax, 1\n into\n mov al, 127\n add al, 1\n into\n \
+                 mov ecx, 2\n ret\noverflow:\n add ebx, 1\n iret\n",
+        expected: &[("ebx", 1), ("ecx", 2), ("eax", 0x80)],
+    },
+    Case {
+        name: "bound calls the handler of vector 5 until the signed index is within its bounds",
+        source: "main:\n ivt 5, clamp\n sub esp, 8\n mov dword ptr [esp], 0\n mov dword ptr [esp + 4], 9\n \
+                 mov eax, 5\n bound eax, dword ptr [esp]\n mov eax, -1\n bound eax, dword ptr [esp]\n \
+                 mov ebx, eax\n add esp, 8\n ret\nclamp:\n mov eax, 9\n add ecx, 1\n iret\n",
+        expected: &[("ebx", 9), ("ecx", 1)],
+    },
+    Case {
+        name: "push and pop of 16 bits move esp by 2, and pop addresses memory after moving esp",
+        source: "main:\n mov ecx, esp\n mov eax, 0x11112222\n push ax\n push eax\n pop ebx\n mov edx, ecx\n \
+                 sub edx, esp\n pop si\n push eax\n pop word ptr [esp]\n pop di\n sub ecx, esp\n ret\n",
+        expected: &[("ebx", 0x1111_2222), ("edx", 2), ("esi", 0x2222), ("edi", 0x2222), ("ecx", 0)],
+    },
+    Case {
+        name: "cpuid loads the vendor of leaf 0 and zeros for a leaf which is not set",
+        source: "main:\n mov eax, 0\n cpuid\n mov esi, ebx\n mov edi, ecx\n mov ebp, edx\n mov eax, 0x80000000\n \
+                 cpuid\n ret\n",
+        expected: &[("esi", 0x566d_7341), ("edi", 0x6c61_7574), ("ebp", 0x7269_564d), ("eax", 0), ("ebx", 0)],
+    },
+    Case {
+        name: "db, dw and dd lay out their items in memory, and a name without a colon is a label",
+        source: "main:\n mov esi, table\n mov eax, dword ptr [esi]\n movzx ebx, byte ptr [esi + 6]\n \
+                 movzx ecx, word ptr [esi + 7]\n mov edx, pointer\n mov edx, dword ptr [edx]\n ret\n.data\n\
+                 table: dd 0x12345678\n db 1, 2, -1\nwords dw 0xbeef\npointer dd words\n",
+        expected: &[("eax", 0x1234_5678), ("ebx", 0xff), ("ecx", 0xbeef), ("edx", 0x1e)],
+    },
+    Case {
+        name: "a string in db is its bytes, and in dw it is padded with zeros to whole words",
+        source: "main:\n mov esi, message\n mov eax, dword ptr [esi]\n movzx ebx, byte ptr [esi + 5]\n \
+                 mov ecx, dword ptr [esi + 6]\n ret\nmessage db 'hi', \"!\", 0, 10\n dw \"abc\"\n",
+        expected: &[("eax", 0x0021_6968), ("ebx", 0x61), ("ecx", 0x6362)],
+    },
+    Case {
+        name: "times repeats the data or the instruction after its count",
+        source: "main:\n times 3 inc eax\n times 0 inc eax\n mov esi, buffer\n mov ebx, dword ptr [esi + 4]\n \
+                 mov ecx, end\n sub ecx, esi\n ret\nbuffer times 2 dw 1, 2\n times 4 db 0\nend:\n",
+        expected: &[("eax", 3), ("ebx", 0x0002_0001), ("ecx", 12)],
+    },
+    Case {
+        name: "dup repeats the items in its parentheses, which may be dup items too",
+        source: "main:\n mov esi, table\n mov eax, dword ptr [esi]\n mov ebx, dword ptr [esi + 4]\n \
+                 mov ecx, end\n sub ecx, esi\n ret\ntable db 2 dup(1, 2 dup(-3)), ?, 3 dup(?)\nend:\n",
+        expected: &[("eax", 0x01fd_fd01), ("ebx", 0xfdfd), ("ecx", 10)],
+    },
+    Case {
+        name: "offset before a label is its address, as the label alone is",
+        source: "main:\n mov esi, offset message\n mov edi, message\n push offset main\n pop ebx\n \
+                 movzx eax, byte ptr [esi]\n ret\nmessage db \"A\"\n",
+        expected: &[("eax", 0x41), ("esi", 0x14), ("edi", 0x14), ("ebx", 0)],
+    },
+    Case {
+        name: "a label in brackets is the address of a variable, with registers and a displacement added",
+        source: "main:\n mov eax, [counter]\n add eax, dword ptr [counter + 4]\n mov ebx, 1\n \
+                 mov ecx, [table + ebx * 4]\n inc dword ptr [counter]\n mov edx, [counter]\n ret\n\
+                 counter dd 5, 7\ntable dd 10, 20\n",
+        expected: &[("eax", 12), ("ecx", 20), ("edx", 6)],
+    },
+    Case {
+        name: "local labels belong to the label before them, and @F and @B find the next and last @@",
+        source: "main:\n mov ecx, 3\n.loop:\n call twice\n dec ecx\n jnz .loop\n jmp @F\n@@:\n inc edi\n\
+                 @@:\n inc esi\n cmp esi, 2\n jl @B\n ret\ntwice:\n mov edx, 2\n.loop:\n inc eax\n dec edx\n \
+                 jnz .loop\n ret\n",
+        expected: &[("eax", 6), ("esi", 2), ("edi", 1)],
+    },
+    Case {
+        name: "numeric labels may be defined again, and 1f and 1b find the next and last 1",
+        source: "main:\n mov ecx, 3\n1:\n inc eax\n dec ecx\n jnz 1b\n jmp 1f\n inc ebx\n1:\n mov edx, 2\n\
+                 1: inc esi\n dec edx\n jnz 1b\n ret\n",
+        expected: &[("eax", 3), ("ebx", 0), ("esi", 2)],
+    },
+    Case {
+        name: "macros replace their uses, with the arguments and a name of their own for every local label",
+        source: "%macro countdown 2\n mov ecx, %2\n%%again:\n inc %1\n dec ecx\n jnz %%again\n%endmacro\n\
+                 twice MACRO dst, src\n LOCAL skip\n cmp src, 0\n je skip\n countdown dst, 2\nskip:\nENDM\n\
+                 main:\n countdown eax, 3\n mov ebx, 1\n twice esi, ebx\n twice edi, edx\n ret\n",
+        expected: &[("eax", 3), ("esi", 2), ("edi", 0)],
+    },
+    Case {
+        name: "binary integers are written after 0b or before b, but 1b after a jump is a numeric label",
+        source: "main:\n mov eax, 0b1010\n mov ebx, 1010b\n mov ecx, 11B\n1:\n inc edx\n cmp edx, 1b\n jl 1b\n ret\n",
+        expected: &[("eax", 10), ("ebx", 10), ("ecx", 3), ("edx", 1)],
+    },
+    Case {
+        name: "the radix of an integer may be written after its digits, as in 0FFh, 777o, 17q and 42d",
+        source: "main:\n mov eax, 0FFh\n mov ebx, 777o\n mov ecx, 17q\n mov edx, 42d\n add edx, 1bh\n ret\n",
+        expected: &[("eax", 255), ("ebx", 511), ("ecx", 15), ("edx", 69)],
+    },
+    Case {
+        name: "immediate data may be a constant expression, with parentheses, shifts and constants named by equ",
+        source: "SIZE equ 4 * (1 + 2)\nmain:\n mov eax, 3 * 4 + 1\n mov ebx, ((1 << 8) - 1) >> 4\n mov ecx, -SIZE\n \
+                 mov esi, 1\n mov edx, [table + (esi << 2) + SIZE - 12]\n ret\ntable dd 5, 6\n",
+        expected: &[("eax", 13), ("ebx", 15), ("ecx", 0xffff_fff4), ("edx", 6)],
+    },
+    Case {
+        name: "addresses may divide constants and take their remainder, with * / % before + - and both before shifts",
+        source: "main:\n mov ebx, 2\n mov eax, [table + 12 / 4 * 4 - 12 % 5 * 2]\n mov ecx, [table + (-8 / 2) + 8]\n \
+                 mov edx, 1 + 3 << 1 + 1\n mov esi, 17 % 5 - 7 / 2\n ret\ntable dd 1, 2, 3, 4\n",
+        expected: &[("eax", 3), ("ecx", 2), ("edx", 16), ("esi", 0xffff_ffff)],
+    },
+    Case {
+        name: "a procedure of MASM, from name proc to name endp, is a label which call targets",
+        source: "main proc\n mov eax, 3\n call square\n mov ebx, eax\n ret\nmain endp\n\nsquare proc\n \
+                 imul eax, eax\n ret\nsquare endp\n",
+        expected: &[("eax", 9), ("ebx", 9)],
+    },
+    Case {
+        name: "the data section is placed after the code, so the data may be written first, as in MASM",
+        source: ".stack 4096\n.data\nmsg db 1, 2, 3\n.code\nmain:\n movzx eax, byte ptr [msg + 2]\n mov ebx, [count]\n \
+                 ret\n.data\ncount dd 7\n",
+        expected: &[("eax", 3), ("ebx", 7)],
+    },
+    Case {
+        name: "$ is the address of the statement and $$ that of its section, and differences of labels are constants",
+        source: "main:\n mov ecx, len\n mov edx, table_end - table\n mov esi, $ - main\n mov edi, [count]\n ret\n\
+                 .data\nmsg db \"hello\", 10\nlen equ $ - msg\ntable dd 1, 2, 3\ntable_end:\ncount dd $ - $$\n",
+        expected: &[("ecx", 6), ("edx", 12), ("esi", 10), ("edi", 18)],
+    },
+    Case {
+        name: "section .data and section .text of NASM start the sections as .data and .text",
+        source: "section .data\nvalue dd 7\nsection .text\nmain:\n mov eax, [value]\n mov ebx, value\n sub ebx, main\n \
+                 ret\n",
+        expected: &[("eax", 7), ("ebx", 18)],
+    },
+    Case {
+        name: "times 510 - ($ - $$) pads the section up to the signature of a boot sector",
+        source: "main:\n mov eax, signature - $$\n movzx ebx, word [signature]\n mov ecx, padding_end - padding\n \
+                 ret\npadding:\ntimes 510 - ($ - $$) db 0\npadding_end:\nsignature dw 0xaa55\n",
+        expected: &[("eax", 510), ("ebx", 0xaa55), ("ecx", 510 - 18)],
+    },
+    Case {
+        name: "short and near may be written before the label of a jump or call",
+        source: "main:\n mov ecx, 3\n1:\n dec ecx\n jnz short 1b\n call near ptr square\n call near square\n \
+                 jmp short done\n inc eax\ndone:\n ret\nsquare:\n add eax, 2\n ret\n",
+        expected: &[("eax", 4), ("ecx", 0)],
+    },
+    Case {
+        name: "the size of a memory operand may be written without ptr, as in NASM",
+        source: "main:\n mov ebx, table\n mov byte [ebx], 7\n mov word [ebx + 2], 300\n movzx eax, byte [ebx]\n \
+                 movzx ecx, word [ebx + 2]\n add dword [ebx + 4], 5\n mov esi, [ebx + 4]\n ret\ntable dd 0, 1\n",
+        expected: &[("eax", 7), ("ecx", 300), ("esi", 6)],
+    },
+    Case {
+        name: "adc and sbb carry and borrow into the upper halves of 64-bit numbers",
+        source: "main:\n mov eax, 0xffffffff\n mov edx, 0\n add eax, 1\n adc edx, 0\n mov ebx, 0\n mov ecx, 1\n \
+                 sub ebx, 1\n sbb ecx, 0\n ret\n",
+        expected: &[("eax", 0), ("edx", 1), ("ebx", 0xffff_ffff), ("ecx", 0), ("cf", 0), ("zf", 1)],
+    },
+    Case {
+        name: "seto, setno, sets, setns, setc, setnc and setpo set a byte by of, sf, cf and pf",
+        source: "main:\n mov eax, 0x7f\n add al, 1\n seto bl\n setno bh\n sets cl\n setns ch\n setc dl\n setnc dh\n \
+                 setpo byte [parity]\n movzx esi, byte [parity]\n ret\nparity db 0\n",
+        expected: &[("ebx", 0x0001), ("ecx", 0x0001), ("edx", 0x0100), ("esi", 1), ("of", 1), ("sf", 1), ("pf", 0)],
+    },
+    Case {
+        name: "setp and setpe set a byte if the low byte of the result has an even number of set bits, setnp does not",
+        source: "main:\n mov eax, 3\n cmp eax, 4\n setp bl\n setpe bh\n setnp cl\n setc ch\n ret\n",
+        expected: &[("ebx", 0x0101), ("ecx", 0x0100), ("cf", 1), ("pf", 1)],
+    },
+    Case {
+        name: "xor of a register with itself is zero",
+        source: "main:\n mov eax, 1234\n xor eax, eax\n ret\n",
+        expected: &[("eax", 0), ("zf", 1), ("cf", 0)],
+    },
+    Case {
+        name: "shl moves the last bit out into cf",
+        source: "main:\n mov eax, 0x80000001\n shl eax, 1\n ret\n",
+        expected: &[("eax", 2), ("cf", 1), ("zf", 0)],
+    },
+    Case {
+        name: "sar keeps the sign and shr does not",
+        source: "main:\n mov eax, -8\n sar eax, 1\n mov ebx, -8\n shr ebx, 1\n ret\n",
+        expected: &[("eax", 0xffff_fffc), ("ebx", 0x7fff_fffc)],
+    },
+    Case {
+        name: "mul writes the high half to edx",
+        source: "main:\n mov eax, 0x10000\n mov ecx, 0x10000\n mul ecx\n ret\n",
+        expected: &[("eax", 0), ("edx", 1), ("cf", 1), ("of", 1)],
+    },
+    Case {
+        name: "imul of three operands",
+        source: "main:\n mov ecx, -6\n imul eax, ecx, 7\n ret\n",
+        expected: &[("eax", (-42i32) as u32)],
+    },
+    Case {
+        name: "movsx and movzx extend a byte",
+        source: "main:\n mov ecx, 0x80\n movsx eax, cl\n movzx ebx, cl\n ret\n",
+        expected: &[("eax", 0xffff_ff80), ("ebx", 0x80)],
+    },
+    Case {
+        name: "partial registers write only their bytes",
+        source: "main:\n mov eax, 0x12345678\n mov al, 0xff\n mov ah, 0\n mov ebx, 0x12345678\n mov bx, 1\n ret\n",
+        expected: &[("eax", 0x1234_00ff), ("ebx", 0x1234_0001)],
+    },
+    Case {
+        name: "push and pop are last in, first out",
+        source: "main:\n mov ecx, esp\n push 1\n push 2\n pop eax\n pop ebx\n sub ecx, esp\n ret\n",
+        expected: &[("eax", 2), ("ebx", 1), ("ecx", 0)],
+    },
+    Case {
+        name: "push moves esp down by 4",
+        source: "main:\n mov ebx, esp\n push eax\n mov eax, ebx\n sub eax, esp\n pop ecx\n ret\n",
+        expected: &[("eax", 4)],
+    },
+    Case {
+        name: "call pushes the return address and ret pops it",
+        source: "main:\n mov ebx, esp\n call f\n sub ebx, esp\n ret\nf:\n mov eax, 42\n ret\n",
+        expected: &[("eax", 42), ("ebx", 0)],
+    },
+    Case {
+        name: "enter and leave restore the frame",
+        source: "main:\n mov ebx, esp\n mov ecx, ebp\n enter\n sub esp, 16\n leave\n \
+                 sub ebx, esp\n sub ecx, ebp\n ret\n",
+        expected: &[("ebx", 0), ("ecx", 0)],
+    },
+    Case {
+        name: "recursion",
+        source: "main:\n push 5\n call fact\n add esp, 4\n ret\nfact:\n mov eax, [esp + 4]\n cmp eax, 1\n \
+                 jle done\n dec eax\n push eax\n call fact\n add esp, 4\n imul eax, [esp + 4]\ndone:\n ret\n",
+        expected: &[("eax", 120)],
+    },
+    Case {
+        name: "je and jne follow zf",
+        source: "main:\n mov eax, 0\n cmp eax, 0\n je one\n mov eax, 99\none:\n inc eax\n cmp eax, 1\n \
+                 jne bad\n ret\nbad:\n mov eax, 99\n ret\n",
+        expected: &[("eax", 1)],
+    },
+    Case {
+        name: "jg compares signed and ja unsigned",
+        source: "main:\n mov eax, 0\n mov ecx, -1\n cmp ecx, 1\n jg bad\n ja above\n jmp bad\nabove:\n \
+                 mov eax, 1\n ret\nbad:\n mov eax, 99\n ret\n",
+        expected: &[("eax", 1)],
+    },
+    Case {
+        name: "jl and jb",
+        source: "main:\n mov eax, 0\n mov ecx, -1\n cmp ecx, 1\n jb bad\n jl less\n jmp bad\nless:\n \
+                 mov eax, 1\n ret\nbad:\n mov eax, 99\n ret\n",
+        expected: &[("eax", 1)],
+    },
+    Case {
+        name: "a loop counts down to zero",
+        source: "main:\n mov eax, 0\n mov ecx, 10\nnext:\n add eax, ecx\n dec ecx\n jnz next\n ret\n",
+        expected: &[("eax", 55), ("ecx", 0), ("zf", 1)],
+    },
+    Case {
+        name: "int calls the handler ivt installs and iret returns",
+        source: "main:\n ivt 0x40, handler\n mov eax, 1\n int 0x40\n add eax, 1\n ret\nhandler:\n \
+                 mov eax, 10\n iret\n",
+        expected: &[("eax", 11)],
+    },
+    Case {
+        name: "memory keeps the bytes in little-endian order",
+        source: "main:\n push 0x11223344\n mov al, [esp]\n movzx ebx, byte ptr [esp + 3]\n pop ecx\n ret\n",
+        expected: &[("eax", 0x44), ("ebx", 0x11), ("ecx", 0x1122_3344)],
+    },
+];
+
+/// One program of the suite which must stop with a runtime error
+struct ErrorCase {
+    name: &'static str,
+    source: &'static str,
+    /// what the runtime error must say after its location
+    reason: &'static str,
+}
+
+const ERROR_CASES: &[ErrorCase] = &[
+    ErrorCase {
+        name: "idiv of the most negative dividend by -1 is a runtime error, as the quotient does not fit",
+        source: "main:\n mov eax, 0x80000000\n mov edx, 0xffffffff\n mov ecx, -1\n idiv ecx\n ret\n",
+        reason: "The quotient of idiv does not fit in 32 bits",
+    },
+    ErrorCase {
+        name: "idiv by zero is a runtime error",
+        source: "main:\n mov eax, 7\n mov edx, 0\n mov ecx, 0\n idiv ecx\n ret\n",
+        reason: "Division by zero",
+    },
+    ErrorCase {
+        name: "div whose quotient does not fit in 32 bits is a runtime error",
+        source: "main:\n mov eax, 0\n mov edx, 1\n mov ecx, 1\n div ecx\n ret\n",
+        reason: "The quotient of div does not fit in 32 bits",
+    },
+];
+
+/// Outcome of one case of the suite
+pub struct SelftestResult {
+    pub name: &'static str,
+    /// what went wrong, such as an error of the program or a register which is not the expected one
+    pub failure: Option<String>,
+}
+
+/// Run every case of the suite on a virtual machine of its own.
+///
+/// # Example
+///
+/// ```no_run
+/// # use asm_vm::selftest::*;
+/// let results = selftest();
+/// print!("{}", to_report(&results));
+/// ```
+pub fn selftest() -> Vec<SelftestResult> {
+    CASES.iter().map(|case| SelftestResult { name: case.name, failure: run_case(case).err() })
+        .chain(ERROR_CASES.iter().map(|case| SelftestResult { name: case.name, failure: run_error_case(case).err() }))
+        .collect()
+}
+
+/// Load and run a program until it finishes, returns the error which stopped it.
+fn run_program(vm: &mut VM, name: &str, source: &str) -> Result<(), String> {
+    catch_error(|| -> Result<(), String> {
+        vm.load_source(format!("{}.asm", name), source.to_string());
+        let mut steps = 0;
+
+        while vm.step() {
+            steps += 1;
+
+            if steps >= MAX_STEPS {
+                return Err(format!("not finished after {} steps", MAX_STEPS));
+            }
+        }
+
+        Ok(())
+    }).and_then(|result| result)
+}
+
+fn run_error_case(case: &ErrorCase) -> Result<(), String> {
+    match run_program(&mut VM::default(), case.name, case.source) {
+        Ok(()) => Err(format!("finished, expected Runtime Error: {}", case.reason)),
+        Err(msg) if msg.starts_with("Runtime Error: ") && msg.ends_with(case.reason) => Ok(()),
+        Err(msg) => Err(format!("{}, expected Runtime Error: {}", msg, case.reason)),
+    }
+}
+
+fn run_case(case: &Case) -> Result<(), String> {
+    let mut vm = VM::default();
+    run_program(&mut vm, case.name, case.source)?;
+
+    let (cf, zf, sf, of) = vm.get_flags();
+    let mut mismatches = Vec::new();
+
+    for (name, expected) in case.expected {
+        let actual = match *name {
+            "cf" => cf as u32,
+            "zf" => zf as u32,
+            "sf" => sf as u32,
+            "of" => of as u32,
+            "pf" => vm.get_parity_flag() as u32,
+            "af" => vm.get_adjust_flag() as u32,
+            "df" => vm.get_direction_flag() as u32,
+            name => vm.get_register(name).unwrap_or_else(|| panic!("Unknown register of the self-test: {}", name)),
+        };
+
+        if actual != *expected {
+            mismatches.push(format!("{} is {:#x}, expected {:#x}", name, actual, expected));
+        }
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(mismatches.join(", "))
+    }
+}
+
+/// Format the results as one line for each case, `PASS` or `FAIL` with what went wrong, and the number of cases
+/// which passed and failed.
+pub fn to_report(results: &[SelftestResult]) -> String {
+    let mut report = String::new();
+
+    for result in results {
+        match &result.failure {
+            None => report.push_str(&format!("PASS  {}\n", result.name)),
+            Some(failure) => report.push_str(&format!("FAIL  {}: {}\n", result.name, failure)),
+        }
+    }
+
+    let failed = results.iter().filter(|result| result.failure.is_some()).count();
+    report.push_str(&format!("\n{} passed, {} failed\n", results.len() - failed, failed));
+    report
+}
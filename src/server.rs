@@ -0,0 +1,699 @@
+//! JSON-RPC 2.0 control server of the virtual machine.
+//!
+//! Every connection controls its own virtual machine. Requests and responses are JSON texts, one per line of a TCP
+//! connection or one per message of a WebSocket connection, such as
+//!
+//! ```text
+//! --> {"jsonrpc": "2.0", "id": 1, "method": "step", "params": {"count": 2}}
+//! <-- {"jsonrpc":"2.0","id":1,"result":{"finished":false,"location":{"file":"a.asm","line":4,"column":5},...}}
+//! ```
+//!
+//! Methods:
+//!
+//! - `load`: load `{"source": ..., "name": ...}`, and stop before the first instruction. With
+//!   `{"quotas": {"instructions": ..., "memory_written": ..., "stack": ..., "output": ..., "time": ...}}`, the
+//!   program is stopped when it exceeds one, within the quotas of the server. The time is in milliseconds, and
+//!   `"forks"` limits the nested `fork`s, 8 by default. A client can not read files of the host: `{"files": [...]}`
+//!   is refused, and so are `%include` and `incbin` in the source.
+//! - `step`: execute `{"count": ...}` instructions, 1 by default.
+//! - `run`: execute until the program is finished, a breakpoint is reached or `{"max_steps": ...}` instructions are
+//!   executed. A program stopped by a quota has the reason `quota_exceeded`, and the state of every method tells the
+//!   quota in `quota_exceeded`. Likewise, a program stopped by an exception without a handler, such as the overflow
+//!   exception of `into`, has the reason `exception`, and the state tells it in `exception`.
+//! - `read-registers`: get the general registers and the flags.
+//! - `read-memory`: get `{"address": ..., "size": ...}` bytes of memory.
+//! - `breakpoint`: add or, with `"remove": true`, remove a breakpoint at `{"line": ..., "file": ...}`, and list the
+//!   breakpoints. A breakpoint without `file` matches the line in every source file.
+//! - `pause`: stop the running `run`, whose result has the reason `paused`.
+//!
+//! A WebSocket connection also streams a `delta` notification after loading and after every instruction, with the
+//! location of the next instruction and the registers, flags and stack slots which have changed, so that a front end
+//! can show them live.
+
+use crate::vm::*;
+use crate::json::*;
+use crate::scanner::SourceOptions;
+use crate::websocket::{self, Message};
+use std::collections::{HashMap, VecDeque};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+/// error reported by the virtual machine, such as a syntax error
+const VM_ERROR: i32 = -32000;
+
+/// most stack slots reported in one `delta` notification
+const MAX_STACK_SLOTS: usize = 1024;
+
+/// Listen on `address`, such as `127.0.0.1:4000`, and serve every connection in its own thread.
+///
+/// Connections speak WebSocket if `websocket` is true, otherwise they send one request per line. Every program is
+/// limited by `quotas`, which a client may only make stricter.
+pub fn serve(address: &str, websocket: bool, quotas: Quotas, verbosity: Verbosity) -> Result<(), String> {
+    let listener = TcpListener::bind(address).map_err(|err| format!("Can not listen on {}, because {}.", address,
+                err))?;
+
+    if verbosity >= Verbosity::NORMAL {
+        eprintln!("Listening on {}", listener.local_addr().map_err(|err| err.to_string())?);
+    }
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Connection failed, because {}.", err);
+                continue;
+            },
+        };
+
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|peer| peer.to_string()).unwrap_or_default();
+
+            if verbosity >= Verbosity::VERBOSE {
+                eprintln!("{} connected", peer);
+            }
+
+            let result = if websocket {
+                serve_websocket(stream, quotas)
+            } else {
+                serve_lines(stream, quotas)
+            };
+
+            if let Err(err) = result {
+                eprintln!("{}: {}", peer, err);
+            }
+
+            if verbosity >= Verbosity::VERBOSE {
+                eprintln!("{} disconnected", peer);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Serve a connection which sends one request per line.
+fn serve_lines(stream: TcpStream, quotas: Quotas) -> Result<(), String> {
+    let mut writer = stream.try_clone().map_err(|err| err.to_string())?;
+    let reader = BufReader::new(stream);
+    let (sender, requests) = mpsc::channel();
+
+    thread::spawn(move || {
+        for line in reader.lines() {
+            match line {
+                Ok(line) => if sender.send(line).is_err() {
+                    break;
+                },
+                Err(_) => break,
+            }
+        }
+    });
+
+    let output = Box::new(move |message: &Json| {
+        writer.write_all(format!("{}\n", message).as_bytes()).map_err(|err| err.to_string())
+    });
+
+    Session::new(requests, output, false, quotas).serve()
+}
+
+/// Serve a WebSocket connection, which sends one request per text message and receives `delta` notifications.
+fn serve_websocket(stream: TcpStream, quotas: Quotas) -> Result<(), String> {
+    let mut writer = stream.try_clone().map_err(|err| err.to_string())?;
+    let mut reader = BufReader::new(stream);
+    websocket::accept(&mut reader, &mut writer)?;
+
+    let writer = Arc::new(Mutex::new(writer));
+    let pong_writer = writer.clone();
+    let (sender, requests) = mpsc::channel();
+
+    thread::spawn(move || {
+        while let Ok(Some(message)) = websocket::read_message(&mut reader) {
+            match message {
+                Message::Text(text) => if sender.send(text).is_err() {
+                    break;
+                },
+                Message::Ping(payload) => {
+                    let _ = websocket::write_pong(&mut *pong_writer.lock().unwrap(), &payload);
+                },
+                Message::Close => {
+                    let _ = websocket::write_close(&mut *pong_writer.lock().unwrap());
+                    break;
+                },
+            }
+        }
+    });
+
+    let output = Box::new(move |message: &Json| websocket::write_text(&mut *writer.lock().unwrap(),
+                &message.to_string()));
+
+    Session::new(requests, output, true, quotas).serve()
+}
+
+/// Breakpoint at a source line
+struct Breakpoint {
+    /// source file, or every source file if `None`
+    file: Option<String>,
+    line: i32,
+}
+
+/// Send a response or a notification to the client.
+type Output = Box<dyn FnMut(&Json) -> Result<(), String>>;
+
+/// Registers, flags and stack reported by the last `delta` notification
+struct Snapshot {
+    registers: Vec<u32>,
+    flags: Vec<bool>,
+    /// value of every 4-bytes stack slot from `esp` to the top of memory, by address
+    stack: HashMap<u32, u32>,
+}
+
+/// Virtual machine controlled by one connection
+struct Session {
+    vm: VM,
+    loaded: bool,
+    breakpoints: Vec<Breakpoint>,
+    requests: Receiver<String>,
+    /// requests received while running, which are answered after it
+    pending: VecDeque<String>,
+    output: Output,
+    /// whether to send a `delta` notification after every instruction
+    deltas: bool,
+    snapshot: Option<Snapshot>,
+    /// quotas of the server, which the quotas of a program may only make stricter
+    quotas: Quotas,
+    /// virtual machines saved by `fork`, the innermost last, which `unfork` returns to
+    forks: Vec<VM>,
+}
+
+/// nested forks of a session without a quota of forks, as each holds a copy of the whole memory
+const MAX_FORKS: u64 = 8;
+
+const REGISTERS: [&str; 8] = ["eax", "ebx", "ecx", "edx", "esi", "edi", "esp", "ebp"];
+const FLAGS: [&str; 4] = ["cf", "zf", "sf", "of"];
+
+impl Session {
+    fn new(requests: Receiver<String>, output: Output, deltas: bool, quotas: Quotas) -> Self {
+        let mut vm: VM = Default::default();
+        vm.set_verbosity(Verbosity::QUIET);
+
+        // the source comes from the network, which must not read the files of the host
+        let mut options = SourceOptions::default();
+        options.set_host_files(false);
+        vm.set_source_options(options);
+
+        Session {
+            vm,
+            loaded: false,
+            breakpoints: Vec::new(),
+            requests,
+            pending: VecDeque::new(),
+            output,
+            deltas,
+            snapshot: None,
+            quotas,
+            forks: Vec::new(),
+        }
+    }
+
+    /// Answer the requests until the connection is closed.
+    fn serve(&mut self) -> Result<(), String> {
+        loop {
+            let line = match self.pending.pop_front() {
+                Some(line) => line,
+                None => match self.requests.recv() {
+                    Ok(line) => line,
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(response) = self.handle(&line) {
+                (self.output)(&response)?;
+            }
+        }
+    }
+
+    /// Answer one request, `None` for a notification, which has no `id`.
+    fn handle(&mut self, line: &str) -> Option<Json> {
+        let request = match Json::parse(line) {
+            Ok(request) => request,
+            Err(msg) => return Some(error_response(Json::Null, PARSE_ERROR, msg)),
+        };
+
+        let id = request.get("id").cloned();
+        let method = request.get("method").and_then(|method| method.as_str());
+
+        let result = match (request.get("jsonrpc").and_then(|version| version.as_str()), method) {
+            (Some("2.0"), Some(method)) => {
+                let params = request.get("params").cloned().unwrap_or_else(|| Json::Object(Vec::new()));
+                self.call(method, &params)
+            },
+            _ => Err((INVALID_REQUEST, "Invalid request".to_string())),
+        };
+
+        let id = id?;
+
+        Some(match result {
+            Ok(result) => Json::object(vec![("jsonrpc", "2.0".into()), ("id", id), ("result", result)]),
+            Err((code, msg)) => error_response(id, code, msg),
+        })
+    }
+
+    fn call(&mut self, method: &str, params: &Json) -> Result<Json, (i32, String)> {
+        match method {
+            "load" => self.load(params),
+            "step" => self.step(params),
+            "run" => self.run(params),
+            "read-registers" => self.read_registers(),
+            "write-register" => self.write_register(params),
+            "read-memory" => self.read_memory(params),
+            "breakpoint" => self.breakpoint(params),
+            "backtrace" => Ok(self.backtrace()),
+            "fork" => self.fork(),
+            "unfork" => self.unfork(),
+            // there is nothing to pause out of `run`
+            "pause" => Ok(self.state()),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+        }
+    }
+
+    fn load(&mut self, params: &Json) -> Result<Json, (i32, String)> {
+        let quotas = match params.get("quotas") {
+            Some(quotas) => self.quotas.min(&parse_quotas(quotas)?),
+            None => self.quotas,
+        };
+
+        let vm = &mut self.vm;
+        vm.set_quotas(quotas);
+
+        let result = match (params.get("source"), params.get("files")) {
+            (Some(Json::String(source)), None) => {
+                let name = params.get("name").and_then(|name| name.as_str()).unwrap_or("source").to_string();
+                catch_error(|| vm.load_source(name, source.to_owned()))
+            },
+            (_, Some(_)) => return Err(invalid_params("files can not be loaded, the files of the host are not served")),
+            _ => return Err(invalid_params("Expected source")),
+        };
+
+        self.loaded = result.is_ok();
+        self.forks.clear();
+        result.map_err(|msg| (VM_ERROR, msg))?;
+
+        self.snapshot = None;
+        self.send_delta(0)?;
+
+        Ok(self.state())
+    }
+
+    fn step(&mut self, params: &Json) -> Result<Json, (i32, String)> {
+        let count = optional_u64(params, "count")?.unwrap_or(1);
+        self.check_loaded()?;
+
+        for step in 1..=count {
+            let vm = &mut self.vm;
+            let running = catch_error(|| vm.step()).map_err(|msg| (VM_ERROR, msg))?;
+            self.send_delta(step)?;
+
+            if !running {
+                break;
+            }
+        }
+
+        Ok(self.state())
+    }
+
+    fn run(&mut self, params: &Json) -> Result<Json, (i32, String)> {
+        let max_steps = optional_u64(params, "max_steps")?;
+        self.check_loaded()?;
+
+        let mut steps = 0;
+
+        let reason = loop {
+            if self.vm.get_quota_exceeded().is_some() {
+                break "quota_exceeded";
+            }
+
+            if self.vm.get_exception().is_some() {
+                break "exception";
+            }
+
+            if self.vm.is_finished() {
+                break "finished";
+            }
+
+            if max_steps == Some(steps) {
+                break "max_steps";
+            }
+
+            let vm = &mut self.vm;
+            catch_error(|| vm.step()).map_err(|msg| (VM_ERROR, msg))?;
+            steps += 1;
+            self.send_delta(steps)?;
+
+            if self.at_breakpoint() {
+                break "breakpoint";
+            }
+
+            if self.paused()? {
+                break "paused";
+            }
+        };
+
+        let mut state = self.state();
+
+        if let Json::Object(members) = &mut state {
+            members.push(("reason".to_string(), reason.into()));
+            members.push(("steps".to_string(), steps.into()));
+        }
+
+        Ok(state)
+    }
+
+    fn read_registers(&mut self) -> Result<Json, (i32, String)> {
+        let (cf, zf, sf, of) = self.vm.get_flags();
+        let mut members = Vec::new();
+
+        for name in &REGISTERS {
+            members.push((*name, self.vm.get_register(name).into()));
+        }
+
+        members.push(("cf", cf.into()));
+        members.push(("zf", zf.into()));
+        members.push(("sf", sf.into()));
+        members.push(("of", of.into()));
+
+        Ok(Json::object(members))
+    }
+
+    fn write_register(&mut self, params: &Json) -> Result<Json, (i32, String)> {
+        let name = params.get("name").and_then(|name| name.as_str())
+            .ok_or_else(|| invalid_params("name must be a register"))?;
+        let value = required_u64(params, "value")?;
+
+        if value > u32::MAX as u64 {
+            return Err(invalid_params("value must be a 32-bits integer"));
+        }
+
+        self.check_loaded()?;
+        self.vm.set_register(name, value as u32).map_err(|msg| invalid_params(&msg))?;
+        self.send_delta(0)?;
+        self.read_registers()
+    }
+
+    /// Go on with a copy of the virtual machine, keeping the original for `unfork`.
+    fn fork(&mut self) -> Result<Json, (i32, String)> {
+        self.check_loaded()?;
+
+        let limit = self.vm.get_quotas().forks.unwrap_or(MAX_FORKS);
+
+        if self.forks.len() as u64 >= limit {
+            return Err((VM_ERROR, format!("Quota exceeded: more than {} nested forks", limit)));
+        }
+
+        let fork = self.vm.fork();
+        self.forks.push(mem::replace(&mut self.vm, fork));
+
+        Ok(self.fork_state())
+    }
+
+    /// Drop the copy of the last `fork` and return to the virtual machine it was copied from.
+    fn unfork(&mut self) -> Result<Json, (i32, String)> {
+        self.vm = self.forks.pop().ok_or_else(|| (VM_ERROR, "No fork to return from".to_string()))?;
+        self.send_delta(0)?;
+
+        Ok(self.fork_state())
+    }
+
+    /// State with the number of nested forks, as `forks`.
+    fn fork_state(&self) -> Json {
+        let mut state = self.state();
+
+        if let Json::Object(members) = &mut state {
+            members.push(("forks".to_string(), self.forks.len().into()));
+        }
+
+        state
+    }
+
+    fn backtrace(&self) -> Json {
+        let frames = self.vm.frames().into_iter().map(|frame| Json::object(vec![
+            ("function", frame.function_label.map_or(Json::Null, |label| label.into())),
+            ("return_address", frame.return_address.map_or(Json::Null, |address| address.into())),
+            ("ebp", frame.ebp.into()),
+            ("locals", Json::Array(vec![frame.locals_range.start.into(), frame.locals_range.end.into()])),
+        ]));
+
+        Json::object(vec![("frames", Json::Array(frames.collect()))])
+    }
+
+    fn read_memory(&mut self, params: &Json) -> Result<Json, (i32, String)> {
+        let address = required_u64(params, "address")?;
+        let size = required_u64(params, "size")?;
+
+        if address > u32::MAX as u64 {
+            return Err(invalid_params("address must be a 32-bits address"));
+        }
+
+        match self.vm.read_memory(address as u32, size as usize) {
+            Some(bytes) => Ok(Json::object(vec![
+                ("address", address.into()),
+                ("symbol", self.vm.symbolize(address as u32).map_or(Json::Null, |symbol| symbol.into())),
+                ("bytes", Json::Array(bytes.iter().map(|byte| (*byte as u32).into()).collect())),
+            ])),
+            None => Err((VM_ERROR, format!("Invalid memory address: {:#x}", address))),
+        }
+    }
+
+    fn breakpoint(&mut self, params: &Json) -> Result<Json, (i32, String)> {
+        let file = match params.get("file") {
+            None | Some(Json::Null) => None,
+            Some(Json::String(file)) => Some(file.to_owned()),
+            Some(_) => return Err(invalid_params("file must be a string")),
+        };
+
+        if let Some(line) = optional_u64(params, "line")? {
+            let line = line as i32;
+            let remove = params.get("remove").and_then(|remove| remove.as_bool()).unwrap_or(false);
+
+            self.breakpoints.retain(|breakpoint| breakpoint.file != file || breakpoint.line != line);
+
+            if !remove {
+                self.breakpoints.push(Breakpoint { file, line });
+            }
+        }
+
+        let breakpoints = self.breakpoints.iter().map(|breakpoint| Json::object(vec![
+            ("file", breakpoint.file.to_owned().into()),
+            ("line", breakpoint.line.into()),
+        ])).collect();
+
+        Ok(Json::object(vec![("breakpoints", Json::Array(breakpoints))]))
+    }
+
+    /// Whether a `pause` request has been received while running, which is answered at once. Other requests are kept
+    /// until the end of the run.
+    fn paused(&mut self) -> Result<bool, (i32, String)> {
+        while let Ok(line) = self.requests.try_recv() {
+            let request = Json::parse(&line).ok();
+
+            if request.as_ref().and_then(|request| request.get("method")).and_then(|method| method.as_str())
+                == Some("pause") {
+                if let Some(id) = request.as_ref().and_then(|request| request.get("id")).cloned() {
+                    let response = Json::object(vec![("jsonrpc", "2.0".into()), ("id", id), ("result", self.state())]);
+                    (self.output)(&response).map_err(|msg| (VM_ERROR, msg))?;
+                }
+
+                return Ok(true);
+            }
+
+            self.pending.push_back(line);
+        }
+
+        Ok(false)
+    }
+
+    fn take_snapshot(&self) -> Snapshot {
+        let (cf, zf, sf, of) = self.vm.get_flags();
+        let esp = self.vm.get_esp();
+        let mut stack = HashMap::new();
+        let mut address = esp;
+
+        while stack.len() < MAX_STACK_SLOTS {
+            match self.vm.read_memory(address, 4) {
+                Some(bytes) => {
+                    stack.insert(address, u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+                },
+                None => break,
+            }
+
+            address = match address.checked_add(4) {
+                Some(address) => address,
+                None => break,
+            };
+        }
+
+        Snapshot {
+            registers: REGISTERS.iter().map(|name| self.vm.get_register(name).unwrap_or(0)).collect(),
+            flags: vec![cf, zf, sf, of],
+            stack,
+        }
+    }
+
+    /// Send the changes since the last `delta` notification, everything after loading.
+    fn send_delta(&mut self, step: u64) -> Result<(), (i32, String)> {
+        if !self.deltas {
+            return Ok(());
+        }
+
+        let snapshot = self.take_snapshot();
+        let last = self.snapshot.as_ref();
+
+        let registers = REGISTERS.iter().enumerate()
+            .filter(|(i, _)| last.is_none_or(|last| last.registers[*i] != snapshot.registers[*i]))
+            .map(|(i, name)| (*name, snapshot.registers[i].into()))
+            .collect();
+
+        let flags = FLAGS.iter().enumerate()
+            .filter(|(i, _)| last.is_none_or(|last| last.flags[*i] != snapshot.flags[*i]))
+            .map(|(i, name)| (*name, snapshot.flags[i].into()))
+            .collect();
+
+        let mut addresses: Vec<&u32> = snapshot.stack.iter()
+            .filter(|(address, value)| last.is_none_or(|last| last.stack.get(address) != Some(value)))
+            .map(|(address, _)| address)
+            .collect();
+        addresses.sort();
+
+        let stack = addresses.iter().map(|address| Json::object(vec![
+            ("address", (**address).into()),
+            ("value", snapshot.stack[address].into()),
+        ])).collect();
+
+        let mut delta = self.state();
+
+        if let Json::Object(members) = &mut delta {
+            members.push(("step".to_string(), step.into()));
+            members.push(("registers".to_string(), Json::object(registers)));
+            members.push(("flags".to_string(), Json::object(flags)));
+            members.push(("stack".to_string(), Json::Array(stack)));
+        }
+
+        self.snapshot = Some(snapshot);
+
+        let notification = Json::object(vec![("jsonrpc", "2.0".into()), ("method", "delta".into()), ("params", delta)]);
+        (self.output)(&notification).map_err(|msg| (VM_ERROR, msg))
+    }
+
+    fn check_loaded(&self) -> Result<(), (i32, String)> {
+        if self.loaded {
+            Ok(())
+        } else {
+            Err((VM_ERROR, "No program is loaded".to_string()))
+        }
+    }
+
+    /// Whether the next instruction is at a breakpoint.
+    fn at_breakpoint(&self) -> bool {
+        let location = match self.vm.get_location() {
+            Some(location) => location,
+            None => return false,
+        };
+
+        self.breakpoints.iter().any(|breakpoint| breakpoint.line == location.get_line() &&
+            breakpoint.file.as_ref().is_none_or(|file| *file == location.get_source_file_name()))
+    }
+
+    /// State of the program: whether it is finished, the location of the next instruction, the exit status and
+    /// whether it stopped by `hlt`.
+    fn state(&self) -> Json {
+        let location = self.vm.get_location().map(|location| Json::object(vec![
+            ("file", location.get_source_file_name().into()),
+            ("line", location.get_line().into()),
+            ("column", location.get_column().into()),
+        ]));
+
+        Json::object(vec![
+            ("finished", self.vm.is_finished().into()),
+            ("location", location.unwrap_or(Json::Null)),
+            ("eip", self.vm.get_eip().into()),
+            ("exit_code", self.vm.get_exit_code().into()),
+            ("halted", self.vm.is_halted().into()),
+            ("max_stack", self.vm.get_max_stack_usage().into()),
+            ("max_call_depth", self.vm.get_max_call_depth().into()),
+            ("quota_exceeded", self.vm.get_quota_exceeded().map_or(Json::Null, |exceeded| Json::object(vec![
+                ("quota", exceeded.quota.name().into()),
+                ("limit", exceeded.limit.into()),
+                ("message", exceeded.to_string().into()),
+            ]))),
+            ("exception", self.vm.get_exception().map_or(Json::Null, |raised| Json::object(vec![
+                ("name", raised.exception.name().into()),
+                ("vector", (raised.exception.vector() as u32).into()),
+                ("message", raised.to_string().into()),
+            ]))),
+        ])
+    }
+}
+
+fn error_response(id: Json, code: i32, msg: String) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id),
+        ("error", Json::object(vec![("code", code.into()), ("message", msg.into())])),
+    ])
+}
+
+/// Parse the quotas of `load`, such as `{"instructions": 100000, "output": 4096}`.
+fn parse_quotas(params: &Json) -> Result<Quotas, (i32, String)> {
+    let members = match params {
+        Json::Object(members) => members,
+        _ => return Err(invalid_params("quotas must be an object")),
+    };
+
+    let mut quotas = Quotas::default();
+
+    for (name, value) in members {
+        let limit = value.as_u64().ok_or_else(|| invalid_params(&format!("quotas.{} must be a positive integer",
+                                                                          name)))?;
+
+        match name.as_str() {
+            "instructions" => quotas.instructions = Some(limit),
+            "memory_written" => quotas.memory_written = Some(limit),
+            "stack" => quotas.stack = Some(limit),
+            "output" => quotas.output = Some(limit),
+            "time" => quotas.time = Some(limit),
+            "forks" => quotas.forks = Some(limit),
+            _ => return Err(invalid_params(&format!("Unknown quota: {}", name))),
+        }
+    }
+
+    Ok(quotas)
+}
+
+fn invalid_params(msg: &str) -> (i32, String) {
+    (INVALID_PARAMS, msg.to_string())
+}
+
+fn optional_u64(params: &Json, name: &str) -> Result<Option<u64>, (i32, String)> {
+    match params.get(name) {
+        None | Some(Json::Null) => Ok(None),
+        Some(value) => match value.as_u64() {
+            Some(value) => Ok(Some(value)),
+            None => Err(invalid_params(&format!("{} must be a non-negative integer", name))),
+        },
+    }
+}
+
+fn required_u64(params: &Json, name: &str) -> Result<u64, (i32, String)> {
+    optional_u64(params, name)?.ok_or_else(|| invalid_params(&format!("Missing {}", name)))
+}
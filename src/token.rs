@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use std::ops::Range;
+
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone, PartialEq)]
 /// Type of token
@@ -16,6 +18,8 @@ pub enum TokenType {
     IMMEDIATE_DATA,
     /// label, such as `main`
     LABEL,
+    /// string, such as `"table.bin"`
+    STRING,
     /// eof
     END_OF_FILE,
 }
@@ -31,10 +35,16 @@ pub enum TokenValue {
     MOVZX,
     /// `movsx`
     MOVSX,
+    /// `lea`
+    LEA,
     /// `add`
     ADD,
     /// `sub`
     SUB,
+    /// `adc`
+    ADC,
+    /// `sbb`
+    SBB,
     /// `inc`
     INC,
     /// `dec`
@@ -67,8 +77,44 @@ pub enum TokenValue {
     PUSH,
     /// `pop`
     POP,
+    /// `pushad`
+    PUSHAD,
+    /// `popad`
+    POPAD,
+    /// `pushfd`
+    PUSHFD,
+    /// `popfd`
+    POPFD,
+    /// `lahf`
+    LAHF,
+    /// `sahf`
+    SAHF,
+    /// `stc`
+    STC,
+    /// `clc`
+    CLC,
+    /// `cmc`
+    CMC,
+    /// `std`
+    STD,
+    /// `cld`
+    CLD,
+    /// `daa`
+    DAA,
+    /// `das`
+    DAS,
+    /// `aaa`
+    AAA,
+    /// `aas`
+    AAS,
     /// `cmp`
     CMP,
+    /// `test`
+    TEST,
+    /// `bsf`
+    BSF,
+    /// `bsr`
+    BSR,
     /// `jmp`
     JMP,
     /// `je`
@@ -91,6 +137,54 @@ pub enum TokenValue {
     JB,
     /// `jbe`
     JBE,
+    /// `jo`
+    JO,
+    /// `jno`
+    JNO,
+    /// `js`
+    JS,
+    /// `jns`
+    JNS,
+    /// `jp`
+    JP,
+    /// `jnp`
+    JNP,
+    /// `jecxz`
+    JECXZ,
+    /// `jcxz`
+    JCXZ,
+    /// `sete`
+    SETE,
+    /// `setne`
+    SETNE,
+    /// `setg`
+    SETG,
+    /// `setge`
+    SETGE,
+    /// `setl`
+    SETL,
+    /// `setle`
+    SETLE,
+    /// `seta`
+    SETA,
+    /// `setae`
+    SETAE,
+    /// `setb`
+    SETB,
+    /// `setbe`
+    SETBE,
+    /// `seto`
+    SETO,
+    /// `setno`
+    SETNO,
+    /// `sets`
+    SETS,
+    /// `setns`
+    SETNS,
+    /// `setp`
+    SETP,
+    /// `setnp`
+    SETNP,
     /// `call`
     CALL,
     /// `ret`
@@ -101,6 +195,46 @@ pub enum TokenValue {
     LEAVE,
     /// `int`
     INT,
+    /// `into`
+    INTO,
+    /// `bound`
+    BOUND,
+    /// `cpuid`
+    CPUID,
+    /// `ivt`, install an interrupt handler, which is not an x86 instruction
+    IVT,
+    /// `iret`
+    IRET,
+    /// `hlt`
+    HLT,
+    /// `nop`
+    NOP,
+    /// `in`
+    IN,
+    /// `out`
+    OUT,
+    /// `incbin`, include the bytes of a file, which is not an x86 instruction
+    INCBIN,
+    /// `org`, set the address of the next instruction, which is not an x86 instruction
+    ORG,
+    /// `global`, make labels visible to the other source files, which is not an x86 instruction
+    GLOBAL,
+    /// `extern`, use labels another source file makes visible, which is not an x86 instruction
+    EXTERN,
+    /// `db`, define bytes, which is not an x86 instruction
+    DB,
+    /// `dw`, define words
+    DW,
+    /// `dd`, define double words
+    DD,
+    /// `.data`, start the data section, which is not an x86 instruction
+    DATA,
+    /// `.text` or `.code`, start the code section
+    TEXT,
+    /// `.stack`, declare the size of the stack
+    STACK,
+    /// `endp`, end the procedure of MASM named after it, which is not an x86 instruction
+    ENDP,
 
     /// register
     /// `eax`
@@ -163,6 +297,30 @@ pub enum TokenValue {
     WORD,
     /// `dword`
     DWORD,
+    /// `times`, repeat the statement after its count
+    REPEAT,
+    /// `dup`, repeat the items of a data definition
+    DUP,
+    /// `offset`, the address of a label
+    OFFSET,
+    /// `short`, the distance of a jump whose target is near it
+    SHORT,
+    /// `near`, the distance of a jump or call whose target is in the same segment
+    NEAR,
+    /// `section` or `segment` of NASM, written before the name of a section, as in `section .data`
+    SECTION,
+    /// `%macro` or `macro`, starts the definition of a macro
+    MACRO,
+    /// `%endmacro` or `endm`, ends the definition of a macro
+    ENDMACRO,
+    /// `local`, the local labels of a macro of MASM
+    LOCAL,
+    /// `%include` or `include`, the tokens of another source file
+    INCLUDE,
+    /// `equ`, gives a constant a name
+    EQU,
+    /// `proc`, starts a procedure of MASM
+    PROC,
 
     /// symbol
     /// `+`
@@ -171,6 +329,10 @@ pub enum TokenValue {
     MINUS,
     /// `*`
     TIMES,
+    /// `/`
+    SLASH,
+    /// `%`
+    PERCENT,
     /// `;`
     SEMICOLON,
     /// `,`
@@ -181,11 +343,23 @@ pub enum TokenValue {
     RBRACK,
     /// `:`
     COLON,
+    /// `(`
+    LPAREN,
+    /// `)`
+    RPAREN,
+    /// `?`
+    QUESTION,
+    /// `<<`
+    LSHIFT,
+    /// `>>`
+    RSHIFT,
 
     /// immediate data
     INTEGER_LITERAL,
     /// label
     LABEL,
+    /// string
+    STRING_LITERAL,
 
     /// eof
     END_OF_FILE,
@@ -203,6 +377,7 @@ impl TokenType {
             TokenType::SYMBOL => "symbol",
             TokenType::IMMEDIATE_DATA => "immediate data",
             TokenType::LABEL => "label",
+            TokenType::STRING => "string",
             TokenType::END_OF_FILE => "eof",
         };
 
@@ -212,11 +387,15 @@ impl TokenType {
 
 #[derive(Default)]
 #[derive(Clone)]
-/// Location of token
+/// Location of token, its line and column from 1 and its span of bytes in the source
 pub struct TokenLocation {
     source_file_name_: String,
     line_: i32,
-    column_: i32
+    column_: i32,
+    /// offset of the first byte of the token
+    start_: usize,
+    /// offset after the last byte of the token, which is `start_` for a token without any, such as the end of file
+    end_: usize,
 }
 
 impl TokenLocation {
@@ -225,9 +404,48 @@ impl TokenLocation {
             source_file_name_: souce_file_name,
             line_: line,
             column_: column,
+            start_: 0,
+            end_: 0,
         }
     }
 
+    /// Set the span of the token, from the byte at offset `start` to the one before `end`.
+    pub fn with_span(mut self, start: usize, end: usize) -> Self {
+        self.start_ = start;
+        self.end_ = end;
+        self
+    }
+
+    pub fn get_source_file_name(&self) -> String {
+        self.source_file_name_.to_owned()
+    }
+
+    pub fn get_line(&self) -> i32 {
+        self.line_
+    }
+
+    pub fn get_column(&self) -> i32 {
+        self.column_
+    }
+
+    pub fn get_start(&self) -> usize {
+        self.start_
+    }
+
+    pub fn get_end(&self) -> usize {
+        self.end_
+    }
+
+    /// Get the offsets of the bytes of the token, so that `&source[location.get_span()]` is the token as written.
+    pub fn get_span(&self) -> Range<usize> {
+        self.start_..self.end_
+    }
+
+    /// Get the bytes of the token as written, such as 4 for `0x10` and 5 for `"abc"`.
+    pub fn get_length(&self) -> usize {
+        self.end_ - self.start_
+    }
+
     pub fn to_string(&self) -> String {
         format!("{}:{}:{}:", self.source_file_name_, self.line_, self.column_)
     }
@@ -328,6 +546,10 @@ impl Token {
         self.type_ = token_type;
     }
 
+    pub fn set_token_location(&mut self, loc: TokenLocation) {
+        self.location_ = loc;
+    }
+
     pub fn set_int_value(&mut self, int_value: i32) {
         if self.type_ != TokenType::IMMEDIATE_DATA {
             panic!("{} is not a immediate data token. Only immediate data token have precedence!", self.name_);
@@ -0,0 +1,436 @@
+//! Parser of the subset of TOML used by configuration and specification files.
+//!
+//! Supported: comments, `key = value` with bare, quoted and dotted keys, `[table]` and `[[array.of.tables]]` headers,
+//! basic and literal strings, including multi-line ones, integers in decimal, hexadecimal, octal and binary with `_`
+//! separators, floats, booleans, arrays and inline tables. Dates are not supported. The document becomes a
+//! [`Json`] object, whose integers are numbers.
+
+use crate::json::*;
+
+/// Parse a TOML document into an object.
+pub fn parse(source_name: &str, text: &str) -> Result<Json, String> {
+    let mut parser = TomlParser { source_name, chars: text.chars().collect(), position: 0, line: 1 };
+    let mut root = Json::Object(Vec::new());
+    // path of the current table, from the last header
+    let mut current: Vec<String> = Vec::new();
+
+    loop {
+        parser.skip_whitespace_and_comments(true);
+
+        let c = match parser.peek() {
+            Some(c) => c,
+            None => return Ok(root),
+        };
+
+        if c == '[' {
+            parser.position += 1;
+            let array = parser.peek() == Some('[');
+
+            if array {
+                parser.position += 1;
+            }
+
+            let path = parser.parse_key()?;
+            parser.expect(']')?;
+
+            if array {
+                parser.expect(']')?;
+            }
+
+            let table = if array {
+                match lookup(&mut root, &path[..path.len() - 1], &parser)? {
+                    Json::Object(members) => {
+                        let name = path.last().unwrap();
+
+                        if !members.iter().any(|(member, _)| member == name) {
+                            members.push((name.to_owned(), Json::Array(Vec::new())));
+                        }
+
+                        match members.iter_mut().find(|(member, _)| member == name) {
+                            Some((_, Json::Array(values))) => {
+                                values.push(Json::Object(Vec::new()));
+                                Ok(())
+                            },
+                            _ => Err(parser.error(&format!("\"{}\" is not an array of tables", path.join(".")))),
+                        }
+                    },
+                    _ => Err(parser.error(&format!("\"{}\" is not a table", path.join(".")))),
+                }
+            } else {
+                lookup(&mut root, &path, &parser).map(|_| ())
+            };
+
+            table?;
+            current = path;
+            parser.end_of_line()?;
+        } else {
+            let key = parser.parse_key()?;
+            parser.expect('=')?;
+            let value = parser.parse_value()?;
+
+            let (name, path) = key.split_last().unwrap();
+            let mut path: Vec<String> = current.iter().chain(path.iter()).cloned().collect();
+            let table = lookup(&mut root, &path, &parser)?;
+
+            match table {
+                Json::Object(members) => {
+                    if members.iter().any(|(member, _)| member == name) {
+                        path.push(name.to_owned());
+                        return Err(parser.error(&format!("Duplicate key: \"{}\"", path.join("."))));
+                    }
+
+                    members.push((name.to_owned(), value));
+                },
+                _ => return Err(parser.error(&format!("\"{}\" is not a table", path.join(".")))),
+            }
+
+            parser.end_of_line()?;
+        }
+    }
+}
+
+/// Find the table at `path`, creating the missing ones. A path through an array of tables goes to its last table.
+fn lookup<'a>(root: &'a mut Json, path: &[String], parser: &TomlParser) -> Result<&'a mut Json, String> {
+    let mut table = root;
+
+    for (i, name) in path.iter().enumerate() {
+        let members = match table {
+            Json::Object(members) => members,
+            _ => return Err(parser.error(&format!("\"{}\" is not a table", path[..i].join(".")))),
+        };
+
+        if !members.iter().any(|(member, _)| member == name) {
+            members.push((name.to_owned(), Json::Object(Vec::new())));
+        }
+
+        table = match members.iter_mut().find(|(member, _)| member == name).map(|(_, value)| value) {
+            Some(Json::Array(values)) => match values.last_mut() {
+                Some(last) => last,
+                None => return Err(parser.error(&format!("\"{}\" is an empty array", path[..=i].join(".")))),
+            },
+            Some(value) => value,
+            None => unreachable!(),
+        };
+    }
+
+    Ok(table)
+}
+
+struct TomlParser<'a> {
+    source_name: &'a str,
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+}
+
+impl<'a> TomlParser<'a> {
+    fn error(&self, msg: &str) -> String {
+        format!("TOML Error: {}:{}: {}", self.source_name, self.line, msg)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+
+        if c == '\n' {
+            self.line += 1;
+        }
+
+        Some(c)
+    }
+
+    /// Skip spaces and comments, and newlines too if `newlines`.
+    fn skip_whitespace_and_comments(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => self.position += 1,
+                '\n' if newlines => {
+                    self.next();
+                },
+                '#' => {
+                    while self.peek().is_some() && self.peek() != Some('\n') {
+                        self.position += 1;
+                    }
+                },
+                _ => break,
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace_and_comments(false);
+
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", expected)))
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_whitespace_and_comments(false);
+
+        match self.peek() {
+            None | Some('\n') => Ok(()),
+            Some(c) => Err(self.error(&format!("Unexpected character after value: '{}'", c))),
+        }
+    }
+
+    /// Parse a dotted key, such as `case.expect` or `"quoted key"`.
+    fn parse_key(&mut self) -> Result<Vec<String>, String> {
+        let mut key = Vec::new();
+
+        loop {
+            self.skip_whitespace_and_comments(false);
+
+            match self.peek() {
+                Some('"') => key.push(self.parse_basic_string()?),
+                Some('\'') => key.push(self.parse_literal_string()?),
+                _ => {
+                    let start = self.position;
+
+                    while let Some(c) = self.peek() {
+                        if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                            self.position += 1;
+                        } else {
+                            break;
+                        }
+                    }
+
+                    if start == self.position {
+                        return Err(self.error("Expected a key"));
+                    }
+
+                    key.push(self.chars[start..self.position].iter().collect());
+                },
+            }
+
+            self.skip_whitespace_and_comments(false);
+
+            if self.peek() == Some('.') {
+                self.position += 1;
+            } else {
+                return Ok(key);
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace_and_comments(false);
+
+        match self.peek() {
+            Some('"') => self.parse_basic_string().map(Json::String),
+            Some('\'') => self.parse_literal_string().map(Json::String),
+            Some('[') => self.parse_array(),
+            Some('{') => self.parse_inline_table(),
+            Some('t') | Some('f') => {
+                let word = self.parse_word();
+
+                match word.as_str() {
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    _ => Err(self.error(&format!("Invalid value: {}", word))),
+                }
+            },
+            Some(_) => self.parse_number(),
+            None => Err(self.error("Expected a value")),
+        }
+    }
+
+    fn parse_word(&mut self) -> String {
+        let start = self.position;
+
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+' || c == '.' {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+
+        self.chars[start..self.position].iter().collect()
+    }
+
+    fn parse_number(&mut self) -> Result<Json, String> {
+        let word = self.parse_word();
+        let text = word.replace('_', "");
+
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(&text)),
+        };
+
+        let radix = match digits.get(..2) {
+            Some("0x") => Some(16),
+            Some("0o") => Some(8),
+            Some("0b") => Some(2),
+            _ => None,
+        };
+
+        let value = match radix {
+            Some(radix) => i64::from_str_radix(&digits[2..], radix).ok().map(|value| value as f64),
+            None => digits.parse::<f64>().ok().filter(|_| !digits.is_empty() && digits
+                        .chars().all(|c| c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || c == '-' ||
+                        c == '+')),
+        };
+
+        match value {
+            Some(value) if negative => Ok(Json::Number(-value)),
+            Some(value) => Ok(Json::Number(value)),
+            None => Err(self.error(&format!("Invalid value: {}", word))),
+        }
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        text.chars().enumerate().all(|(i, c)| self.chars.get(self.position + i) == Some(&c))
+    }
+
+    fn parse_basic_string(&mut self) -> Result<String, String> {
+        let multi_line = self.starts_with("\"\"\"");
+        self.position += if multi_line { 3 } else { 1 };
+
+        // a newline right after the opening delimiter is trimmed
+        if multi_line && self.peek() == Some('\n') {
+            self.next();
+        } else if multi_line && self.starts_with("\r\n") {
+            self.position += 1;
+            self.next();
+        }
+
+        let mut string = String::new();
+
+        loop {
+            if multi_line && self.starts_with("\"\"\"") {
+                self.position += 3;
+                return Ok(string);
+            }
+
+            let c = self.next().ok_or_else(|| self.error("Unterminated string"))?;
+
+            match c {
+                '"' if !multi_line => return Ok(string),
+                '\n' if !multi_line => return Err(self.error("Unterminated string")),
+                '\\' => {
+                    let escape = self.next().ok_or_else(|| self.error("Unterminated string"))?;
+
+                    match escape {
+                        'n' => string.push('\n'),
+                        't' => string.push('\t'),
+                        'r' => string.push('\r'),
+                        'b' => string.push('\u{8}'),
+                        'f' => string.push('\u{c}'),
+                        '"' => string.push('"'),
+                        '\\' => string.push('\\'),
+                        'u' | 'U' => {
+                            let size = if escape == 'u' { 4 } else { 8 };
+                            let digits: String = self.chars.iter().skip(self.position).take(size).collect();
+                            let code = u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32)
+                                .ok_or_else(|| self.error("Invalid unicode escape"))?;
+                            self.position += size;
+                            string.push(code);
+                        },
+                        // line ending backslash trims the following whitespace
+                        '\n' | ' ' | '\t' | '\r' if multi_line => {
+                            while let Some(' ') | Some('\t') | Some('\r') | Some('\n') = self.peek() {
+                                self.next();
+                            }
+                        },
+                        _ => return Err(self.error(&format!("Invalid escape: \\{}", escape))),
+                    }
+                },
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn parse_literal_string(&mut self) -> Result<String, String> {
+        let multi_line = self.starts_with("'''");
+        self.position += if multi_line { 3 } else { 1 };
+
+        if multi_line && self.peek() == Some('\n') {
+            self.next();
+        }
+
+        let mut string = String::new();
+
+        loop {
+            if multi_line && self.starts_with("'''") {
+                self.position += 3;
+                return Ok(string);
+            }
+
+            match self.next() {
+                Some('\'') if !multi_line => return Ok(string),
+                Some('\n') if !multi_line => return Err(self.error("Unterminated string")),
+                Some(c) => string.push(c),
+                None => return Err(self.error("Unterminated string")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Json, String> {
+        self.position += 1;
+        let mut values = Vec::new();
+
+        loop {
+            self.skip_whitespace_and_comments(true);
+
+            if self.peek() == Some(']') {
+                self.position += 1;
+                return Ok(Json::Array(values));
+            }
+
+            values.push(self.parse_value()?);
+            self.skip_whitespace_and_comments(true);
+
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some(']') => {},
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_inline_table(&mut self) -> Result<Json, String> {
+        self.position += 1;
+        let mut table = Json::Object(Vec::new());
+
+        self.skip_whitespace_and_comments(false);
+
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(table);
+        }
+
+        loop {
+            let key = self.parse_key()?;
+            self.expect('=')?;
+            let value = self.parse_value()?;
+
+            let (name, path) = key.split_last().unwrap();
+
+            match lookup(&mut table, path, self)? {
+                Json::Object(members) if !members.iter().any(|(member, _)| member == name) => {
+                    members.push((name.to_owned(), value));
+                },
+                _ => return Err(self.error(&format!("Duplicate key: \"{}\"", key.join(".")))),
+            }
+
+            self.skip_whitespace_and_comments(false);
+
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some('}') => {
+                    self.position += 1;
+                    return Ok(table);
+                },
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Snapshots and traces of a run, and the first divergence between two of them.
+//!
+//! A snapshot is the state after a run, written by `asm-vm run --snapshot <path>` as one JSON object:
+//!
+//! ```json
+//! {"eip": 12, "exit_code": null, "registers": {"eax": 18, ...}, "flags": {"cf": false, ...},
+//!  "memory": [{"address": 2097136, "bytes": "0c000000"}]}
+//! ```
+//!
+//! A trace is written by `asm-vm run --trace <path>` with a line of JSON for every instruction executed, with the
+//! registers and flags after it and the memory it wrote:
+//!
+//! ```json
+//! {"step": 1, "eip": 0, "location": {"file": "a.asm", "line": 2, "column": 2}, "registers": {"eax": 3, ...},
+//!  "flags": {"cf": false, ...}, "writes": []}
+//! ```
+
+use crate::json::*;
+use crate::token::TokenLocation;
+use crate::vm::VM;
+use std::collections::BTreeMap;
+
+const REGISTERS: [&str; 8] = ["eax", "ebx", "ecx", "edx", "esi", "edi", "esp", "ebp"];
+const FLAGS: [&str; 4] = ["cf", "zf", "sf", "of"];
+
+/// differing bytes of memory reported before the rest are only counted
+const MAX_MEMORY_DIFFERENCES: usize = 16;
+
+fn registers(vm: &VM) -> Json {
+    Json::object(REGISTERS.iter().map(|name| (*name, vm.get_register(name).unwrap_or(0).into())).collect())
+}
+
+fn flags(vm: &VM) -> Json {
+    let (cf, zf, sf, of) = vm.get_flags();
+    Json::object(FLAGS.iter().zip([cf, zf, sf, of]).map(|(name, flag)| (*name, flag.into())).collect())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+/// Get the memory of a snapshot or the writes of a record, as pairs of the address and the bytes.
+fn segments(segments: &Json) -> Result<Vec<(u32, Vec<u8>)>, String> {
+    let invalid = || "memory must be an array of addresses and bytes in hexadecimal".to_string();
+
+    segments.as_array().ok_or_else(invalid)?.iter().map(|segment| {
+        let address = segment.get("address").and_then(|address| address.as_u64())
+            .filter(|address| *address <= u32::MAX as u64).ok_or_else(invalid)?;
+        let bytes = segment.get("bytes").and_then(|bytes| bytes.as_str()).and_then(from_hex).ok_or_else(invalid)?;
+        Ok((address as u32, bytes))
+    }).collect()
+}
+
+fn to_segments(segments: &[(u32, Vec<u8>)]) -> Json {
+    Json::Array(segments.iter().map(|(address, bytes)| Json::object(vec![
+        ("address", (*address).into()),
+        ("bytes", to_hex(bytes).into()),
+    ])).collect())
+}
+
+/// Get the state of `vm` as a snapshot.
+pub fn snapshot(vm: &VM) -> Json {
+    Json::object(vec![
+        ("eip", vm.get_eip().into()),
+        ("exit_code", vm.get_exit_code().into()),
+        ("registers", registers(vm)),
+        ("flags", flags(vm)),
+        ("memory", to_segments(&vm.get_memory_segments())),
+    ])
+}
+
+/// Get the record of a trace for the instruction at `location` and `eip`, which was just executed by `vm` and wrote
+/// `writes`.
+pub fn record(vm: &VM, location: &TokenLocation, eip: u32, writes: &[(u32, Vec<u8>)]) -> Json {
+    Json::object(vec![
+        ("step", vm.get_instructions_executed().into()),
+        ("eip", eip.into()),
+        ("location", Json::object(vec![
+            ("file", location.get_source_file_name().into()),
+            ("line", location.get_line().into()),
+            ("column", location.get_column().into()),
+        ])),
+        ("registers", registers(vm)),
+        ("flags", flags(vm)),
+        ("writes", to_segments(writes)),
+    ])
+}
+
+/// Snapshot or trace read from a file
+enum Recording {
+    Snapshot(Json),
+    Trace(Vec<Json>),
+}
+
+fn parse(name: &str, text: &str) -> Result<Recording, String> {
+    if let Ok(snapshot) = Json::parse(text) {
+        if snapshot.get("memory").is_some() {
+            return Ok(Recording::Snapshot(snapshot));
+        }
+    }
+
+    let mut records = Vec::new();
+
+    for (i, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let record = Json::parse(line).map_err(|msg| format!("{}:{}: {}", name, i + 1, msg))?;
+
+        if record.get("step").is_none() {
+            return Err(format!("{}:{}: neither a snapshot nor a record of a trace", name, i + 1));
+        }
+
+        records.push(record);
+    }
+
+    Ok(Recording::Trace(records))
+}
+
+/// Get a number of a snapshot or record, 0 if it is missing.
+fn number(value: &Json, name: &str) -> u32 {
+    value.get(name).and_then(|value| value.as_u64()).unwrap_or(0) as u32
+}
+
+/// Compare the registers and flags of two snapshots or records, returns a line for each difference.
+fn diff_registers(left: &Json, right: &Json) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    for name in REGISTERS {
+        let (left, right) = (left.get("registers").and_then(|registers| registers.get(name)),
+                             right.get("registers").and_then(|registers| registers.get(name)));
+
+        if left != right {
+            let value = |value: Option<&Json>| value.and_then(|value| value.as_u64())
+                .map_or("-".to_string(), |value| format!("{:#010x}", value));
+            differences.push(format!("{}: {} != {}", name, value(left), value(right)));
+        }
+    }
+
+    for name in FLAGS {
+        let (left, right) = (left.get("flags").and_then(|flags| flags.get(name)),
+                             right.get("flags").and_then(|flags| flags.get(name)));
+
+        if left != right {
+            let value = |value: Option<&Json>| value.map_or("-".to_string(), |value| value.to_string());
+            differences.push(format!("{}: {} != {}", name, value(left), value(right)));
+        }
+    }
+
+    differences
+}
+
+/// Compare two sets of memory, returns a line for each run of differing bytes.
+fn diff_memory(left: &[(u32, Vec<u8>)], right: &[(u32, Vec<u8>)]) -> Vec<String> {
+    let bytes = |segments: &[(u32, Vec<u8>)]| -> BTreeMap<u32, u8> {
+        segments.iter()
+            .flat_map(|(address, bytes)| bytes.iter().enumerate()
+                .map(move |(i, byte)| (address.wrapping_add(i as u32), *byte)))
+            .collect()
+    };
+    let (left, right) = (bytes(left), bytes(right));
+
+    let mut addresses: Vec<u32> = left.keys().chain(right.keys()).copied()
+        .filter(|address| left.get(address).unwrap_or(&0) != right.get(address).unwrap_or(&0))
+        .collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+
+    // runs of consecutive addresses
+    let mut runs: Vec<(u32, usize)> = Vec::new();
+
+    for address in addresses {
+        match runs.last_mut() {
+            Some((start, size)) if start.wrapping_add(*size as u32) == address => *size += 1,
+            _ => runs.push((address, 1)),
+        }
+    }
+
+    let hex = |memory: &BTreeMap<u32, u8>, start: u32, size: usize| -> String {
+        (0..size as u32).map(|i| format!("{:02x}", memory.get(&start.wrapping_add(i)).unwrap_or(&0)))
+            .collect::<Vec<_>>().join(" ")
+    };
+
+    let mut differences: Vec<String> = runs.iter().take(MAX_MEMORY_DIFFERENCES)
+        .map(|(start, size)| format!("mem[{:#x}]: {} != {}", start, hex(&left, *start, *size),
+                                     hex(&right, *start, *size)))
+        .collect();
+
+    if runs.len() > MAX_MEMORY_DIFFERENCES {
+        differences.push(format!("... and {} more", runs.len() - MAX_MEMORY_DIFFERENCES));
+    }
+
+    differences
+}
+
+fn describe(record: &Json) -> String {
+    let location = record.get("location")
+        .map(|location| format!("{}:{}:{}", location.get("file").and_then(|file| file.as_str()).unwrap_or("?"),
+                                number(location, "line"), number(location, "column")));
+
+    format!("{} ({:#010x})", location.unwrap_or_else(|| "?".to_string()), number(record, "eip"))
+}
+
+/// Compare two snapshots or two traces, the texts of the files `left_name` and `right_name`, returns a report of the
+/// first divergence, `None` if there is none.
+///
+/// Records of traces are compared step by step, by their registers, flags and the memory they wrote, but not by their
+/// locations, so that a program can be compared with another version of it, such as an optimized one.
+///
+/// # Example
+///
+/// ```no_run
+/// # use asm_vm::trace::diff;
+/// # use std::fs;
+/// let left = fs::read_to_string("before.trace").unwrap();
+/// let right = fs::read_to_string("after.trace").unwrap();
+/// if let Some(report) = diff("before.trace", &left, "after.trace", &right).unwrap() {
+///     print!("{}", report);
+/// }
+/// ```
+pub fn diff(left_name: &str, left: &str, right_name: &str, right: &str) -> Result<Option<String>, String> {
+    let (left, right) = match (parse(left_name, left)?, parse(right_name, right)?) {
+        (Recording::Snapshot(left), Recording::Snapshot(right)) => return diff_snapshots(&left, &right),
+        (Recording::Trace(left), Recording::Trace(right)) => (left, right),
+        _ => return Err("Can not compare a snapshot with a trace".to_string()),
+    };
+
+    for (i, (left, right)) in left.iter().zip(&right).enumerate() {
+        let mut differences = diff_registers(left, right);
+        differences.extend(diff_memory(&segments(left.get("writes").unwrap_or(&Json::Null))?,
+                                       &segments(right.get("writes").unwrap_or(&Json::Null))?));
+
+        if !differences.is_empty() {
+            let mut report = format!("First divergence at step {}:\n", i + 1);
+            report += &format!("    {}: {}\n", left_name, describe(left));
+            report += &format!("    {}: {}\n", right_name, describe(right));
+
+            for difference in differences {
+                report += &format!("    {}\n", difference);
+            }
+
+            return Ok(Some(report));
+        }
+    }
+
+    let report = match left.len().cmp(&right.len()) {
+        std::cmp::Ordering::Equal => return Ok(None),
+        std::cmp::Ordering::Less => format!("{} stops after {} steps, {} goes on at {}", left_name, left.len(),
+                                            right_name, describe(&right[left.len()])),
+        std::cmp::Ordering::Greater => format!("{} stops after {} steps, {} goes on at {}", right_name, right.len(),
+                                               left_name, describe(&left[right.len()])),
+    };
+
+    Ok(Some(format!("First divergence at step {}:\n    {}\n", left.len().min(right.len()) + 1, report)))
+}
+
+fn diff_snapshots(left: &Json, right: &Json) -> Result<Option<String>, String> {
+    let mut differences = Vec::new();
+
+    for name in ["eip", "exit_code"] {
+        if left.get(name) != right.get(name) {
+            let value = |snapshot: &Json| snapshot.get(name).map_or("-".to_string(), |value| value.to_string());
+            differences.push(format!("{}: {} != {}", name, value(left), value(right)));
+        }
+    }
+
+    differences.extend(diff_registers(left, right));
+    differences.extend(diff_memory(&segments(left.get("memory").unwrap_or(&Json::Null))?,
+                                   &segments(right.get("memory").unwrap_or(&Json::Null))?));
+
+    if differences.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(differences.iter().map(|difference| format!("{}\n", difference)).collect()))
+}
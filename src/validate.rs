@@ -0,0 +1,372 @@
+//! Strict validation of operands, which rejects the combinations real x86 does not allow, such as moving from memory
+//! to memory, before the program runs.
+
+use crate::token::*;
+use crate::instruction::*;
+use crate::assembler::*;
+use crate::scanner::SourceOptions;
+use crate::isa::*;
+
+/// Describe the kind of an operand for diagnostics.
+fn describe(operand: &Operand) -> String {
+    match operand {
+        Operand::Register(register) => format!("{}-bits register {}", register_info(*register).map_or(32, |(_, size)|
+                    size * 8), operand),
+        Operand::Immediate(value) => format!("immediate data {}", value),
+        Operand::Memory(memory) => format!("{}-bits memory {}", memory.size * 8, operand),
+        Operand::Label(name) => format!("label {}", name),
+        Operand::String(_) => format!("string {}", operand),
+    }
+}
+
+/// Check that the operand is a register or memory.
+fn register_or_memory(operand: &Operand, position: &str) -> Result<usize, String> {
+    match operand {
+        Operand::Register(_) | Operand::Memory(_) => Ok(operand.size().unwrap_or(4)),
+        _ => Err(format!("the {} operand must be a register or memory, but it is {}", position, describe(operand))),
+    }
+}
+
+/// Check that the immediate data fits in `size` bytes, as either signed or unsigned integer.
+fn immediate_fits(value: i64, size: usize, position: &str) -> Result<(), String> {
+    if fits_size(value, size) {
+        Ok(())
+    } else {
+        Err(format!("the {} operand {} does not fit in {} bits", position, value, size * 8))
+    }
+}
+
+/// Check the operands of `mov`, `add`, `sub`, `and`, `or`, `xor` and `cmp`.
+fn check_binary(destination: &Operand, source: &Operand) -> Result<(), String> {
+    let size = register_or_memory(destination, "destination")?;
+
+    match (destination, source) {
+        (Operand::Memory(_), Operand::Memory(_)) => {
+            Err("both operands are memory, one of them must be a register or immediate data".to_string())
+        },
+        (_, Operand::Immediate(value)) => immediate_fits(*value, size, "source"),
+        (_, Operand::Register(_)) | (_, Operand::Memory(_)) if source.size() != Some(size) => {
+            Err(format!("the destination is {}, but the source is {}", describe(destination), describe(source)))
+        },
+        (_, Operand::Label(_)) if size != 4 => {
+            Err(format!("the destination is {}, but the source is {}, a 32-bits address", describe(destination),
+                        describe(source)))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Check the operands of an instruction against its forms in the table of [`crate::isa`], returns the reason of the
+/// first problem.
+pub fn check_instruction(instruction: &Instruction) -> Result<(), String> {
+    let operands = instruction.operands.as_slice();
+
+    let info = match instruction_info(instruction.mnemonic) {
+        Some(info) => info,
+        None => return Err(format!("{} is not supported", instruction.name)),
+    };
+
+    let counts = info.operand_counts();
+    let repeated = info.repeats && counts.first().is_some_and(|least| operands.len() >= *least);
+
+    if !counts.contains(&operands.len()) && !repeated {
+        let counts: Vec<String> = counts.iter().map(|count| count.to_string()).collect();
+        return Err(format!("{} operands are given, but {} takes {}{}", operands.len(), instruction.name,
+                           counts.join(" or "), if info.repeats { " or more" } else { "" }));
+    }
+
+    // the reasons of the common problems are told in detail
+    check_operands(instruction)?;
+
+    if info.matches(operands) {
+        Ok(())
+    } else {
+        let kinds: Vec<String> = operands.iter().map(describe).collect();
+        let forms: Vec<String> = info.forms.iter().map(|form| info.format_form(form)).collect();
+        Err(format!("the operands are {}, but the forms of {} are {}", kinds.join(", "), instruction.name,
+                    forms.join("; ")))
+    }
+}
+
+/// Check the operands of an instruction whose number of operands is valid, returns the reason of a common problem in
+/// detail.
+fn check_operands(instruction: &Instruction) -> Result<(), String> {
+    let operands = instruction.operands.as_slice();
+
+    match instruction.mnemonic {
+        TokenValue::MOV | TokenValue::ADD | TokenValue::SUB | TokenValue::ADC | TokenValue::SBB | TokenValue::AND |
+            TokenValue::OR | TokenValue::XOR | TokenValue::CMP | TokenValue::TEST => {
+            check_binary(&operands[0], &operands[1])
+        },
+        TokenValue::MOVSX | TokenValue::MOVZX => {
+            let destination = match operands[0] {
+                Operand::Register(_) => operands[0].size().unwrap_or(4),
+                _ => return Err(format!("the destination must be a register, but it is {}", describe(&operands[0]))),
+            };
+
+            let source = register_or_memory(&operands[1], "source")?;
+
+            if source >= destination {
+                return Err(format!("the destination must be larger than the source, but the destination is {} and \
+                                    the source is {}", describe(&operands[0]), describe(&operands[1])));
+            }
+
+            Ok(())
+        },
+        TokenValue::BOUND => {
+            match (&operands[0], &operands[1]) {
+                (Operand::Register(_), Operand::Memory(_)) if operands[0].size() == Some(1) => {
+                    Err(format!("the index must be a 16-bits or 32-bits register, but it is {}",
+                                describe(&operands[0])))
+                },
+                (Operand::Register(_), Operand::Memory(_)) if operands[0].size() != operands[1].size() => {
+                    Err(format!("the index is {}, but the bounds are {}", describe(&operands[0]),
+                                describe(&operands[1])))
+                },
+                (Operand::Register(_), Operand::Memory(_)) => Ok(()),
+                (Operand::Register(_), _) => {
+                    Err(format!("the bounds must be memory, but they are {}", describe(&operands[1])))
+                },
+                _ => Err(format!("the index must be a register, but it is {}", describe(&operands[0]))),
+            }
+        },
+        TokenValue::LEA => {
+            match (&operands[0], &operands[1]) {
+                (Operand::Register(_), Operand::Memory(_)) if operands[0].size() != Some(1) => Ok(()),
+                (Operand::Register(_), Operand::Memory(_)) => {
+                    Err(format!("the destination must be a 16-bits or 32-bits register, but it is {}",
+                                describe(&operands[0])))
+                },
+                (Operand::Register(_), _) => {
+                    Err(format!("the source must be memory, but it is {}", describe(&operands[1])))
+                },
+                _ => Err(format!("the destination must be a register, but it is {}", describe(&operands[0]))),
+            }
+        },
+        TokenValue::INC | TokenValue::DEC | TokenValue::NOT | TokenValue::NEG | TokenValue::MUL | TokenValue::DIV |
+            TokenValue::IDIV => {
+            register_or_memory(&operands[0], "only").map(|_| ())
+        },
+        TokenValue::IMUL => {
+            if operands.len() == 1 {
+                return register_or_memory(&operands[0], "only").map(|_| ());
+            }
+
+            let size = match operands[0] {
+                Operand::Register(_) if operands[0].size() != Some(1) => operands[0].size().unwrap_or(4),
+                _ => return Err(format!("the destination must be a 16 or 32-bits register, but it is {}",
+                                        describe(&operands[0]))),
+            };
+
+            if register_or_memory(&operands[1], "source")? != size {
+                return Err(format!("the destination is {}, but the source is {}", describe(&operands[0]),
+                                   describe(&operands[1])));
+            }
+
+            match operands.get(2) {
+                None => Ok(()),
+                Some(Operand::Immediate(value)) => immediate_fits(*value, size, "third"),
+                Some(operand) => Err(format!("the third operand must be immediate data, but it is {}",
+                                             describe(operand))),
+            }
+        },
+        TokenValue::BSF | TokenValue::BSR => {
+            let size = match operands[0] {
+                Operand::Register(_) if operands[0].size() != Some(1) => operands[0].size().unwrap_or(4),
+                _ => return Err(format!("the destination must be a 16 or 32-bits register, but it is {}",
+                                        describe(&operands[0]))),
+            };
+
+            if register_or_memory(&operands[1], "source")? != size {
+                return Err(format!("the destination is {}, but the source is {}", describe(&operands[0]),
+                                   describe(&operands[1])));
+            }
+
+            Ok(())
+        },
+        TokenValue::SHL | TokenValue::SHR | TokenValue::SAR => {
+            register_or_memory(&operands[0], "destination")?;
+
+            match &operands[1] {
+                Operand::Immediate(value) if (0..=255).contains(value) => Ok(()),
+                Operand::Immediate(value) => Err(format!("the count {} does not fit in 8 bits", value)),
+                operand => Err(format!("the count must be immediate data, but it is {}", describe(operand))),
+            }
+        },
+        TokenValue::PUSH | TokenValue::POP => {
+            match &operands[0] {
+                Operand::Immediate(value) if instruction.mnemonic == TokenValue::PUSH => {
+                    immediate_fits(*value, 4, "only")
+                },
+                Operand::Label(_) if instruction.mnemonic == TokenValue::PUSH => Ok(()),
+                Operand::Register(_) | Operand::Memory(_) if operands[0].size() == Some(1) => {
+                    Err(format!("the operand must be 16 or 32 bits, but it is {}", describe(&operands[0])))
+                },
+                operand => register_or_memory(operand, "only").map(|_| ()),
+            }
+        },
+        TokenValue::CALL => {
+            match &operands[0] {
+                Operand::Label(_) => Ok(()),
+                Operand::Register(_) | Operand::Memory(_) if operands[0].size().unwrap_or(4) == 4 => Ok(()),
+                operand => Err(format!("the target must be a label, or a 32-bits register or memory, but it is {}",
+                                       describe(operand))),
+            }
+        },
+        TokenValue::JMP | TokenValue::JE | TokenValue::JNE | TokenValue::JG | TokenValue::JGE |
+            TokenValue::JL | TokenValue::JLE | TokenValue::JA | TokenValue::JAE | TokenValue::JB | TokenValue::JBE |
+            TokenValue::JO | TokenValue::JNO | TokenValue::JS | TokenValue::JNS | TokenValue::JP | TokenValue::JNP |
+            TokenValue::JECXZ | TokenValue::JCXZ => {
+            match &operands[0] {
+                Operand::Label(_) => Ok(()),
+                operand => Err(format!("the target must be a label, but it is {}", describe(operand))),
+            }
+        },
+        TokenValue::SETE | TokenValue::SETNE | TokenValue::SETG | TokenValue::SETGE | TokenValue::SETL |
+            TokenValue::SETLE | TokenValue::SETA | TokenValue::SETAE | TokenValue::SETB | TokenValue::SETBE |
+            TokenValue::SETO | TokenValue::SETNO | TokenValue::SETS | TokenValue::SETNS | TokenValue::SETP |
+            TokenValue::SETNP => {
+            match register_or_memory(&operands[0], "only")? {
+                1 => Ok(()),
+                _ => Err(format!("the operand must be 8 bits, but it is {}", describe(&operands[0]))),
+            }
+        },
+        TokenValue::ENTER if !operands.is_empty() => {
+            match (&operands[0], &operands[1]) {
+                (Operand::Immediate(size), _) if !(0..=0xffff).contains(size) => {
+                    Err(format!("the frame size {} does not fit in 16 bits", size))
+                },
+                (Operand::Immediate(_), Operand::Immediate(level)) if !(0..=0xff).contains(level) => {
+                    Err(format!("the nesting level {} does not fit in 8 bits", level))
+                },
+                (Operand::Immediate(_), Operand::Immediate(_)) => Ok(()),
+                (Operand::Immediate(_), operand) | (operand, _) => {
+                    Err(format!("the operands must be immediate data, but one is {}", describe(operand)))
+                },
+            }
+        },
+        TokenValue::NOP if !operands.is_empty() => {
+            match register_or_memory(&operands[0], "only")? {
+                2 | 4 => Ok(()),
+                _ => Err(format!("the operand must be 16 or 32 bits, but it is {}", describe(&operands[0]))),
+            }
+        },
+        TokenValue::RET | TokenValue::LEAVE | TokenValue::ENTER | TokenValue::NOP | TokenValue::IRET |
+            TokenValue::PUSHAD | TokenValue::POPAD | TokenValue::PUSHFD | TokenValue::POPFD | TokenValue::LAHF |
+            TokenValue::SAHF | TokenValue::STC | TokenValue::CLC | TokenValue::CMC | TokenValue::STD | TokenValue::CLD |
+            TokenValue::HLT | TokenValue::DAA | TokenValue::DAS | TokenValue::AAA | TokenValue::AAS |
+            TokenValue::INTO | TokenValue::CPUID | TokenValue::DATA | TokenValue::TEXT => Ok(()),
+        TokenValue::IN | TokenValue::OUT => {
+            let (accumulator, port) = match instruction.mnemonic {
+                TokenValue::IN => (&operands[0], &operands[1]),
+                _ => (&operands[1], &operands[0]),
+            };
+
+            if !matches!(accumulator, Operand::Register(TokenValue::AL | TokenValue::AX | TokenValue::EAX)) {
+                return Err(format!("the accumulator must be al, ax or eax, but it is {}", describe(accumulator)));
+            }
+
+            match port {
+                Operand::Register(TokenValue::DX) => Ok(()),
+                Operand::Immediate(value) if (0..=255).contains(value) => Ok(()),
+                operand => Err(format!("the port must be immediate data from 0 to 255 or dx, but it is {}",
+                                       describe(operand))),
+            }
+        },
+        TokenValue::INT => {
+            match &operands[0] {
+                Operand::Immediate(value) if (0..=255).contains(value) => Ok(()),
+                operand => Err(format!("the vector must be immediate data from 0 to 255, but it is {}",
+                                       describe(operand))),
+            }
+        },
+        TokenValue::IVT => {
+            match &operands[0] {
+                Operand::Immediate(value) if (0..=255).contains(value) => {},
+                operand => return Err(format!("the vector must be immediate data from 0 to 255, but it is {}",
+                                              describe(operand))),
+            }
+
+            match &operands[1] {
+                Operand::Label(_) => Ok(()),
+                Operand::Register(_) if operands[1].size() == Some(4) => Ok(()),
+                Operand::Immediate(value) => immediate_fits(*value, 4, "second"),
+                operand => Err(format!("the handler must be a label or a 32-bits register, but it is {}",
+                                       describe(operand))),
+            }
+        },
+        TokenValue::ORG => {
+            match &operands[0] {
+                Operand::Immediate(value) => immediate_fits(*value, 4, "only"),
+                operand => Err(format!("the address must be immediate data, but it is {}", describe(operand))),
+            }
+        },
+        TokenValue::GLOBAL | TokenValue::EXTERN | TokenValue::ENDP => {
+            match operands.iter().find(|operand| !matches!(operand, Operand::Label(_))) {
+                Some(operand) => Err(format!("every operand must be a label, but one is {}", describe(operand))),
+                None => Ok(()),
+            }
+        },
+        TokenValue::DB | TokenValue::DW | TokenValue::DD => {
+            let size = data_size(instruction.mnemonic).unwrap_or(1);
+
+            for (i, operand) in operands.iter().enumerate() {
+                match operand {
+                    Operand::Immediate(value) if !fits_size(*value, size) => {
+                        return Err(format!("item {} is {}, which does not fit in {} bits", i + 1, value, size * 8));
+                    },
+                    Operand::Immediate(_) => {},
+                    Operand::Label(_) if size != 4 => {
+                        return Err(format!("item {} is a label, a 32-bits address, which only dd can define", i + 1));
+                    },
+                    Operand::Label(_) | Operand::String(_) => {},
+                    operand => return Err(format!("every item must be immediate data, a label or a string, but item \
+                                                   {} is {}", i + 1, describe(operand))),
+                }
+            }
+
+            Ok(())
+        },
+        TokenValue::INCBIN => {
+            if !matches!(operands[0], Operand::String(_)) {
+                return Err(format!("the file name must be a string, but it is {}", describe(&operands[0])));
+            }
+
+            match operands[1..].iter().find(|operand| !matches!(operand, Operand::Immediate(value) if *value >= 0)) {
+                Some(operand) => Err(format!("the offset and length must be immediate data, but one is {}",
+                                             describe(operand))),
+                None => Ok(()),
+            }
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Check every instruction, returns a diagnostic for each invalid one.
+pub fn validate(statements: &[Statement]) -> Vec<String> {
+    statements.iter()
+        .filter_map(|statement| match statement {
+            Statement::Instruction(instruction) => check_instruction(instruction).err().map(|msg| {
+                format!("Syntax Error: {} {}: {}", instruction.location.to_string(), instruction, msg)
+            }),
+            Statement::Label(..) => None,
+        })
+        .collect()
+}
+
+/// Scan with `options`, parse and validate source files, an error of scanning or parsing stops at once.
+pub fn validate_files(source_file_names: &[String], options: &SourceOptions) -> Result<Vec<String>, String> {
+    let mut diagnostics = Vec::new();
+
+    for source_file_name in source_file_names {
+        let mut tokens = scan_file(source_file_name.to_owned(), options);
+
+        if let Some(msg) = preprocess_tokens(&mut tokens, options).into_iter().next() {
+            return Err(msg);
+        }
+
+        diagnostics.append(&mut validate(&Parser::new(&tokens).parse_program()?));
+    }
+
+    Ok(diagnostics)
+}
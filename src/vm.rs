@@ -1,20 +1,343 @@
 use crate::token::*;
 use crate::scanner::*;
+use crate::flags::{self, Arithmetic};
+use crate::instruction::{preprocess_tokens, register_info, Parser, Statement};
+use crate::assembler::{data_size, define_data, include_binary, instruction_size, next_address,
+                       org_address};
+use crate::link::{is_declaration, link};
+use crate::policy::Policy;
+use crate::printf::printf;
+use crate::random::Random;
+use crate::cache::{Cache, CacheConfig, CacheStats};
+use crate::branch::{BranchModel, BranchStats};
+use crate::pipeline::{Issue, Pipeline, PipelineStats, Usage};
+use crate::port::SharedPortDevice;
+use crate::cfg;
+use crate::trace;
 use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
 use std::vec::Vec;
 use std::result::Result;
 use std::convert::TryInto;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+use std::time::{Instant, SystemTime};
 
 const MAX: usize = 2 * 1024 * 1024;
+/// calls which may be nested, unless set by [`VM::set_call_depth_limit`]
+pub const DEFAULT_CALL_DEPTH_LIMIT: usize = 10000;
+/// frames printed at each end of a long backtrace
+const BACKTRACE_ENDS: usize = 10;
+/// virtual time every instruction takes, as if the machine ran 1 million instructions per second
+pub const NANOSECONDS_PER_INSTRUCTION: u64 = 1000;
+/// interrupt vector of the timer, as IRQ 0 of the PC
+pub const TIMER_VECTOR: u8 = 0x08;
+/// instructions whose stages are kept for the diagram of the pipeline, see [`VM::get_pipeline_diagram`]
+pub const PIPELINE_DIAGRAM_LENGTH: usize = 12;
+/// leaves `cpuid` returns unless changed by [`VM::set_cpuid_leaf`], as `eax`, `ebx`, `ecx` and `edx`: leaf 0 has the
+/// highest leaf and the vendor "AsmVMVirtual", and leaf 1 the signature of family 4 without any feature
+pub const DEFAULT_CPUID_LEAVES: [(u32, [u32; 4]); 2] = [
+    (0, [1, u32::from_le_bytes(*b"AsmV"), u32::from_le_bytes(*b"tual"), u32::from_le_bytes(*b"MVir")]),
+    (1, [0x400, 0, 0, 0]),
+];
+
+thread_local! {
+    /// whether the current thread is in `catch_error`, whose panics are returned instead of printed
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+static HOOK: Once = Once::new();
+
+/// Run `f`, and return the error reported by the virtual machine instead of panicking, such as a syntax error.
+///
+/// The error is not printed to the standard error, so that an embedding program or a server can report it in its own
+/// way.
+pub fn catch_error<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    HOOK.call_once(|| {
+        let hook = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING.with(|catching| catching.get()) {
+                hook(info);
+            }
+        }));
+    });
+
+    let nested = CATCHING.with(|catching| catching.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|catching| catching.set(nested));
+
+    result.map_err(|payload| match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "Unknown error".to_string(),
+        },
+    })
+}
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+/// How much the virtual machine reports about a run
+pub enum Verbosity {
+    /// report nothing but fatal errors
+    QUIET,
+    /// report diagnostics, such as an empty source file
+    NORMAL,
+    /// report diagnostics and details of preprocessing
+    VERBOSE,
+}
+
+/// Frame of a function which has not returned, see [`VM::frames`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// label of the function, the nearest target of `call`, entry label or interrupt handler at or before the
+    /// instruction the frame is executing, if any
+    pub function_label: Option<String>,
+    /// address the function returns to, at `ebp + 4`, or `None` for the frame of the entry label, which returns out of
+    /// the program
+    pub return_address: Option<u32>,
+    /// `ebp` of the frame, where the `ebp` of the caller is saved
+    pub ebp: u32,
+    /// addresses of the local variables, from `esp` of the frame up to `ebp`
+    pub locals_range: Range<u32>,
+}
+
+/// Named range of memory which addresses are described by, see [`VM::regions`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    /// name of the region, such as the label of data
+    pub name: String,
+    pub range: Range<u32>,
+    /// address the offsets in the region are counted from, such as `ebp` of a frame
+    pub base: u32,
+}
+
+/// bytes on one line of a hexdump
+const HEXDUMP_BYTES: usize = 16;
+/// instructions between two checks of the quota of time, as reading the clock is slow
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// Resource of a program which a quota limits, see [`VM::set_quotas`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Quota {
+    /// instructions executed
+    Instructions,
+    /// bytes of memory written by the program, such as by `mov`, `push` or the `read` system call
+    MemoryWritten,
+    /// bytes of stack below `esp` at the start
+    Stack,
+    /// bytes written to the standard output and error
+    Output,
+    /// milliseconds of wall-clock time since the program started running
+    Time,
+}
+
+impl Quota {
+    /// Name of the quota in settings and results, such as `memory_written`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Quota::Instructions => "instructions",
+            Quota::MemoryWritten => "memory_written",
+            Quota::Stack => "stack",
+            Quota::Output => "output",
+            Quota::Time => "time",
+        }
+    }
+}
+
+/// Hard limits on the resources of a program, so that an untrusted one can be run safely, `None` for no limit
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Quotas {
+    pub instructions: Option<u64>,
+    pub memory_written: Option<u64>,
+    pub stack: Option<u64>,
+    pub output: Option<u64>,
+    pub time: Option<u64>,
+    /// nested forks a session of the server may keep, see `fork` of [`crate::server`]
+    pub forks: Option<u64>,
+    /// whether the source may not read files of the host, with `%include`, `include` and `incbin`
+    pub deny_host_files: bool,
+}
+
+impl Quotas {
+    /// Get the limit of `quota`.
+    pub fn get(&self, quota: Quota) -> Option<u64> {
+        match quota {
+            Quota::Instructions => self.instructions,
+            Quota::MemoryWritten => self.memory_written,
+            Quota::Stack => self.stack,
+            Quota::Output => self.output,
+            Quota::Time => self.time,
+        }
+    }
+
+    /// Get the stricter limit of every quota, such as the quotas a client asks for within those of a server.
+    pub fn min(&self, other: &Quotas) -> Quotas {
+        let min = |a: Option<u64>, b: Option<u64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        Quotas {
+            instructions: min(self.instructions, other.instructions),
+            memory_written: min(self.memory_written, other.memory_written),
+            stack: min(self.stack, other.stack),
+            output: min(self.output, other.output),
+            time: min(self.time, other.time),
+            forks: min(self.forks, other.forks),
+            deny_host_files: self.deny_host_files || other.deny_host_files,
+        }
+    }
+}
+
+/// Outcome of a program stopped because it exceeded a quota
+#[derive(Clone)]
+pub struct QuotaExceeded {
+    pub quota: Quota,
+    pub limit: u64,
+    /// location of the instruction which exceeded the quota, or which was not executed for the quota of instructions
+    pub location: Option<TokenLocation>,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let location = self.location.as_ref().map_or(String::new(), |location| format!("{} ", location.to_string()));
+
+        let what = match self.quota {
+            Quota::Instructions => "instructions executed",
+            Quota::MemoryWritten => "bytes of memory written",
+            Quota::Stack => "bytes of stack used",
+            Quota::Output => "bytes of output written",
+            Quota::Time => return write!(f, "Runtime Error: {}Timeout: not finished after {} ms", location, self.limit),
+        };
+
+        write!(f, "Runtime Error: {}Quota exceeded: more than {} {}", location, self.limit, what)
+    }
+}
+
+/// Exception of x86 raised by an instruction, which calls the handler `ivt` installed for its vector, or stops the
+/// program without one, see [`VM::get_exception`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Exception {
+    /// `#OF`, raised by `into` when `of` is set
+    Overflow,
+    /// `#BR`, raised by `bound` when the index is outside its bounds
+    BoundRange,
+}
+
+impl Exception {
+    /// Interrupt vector of the exception on x86.
+    pub fn vector(self) -> u8 {
+        match self {
+            Exception::Overflow => 4,
+            Exception::BoundRange => 5,
+        }
+    }
+
+    /// Name of the exception in results, such as `overflow`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Exception::Overflow => "overflow",
+            Exception::BoundRange => "bound_range",
+        }
+    }
+
+    /// Whether the exception is a fault, whose handler returns to the instruction which raised it to try it again,
+    /// rather than a trap, whose handler returns to the next instruction.
+    pub fn is_fault(self) -> bool {
+        matches!(self, Exception::BoundRange)
+    }
+}
+
+/// Outcome of a program stopped by an exception without a handler
+#[derive(Clone)]
+pub struct ExceptionRaised {
+    pub exception: Exception,
+    /// location of the instruction which raised the exception
+    pub location: Option<TokenLocation>,
+}
+
+impl fmt::Display for ExceptionRaised {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let location = self.location.as_ref().map_or(String::new(), |location| format!("{} ", location.to_string()));
+
+        let what = match self.exception {
+            Exception::Overflow => "Overflow exception (#OF): into with of set",
+            Exception::BoundRange => "Bound range exceeded exception (#BR): the index of bound is outside its bounds",
+        };
+
+        write!(f, "Runtime Error: {}{}", location, what)
+    }
+}
+
+/// State an instruction depends on besides memory, which repeats in a loop which never ends
+#[derive(Clone, Copy, PartialEq)]
+struct LoopState {
+    /// token of the next instruction
+    position: usize,
+    registers: [[u8; 4]; 8],
+    /// `cf`, `zf`, `sf`, `of` and the interrupt flag
+    flags: [bool; 5],
+    depth: usize,
+}
+
+/// Finds a state which repeats by Brent's algorithm, saving a state after 1, 2, 4, ... instructions and comparing the
+/// states after it with the saved one, so a loop of any length is found with constant memory
+#[derive(Clone)]
+struct LoopDetector {
+    saved: Option<LoopState>,
+    /// instructions before the next state is saved
+    power: u64,
+    /// instructions since the saved state
+    length: u64,
+}
+
+impl Default for LoopDetector {
+    fn default() -> Self {
+        LoopDetector { saved: None, power: 1, length: 0 }
+    }
+}
+
+impl LoopDetector {
+    fn reset(&mut self) {
+        *self = Default::default();
+    }
+
+    /// Add the state after an instruction, returns the number of instructions of the loop if it repeats.
+    fn check(&mut self, state: LoopState) -> Option<u64> {
+        self.length += 1;
+
+        if self.saved == Some(state) {
+            return Some(self.length);
+        }
+
+        if self.length >= self.power {
+            self.saved = Some(state);
+            self.power *= 2;
+            self.length = 0;
+        }
+
+        None
+    }
+}
 
 /// Visual Machine for x86 assembly
 pub struct VM {
     /// simulate the `stack`
-    stack: [u8; MAX],
+    stack: Box<[u8]>,
+    /// address of the first byte of `stack`
+    memory_base: u32,
     /// simulate the `text`
     text: Vec<Token>,
     /// label location table, to implement `call` instruction.
     index: HashMap<String, i32>,
+    /// location of the entry label, such as `main`
+    entrance: i32,
     /// `eax`, accumulator register
     eax: [u8; 4],
     /// `ebx`, base register
@@ -31,8 +354,16 @@ pub struct VM {
     esp: [u8; 4],
     /// `ebp`, base pointer register
     ebp: [u8; 4],
-    /// `eip`, instruction pointer register
-    eip: [u8; 4],
+    /// index of the next token to execute in `text`, `eip` is the address of its instruction
+    position: usize,
+    /// address of the first instruction
+    origin: u32,
+    /// address and size in bytes of every instruction, by the index of its first token in `text`
+    addresses: HashMap<usize, (u32, u32)>,
+    /// index of the first token of every instruction, by its address
+    instructions: HashMap<u32, usize>,
+    /// address of every label
+    symbols: HashMap<String, u32>,
     /// `cf`, carry flag
     cf: bool,
     /// `zf`, zero flag
@@ -41,20 +372,122 @@ pub struct VM {
     sf: bool,
     /// `of`, overflow flag
     of: bool,
+    /// `pf`, parity flag, whether the low byte of the result has an even number of set bits
+    pf: bool,
+    /// `af`, adjust flag, carry out of bit 3 of the result, or borrow into it, for BCD arithmetic
+    af: bool,
+    /// `if`, interrupt flag, whether the timer may interrupt the program
+    interrupt_flag: bool,
+    /// `df`, direction flag, whether the string instructions step `esi` and `edi` down rather than up
+    direction_flag: bool,
     /// lexical scanner
     scanner: Scanner,
+    /// options the source files are read with, see [`VM::set_source_options`]
+    source_options: SourceOptions,
     /// call stack depth
-    depth: u8,
+    depth: usize,
+    /// deepest call stack depth during the run
+    max_depth: usize,
+    /// most calls which may be nested
+    call_depth_limit: usize,
+    /// position of every `call` which has not returned, the innermost last
+    call_stack: Vec<usize>,
+    /// `esp` when the program started, and the lowest `esp` since, for the statistics of the stack
+    stack_range: Option<(u32, u32)>,
     /// error flag
     error_flag_: bool,
+    /// level of diagnostics
+    verbosity: Verbosity,
+    /// status passed to the `exit` system call
+    exit_code: Option<u32>,
+    /// whether the program stopped by `hlt`
+    halted: bool,
+    /// input of the `read` system call instead of the standard input
+    input: Option<Cursor<Vec<u8>>>,
+    /// output of the `write` system call to the standard output, if captured
+    output: Option<Vec<u8>>,
+    /// label to start from instead of `main`, `start`, `_main` or `_start`
+    entry_label: Option<String>,
+    /// address of the handler installed by `ivt` for every interrupt vector
+    interrupt_vectors: HashMap<u8, u32>,
+    /// regions added by the host, such as blocks of a heap, until the next program is loaded
+    regions: Vec<Region>,
+    /// device the host attached to every port, see [`VM::set_port_device`]
+    ports: HashMap<u16, SharedPortDevice>,
+    /// values of `eax`, `ebx`, `ecx` and `edx` `cpuid` returns for every leaf, see [`VM::set_cpuid_leaf`]
+    cpuid_leaves: HashMap<u32, [u32; 4]>,
+    /// seed of the garbage in the registers and memory when a program is loaded, instead of zeros
+    random_seed: Option<u64>,
+    /// seed of the random number service of `int 0x81` when a program is loaded
+    service_seed: u64,
+    /// generator of the random number service of `int 0x81`
+    service_random: Random,
+    /// virtual time since the program was loaded in nanoseconds, which every instruction advances
+    virtual_time: u64,
+    /// whether the time system calls return the time of the host instead of the virtual time
+    wall_clock: bool,
+    /// virtual time when the timer expires, and the interval between expiries, 0 for a timer which expires once
+    timer: Option<(u64, u64)>,
+    /// faults skipped before the next one stops the program, `None` to stop at the first one
+    fault_limit: Option<usize>,
+    /// faults which were skipped since the program was loaded, in order
+    faults: Vec<String>,
+    /// errors found while loading a line, such as an unknown label, reported when it is executed, by its first token
+    deferred_errors: HashMap<usize, String>,
+    quotas: Quotas,
+    /// instructions executed since the program was loaded
+    instructions_executed: u64,
+    /// bytes of memory written by the program since it was loaded, counted by `set_value`
+    memory_written: Cell<u64>,
+    /// bytes the program tried to write to the standard output and error since it was loaded
+    output_written: u64,
+    /// quota which stopped the program
+    quota_exceeded: Option<QuotaExceeded>,
+    /// exception without a handler which stopped the program
+    exception: Option<ExceptionRaised>,
+    /// when the program started running, for the quota of time
+    started: Option<Instant>,
+    /// instructions which programs must not or must use
+    policy: Policy,
+    /// where a record of every instruction is written, see [`crate::trace`]
+    trace: Option<Box<dyn Write + Send>>,
+    /// memory written by the current instruction while tracing, as pairs of the address and the size
+    written: RefCell<Vec<(u32, usize)>>,
+    /// stop a program whose state repeats, see [`VM::set_loop_detection`]
+    loop_detection: bool,
+    loop_detector: LoopDetector,
+    /// whether the current instruction changed memory, while loops are detected
+    memory_changed: Cell<bool>,
+    /// simulated data cache, see [`VM::set_cache`]
+    cache: Option<Cache>,
+    /// hits and misses of the data cache, by the first token of the instruction
+    cache_stats: HashMap<usize, CacheStats>,
+    /// first token of the instruction being executed, whose accesses to memory go through the cache
+    executing: Option<usize>,
+    /// immediate data of the instruction being executed, which its operands point to until the next instruction
+    immediates: Vec<Box<[u8]>>,
+    /// what is recorded of every conditional jump, see [`VM::set_branch_model`]
+    branch_model: Option<BranchModel>,
+    /// record of every conditional jump, by its first token
+    branch_stats: HashMap<usize, BranchStats>,
+    /// model of a 5-stage pipeline the executed instructions go through, see [`VM::set_pipeline`]
+    pipeline: Option<Pipeline>,
+    /// cycles of every instruction, by its first token
+    pipeline_stats: HashMap<usize, PipelineStats>,
+    /// how every instruction executed uses the pipeline, the token after it and its text, by its first token
+    pipeline_usages: HashMap<usize, (Usage, usize, String)>,
+    /// stages of the first instructions executed, with their first token
+    pipeline_diagram: Vec<(usize, Issue)>,
 }
 
 impl Default for VM {
     fn default() -> Self {
         VM {
-            stack: [0; MAX],
+            stack: vec![0; MAX].into_boxed_slice(),
+            memory_base: 0,
             text: Vec::new(),
             index: HashMap::new(),
+            entrance: 0,
             eax: [0; 4],
             ebx: [0; 4],
             ecx: [0; 4],
@@ -63,14 +496,69 @@ impl Default for VM {
             edi: [0; 4],
             esp: ((MAX - 1) as u32).to_le_bytes(),
             ebp: ((MAX - 1) as u32).to_le_bytes(),
-            eip: [0; 4],
+            position: 0,
+            origin: 0,
+            addresses: HashMap::new(),
+            instructions: HashMap::new(),
+            symbols: HashMap::new(),
             cf: false,
             zf: false,
             sf: false,
             of: false,
+            pf: false,
+            af: false,
+            interrupt_flag: true,
+            direction_flag: false,
             scanner: Default::default(),
+            source_options: Default::default(),
             depth: 1,
+            max_depth: 1,
+            call_depth_limit: DEFAULT_CALL_DEPTH_LIMIT,
+            call_stack: Vec::new(),
+            stack_range: None,
             error_flag_: false,
+            verbosity: Verbosity::NORMAL,
+            exit_code: None,
+            halted: false,
+            input: None,
+            output: None,
+            entry_label: None,
+            interrupt_vectors: HashMap::new(),
+            regions: Vec::new(),
+            ports: HashMap::new(),
+            cpuid_leaves: HashMap::from(DEFAULT_CPUID_LEAVES),
+            random_seed: None,
+            service_seed: 0,
+            service_random: Random::new(0),
+            virtual_time: 0,
+            wall_clock: false,
+            timer: None,
+            fault_limit: None,
+            faults: Vec::new(),
+            deferred_errors: HashMap::new(),
+            quotas: Quotas::default(),
+            instructions_executed: 0,
+            memory_written: Cell::new(0),
+            output_written: 0,
+            quota_exceeded: None,
+            exception: None,
+            started: None,
+            policy: Policy::default(),
+            trace: None,
+            written: RefCell::new(Vec::new()),
+            loop_detection: false,
+            loop_detector: Default::default(),
+            memory_changed: Cell::new(false),
+            cache: None,
+            cache_stats: HashMap::new(),
+            executing: None,
+            immediates: Vec::new(),
+            branch_model: None,
+            branch_stats: HashMap::new(),
+            pipeline: None,
+            pipeline_stats: HashMap::new(),
+            pipeline_usages: HashMap::new(),
+            pipeline_diagram: Vec::new(),
         }
     }
 }
@@ -80,9 +568,11 @@ impl VM {
     /// New VM from a assembly source file.
     pub fn new(source_file_name: String) -> Self {
         VM {
-            stack: [0; MAX],
+            stack: vec![0; MAX].into_boxed_slice(),
+            memory_base: 0,
             text: Vec::new(),
             index: HashMap::new(),
+            entrance: 0,
             eax: [0; 4],
             ebx: [0; 4],
             ecx: [0; 4],
@@ -91,14 +581,69 @@ impl VM {
             edi: [0; 4],
             esp: ((MAX - 1) as u32).to_le_bytes(),
             ebp: ((MAX - 1) as u32).to_le_bytes(),
-            eip: [0; 4],
+            position: 0,
+            origin: 0,
+            addresses: HashMap::new(),
+            instructions: HashMap::new(),
+            symbols: HashMap::new(),
             cf: false,
             zf: false,
             sf: false,
             of: false,
+            pf: false,
+            af: false,
+            interrupt_flag: true,
+            direction_flag: false,
             scanner: Scanner::new(source_file_name),
+            source_options: SourceOptions::default(),
             depth: 1,
+            max_depth: 1,
+            call_depth_limit: DEFAULT_CALL_DEPTH_LIMIT,
+            call_stack: Vec::new(),
+            stack_range: None,
             error_flag_: false,
+            verbosity: Verbosity::NORMAL,
+            exit_code: None,
+            halted: false,
+            input: None,
+            output: None,
+            entry_label: None,
+            interrupt_vectors: HashMap::new(),
+            regions: Vec::new(),
+            ports: HashMap::new(),
+            cpuid_leaves: HashMap::from(DEFAULT_CPUID_LEAVES),
+            random_seed: None,
+            service_seed: 0,
+            service_random: Random::new(0),
+            virtual_time: 0,
+            wall_clock: false,
+            timer: None,
+            fault_limit: None,
+            faults: Vec::new(),
+            deferred_errors: HashMap::new(),
+            quotas: Quotas::default(),
+            instructions_executed: 0,
+            memory_written: Cell::new(0),
+            output_written: 0,
+            quota_exceeded: None,
+            exception: None,
+            started: None,
+            policy: Policy::default(),
+            trace: None,
+            written: RefCell::new(Vec::new()),
+            loop_detection: false,
+            loop_detector: Default::default(),
+            memory_changed: Cell::new(false),
+            cache: None,
+            cache_stats: HashMap::new(),
+            executing: None,
+            immediates: Vec::new(),
+            branch_model: None,
+            branch_stats: HashMap::new(),
+            pipeline: None,
+            pipeline_stats: HashMap::new(),
+            pipeline_usages: HashMap::new(),
+            pipeline_diagram: Vec::new(),
         }
     }
 
@@ -108,14 +653,14 @@ impl VM {
     }
 
     fn error_report(&mut self, msg: &String) {
-        self.error_syntax(&format!("Syntax Error: {} {}", self.text[self.get_eip()].get_token_location().to_string(),
+        self.error_syntax(&format!("Syntax Error: {} {}", self.text[self.position].get_token_location().to_string(),
                     msg));
     }
 
     fn expect_token_type(&mut self, token_type: TokenType, token_name: String, advance_to_next_token: bool) -> bool {
-        if self.text[self.get_eip()].get_token_type() != token_type {
+        if self.text[self.position].get_token_type() != token_type {
             self.error_report(&format!("Expected \"{}\", but find \"{}\"", token_name,
-                        self.text[self.get_eip()].get_token_name()));
+                        self.text[self.position].get_token_name()));
             return false;
         }
 
@@ -127,9 +672,9 @@ impl VM {
     }
 
     fn expect_token_value(&mut self, token_value: TokenValue, token_name: String, advance_to_next_token: bool) -> bool {
-        if self.text[self.get_eip()].get_token_value() != token_value {
+        if self.text[self.position].get_token_value() != token_value {
             self.error_report(&format!("Expected \"{}\", but find \"{}\"", token_name,
-                        self.text[self.get_eip()].get_token_name()));
+                        self.text[self.position].get_token_name()));
             return false;
         }
 
@@ -141,7 +686,7 @@ impl VM {
     }
 
     fn validate_token_type(&mut self, token_type: TokenType, advance_to_next_token: bool) -> bool {
-        if self.text[self.get_eip()].get_token_type() != token_type {
+        if self.text[self.position].get_token_type() != token_type {
             return false;
         }
 
@@ -153,7 +698,7 @@ impl VM {
     }
 
     fn validate_token_value(&mut self, token_value: TokenValue, advance_to_next_token: bool) -> bool {
-        if self.text[self.get_eip()].get_token_value() != token_value {
+        if self.text[self.position].get_token_value() != token_value {
             return false;
         }
 
@@ -164,102 +709,290 @@ impl VM {
         true
     }
 
-    fn get_eip(&self) -> usize {
-        u32::from_le_bytes(self.eip) as usize
-    }
-
-    /// change `eip`.
+    /// Move to another token of the current instruction.
     ///
-    /// eip += displacement;
+    /// position += displacement;
     fn go_from_here(&mut self, displacement: i32) {
-        let value: u32 = match (self.get_eip() as i32 + displacement).try_into() {
-            Ok(value) => value,
-            Err(err) => panic!("Invaild instruction address: {}", err),
+        self.position = match (self.position as i32 + displacement).try_into() {
+            Ok(position) => position,
+            Err(err) => panic!("Invaild token position: {}", err),
         };
+    }
 
-        self.eip = value.to_le_bytes();
+    /// Continue from the instruction at `address`, as `jmp`, `call` and `ret` do.
+    fn go_to(&mut self, address: u32) {
+        self.position = match self.instructions.get(&address) {
+            Some(position) => *position,
+            None => panic!("Runtime Error: {:#010x} is not the address of an instruction", address),
+        };
     }
 
     /// Preprocess assembly source code.
     ///
     /// 1. Read all token from source file, and store into `self.text`.
     /// 2. Record the location of `label`, and store into `self.index`.
-    /// 3. Replace the the `label` in `call label` instruction with the corresponding displacement.
+    /// 3. First pass: lay out the instructions, and record the address of every `label` into `self.symbols`.
+    /// 4. Second pass: replace every use of a `label` with its address, so a label can be used before it is defined.
     fn preprocess(&mut self) {
-        let mut count = -1;
-        let mut entrance = 0;
+        self.scan();
+        self.resolve_labels();
+    }
 
-        loop {
-            let last_token = self.scanner.get_token();
+    /// Read all token from the current source file, append them to `self.text` and record the location of `label`
+    /// into `self.index`, so that several source files share one text and one label table.
+    fn scan(&mut self) {
+        let mut tokens = Vec::new();
+        let mut options = self.source_options.clone();
 
-            self.scanner.get_next_token();
-            count = count + 1;
+        if self.quotas.deny_host_files {
+            options.set_host_files(false);
+        }
+
+        self.scanner.set_options(&options);
 
+        loop {
+            self.scanner.get_next_token();
             let token = self.scanner.get_token();
 
+            if token.get_token_type() == TokenType::END_OF_FILE {
+                break;
+            }
+
+            tokens.push(token);
+        }
+
+        if let Some(msg) = preprocess_tokens(&mut tokens, &options).into_iter().next() {
+            panic!("{}", msg);
+        }
+
+        for token in tokens {
+            let count = self.text.len() as i32;
+
             if token.get_token_value() == TokenValue::COLON {
-                if last_token.get_token_type() != TokenType::LABEL {
-                    panic!("Syntax Error: {} Expected \"label\", but find \"{}\"",
-                            token.get_token_location().to_string(), token.get_token_name());
+                let last_token = match self.text.last() {
+                    Some(last_token) if last_token.get_token_type() == TokenType::LABEL => last_token.to_owned(),
+                    _ => panic!("Syntax Error: {} Expected \"label\", but find \"{}\"",
+                                token.get_token_location().to_string(), token.get_token_name()),
+                };
+
+                if let Some(address) = self.index.get(&last_token.get_token_name()) {
+                    panic!("Syntax Error: {} Duplicate label: \"{}\", first defined at {}",
+                            last_token.get_token_location().to_string(), last_token.get_token_name(),
+                            self.text[*address as usize].get_token_location().to_string());
                 }
 
                 self.index.insert(last_token.get_token_name(), count - 1);
 
-                match last_token.get_token_name().as_str() {
-                    "main" | "start" | "_main" | "_start" => entrance = count - 1,
+                match (&self.entry_label, last_token.get_token_name().as_str()) {
+                    (Some(entry_label), name) if name == entry_label => self.entrance = count - 1,
+                    (None, "main" | "start" | "_main" | "_start") => self.entrance = count - 1,
                     _ => {},
                 }
             }
 
-            match token.get_token_type() {
-                TokenType::END_OF_FILE => break,
-                _ => self.text.push(token),
+            self.text.push(token);
+        }
+    }
+
+    /// Lay out the instructions from `self.origin` with the sizes of their machine code, so that every instruction
+    /// and every label has a byte address.
+    ///
+    /// An instruction without an encoding, such as `mov` from memory to memory, takes one byte, so that it still has
+    /// an address of its own. The bytes `incbin` includes, and those `db`, `dw` and `dd` define, are copied into memory
+    /// at their addresses.
+    fn lay_out(&mut self) {
+        self.addresses.clear();
+        self.instructions.clear();
+        self.symbols.clear();
+
+        let mut pending = Vec::new();
+        let mut data = Vec::new();
+        let mut definitions = Vec::new();
+        let mut address = self.origin;
+        let mut parser = Parser::new(&self.text);
+
+        loop {
+            let start = parser.position();
+
+            let size = match parser.parse_statement() {
+                Ok(None) => break,
+                Ok(Some(Statement::Label(name, _))) => {
+                    pending.push(name);
+                    continue;
+                },
+                // the labels before `org` are at the address it sets, before any instruction it replaces the origin
+                Ok(Some(Statement::Instruction(instruction))) if instruction.mnemonic == TokenValue::ORG => {
+                    let after = if self.addresses.is_empty() { 0 } else { address };
+                    address = org_address(&instruction, after).unwrap_or_else(|msg| panic!("{}", msg));
+                    continue;
+                },
+                Ok(Some(Statement::Instruction(instruction))) if is_declaration(instruction.mnemonic) ||
+                    cfg::is_section(instruction.mnemonic) || instruction.mnemonic == TokenValue::ENDP => continue,
+                Ok(Some(Statement::Instruction(instruction))) if instruction.mnemonic == TokenValue::INCBIN => {
+                    let bytes = include_binary(&instruction).unwrap_or_else(|msg| panic!("{}", msg));
+                    let size = bytes.len() as u32;
+                    data.push((address, bytes));
+                    size
+                },
+                // the labels the data uses may come after it, so the bytes are defined once every label has an address
+                Ok(Some(Statement::Instruction(instruction))) if data_size(instruction.mnemonic).is_some() => {
+                    let size = instruction_size(&instruction).unwrap_or_else(|msg| panic!("{}", msg));
+                    definitions.push((address, instruction));
+                    size
+                },
+                Ok(Some(Statement::Instruction(instruction))) => instruction_size(&instruction).unwrap_or(1),
+                Err(_) => {
+                    parser.skip_line();
+
+                    if self.text[start].get_token_type() != TokenType::INSTRUCTION {
+                        continue;
+                    }
+
+                    1
+                },
+            };
+
+            for name in pending.drain(..) {
+                self.symbols.insert(name, address);
             }
+
+            self.addresses.insert(start, (address, size));
+            self.instructions.insert(address, start);
+            address = next_address(address, size, &self.text[start].get_token_location())
+                .unwrap_or_else(|msg| panic!("{}", msg));
         }
 
-        let mut flag = false;
-        count = -1;
+        // labels at the end, and falling through the last instruction, lead to the end of text
+        for name in pending {
+            self.symbols.insert(name, address);
+        }
 
-        for token in &mut self.text {
-            count = count + 1;
+        self.instructions.entry(address).or_insert(self.text.len());
 
-            if !flag {
-                match token.get_token_value() {
-                    TokenValue::CALL | TokenValue::JMP | TokenValue::JE | TokenValue::JNE | TokenValue::JG | TokenValue::JGE |
-                        TokenValue::JL | TokenValue::JLE | TokenValue::JA | TokenValue::JAE | TokenValue::JB |
-                        TokenValue::JBE => {
-                            flag = true;
-                    },
-                    _ => {},
-                }
-            } else {
-                if token.get_token_type() != TokenType::LABEL {
-                    panic!("Syntax Error: {} Expected \"label\", but find \"{}\"",
-                            token.get_token_location().to_string(), token.get_token_name());
-                }
+        // an unknown label is reported where it is used, when the labels are resolved
+        for (address, instruction) in definitions {
+            if let Ok(bytes) = define_data(&instruction, &self.symbols) {
+                data.push((address, bytes));
+            }
+        }
 
-                let label_name = token.get_token_name();
+        // the bytes of `incbin` and of the definitions are in memory at their addresses
+        for (address, bytes) in data {
+            let (pointer, start, size) = self.memory(address, bytes.len());
+            unsafe { (&mut *pointer)[start..start + size].copy_from_slice(&bytes); }
+        }
+    }
 
-                if !self.index.contains_key(&label_name) {
-                    panic!("Syntax Error: {} Unknown label: \"{}\"", token.get_token_location().to_string(), label_name);
-                }
+    /// Replace every use of a `label` with its address, once every source file has been scanned, such as the target
+    /// of `call` and jump instructions or the immediate data of `mov eax, label`.
+    fn resolve_labels(&mut self) {
+        if let Some(entry_label) = &self.entry_label {
+            if !self.text.is_empty() && !self.index.contains_key(entry_label) {
+                panic!("Syntax Error: Entry label \"{}\" is not defined", entry_label);
+            }
+        }
+
+        // the syntax errors are reported by the layout
+        if let Ok(statements) = Parser::new(&self.text).parse_program() {
+            if let Some(msg) = link(&statements).into_iter().chain(self.policy.check(&statements)).next() {
+                panic!("{}", msg);
+            }
+        }
 
-                let label_address = self.index.get(&label_name).unwrap();
+        self.lay_out();
+        self.deferred_errors.clear();
 
-                token.set_token_type(TokenType::IMMEDIATE_DATA);
-                token.set_int_value(label_address - count - 1);
+        for i in 0..self.text.len() {
+            let token = &self.text[i];
 
-                flag = false;
+            // `call` can also take its target from a register or memory
+            let is_indirect = token.get_token_type() == TokenType::REGISTER ||
+                matches!(token.get_token_value(), TokenValue::BYTE | TokenValue::WORD | TokenValue::DWORD);
+            let is_target = i > 0 && ((self.text[i - 1].get_token_value() == TokenValue::CALL && !is_indirect) ||
+                cfg::is_jump(self.text[i - 1].get_token_value()));
+
+            if token.get_token_type() != TokenType::LABEL {
+                if is_target {
+                    let msg = format!("Syntax Error: {} Expected \"label\", but find \"{}\"",
+                                      token.get_token_location().to_string(), token.get_token_name());
+                    self.defer_error(i, msg);
+                }
+
+                continue;
+            }
+
+            // the definition of a label
+            if self.text.get(i + 1).map(|token| token.get_token_value()) == Some(TokenValue::COLON) {
+                continue;
             }
+
+            let label_name = token.get_token_name();
+
+            let label_address = match self.symbols.get(&label_name) {
+                Some(label_address) => *label_address,
+                None => {
+                    let msg = format!("Syntax Error: {} Unknown label: \"{}\"", token.get_token_location().to_string(),
+                                      label_name);
+                    self.defer_error(i, msg);
+                    continue;
+                },
+            };
+
+            let token = &mut self.text[i];
+            token.set_token_type(TokenType::IMMEDIATE_DATA);
+            token.set_int_value(label_address as i32);
+        }
+
+        self.position = self.entrance as usize;
+
+        if self.verbosity >= Verbosity::VERBOSE {
+            eprintln!("Preprocess: {} tokens, {} labels, entry at {:#010x}", self.text.len(), self.index.len(),
+                    self.get_eip());
+        }
+    }
+
+    /// Report an error of the token at `position` found while loading, or with a fault limit, when its line is
+    /// executed, so that the rest of the program still runs.
+    fn defer_error(&mut self, position: usize, msg: String) {
+        if self.fault_limit.is_none() {
+            panic!("{}", msg);
         }
 
-        self.eip = (entrance as u32).to_le_bytes();
+        let start = self.line_start(position);
+        self.deferred_errors.entry(start).or_insert(msg);
+    }
+
+    /// Get the index of the first token of the line of the token at `position`.
+    fn line_start(&self, position: usize) -> usize {
+        let location = self.text[position].get_token_location();
+        let mut start = position;
+
+        while start > 0 && is_same_line(&self.text[start - 1].get_token_location(), &location) {
+            start -= 1;
+        }
+
+        start
+    }
+
+    /// Get the index of the first token after the line of the token at `position`.
+    fn line_end(&self, position: usize) -> usize {
+        let location = match self.text.get(position) {
+            Some(token) => token.get_token_location(),
+            None => return position,
+        };
+        let mut end = position + 1;
+
+        while end < self.text.len() && is_same_line(&self.text[end].get_token_location(), &location) {
+            end += 1;
+        }
+
+        end
     }
 
     fn parse_register(&mut self) -> Result<(*mut [u8], usize, usize), String> {
         self.go_from_here(1);
 
-        match self.text[self.get_eip() - 1].get_token_value() {
+        match self.text[self.position - 1].get_token_value() {
             TokenValue::EAX => return Ok((&mut self.eax as *mut [u8], 0, 4)),
             TokenValue::AX => return Ok((&mut self.eax as *mut [u8], 0, 2)),
             TokenValue::AH => return Ok((&mut self.eax as *mut [u8], 1, 1)),
@@ -299,13 +1032,28 @@ impl VM {
             }
 
             let (left, _right) = value.split_at_mut(size);
-            left.copy_from_slice(&(*pointer)[start..start + size]);
+            left.copy_from_slice(&(&*pointer)[start..start + size]);
         }
 
         u32::from_le_bytes(value)
     }
 
+    /// Get the value of immediate data as written, rather than sign-extended from its smallest size.
+    fn get_immediate_value((pointer, _start, _size): (*mut [u8], usize, usize)) -> u32 {
+        let mut bytes = [0; 4];
+        unsafe { bytes.copy_from_slice(&(&*pointer)[0..4]); }
+        u32::from_le_bytes(bytes)
+    }
+
     fn set_value(&self, (pointer, start, size): (*mut [u8], usize, usize), value: u32) {
+        if pointer as *const u8 == self.stack.as_ptr() {
+            self.count_written(self.memory_base.wrapping_add(start as u32), size);
+
+            if self.loop_detection && unsafe { (&*pointer)[start..start + size] != value.to_le_bytes()[0..size] } {
+                self.memory_changed.set(true);
+            }
+        }
+
         unsafe {
             let (_left, right) = (*pointer).split_at_mut(start);
             let (left, _right) = right.split_at_mut(size);
@@ -313,10 +1061,19 @@ impl VM {
         }
     }
 
+    /// Count `size` bytes of memory written by the program against its quota.
+    fn count_written(&self, address: u32, size: usize) {
+        self.memory_written.set(self.memory_written.get() + size as u64);
+
+        if self.trace.is_some() {
+            self.written.borrow_mut().push((address, size));
+        }
+    }
+
     fn parse_immediate_data(&mut self) -> (*mut [u8], usize, usize) {
         let sign = self.validate_token_value(TokenValue::MINUS, true);
 
-        let mut value: i64 = self.text[self.get_eip()].get_int_value().try_into().unwrap();
+        let mut value = i64::from(self.text[self.position].get_int_value());
         self.go_from_here(1);
 
         if sign {
@@ -333,8 +1090,8 @@ impl VM {
             } else if value <= std::u32::MAX as i64 {
                 size = 4;
             } else {
-                panic!("Syntax Error: {} Integer literal: \"{}\" is too big!", self.text[self.get_eip() -
-                        1].get_token_location().to_string(), self.text[self.get_eip() - 1].get_token_name());
+                panic!("Syntax Error: {} Integer literal: \"{}\" is too big!", self.text[self.position -
+                        1].get_token_location().to_string(), self.text[self.position - 1].get_token_name());
             }
         } else {
             if value >= std::i8::MIN as i64 {
@@ -344,12 +1101,14 @@ impl VM {
             } else if value >= std::i32::MIN as i64 {
                 size = 4;
             } else {
-                panic!("Syntax Error: {} Integer literal: \"{}\" is too small!", self.text[self.get_eip() -
-                        1].get_token_location().to_string(), self.text[self.get_eip() - 1].get_token_name());
+                panic!("Syntax Error: {} Integer literal: \"{}\" is too small!", self.text[self.position -
+                        1].get_token_location().to_string(), self.text[self.position - 1].get_token_name());
             }
         }
 
-        let pointer = Box::into_raw(Box::new((value as u32).to_le_bytes()));
+        let mut bytes: Box<[u8]> = Box::new((value as u32).to_le_bytes());
+        let pointer = &mut *bytes as *mut [u8];
+        self.immediates.push(bytes);
 
         (pointer, 0, size)
     }
@@ -358,72 +1117,77 @@ impl VM {
         let mut result = lhs;
 
         loop {
-            let current_precedence = self.text[self.get_eip()].get_precedence();
+            let current_precedence = self.text[self.position].get_precedence();
 
             if current_precedence < precedence {
                 return result;
             }
 
-            let operation = self.text[self.get_eip()].get_token_value();
+            let operation = self.text[self.position].get_token_value();
             self.go_from_here(1);
 
-            let mut rhs = match self.text[self.get_eip()].get_token_type() {
-                TokenType::REGISTER => {
-                    VM::get_value(self.parse_register().unwrap())
-                },
-                TokenType::IMMEDIATE_DATA => {
-                    self.go_from_here(1);
-                    self.text[self.get_eip() - 1].get_int_value()
-                },
-                _ => {
-                    self.error_report(&format!("Unexpected token: {}", self.text[self.get_eip()].get_token_name()));
-                    std::u32::MAX
-                },
-            };
-
-            let next_precedence = self.text[self.get_eip()].get_precedence();
+            let mut rhs = self.parse_operand_value();
+            let next_precedence = self.text[self.position].get_precedence();
 
             if current_precedence < next_precedence {
                 rhs = self.parse_binary_operation(rhs, current_precedence + 1);
             }
 
             result = match operation {
-                TokenValue::PLUS => lhs + rhs,
-                TokenValue::MINUS => lhs - rhs,
-                TokenValue::TIMES => lhs * rhs,
+                TokenValue::PLUS => result.wrapping_add(rhs),
+                TokenValue::MINUS => result.wrapping_sub(rhs),
+                TokenValue::TIMES => result.wrapping_mul(rhs),
+                // division is signed, as in the expressions of the assembler, where `-8 / 2` is `-4`
+                TokenValue::SLASH | TokenValue::PERCENT if rhs == 0 => {
+                    self.error_report(&"Division by zero in expression".to_string());
+                    u32::MAX
+                },
+                TokenValue::SLASH => (result as i32).wrapping_div(rhs as i32) as u32,
+                TokenValue::PERCENT => (result as i32).wrapping_rem(rhs as i32) as u32,
+                TokenValue::LSHIFT => result.checked_shl(rhs).unwrap_or(0),
+                TokenValue::RSHIFT => result.checked_shr(rhs).unwrap_or(0),
                 _ => std::u32::MAX,
             };
         }
     }
 
-    fn parse_address(&mut self) -> usize {
-        let lhs = match self.text[self.get_eip()].get_token_type() {
-            TokenType::REGISTER => {
-                    VM::get_value(self.parse_register().unwrap())
-            },
+    /// Get the value of a register, immediate data, a negated value or an expression in parentheses, which an
+    /// address expression is made of.
+    fn parse_operand_value(&mut self) -> u32 {
+        match self.text[self.position].get_token_type() {
+            TokenType::REGISTER => return VM::get_value(self.parse_register().unwrap()),
             TokenType::IMMEDIATE_DATA => {
                 self.go_from_here(1);
-                self.text[self.get_eip() - 1].get_int_value()
+                return self.text[self.position - 1].get_int_value();
             },
-            _ => {
-                let value;
-                if self.text[self.get_eip()].get_token_value() == TokenValue::MINUS {
-                    self.go_from_here(2);
-                    value = self.text[self.get_eip() - 1].get_int_value().overflowing_neg().0;
-                } else {
-                    self.error_report(&format!("Unexpected token: {}", self.text[self.get_eip()].get_token_name()));
-                    value = std::u32::MAX;
-                }
+            _ => {},
+        }
 
+        match self.text[self.position].get_token_value() {
+            TokenValue::MINUS => {
+                self.go_from_here(1);
+                self.parse_operand_value().wrapping_neg()
+            },
+            TokenValue::LPAREN => {
+                self.go_from_here(1);
+                let value = self.parse_address() as u32;
+                self.expect_token_value(TokenValue::RPAREN, ")".to_string(), true);
                 value
             },
-        };
+            _ => {
+                self.error_report(&format!("Unexpected token: {}", self.text[self.position].get_token_name()));
+                u32::MAX
+            },
+        }
+    }
 
+    fn parse_address(&mut self) -> usize {
+        let lhs = self.parse_operand_value();
         self.parse_binary_operation(lhs, 0) as usize
     }
 
     fn parse_memory(&mut self) -> Result<(*mut [u8], usize, usize), String> {
-        let size = match self.text[self.get_eip()].get_token_value() {
+        let size = match self.text[self.position].get_token_value() {
             TokenValue::BYTE => 1,
             TokenValue::WORD => 2,
             TokenValue::DWORD => 4,
@@ -440,7 +1204,7 @@ impl VM {
             return Err("Missing left brack '[' !".to_string());
         }
 
-        let memory_address: usize = match self.parse_address().try_into() {
+        let memory_address: u32 = match self.parse_address().try_into() {
             Ok(memory_address) => memory_address,
             Err(err) => panic!("Invaild memory address: {}", err),
         };
@@ -449,11 +1213,26 @@ impl VM {
             return Err("Missing right brack ']' !".to_string());
         }
 
-        return Ok((&mut self.stack as *mut [u8], memory_address, size));
+        Ok(self.memory(memory_address, size))
+    }
+
+    /// Get the memory operand of `size` bytes at `address`.
+    fn memory(&mut self, address: u32, size: usize) -> (*mut [u8], usize, usize) {
+        let start = address.wrapping_sub(self.memory_base) as usize;
+
+        if start + size > self.stack.len() {
+            panic!("Invalid memory address: {:#x}", address);
+        }
+
+        if let (Some(cache), Some(instruction)) = (&mut self.cache, self.executing) {
+            self.cache_stats.entry(instruction).or_default().add(cache.access(address, size));
+        }
+
+        (&mut *self.stack as *mut [u8], start, size)
     }
 
     fn parse_source(&mut self) -> Result<(*mut [u8], usize, usize), String> {
-        match self.text[self.get_eip()].get_token_value() {
+        match self.text[self.position].get_token_value() {
             TokenValue::BYTE | TokenValue::WORD | TokenValue::DWORD => {
                 return self.parse_memory();
             },
@@ -461,19 +1240,19 @@ impl VM {
         }
 
         if self.validate_token_type(TokenType::REGISTER, false) {
-            return self.parse_register();
+            self.parse_register()
         } else if self.validate_token_type(TokenType::IMMEDIATE_DATA, false) ||
             self.validate_token_value(TokenValue::MINUS, false) {
-            return Ok(self.parse_immediate_data());
+            Ok(self.parse_immediate_data())
         } else {
-            self.error_report(&format!("Unexpected token: {}", self.text[self.get_eip()].get_token_name()));
-            return Err(format!("{}: Unexpected token: {}", self.text[self.get_eip()].get_token_location().to_string(),
-                        self.text[self.get_eip()].get_token_name()));
+            self.error_report(&format!("Unexpected token: {}", self.text[self.position].get_token_name()));
+            Err(format!("{}: Unexpected token: {}", self.text[self.position].get_token_location().to_string(),
+                        self.text[self.position].get_token_name()))
         }
     }
 
     fn parse_destination(&mut self) -> Result<(*mut [u8], usize, usize), String> {
-        match self.text[self.get_eip()].get_token_value() {
+        match self.text[self.position].get_token_value() {
             TokenValue::BYTE | TokenValue::WORD | TokenValue::DWORD => {
                 return self.parse_memory();
             },
@@ -481,11 +1260,11 @@ impl VM {
         }
 
         if self.validate_token_type(TokenType::REGISTER, false) {
-            return self.parse_register();
+            self.parse_register()
         } else {
-            self.error_report(&format!("Unexpected token: {}", self.text[self.get_eip()].get_token_name()));
-            return Err(format!("{}: Unexpected token: {}", self.text[self.get_eip()].get_token_location().to_string(),
-                        self.text[self.get_eip()].get_token_name()));
+            self.error_report(&format!("Unexpected token: {}", self.text[self.position].get_token_name()));
+            Err(format!("{}: Unexpected token: {}", self.text[self.position].get_token_location().to_string(),
+                        self.text[self.position].get_token_name()))
         }
     }
 
@@ -515,18 +1294,18 @@ impl VM {
             let data = self.parse_immediate_data();
 
             if destination.2 < data.2 {
-                panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.get_eip() -
+                panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.position -
                         1].get_token_location().to_string(), destination.2, data.2);
             }
 
             let mut bytes = [0; 4];
-            unsafe { bytes.copy_from_slice(&(*data.0)[0..4]); }
+            unsafe { bytes.copy_from_slice(&(&*data.0)[0..4]); }
             value = u32::from_le_bytes(bytes);
         } else {
             let source = self.parse_source().unwrap();
 
             if destination.2 != source.2 {
-                panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.get_eip() -
+                panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.position -
                         1].get_token_location().to_string(), destination.2, source.2);
             }
 
@@ -571,7 +1350,7 @@ impl VM {
         let source = self.parse_source().unwrap();
 
         if destination.2 <= source.2 {
-            panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.get_eip() -
+            panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.position -
                     1].get_token_location().to_string(), destination.2, source.2);
         }
 
@@ -584,7 +1363,7 @@ impl VM {
             }
 
             let (left, _right) = bytes.split_at_mut(source.2);
-            left.copy_from_slice(&(*source.0)[source.1..source.1 + source.2]);
+            left.copy_from_slice(&(&*source.0)[source.1..source.1 + source.2]);
         }
 
         self.set_value(destination, u32::from_le_bytes(bytes));
@@ -625,7 +1404,7 @@ impl VM {
         let source = self.parse_source().unwrap();
 
         if destination.2 <= source.2 {
-            panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.get_eip() -
+            panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.position -
                     1].get_token_location().to_string(), destination.2, source.2);
         }
 
@@ -633,55 +1412,75 @@ impl VM {
         unsafe {
 
             let (left, _right) = bytes.split_at_mut(source.2);
-            left.copy_from_slice(&(*source.0)[source.1..source.1 + source.2]);
+            left.copy_from_slice(&(&*source.0)[source.1..source.1 + source.2]);
         }
 
         self.set_value(destination, u32::from_le_bytes(bytes));
     }
 
-    fn set_cf_and_of(&mut self, result: u32, size: usize) {
-        let tmp = result as i32;
+    /// `lea` instruction, which stores the address of the memory operand rather than reading it
+    ///
+    /// lea &lt;reg16&gt;, [addr]
+    ///
+    /// lea &lt;reg32&gt;, [addr]
+    fn lea(&mut self) {
+        self.go_from_here(1);
 
-        match size {
-            1 => {
-                if result < std::u8::MIN as u32 || result > std::u8::MAX as u32 {
-                    self.cf = true;
-                }
+        if !self.expect_token_type(TokenType::REGISTER, "register".to_string(), false) {
+            return;
+        }
 
-                if tmp < std::i8::MIN as i32 || tmp > std::i8::MAX as i32 {
-                    self.of = true;
-                }
-            },
-            2 => {
-                if result < std::u16::MIN as u32 || result > std::u16::MAX as u32{
-                    self.cf = true;
-                }
+        let destination = self.parse_register().unwrap();
 
-                if tmp < std::i16::MIN as i32 || tmp > std::i16::MAX as i32 {
-                    self.of = true;
-                }
-            },
-            4 => {},
-            _ => panic!("Invaild length: {}", size),
+        if destination.2 == 1 {
+            panic!("Syntax Error: {} The destination of lea can not be an 8-bits register", self.text[self.position -
+                    1].get_token_location().to_string());
+        }
+
+        if !self.expect_token_value(TokenValue::COMMA, ",".to_string(), true) {
+            return;
         }
-    }
 
-    fn set_sf_and_zf(&mut self, result: u32) {
-        let tmp = result as i32;
+        // the size is only written for the other instructions, an address has none
+        if (self.validate_token_value(TokenValue::BYTE, true) || self.validate_token_value(TokenValue::WORD, true) ||
+            self.validate_token_value(TokenValue::DWORD, true)) &&
+            !self.expect_token_value(TokenValue::PTR, "ptr".to_string(), true) {
+            return;
+        }
 
-        if tmp > 0 {
-            self.sf = false;
-            self.zf = false;
-        } else if tmp == 0 {
-            self.sf = false;
-            self.zf = true;
-        } else {
-            self.sf = true;
-            self.zf = false;
+        if !self.expect_token_value(TokenValue::LBRACK, "[".to_string(), true) {
+            return;
+        }
+
+        let address = self.parse_address() as u32;
+
+        if !self.expect_token_value(TokenValue::RBRACK, "]".to_string(), true) {
+            return;
         }
+
+        self.set_value(destination, address);
+    }
+
+    /// Set all arithmetic flags from the result of `add`, `sub`, `cmp` or `neg`.
+    fn set_arithmetic_flags(&mut self, arithmetic: Arithmetic) {
+        self.cf = arithmetic.cf;
+        self.zf = arithmetic.zf;
+        self.sf = arithmetic.sf;
+        self.of = arithmetic.of;
+        self.pf = flags::parity(arithmetic.result);
+        self.af = arithmetic.af;
+    }
+
+    /// Set `sf` from the top bit, `zf` from the bits and `pf` from the low byte of the result of `size` bytes, the
+    /// higher bits are ignored.
+    fn set_result_flags(&mut self, result: u32, size: usize) {
+        self.sf = result & flags::sign_bit(size) != 0;
+        self.zf = result & flags::mask(size) == 0;
+        self.pf = flags::parity(result);
     }
 
-    /// binary operation, including `add`, `sub`, `and`, `or`, `xor`.
+    /// binary operation, including `add`, `sub`, `and`, `or`, `xor`, and `test`, which is `and` without storing the
+    /// result.
     ///
     /// bop &lt;reg&gt;, &lt;reg&gt;
     ///
@@ -693,7 +1492,7 @@ impl VM {
     ///
     /// bop &lt;mem&gt;, &lt;con&gt;
     fn binary_operation(&mut self) {
-        let instruction = self.text[self.get_eip()].to_owned();
+        let instruction = self.text[self.position].to_owned();
         self.go_from_here(1);
 
         let destination = self.parse_destination().unwrap();
@@ -702,45 +1501,62 @@ impl VM {
             return;
         }
 
+        let immediate = self.validate_token_type(TokenType::IMMEDIATE_DATA, false) ||
+            self.validate_token_value(TokenValue::MINUS, false);
         let source = self.parse_source().unwrap();
 
         if source.2 != 0 && destination.2 < source.2 {
-            panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.get_eip() -
+            panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.position -
                     1].get_token_location().to_string(), destination.2, source.2);
         }
 
         let first_operand = VM::get_value(destination);
-        let second_operand = VM::get_value(source);
+        let second_operand = if immediate { VM::get_immediate_value(source) } else { VM::get_value(source) };
         let result;
         match instruction.get_token_value() {
             TokenValue::ADD => {
-                let pair = first_operand.overflowing_add(second_operand);
-                result = pair.0;
-                self.cf = pair.1;
-                self.of = (first_operand as i32).overflowing_add(second_operand as i32).1;
-                self.set_cf_and_of(result, destination.2);
+                let arithmetic = flags::add(first_operand, second_operand, destination.2);
+                result = arithmetic.result;
+                self.set_arithmetic_flags(arithmetic);
             },
             TokenValue::SUB => {
-                let pair = first_operand.overflowing_sub(second_operand);
-                result = pair.0;
-                self.cf = pair.1;
-                self.of = (first_operand as i32).overflowing_add(second_operand as i32).1;
-                self.set_cf_and_of(result, destination.2);
+                let arithmetic = flags::sub(first_operand, second_operand, destination.2);
+                result = arithmetic.result;
+                self.set_arithmetic_flags(arithmetic);
+            },
+            TokenValue::ADC => {
+                let arithmetic = flags::adc(first_operand, second_operand, self.cf, destination.2);
+                result = arithmetic.result;
+                self.set_arithmetic_flags(arithmetic);
+            },
+            TokenValue::SBB => {
+                let arithmetic = flags::sbb(first_operand, second_operand, self.cf, destination.2);
+                result = arithmetic.result;
+                self.set_arithmetic_flags(arithmetic);
             },
             TokenValue::AND => {
                 result = first_operand & second_operand;
                 self.cf = false;
                 self.of = false;
+                self.set_result_flags(result, destination.2);
             },
             TokenValue::OR => {
                 result = first_operand | second_operand;
                 self.cf = false;
                 self.of = false;
+                self.set_result_flags(result, destination.2);
             },
             TokenValue::XOR => {
                 result = first_operand ^ second_operand;
                 self.cf = false;
                 self.of = false;
+                self.set_result_flags(result, destination.2);
+            },
+            TokenValue::TEST => {
+                self.cf = false;
+                self.of = false;
+                self.set_result_flags(first_operand & second_operand, destination.2);
+                return;
             },
             _ => {
                 result = std::u32::MAX;
@@ -748,8 +1564,6 @@ impl VM {
             },
         };
 
-        self.set_sf_and_zf(result);
-
         self.set_value(destination, result);
     }
 
@@ -770,41 +1584,31 @@ impl VM {
         self.go_from_here(1);
 
         let multiplier = self.parse_destination().unwrap();
+        let size = multiplier.2;
+        let old_eax = &mut self.eax as *mut [u8];
+        let old_edx = &mut self.edx as *mut [u8];
+        let (result, overflow) = flags::mul(VM::get_value((old_eax, 0, size)), VM::get_value(multiplier), size);
+        self.cf = overflow;
+        self.of = overflow;
 
-        match multiplier.2 {
+        match size {
             1 => {
-                let multiplicand: u32 = self.eax[0].try_into().unwrap();
-                let result = multiplicand.wrapping_mul(VM::get_value(multiplier));
-                let old_eax = &mut self.eax as *mut [u8];
-                self.set_value((old_eax, 0, 2), result);
-                self.cf = result > 255;
-                self.of = self.cf;
-                self.set_sf_and_zf(result);
+                self.set_value((old_eax, 0, 2), result as u32);
+                // the product is in `ax`
+                self.set_result_flags(result as u32, 2);
             },
             2 => {
-                let mut bytes = [0; 2];
-                &bytes.copy_from_slice(&self.eax[0..2]);
-                let multiplicand: u32 = u16::from_le_bytes(bytes).try_into().unwrap();
-                let result = multiplicand.wrapping_mul(VM::get_value(multiplier));
-                let old_eax = &mut self.eax as *mut [u8];
-                let old_edx = &mut self.edx as *mut [u8];
-                self.set_value((old_eax, 0, 2), result);
-                self.set_value((old_edx, 0, 2), result >> 16);
-                self.cf = result >= (1u32 << 16);
-                self.of = self.cf;
-                self.set_sf_and_zf(result);
+                self.set_value((old_eax, 0, 2), result as u32);
+                self.set_value((old_edx, 0, 2), (result >> 16) as u32);
+                // the product is in `dx:ax`
+                self.set_result_flags(result as u32, 4);
             },
             4 => {
-                let multiplicand: u64 = u32::from_le_bytes(self.eax).try_into().unwrap();
-                let result = multiplicand.wrapping_mul(VM::get_value(multiplier) as u64);
-                let old_eax = &mut self.eax as *mut [u8];
-                let old_edx = &mut self.edx as *mut [u8];
                 self.set_value((old_eax, 0, 4), result as u32);
                 self.set_value((old_edx, 0, 4), (result >> 32) as u32);
-                self.cf = result >= (1u64 << 32);
-                self.of = self.cf;
 
                 let tmp = result as i64;
+                self.pf = flags::parity(result as u32);
 
                 if tmp > 0 {
                     self.sf = false;
@@ -821,147 +1625,177 @@ impl VM {
         }
     }
 
-    /// `imul` instruction, only support for integer.
+    /// `imul` instruction
     ///
-    /// imul &lt;reg32&gt;, &lt;reg32&gt;
+    /// imul &lt;reg&gt; / &lt;mem&gt;
     ///
-    /// imul &lt;reg32&gt;, &lt;mem&gt;
+    /// imul &lt;reg&gt;, &lt;reg&gt; / &lt;mem&gt;
     ///
-    /// imul &lt;reg32&gt;, &lt;reg32&gt;, &lt;con&gt;
+    /// imul &lt;reg&gt;, &lt;reg&gt; / &lt;mem&gt;, &lt;con&gt;
     ///
-    /// imul &lt;reg32&gt;, &lt;mem&gt;, &lt;con&gt;
+    /// The form of one operand multiplies `al`, `ax` or `eax` into `ax`, `dx:ax` or `edx:eax`, the others keep the
+    /// truncated product in the 16 or 32-bits destination. `cf` and `of` are set if the signed product does not fit
+    /// in the operand size, and the other flags are unchanged.
     fn imul(&mut self) {
         self.go_from_here(1);
 
-        if !self.expect_token_type(TokenType::REGISTER, "register".to_string(), false) {
+        let is_register = self.validate_token_type(TokenType::REGISTER, false);
+        let first_operand = self.parse_destination().unwrap();
+        let size = first_operand.2;
+
+        if !self.validate_token_value(TokenValue::COMMA, true) {
+            let old_eax = &mut self.eax as *mut [u8];
+            let old_edx = &mut self.edx as *mut [u8];
+            let (result, overflow) = flags::imul(VM::get_value((old_eax, 0, size)), VM::get_value(first_operand),
+                                                 size);
+            self.cf = overflow;
+            self.of = overflow;
+
+            match size {
+                1 => self.set_value((old_eax, 0, 2), result as u32),
+                2 => {
+                    self.set_value((old_eax, 0, 2), result as u32);
+                    self.set_value((old_edx, 0, 2), (result >> 16) as u32);
+                },
+                _ => {
+                    self.set_value((old_eax, 0, 4), result as u32);
+                    self.set_value((old_edx, 0, 4), (result >> 32) as u32);
+                },
+            }
+
             return;
         }
 
-        let destination = self.parse_register().unwrap();
-
-        if !self.expect_token_value(TokenValue::COMMA, ",".to_string(), true) {
+        if !is_register {
+            self.error_report(&"The destination of imul must be a register".to_string());
             return;
         }
 
-        let first_operand = self.parse_destination().unwrap();
-        let second_operand;
-        let result;
+        let destination = first_operand;
+        let source = self.parse_destination().unwrap();
 
-        if self.validate_token_value(TokenValue::COMMA, true) {
-            if !self.validate_token_type(TokenType::IMMEDIATE_DATA, false) {
+        let multiplier = if self.validate_token_value(TokenValue::COMMA, true) {
+            if !self.validate_token_type(TokenType::IMMEDIATE_DATA, false) &&
+                !self.validate_token_value(TokenValue::MINUS, false) {
+                self.error_report(&format!("Expected \"immediate data\", but find \"{}\"",
+                                           self.text[self.position].get_token_name()));
                 return;
             }
 
-            second_operand = self.text[self.get_eip()].get_int_value();
-            self.go_from_here(1);
+            let immediate = self.parse_immediate_data();
+            VM::get_immediate_value(immediate)
+        } else {
+            VM::get_value(destination)
+        };
 
-            let pair = VM::get_value(first_operand).overflowing_mul(second_operand);
-            result = pair.0;
-            self.cf = pair.1;
+        let (result, overflow) = flags::imul(VM::get_value(source), multiplier, size);
+        self.cf = overflow;
+        self.of = overflow;
 
-            // self.set_flag(result, destination.2);
+        self.set_value(destination, result as u32);
+    }
 
-            self.set_value(destination, result);
-        } else {
-            let pair = VM::get_value(destination).overflowing_mul(VM::get_value(first_operand));
-            result = pair.0;
-            self.cf = pair.1;
+    /// Get the dividend of `div` and `idiv` by an operand of `size` bytes, which is `ax`, `dx:ax` or `edx:eax`.
+    fn dividend(&mut self, size: usize) -> u64 {
+        let old_eax = &mut self.eax as *mut [u8];
+        let old_edx = &mut self.edx as *mut [u8];
+
+        match size {
+            1 => VM::get_value((old_eax, 0, 2)) as u64 & 0xffff,
+            _ => {
+                let high = VM::get_value((old_edx, 0, size)) & flags::mask(size);
+                let low = VM::get_value((old_eax, 0, size)) & flags::mask(size);
+                (high as u64) << (size * 8) | low as u64
+            },
+        }
+    }
+
+    /// Set the quotient and the remainder of `div` and `idiv` by an operand of `size` bytes, into `al` and `ah`,
+    /// `ax` and `dx`, or `eax` and `edx`.
+    fn set_quotient(&mut self, size: usize, quotient: u32, remainder: u32) {
+        let old_eax = &mut self.eax as *mut [u8];
+        let old_edx = &mut self.edx as *mut [u8];
+
+        match size {
+            1 => {
+                self.set_value((old_eax, 0, 1), quotient);
+                self.set_value((old_eax, 1, 1), remainder);
+            },
+            _ => {
+                self.set_value((old_eax, 0, size), quotient);
+                self.set_value((old_edx, 0, size), remainder);
+            },
+        }
+    }
 
-            self.set_value(destination, result);
+    /// Stop the program for the divide error of `div` or `idiv` at `instruction`, which is division by zero or a
+    /// quotient too large for the operand size.
+    fn divide_error(&self, instruction: usize, divisor: u32, size: usize) -> ! {
+        let location = self.text[instruction].get_token_location().to_string();
+
+        if divisor == 0 {
+            panic!("Runtime Error: {} Division by zero", location);
         }
+
+        panic!("Runtime Error: {} The quotient of {} does not fit in {} bits", location,
+               self.text[instruction].get_token_name(), size * 8);
     }
 
     /// `div` instruction
     ///
-    /// div &lt;reg8&gt;
+    /// div &lt;reg8&gt; / &lt;mem8&gt;
     ///
-    /// div &lt;mem8&gt;
+    /// div &lt;reg16&gt; / &lt;mem16&gt;
     ///
-    /// div &lt;reg16&gt;
+    /// div &lt;reg32&gt; / &lt;mem32&gt;
     ///
-    /// div &lt;mem16&gt;
-    ///
-    /// div &lt;reg32&gt;
-    ///
-    /// div &lt;mem32&gt;
+    /// Divide `ax`, `dx:ax` or `edx:eax` by the unsigned operand. The flags are unchanged.
     fn div(&mut self) {
-        let is_unsigned = self.validate_token_value(TokenValue::MUL, true);
+        let instruction = self.position;
+        self.go_from_here(1);
 
         let divisor = self.parse_destination().unwrap();
+        let size = divisor.2;
+        let value = VM::get_value(divisor) & flags::mask(size);
+        let dividend = self.dividend(size);
 
-        match divisor.2 {
-            1 => {
-                let mut bytes = [0; 2];
-                &bytes.copy_from_slice(&self.eax[0..2]);
-                let dividend = u16::from_le_bytes(bytes);
-                let quotient;
-                let remainder;
-
-                if is_unsigned {
-                    quotient = dividend.wrapping_div(VM::get_value(divisor) as u16);
-                    remainder = dividend.wrapping_rem(VM::get_value(divisor) as u16);
-                } else {
-                    quotient = (dividend as i16).wrapping_div(VM::get_value(divisor) as i16) as u16;
-                    remainder = (dividend as i16).wrapping_rem(VM::get_value(divisor) as i16) as u16;
-                }
-
-                let old_eax = &mut self.eax as *mut [u8];
-                let old_edx = &mut self.edx as *mut [u8];
-                self.set_value((old_eax, 0, 1), quotient as u32);
-                self.set_value((old_edx, 1, 1), remainder as u32);
-            },
-            2 => {
-                let mut bytes = [0; 4];
-                {
-                    let (left, right) = bytes.split_at_mut(2);
-                    left.copy_from_slice(&self.eax[0..2]);
-                    right.copy_from_slice(&self.edx[0..2]);
-                }
-
-                let dividend = u32::from_le_bytes(bytes);
-                let quotient;
-                let remainder;
+        let quotient = match dividend.checked_div(value as u64) {
+            Some(quotient) if quotient <= flags::mask(size) as u64 => quotient,
+            _ => self.divide_error(instruction, value, size),
+        };
 
-                if is_unsigned {
-                    quotient = dividend.wrapping_div(VM::get_value(divisor));
-                    remainder = dividend.wrapping_rem(VM::get_value(divisor));
-                } else {
-                    quotient = (dividend as i32).wrapping_div(VM::get_value(divisor) as i32) as u32;
-                    remainder = (dividend as i32).wrapping_rem(VM::get_value(divisor) as i32) as u32;
-                }
+        self.set_quotient(size, quotient as u32, (dividend % value as u64) as u32);
+    }
 
-                let old_eax = &mut self.eax as *mut [u8];
-                let old_edx = &mut self.edx as *mut [u8];
-                self.set_value((old_eax, 0, 2), quotient);
-                self.set_value((old_edx, 0, 2), remainder);
-            },
-            4 => {
-                let mut bytes = [0; 8];
-                {
-                    let (left, right) = bytes.split_at_mut(4);
-                    left.copy_from_slice(&self.eax);
-                    right.copy_from_slice(&self.edx);
-                }
+    /// `idiv` instruction
+    ///
+    /// idiv &lt;reg8&gt; / &lt;mem8&gt;
+    ///
+    /// idiv &lt;reg16&gt; / &lt;mem16&gt;
+    ///
+    /// idiv &lt;reg32&gt; / &lt;mem32&gt;
+    ///
+    /// Divide `ax`, `dx:ax` or `edx:eax` by the signed operand. The quotient is truncated toward zero, and the
+    /// remainder has the sign of the dividend. The flags are unchanged.
+    fn idiv(&mut self) {
+        let instruction = self.position;
+        self.go_from_here(1);
 
-                let dividend = u64::from_le_bytes(bytes);
-                let quotient;
-                let remainder;
+        let divisor = self.parse_destination().unwrap();
+        let size = divisor.2;
+        let value = flags::sign_extend(VM::get_value(divisor), size);
+        let dividend = match size {
+            4 => self.dividend(size) as i64,
+            _ => flags::sign_extend(self.dividend(size) as u32, size * 2),
+        };
 
-                if is_unsigned {
-                    quotient = dividend.wrapping_div(VM::get_value(divisor) as u64);
-                    remainder = dividend.wrapping_rem(VM::get_value(divisor) as u64);
-                } else {
-                    quotient = (dividend as i64).wrapping_div(VM::get_value(divisor) as i64) as u64;
-                    remainder = (dividend as i64).wrapping_rem(VM::get_value(divisor) as i64) as u64;
-                }
+        // the quotient of the most negative dividend by -1 overflows even in 64 bits
+        let quotient = match dividend.checked_div(value) {
+            Some(quotient) if quotient == flags::sign_extend(quotient as u32, size) => quotient,
+            _ => self.divide_error(instruction, value as u32, size),
+        };
 
-                let old_eax = &mut self.eax as *mut [u8];
-                let old_edx = &mut self.edx as *mut [u8];
-                self.set_value((old_eax, 0, 4), quotient as u32);
-                self.set_value((old_edx, 0, 4), remainder as u32);
-            },
-            _ => {},
-        }
+        self.set_quotient(size, quotient as u32, (dividend % value) as u32);
     }
 
     /// unary operation, including `inc`, `dec`, `not`, `neg`.
@@ -970,7 +1804,7 @@ impl VM {
     ///
     /// uop &lt;mem&gt;
     fn unary_operation(&mut self) {
-        let instruction = self.text[self.get_eip()].to_owned();
+        let instruction = self.text[self.position].to_owned();
         self.go_from_here(1);
 
         let destination = self.parse_destination().unwrap();
@@ -978,23 +1812,29 @@ impl VM {
         let operand = VM::get_value(destination);
         let result;
         match instruction.get_token_value() {
-            TokenValue::INC => {
-                result = operand.overflowing_add(1).0;
-                self.of = (operand as i32).overflowing_add(1).1;
-                self.set_cf_and_of(result, destination.2);
-            },
-            TokenValue::DEC => {
-                result = operand.overflowing_sub(1).0;
-                self.of = (operand as i32).overflowing_sub(1).1;
-                self.set_cf_and_of(result, destination.2);
+            TokenValue::INC | TokenValue::DEC => {
+                let arithmetic = if instruction.get_token_value() == TokenValue::INC {
+                    flags::inc(operand, destination.2)
+                } else {
+                    flags::dec(operand, destination.2)
+                };
+                result = arithmetic.result;
+
+                // `cf` is unchanged
+                self.zf = arithmetic.zf;
+                self.sf = arithmetic.sf;
+                self.of = arithmetic.of;
+                self.pf = flags::parity(arithmetic.result);
+                self.af = arithmetic.af;
             },
             TokenValue::NOT => {
-                result = !VM::get_value(destination);
+                // no flag is changed
+                result = !operand;
             },
             TokenValue::NEG => {
-                let pair = VM::get_value(destination).overflowing_neg();
-                result = pair.0;
-                self.cf = pair.1;
+                let arithmetic = flags::neg(operand, destination.2);
+                result = arithmetic.result;
+                self.set_arithmetic_flags(arithmetic);
             },
             _ => {
                 result = std::u32::MAX;
@@ -1002,13 +1842,47 @@ impl VM {
             },
         };
 
-        self.set_sf_and_zf(result);
-
         self.set_value(destination, result);
     }
 
+    /// `bsf` and `bsr` instructions, which find the index of the lowest or the highest set bit of the source. If the
+    /// source is 0, `zf` is set and the destination is kept.
+    ///
+    /// bsf &lt;reg&gt;, &lt;reg&gt;
+    ///
+    /// bsf &lt;reg&gt;, &lt;mem&gt;
+    fn bit_scan(&mut self) {
+        let mnemonic = self.text[self.position].get_token_value();
+        self.go_from_here(1);
+
+        if !self.expect_token_type(TokenType::REGISTER, "register".to_string(), false) {
+            return;
+        }
+
+        let destination = self.parse_register().unwrap();
+
+        if !self.expect_token_value(TokenValue::COMMA, ",".to_string(), true) {
+            return;
+        }
+
+        let source = self.parse_source().unwrap();
+
+        if destination.2 == 1 || destination.2 != source.2 {
+            panic!("Syntax Error: {} The destination is {} bytes, but source is {} bytes", self.text[self.position -
+                    1].get_token_location().to_string(), destination.2, source.2);
+        }
+
+        let value = VM::get_value(source) & flags::mask(source.2);
+        self.zf = value == 0;
+
+        if value != 0 {
+            let index = if mnemonic == TokenValue::BSF { value.trailing_zeros() } else { 31 - value.leading_zeros() };
+            self.set_value(destination, index);
+        }
+    }
+
     fn bitshift(&mut self) {
-        let instruction = self.text[self.get_eip()].to_owned();
+        let instruction = self.text[self.position].to_owned();
         self.go_from_here(1);
 
         let destination = self.parse_destination().unwrap();
@@ -1022,7 +1896,7 @@ impl VM {
         }
 
         let operand = VM::get_value(destination) as u64;
-        let count = self.text[self.get_eip()].get_int_value();
+        let count = self.text[self.position].get_int_value();
         self.go_from_here(1);
 
         let result;
@@ -1049,283 +1923,1858 @@ impl VM {
             },
         };
 
-        self.set_sf_and_zf(result as u32);
+        self.set_result_flags(result as u32, destination.2);
 
         self.set_value(destination, result as u32);
     }
 
-    /// `push` instruction
+    /// `push` instruction, which moves `esp` down by the size of the operand, 2 or 4 bytes
+    ///
+    /// push &lt;reg16&gt;
     ///
     /// push &lt;reg32&gt;
     ///
-    /// push &lt;mem&gt;
+    /// push &lt;mem16&gt;
+    ///
+    /// push &lt;mem32&gt;
     ///
     /// push &lt;con32&gt;
     fn push(&mut self) {
         self.go_from_here(1);
 
+        let location = self.text[self.position].get_token_location();
+        let immediate = self.validate_token_type(TokenType::IMMEDIATE_DATA, false) ||
+            self.validate_token_value(TokenValue::MINUS, false);
         let source = self.parse_source().unwrap();
 
+        // immediate data, such as the address of a label, is pushed as 4 bytes
+        let (size, value) = if immediate {
+            (4, VM::get_immediate_value(source))
+        } else {
+            (source.2, VM::get_value(source))
+        };
+
+        if size == 1 {
+            panic!("Syntax Error: {} The operand of push must be 16 or 32 bits", location.to_string());
+        }
+
         let old_esp = &mut self.esp as *mut [u8];
-        let old_stack = &mut self.stack as *mut [u8];
 
-        let new_esp = VM::get_value((old_esp, 0, 4)) - source.2 as u32;
+        let new_esp = VM::get_value((old_esp, 0, 4)).wrapping_sub(size as u32);
         self.set_value((old_esp, 0, 4), new_esp);
-        self.set_value((old_stack, new_esp as usize, source.2), VM::get_value(source));
+        let top = self.memory(new_esp, size);
+        self.set_value(top, value);
     }
 
-    /// `pop` instruction
+    /// `pop` instruction, which moves `esp` up by the size of the operand, 2 or 4 bytes
+    ///
+    /// pop &lt;reg16&gt;
     ///
     /// pop &lt;reg32&gt;
     ///
-    /// pop &lt;mem&gt;
+    /// pop &lt;mem16&gt;
+    ///
+    /// pop &lt;mem32&gt;
     fn pop(&mut self) {
         self.go_from_here(1);
 
-        let destination = self.parse_destination().unwrap();
+        let location = self.text[self.position].get_token_location();
+
+        // the operand is evaluated after `esp` is moved up, as x86 does for `pop esp` and `pop dword ptr [esp]`, so
+        // its size is told from its first token
+        let size = match self.text[self.position].get_token_value() {
+            TokenValue::WORD => 2,
+            TokenValue::DWORD => 4,
+            token => register_info(token).map_or(1, |(_, size)| size),
+        };
+
+        if size == 1 {
+            panic!("Syntax Error: {} The operand of pop must be a 16-bits or 32-bits register or memory",
+                   location.to_string());
+        }
 
         let old_esp = &mut self.esp as *mut [u8];
+        let esp = VM::get_value((old_esp, 0, 4));
 
-        let value = VM::get_value((&mut self.stack as *mut [u8], VM::get_value((old_esp, 0, 4)) as usize, destination.2));
+        let value = VM::get_value(self.memory(esp, size));
+        self.set_value((old_esp, 0, 4), esp.wrapping_add(size as u32));
+        let destination = self.parse_destination().unwrap();
         self.set_value(destination, value);
-        let new_esp = VM::get_value((old_esp, 0, 4)) + destination.2 as u32;
+    }
+
+    /// Push a dword onto the stack.
+    fn push_dword(&mut self, value: u32) {
+        let old_esp = &mut self.esp as *mut [u8];
+
+        let new_esp = VM::get_value((old_esp, 0, 4)).wrapping_sub(4);
         self.set_value((old_esp, 0, 4), new_esp);
+        let top = self.memory(new_esp, 4);
+        self.set_value(top, value);
     }
 
-    /// `cmp` instruction
-    /// cmp &lt;reg&gt;, &lt;reg&gt;
+    /// Pop a dword from the stack.
+    fn pop_dword(&mut self) -> u32 {
+        let old_esp = &mut self.esp as *mut [u8];
+        let esp = VM::get_value((old_esp, 0, 4));
+
+        let value = VM::get_value(self.memory(esp, 4));
+        self.set_value((old_esp, 0, 4), esp.wrapping_add(4));
+        value
+    }
+
+    /// `pushad` instruction, which pushes `eax`, `ecx`, `edx`, `ebx`, `esp` before the first push, `ebp`, `esi` and
+    /// `edi`
+    fn pushad(&mut self) {
+        self.go_from_here(1);
+
+        for register in [self.eax, self.ecx, self.edx, self.ebx, self.esp, self.ebp, self.esi, self.edi] {
+            self.push_dword(u32::from_le_bytes(register));
+        }
+    }
+
+    /// `popad` instruction, which pops the registers `pushad` pushes in reverse order, skipping `esp`
+    fn popad(&mut self) {
+        self.go_from_here(1);
+
+        self.edi = self.pop_dword().to_le_bytes();
+        self.esi = self.pop_dword().to_le_bytes();
+        self.ebp = self.pop_dword().to_le_bytes();
+        self.pop_dword();
+        self.ebx = self.pop_dword().to_le_bytes();
+        self.edx = self.pop_dword().to_le_bytes();
+        self.ecx = self.pop_dword().to_le_bytes();
+        self.eax = self.pop_dword().to_le_bytes();
+    }
+
+    /// `pushfd` instruction, which pushes the flags as the bits of `eflags`, see [`VM::get_eflags`]
+    fn pushfd(&mut self) {
+        self.go_from_here(1);
+
+        let flags = self.get_eflags();
+        self.push_dword(flags);
+    }
+
+    /// `popfd` instruction, which pops the bits of `eflags` into the flags, see [`VM::set_eflags`]
+    fn popfd(&mut self) {
+        self.go_from_here(1);
+
+        let flags = self.pop_dword();
+        self.set_eflags(flags);
+    }
+
+    /// `in` and `out` instructions
     ///
-    /// cmp &lt;reg&gt;, &lt;mem&gt;
+    /// in al / ax / eax, &lt;imm8&gt; / dx
     ///
-    /// cmp &lt;mem&gt;, &lt;reg&gt;
+    /// out &lt;imm8&gt; / dx, al / ax / eax
     ///
-    /// cmp &lt;reg&gt;, &lt;con&gt;
-    fn cmp(&mut self) {
+    /// The device attached to the port reads or writes as many bytes as the accumulator holds, see [`crate::port`].
+    fn port_io(&mut self) {
+        let is_in = self.text[self.position].get_token_value() == TokenValue::IN;
         self.go_from_here(1);
 
-        let destination = self.parse_destination().unwrap();
-        let first_operand = VM::get_value(destination);
-
-        if !self.expect_token_value(TokenValue::COMMA, ",".to_string(), true) {
-            return;
-        }
+        let (accumulator, port) = if is_in {
+            let accumulator = self.parse_accumulator();
 
-        let source = self.parse_source().unwrap();
-        let second_operand = VM::get_value(source);
+            if !self.expect_token_value(TokenValue::COMMA, ",".to_string(), true) {
+                return;
+            }
 
-        if first_operand > second_operand {
-            self.cf = false;
-            self.zf = false;
-        } else if first_operand == second_operand {
-            self.cf = false;
-            self.zf = true;
+            (accumulator, self.parse_port())
         } else {
-            self.cf = true;
-            self.zf = false;
-        }
+            let port = self.parse_port();
 
-        let mut bytes;
-        unsafe {
-            if (*destination.0)[destination.1 + destination.2 - 1] >= 128 {
-                bytes = [0xff; 4];
-            } else {
-                bytes = [0x00; 4];
+            if !self.expect_token_value(TokenValue::COMMA, ",".to_string(), true) {
+                return;
             }
 
-            let (left, _right) = bytes.split_at_mut(destination.2);
-            left.copy_from_slice(&(*destination.0)[destination.1..destination.1 + destination.2]);
-        }
-        let first_operand = i32::from_le_bytes(bytes);
+            (self.parse_accumulator(), port)
+        };
 
-        unsafe {
-            if (*source.0)[source.1 + source.2 - 1] >= 128 {
-                bytes = [0xff; 4];
-            } else {
-                bytes = [0x00; 4];
-            }
+        let (accumulator, port) = match (accumulator, port) {
+            (Some(accumulator), Some(port)) => (accumulator, port),
+            _ => return,
+        };
 
-            let (left, _right) = bytes.split_at_mut(source.2);
-            left.copy_from_slice(&(*source.0)[source.1..source.1 + source.2]);
-        }
-        let second_operand = i32::from_le_bytes(bytes);
-        self.sf = first_operand < second_operand;
+        let size = accumulator.2;
+        let device = self.ports.get(&port).cloned();
+
+        if is_in {
+            // an unconnected bus reads as all ones
+            let value = match device {
+                Some(device) => device.lock().unwrap().read(port, size),
+                None => flags::mask(size),
+            };
 
-        let tmp = first_operand - second_operand;
-        self.of = (first_operand * second_operand <= 0) & (tmp * second_operand > 0);
+            self.set_value(accumulator, value);
+        } else if let Some(device) = device {
+            device.lock().unwrap().write(port, size, VM::get_value(accumulator) & flags::mask(size));
+        }
     }
 
-    fn jump(&mut self) {
-        let instruction = self.text[self.get_eip()].to_owned();
+    /// Parse the accumulator of `in` and `out`, which is `al`, `ax` or `eax`.
+    fn parse_accumulator(&mut self) -> Option<(*mut [u8], usize, usize)> {
+        match self.text[self.position].get_token_value() {
+            TokenValue::AL | TokenValue::AX | TokenValue::EAX => self.parse_register().ok(),
+            _ => {
+                self.error_report(&format!("Expected \"al\", \"ax\" or \"eax\", but find \"{}\"",
+                                           self.text[self.position].get_token_name()));
+                None
+            },
+        }
+    }
 
-        self.go_from_here(1);
+    /// Parse the port of `in` and `out`, which is immediate data from 0 to 255 or `dx`.
+    fn parse_port(&mut self) -> Option<u16> {
+        if self.validate_token_value(TokenValue::DX, true) {
+            let old_edx = &mut self.edx as *mut [u8];
+            return Some(VM::get_value((old_edx, 0, 2)) as u16);
+        }
 
         if !self.expect_token_type(TokenType::IMMEDIATE_DATA, "immediate data".to_string(), false) {
-            return;
+            return None;
         }
 
-        let displacement = self.text[self.get_eip()].get_int_value() as i32;
-        self.go_from_here(1);
+        let port = self.text[self.position].get_int_value();
 
-        match instruction.get_token_value() {
-            TokenValue::JMP => {
-                self.go_from_here(displacement);
-            },
-            TokenValue::JE => {
-                if self.zf {
-                    self.go_from_here(displacement);
-                }
-            },
-            TokenValue::JNE => {
-                if !self.zf {
-                    self.go_from_here(displacement);
-                }
-            },
-            TokenValue::JG => {
-                if !self.zf && self.sf == self.of {
-                    self.go_from_here(displacement);
-                }
-            },
-            TokenValue::JGE => {
-                if self.sf == self.of {
-                    self.go_from_here(displacement);
-                }
-            },
-            TokenValue::JL => {
-                if self.sf != self.of {
-                    self.go_from_here(displacement);
-                }
-            },
-            TokenValue::JLE => {
-                if self.zf || self.sf != self.of {
-                    self.go_from_here(displacement);
-                }
-            },
-            TokenValue::JA => {
-                if !self.cf && !self.zf {
-                    self.go_from_here(displacement);
-                }
-            },
-            TokenValue::JAE => {
-                if !self.cf {
-                    self.go_from_here(displacement);
-                }
-            },
-            TokenValue::JB => {
-                if self.cf {
-                    self.go_from_here(displacement);
-                }
-            },
-            TokenValue::JBE => {
-                if self.cf || self.zf {
-                    self.go_from_here(displacement);
-                }
-            },
-            _ => {},
+        if port > 0xff {
+            self.error_report(&format!("The port {} does not fit in 8 bits, use dx for it", port));
+            return None;
         }
-    }
 
-    /// `call` instruction
-    ///
-    /// call &lt;label&gt;
-    fn call(&mut self) {
         self.go_from_here(1);
+        Some(port as u16)
+    }
 
-        if !self.expect_token_type(TokenType::IMMEDIATE_DATA, "immedate data".to_string(), false) {
-            return;
-        }
-
-        let displacement = self.text[self.get_eip()].get_int_value() as i32;
+    /// `lahf` instruction, which loads the low byte of `eflags` into `ah`, see [`VM::get_eflags`]
+    fn lahf(&mut self) {
         self.go_from_here(1);
 
-        let old_esp = &mut self.esp as *mut [u8];
-        let old_stack = &mut self.stack as *mut [u8];
-
-        let new_esp = VM::get_value((old_esp, 0, 4)) - 4;
-        self.set_value((old_esp, 0, 4), new_esp);
-        self.set_value((old_stack, new_esp as usize, 4), self.get_eip() as u32);
+        self.eax[1] = self.get_eflags() as u8;
+    }
 
-        self.depth = self.depth + 1;
+    /// `sahf` instruction, which stores `ah` into the low byte of `eflags`, keeping `of`, `if` and `df`
+    fn sahf(&mut self) {
+        self.go_from_here(1);
 
-        self.go_from_here(displacement);
+        let flags = self.get_eflags() & !0xff | self.eax[1] as u32;
+        self.set_eflags(flags);
     }
 
-    /// `ret` instruction
-    fn ret(&mut self) {
+    /// `daa` and `das` instructions, which adjust `al` after adding or subtracting two packed BCD bytes, two decimal
+    /// digits each, into the packed BCD sum or difference, with the decimal carry or borrow in `cf`
+    ///
+    /// `sf`, `zf` and `pf` are set from `al`, and `of` is unchanged, which x86 leaves undefined.
+    fn decimal_adjust(&mut self) {
+        let is_add = self.text[self.position].get_token_value() == TokenValue::DAA;
         self.go_from_here(1);
 
-        if self.depth > 1 {
-            let old_esp = &mut self.esp as *mut [u8];
-            let old_stack = &mut self.stack as *mut [u8];
-            let old_eip = &mut self.eip as *mut [u8];
+        let adjust = |al: u8, value: u8| if is_add { al.overflowing_add(value) } else { al.overflowing_sub(value) };
+        let old_al = self.eax[0];
+        let old_cf = self.cf;
+        self.cf = false;
 
-            let value = VM::get_value((old_stack, VM::get_value((old_esp, 0, 4)) as usize, 4));
-            self.set_value((old_eip, 0, 4), value);
-            let new_esp = VM::get_value((old_esp, 0, 4)) + 4;
-            self.set_value((old_esp, 0, 4), new_esp);
+        if old_al & 0xf > 9 || self.af {
+            let (al, carry) = adjust(self.eax[0], 0x06);
+            self.eax[0] = al;
+            self.cf = old_cf || carry;
+            self.af = true;
+        } else {
+            self.af = false;
         }
 
-        self.depth = self.depth - 1;
+        if old_al > 0x99 || old_cf {
+            self.eax[0] = adjust(self.eax[0], 0x60).0;
+            self.cf = true;
+        } else if is_add {
+            self.cf = false;
+        }
+
+        self.set_result_flags(self.eax[0] as u32, 1);
     }
 
-    /// `enter` instruction
-    fn enter(&mut self) {
+    /// `aaa` and `aas` instructions, which adjust `al` after adding or subtracting two unpacked BCD bytes, one decimal
+    /// digit each, into the digit of the sum or difference, carrying into or borrowing from `ah`
+    ///
+    /// `cf` and `af` are set on the decimal carry or borrow, and the other flags are unchanged, which x86 leaves
+    /// undefined.
+    fn ascii_adjust(&mut self) {
+        let is_add = self.text[self.position].get_token_value() == TokenValue::AAA;
         self.go_from_here(1);
 
-        let old_esp = &mut self.esp as *mut [u8];
-        let old_stack = &mut self.stack as *mut [u8];
-        let old_ebp = &mut self.ebp as *mut [u8];
+        let adjusted = self.eax[0] & 0xf > 9 || self.af;
 
-        let new_esp = VM::get_value((old_esp, 0, 4)) - 4;
-        self.set_value((old_esp, 0, 4), new_esp);
-        self.set_value((old_stack, new_esp as usize, 4), VM::get_value((old_ebp, 0, 4)));
+        if adjusted {
+            let ax = u16::from_le_bytes([self.eax[0], self.eax[1]]);
+            let ax = if is_add { ax.wrapping_add(0x106) } else { ax.wrapping_sub(0x06).wrapping_sub(0x100) };
+            self.eax[0..2].copy_from_slice(&ax.to_le_bytes());
+        }
 
-        self.ebp = self.esp;
+        self.eax[0] &= 0x0f;
+        self.af = adjusted;
+        self.cf = adjusted;
     }
 
-    /// `leave` instruction
-    fn leave(&mut self) {
+    /// `stc`, `clc`, `cmc`, `std` and `cld` instructions, which set, clear or complement `cf`, and set or clear `df`
+    fn flag_control(&mut self) {
+        match self.text[self.position].get_token_value() {
+            TokenValue::STC => self.cf = true,
+            TokenValue::CLC => self.cf = false,
+            TokenValue::CMC => self.cf = !self.cf,
+            TokenValue::STD => self.direction_flag = true,
+            TokenValue::CLD => self.direction_flag = false,
+            _ => self.error_report(&format!("Unexpected instruction: {}", self.text[self.position].get_token_name())),
+        }
+
         self.go_from_here(1);
+    }
 
-        self.esp = self.ebp;
+    /// `cmp` instruction
+    /// cmp &lt;reg&gt;, &lt;reg&gt;
+    ///
+    /// cmp &lt;reg&gt;, &lt;mem&gt;
+    ///
+    /// cmp &lt;mem&gt;, &lt;reg&gt;
+    ///
+    /// cmp &lt;reg&gt;, &lt;con&gt;
+    fn cmp(&mut self) {
+        self.go_from_here(1);
 
-        let old_esp = &mut self.esp as *mut [u8];
-        let old_stack = &mut self.stack as *mut [u8];
-        let old_ebp = &mut self.ebp as *mut [u8];
+        let destination = self.parse_destination().unwrap();
+        let first_operand = VM::get_value(destination);
 
-        let value = VM::get_value((old_stack, VM::get_value((old_esp, 0, 4)) as usize, 4));
-        self.set_value((old_ebp, 0, 4), value);
-        let new_esp = VM::get_value((old_esp, 0, 4)) + 4;
-        self.set_value((old_esp, 0, 4), new_esp);
-    }
+        if !self.expect_token_value(TokenValue::COMMA, ",".to_string(), true) {
+            return;
+        }
+
+        let immediate = self.validate_token_type(TokenType::IMMEDIATE_DATA, false) ||
+            self.validate_token_value(TokenValue::MINUS, false);
+        let source = self.parse_source().unwrap();
+        let second_operand = if immediate { VM::get_immediate_value(source) } else { VM::get_value(source) };
+
+        let arithmetic = flags::sub(first_operand, second_operand, destination.2);
+        self.set_arithmetic_flags(arithmetic);
+    }
+
+    fn jump(&mut self) {
+        let instruction = self.text[self.position].to_owned();
+        let start = self.position;
+
+        self.go_from_here(1);
+
+        if !self.expect_token_type(TokenType::IMMEDIATE_DATA, "immediate data".to_string(), false) {
+            return;
+        }
+
+        let target = self.text[self.position].get_int_value();
+        self.go_from_here(1);
+
+        let mnemonic = instruction.get_token_value();
+        let taken = self.condition(mnemonic);
+
+        if let (Some(model), true) = (self.branch_model, mnemonic != TokenValue::JMP) {
+            self.branch_stats.entry(start).or_default().record(taken, model);
+        }
+
+        if taken {
+            self.go_to(target);
+        }
+    }
+
+    /// Whether the condition of a jump or a `setcc` holds by the flags, or by `ecx` or `cx` for `jecxz` and `jcxz`,
+    /// which it always does for `jmp`.
+    fn condition(&self, mnemonic: TokenValue) -> bool {
+        match mnemonic {
+            TokenValue::JMP => true,
+            TokenValue::JE | TokenValue::SETE => self.zf,
+            TokenValue::JNE | TokenValue::SETNE => !self.zf,
+            TokenValue::JG | TokenValue::SETG => !self.zf && self.sf == self.of,
+            TokenValue::JGE | TokenValue::SETGE => self.sf == self.of,
+            TokenValue::JL | TokenValue::SETL => self.sf != self.of,
+            TokenValue::JLE | TokenValue::SETLE => self.zf || self.sf != self.of,
+            TokenValue::JA | TokenValue::SETA => !self.cf && !self.zf,
+            TokenValue::JAE | TokenValue::SETAE => !self.cf,
+            TokenValue::JB | TokenValue::SETB => self.cf,
+            TokenValue::JBE | TokenValue::SETBE => self.cf || self.zf,
+            TokenValue::JO | TokenValue::SETO => self.of,
+            TokenValue::JNO | TokenValue::SETNO => !self.of,
+            TokenValue::JS | TokenValue::SETS => self.sf,
+            TokenValue::JNS | TokenValue::SETNS => !self.sf,
+            TokenValue::JP | TokenValue::SETP => self.pf,
+            TokenValue::JNP | TokenValue::SETNP => !self.pf,
+            TokenValue::JECXZ => u32::from_le_bytes(self.ecx) == 0,
+            TokenValue::JCXZ => u16::from_le_bytes([self.ecx[0], self.ecx[1]]) == 0,
+            _ => false,
+        }
+    }
+
+    /// `setcc` instruction, which sets the byte to 1 if the condition holds and to 0 otherwise
+    ///
+    /// setcc &lt;reg8&gt;
+    ///
+    /// setcc &lt;mem8&gt;
+    fn set(&mut self) {
+        let instruction = self.position;
+        let mnemonic = self.text[instruction].get_token_value();
+        self.go_from_here(1);
+
+        let destination = self.parse_destination().unwrap();
+
+        if destination.2 != 1 {
+            panic!("Syntax Error: {} The operand of {} must be a byte, but it is {} bytes", self.text[self.position -
+                    1].get_token_location().to_string(), self.text[instruction].get_token_name(), destination.2);
+        }
+
+        self.set_value(destination, self.condition(mnemonic) as u32);
+    }
+
+    /// `call` instruction
+    ///
+    /// call &lt;label&gt;
+    ///
+    /// call &lt;register&gt; / dword ptr [&lt;address&gt;]
+    ///
+    /// An indirect call takes the address of the target from a 32-bits operand, which is read before the return
+    /// address is pushed.
+    fn call(&mut self) {
+        let instruction = self.position;
+        let (address, size) = self.addresses[&self.position];
+        self.enter_frame(self.position);
+        self.go_from_here(1);
+
+        let target = if self.validate_token_type(TokenType::IMMEDIATE_DATA, false) {
+            let target = self.text[self.position].get_int_value();
+            self.go_from_here(1);
+            target
+        } else {
+            let operand = match self.parse_destination() {
+                Ok(operand) => operand,
+                Err(_) => return,
+            };
+
+            if operand.2 != 4 {
+                panic!("Syntax Error: {} The operand of {} must be 32-bits, but it is {} bytes",
+                       self.text[self.position - 1].get_token_location().to_string(),
+                       self.text[instruction].get_token_name(), operand.2);
+            }
+
+            VM::get_value(operand)
+        };
+
+        let old_esp = &mut self.esp as *mut [u8];
+
+        let new_esp = VM::get_value((old_esp, 0, 4)) - 4;
+        self.set_value((old_esp, 0, 4), new_esp);
+        let top = self.memory(new_esp, 4);
+        // the return address is the address of the next instruction
+        self.set_value(top, address.wrapping_add(size));
+
+        self.go_to(target);
+    }
+
+    /// Count a call, or an interrupt, from the instruction at `position`, which stops the program beyond the call
+    /// depth limit.
+    fn enter_frame(&mut self, position: usize) {
+        if self.depth > self.call_depth_limit {
+            panic!("Runtime Error: {} maximum call depth {} exceeded\n{}",
+                   self.text[position].get_token_location().to_string(), self.call_depth_limit, self.backtrace());
+        }
+
+        self.call_stack.push(position);
+        self.depth = self.depth + 1;
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+
+    /// `ret` instruction
+    fn ret(&mut self) {
+        self.go_from_here(1);
+
+        if self.depth > 1 {
+            let old_esp = &mut self.esp as *mut [u8];
+
+            let value = VM::get_value(self.memory(VM::get_value((old_esp, 0, 4)), 4));
+            self.go_to(value);
+            let new_esp = VM::get_value((old_esp, 0, 4)) + 4;
+            self.set_value((old_esp, 0, 4), new_esp);
+            self.call_stack.pop();
+        }
+
+        self.depth = self.depth - 1;
+    }
+
+    /// `enter` instruction
+    ///
+    /// enter
+    ///
+    /// enter &lt;imm16&gt;, &lt;imm8&gt;
+    ///
+    /// It pushes `ebp`, copies the frame pointers of the `nesting level - 1` enclosing frames, pushes the new frame
+    /// pointer if the nesting level is not 0, then sets `ebp` to the frame and allocates `frame size` bytes below it.
+    /// `enter` without operands is `enter 0, 0`.
+    fn enter(&mut self) {
+        let instruction = self.position;
+        self.go_from_here(1);
+
+        let (frame_size, nesting_level) =
+            if self.text.get(self.position).map(|token| token.get_token_type()) == Some(TokenType::IMMEDIATE_DATA) {
+                let frame_size = self.text[self.position].get_int_value();
+                self.go_from_here(1);
+
+                if !self.expect_token_value(TokenValue::COMMA, ",".to_string(), true) ||
+                    !self.expect_token_type(TokenType::IMMEDIATE_DATA, "immediate data".to_string(), false) {
+                    return;
+                }
+
+                let nesting_level = self.text[self.position].get_int_value();
+                self.go_from_here(1);
+                (frame_size, nesting_level)
+            } else {
+                (0, 0)
+            };
+
+        if frame_size > 0xffff || nesting_level > 0xff {
+            panic!("Syntax Error: {} The frame size of {} must fit in 16 bits and the nesting level in 8 bits",
+                   self.text[instruction].get_token_location().to_string(), self.text[instruction].get_token_name());
+        }
+
+        let old_ebp = &mut self.ebp as *mut [u8];
+        let mut ebp = VM::get_value((old_ebp, 0, 4));
+        self.push_dword(ebp);
+        let frame = VM::get_value((&mut self.esp as *mut [u8], 0, 4));
+
+        // only the low 5 bits of the nesting level count
+        let nesting_level = nesting_level % 32;
+
+        if nesting_level > 0 {
+            for _ in 1..nesting_level {
+                ebp = ebp.wrapping_sub(4);
+                let pointer = VM::get_value(self.memory(ebp, 4));
+                self.push_dword(pointer);
+            }
+
+            self.push_dword(frame);
+        }
+
+        let old_esp = &mut self.esp as *mut [u8];
+        let new_esp = VM::get_value((old_esp, 0, 4)).wrapping_sub(frame_size);
+        self.set_value((old_esp, 0, 4), new_esp);
+        self.set_value((old_ebp, 0, 4), frame);
+    }
+
+    /// `leave` instruction
+    fn leave(&mut self) {
+        self.go_from_here(1);
+
+        self.esp = self.ebp;
+
+        let old_esp = &mut self.esp as *mut [u8];
+        let old_ebp = &mut self.ebp as *mut [u8];
+
+        let value = VM::get_value(self.memory(VM::get_value((old_esp, 0, 4)), 4));
+        self.set_value((old_ebp, 0, 4), value);
+        let new_esp = VM::get_value((old_esp, 0, 4)) + 4;
+        self.set_value((old_esp, 0, 4), new_esp);
+    }
+
+    /// `int` instruction
+    ///
+    /// int &lt;con8&gt;
+    ///
+    /// Without a handler installed by `ivt`, `int 0x80`, the system call of Linux, `int 0x81`, the random number
+    /// service, `int 0x82`, the print service, and `int 0x83`, the input service, are supported.
+    fn interrupt(&mut self) {
+        let (address, size) = self.addresses[&self.position];
+        let position = self.position;
+        self.go_from_here(1);
+
+        if !self.expect_token_type(TokenType::IMMEDIATE_DATA, "immediate data".to_string(), false) {
+            return;
+        }
+
+        let vector = self.text[self.position].get_int_value();
+        self.go_from_here(1);
+
+        let handler = match vector {
+            0..=0xff => self.interrupt_vectors.get(&(vector as u8)).copied(),
+            _ => None,
+        };
+
+        if let Some(handler) = handler {
+            self.call_handler(position, address.wrapping_add(size), handler);
+            return;
+        }
+
+        match vector {
+            0x80 => self.system_call(),
+            0x81 => self.random_service(),
+            0x82 => self.print_service(position),
+            0x83 => self.input_service(),
+            _ => self.error_report(&format!("Unsupported interrupt: {:#x}", vector)),
+        }
+    }
+
+    /// Call the handler of an interrupt, pushing the flags, then the return address, like an interrupt of real x86 in
+    /// the same privilege level, and disabling the interrupts of the timer until `iret`. `position` is the
+    /// instruction interrupted, for the backtrace.
+    fn call_handler(&mut self, position: usize, return_address: u32, handler: u32) {
+        self.enter_frame(position);
+
+        let old_esp = &mut self.esp as *mut [u8];
+        let new_esp = VM::get_value((old_esp, 0, 4)) - 8;
+        self.set_value((old_esp, 0, 4), new_esp);
+        let flags = self.get_eflags();
+        let top = self.memory(new_esp, 8);
+        self.set_value((top.0, top.1 + 4, 4), flags);
+        self.set_value((top.0, top.1, 4), return_address);
+
+        self.interrupt_flag = false;
+        self.go_to(handler);
+    }
+
+    /// Interrupt the program with the handler of [`TIMER_VECTOR`] if the timer has expired, the interrupts are
+    /// enabled and a handler is installed, a periodic timer is armed again for its next expiry after now.
+    fn check_timer(&mut self) {
+        let (deadline, interval) = match self.timer {
+            Some(timer) if timer.0 <= self.virtual_time => timer,
+            _ => return,
+        };
+
+        let handler = self.interrupt_vectors.get(&TIMER_VECTOR).copied();
+
+        // pending until the handler returns, an expiry without a handler is lost
+        if handler.is_some() && !self.interrupt_flag {
+            return;
+        }
+
+        self.timer = match interval {
+            0 => None,
+            _ => Some((deadline + (self.virtual_time - deadline) / interval * interval + interval, interval)),
+        };
+
+        if let (Some(handler), false) = (handler, self.is_finished()) {
+            let position = self.next_instruction();
+            self.call_handler(position, self.get_eip(), handler);
+        }
+    }
+
+    /// `ivt` instruction, which installs the handler of an interrupt vector, so that `int` calls it
+    ///
+    /// ivt &lt;imm8&gt;, &lt;label&gt;
+    ///
+    /// ivt &lt;imm8&gt;, &lt;reg32&gt;
+    fn ivt(&mut self) {
+        self.go_from_here(1);
+
+        if !self.expect_token_type(TokenType::IMMEDIATE_DATA, "immediate data".to_string(), false) {
+            return;
+        }
+
+        let vector = self.text[self.position].get_int_value();
+
+        if !(0..=0xff).contains(&vector) {
+            self.error_report(&format!("Interrupt vector {} is not from 0 to 255", vector));
+        }
+
+        self.go_from_here(1);
+
+        if !self.expect_token_value(TokenValue::COMMA, ",".to_string(), true) {
+            return;
+        }
+
+        let handler = if self.validate_token_type(TokenType::IMMEDIATE_DATA, false) {
+            VM::get_immediate_value(self.parse_source().unwrap())
+        } else {
+            VM::get_value(self.parse_source().unwrap())
+        };
+
+        self.interrupt_vectors.insert(vector as u8, handler);
+    }
+
+    /// `iret` instruction, which returns from an interrupt handler, popping the return address, then the flags
+    fn iret(&mut self) {
+        self.go_from_here(1);
+
+        if self.depth > 1 {
+            let old_esp = &mut self.esp as *mut [u8];
+            let esp = VM::get_value((old_esp, 0, 4));
+
+            let value = VM::get_value(self.memory(esp, 4));
+            let flags = VM::get_value(self.memory(esp.wrapping_add(4), 4));
+            self.go_to(value);
+            self.set_eflags(flags);
+            self.set_value((old_esp, 0, 4), esp.wrapping_add(8));
+            self.call_stack.pop();
+        }
+
+        self.depth -= 1;
+    }
+
+    /// `hlt` instruction, which stops the program, as no interrupt wakes the processor up again
+    fn hlt(&mut self) {
+        self.go_from_here(1);
+        self.halted = true;
+    }
+
+    /// `into` instruction, which raises the overflow exception if `of` is set
+    fn interrupt_on_overflow(&mut self) {
+        let position = self.position;
+        self.go_from_here(1);
+
+        if self.of {
+            self.raise(Exception::Overflow, position);
+        }
+    }
+
+    /// `bound` instruction, which raises the bound range exception if the signed index is below the lower bound or
+    /// above the upper bound, which are a pair of words or dwords in memory, the lower first
+    ///
+    /// bound &lt;reg16&gt;, word ptr &lt;mem&gt;
+    ///
+    /// bound &lt;reg32&gt;, dword ptr &lt;mem&gt;
+    fn bound(&mut self) {
+        let position = self.position;
+        self.go_from_here(1);
+
+        if !self.expect_token_type(TokenType::REGISTER, "register".to_string(), false) {
+            return;
+        }
+
+        let index = self.parse_register().unwrap();
+
+        if !self.expect_token_value(TokenValue::COMMA, ",".to_string(), true) {
+            return;
+        }
+
+        let size = match self.text[self.position].get_token_value() {
+            TokenValue::WORD => 2,
+            TokenValue::DWORD => 4,
+            _ => 1,
+        };
+
+        if index.2 == 1 || size != index.2 {
+            panic!("Syntax Error: {} The bounds of bound must be memory of the size of a 16-bits or 32-bits index",
+                   self.text[self.position].get_token_location().to_string());
+        }
+
+        self.go_from_here(1);
+
+        if !self.expect_token_value(TokenValue::PTR, "ptr".to_string(), true) ||
+            !self.expect_token_value(TokenValue::LBRACK, "[".to_string(), true) {
+            return;
+        }
+
+        let address = self.parse_address() as u32;
+
+        if !self.expect_token_value(TokenValue::RBRACK, "]".to_string(), true) {
+            return;
+        }
+
+        let value = VM::get_value(index) as i32;
+        let lower = VM::get_value(self.memory(address, size)) as i32;
+        let upper = VM::get_value(self.memory(address.wrapping_add(size as u32), size)) as i32;
+
+        if value < lower || value > upper {
+            self.raise(Exception::BoundRange, position);
+        }
+    }
+
+    /// `cpuid` instruction, which loads `eax`, `ebx`, `ecx` and `edx` with the values of the leaf in `eax`, see
+    /// [`VM::set_cpuid_leaf`], or with zeros for a leaf which is not set. The subleaf in `ecx` is ignored.
+    fn cpuid(&mut self) {
+        self.go_from_here(1);
+
+        let leaf = u32::from_le_bytes(self.eax);
+        let [eax, ebx, ecx, edx] = self.cpuid_leaves.get(&leaf).copied().unwrap_or_default();
+        self.eax = eax.to_le_bytes();
+        self.ebx = ebx.to_le_bytes();
+        self.ecx = ecx.to_le_bytes();
+        self.edx = edx.to_le_bytes();
+    }
+
+    /// `nop` instruction, whose operands, if any, are skipped without evaluating them, up to the end of the line or the
+    /// next instruction on it, such as the next copy `times 3 nop` makes
+    fn nop(&mut self) {
+        let end = self.line_end(self.position);
+        self.go_from_here(1);
+
+        while self.position < end && self.text[self.position].get_token_type() != TokenType::INSTRUCTION {
+            self.position += 1;
+        }
+    }
+
+    /// Set the flags from the bits of `eflags`, see [`VM::get_eflags`].
+    fn set_eflags(&mut self, eflags: u32) {
+        self.cf = eflags & 1 != 0;
+        self.pf = eflags & 1 << 2 != 0;
+        self.af = eflags & 1 << 4 != 0;
+        self.zf = eflags & 1 << 6 != 0;
+        self.sf = eflags & 1 << 7 != 0;
+        self.interrupt_flag = eflags & 1 << 9 != 0;
+        self.direction_flag = eflags & 1 << 10 != 0;
+        self.of = eflags & 1 << 11 != 0;
+    }
+
+    /// Get the flags as the bits of `eflags`: `cf` is bit 0, `pf` bit 2, `af` bit 4, `zf` bit 6, `sf` bit 7, `if` bit
+    /// 9, `df` bit 10 and `of` bit 11, and bit 1 is always set.
+    fn get_eflags(&self) -> u32 {
+        0x2 | self.cf as u32 | (self.pf as u32) << 2 | (self.af as u32) << 4 | (self.zf as u32) << 6 |
+            (self.sf as u32) << 7 | (self.interrupt_flag as u32) << 9 | (self.direction_flag as u32) << 10 |
+            (self.of as u32) << 11
+    }
+
+    /// System call of Linux, the number is in `eax`, the arguments are in `ebx`, `ecx` and `edx`, and the result is
+    /// returned in `eax`.
+    ///
+    /// 1. `exit(status)`
+    /// 2. `read(fd, buffer, count)`, only from standard input.
+    /// 3. `write(fd, buffer, count)`, only to standard output and standard error.
+    /// 4. `time(tloc)`, seconds since the epoch, also stored at `tloc` unless it is 0.
+    /// 5. `gettimeofday(tv, tz)`, seconds and microseconds since the epoch stored at `tv`, `tz` is ignored.
+    /// 6. `alarm(seconds)`, sets the timer to expire once after `seconds`, or cancels it if 0, returns the seconds
+    ///    left of the previous one.
+    /// 7. `setitimer(which, new, old)`, sets the timer from `itimerval` at `new`, which is the interval, then the
+    ///    time to the first expiry, both as seconds and microseconds, and stores the previous one at `old` unless it
+    ///    is 0, only `ITIMER_REAL`.
+    /// 8. `nanosleep(req, rem)`, advances the virtual time by the seconds and nanoseconds at `req` without blocking
+    ///    the host. If the timer expires before, the sleep stops there, the time left is stored at `rem` unless it is
+    ///    0, and `EINTR` is returned, so the handler of [`TIMER_VECTOR`] runs.
+    ///
+    /// The time is the virtual time, from 0 when the program is loaded, unless the clock is the wall clock. The timer
+    /// always runs on the virtual time, and interrupts the program with the handler `ivt` installs for
+    /// [`TIMER_VECTOR`] when it expires.
+    fn system_call(&mut self) {
+        let (fd, buffer, count) = (self.get_ebx(), self.get_ecx(), self.get_edx() as usize);
+
+        let result = match self.get_eax() {
+            1 => {
+                self.exit_code = Some(fd);
+                return;
+            },
+            3 => {
+                let (pointer, start, size) = self.memory(buffer, count);
+                let bytes = unsafe { &mut (&mut *pointer)[start..start + size] };
+
+                let read = match (fd, &mut self.input) {
+                    (0, Some(input)) => input.read(bytes),
+                    (0, None) => std::io::stdin().read(bytes),
+                    _ => Err(std::io::Error::from(std::io::ErrorKind::InvalidInput)),
+                };
+
+                match (fd, read) {
+                    (0, Ok(count)) => {
+                        self.count_written(buffer, count);
+                        count as u32
+                    },
+                    (0, Err(_)) => (-5i32) as u32,
+                    _ => (-9i32) as u32,
+                }
+            },
+            4 => {
+                let (pointer, start, size) = self.memory(buffer, count);
+                let bytes = unsafe { &(&*pointer)[start..start + size] };
+
+                let written = match fd {
+                    1 => self.write_standard_output(bytes),
+                    2 => {
+                        let bytes = self.charge_output(bytes);
+                        std::io::stderr().write_all(bytes)
+                    },
+                    _ => Err(std::io::Error::from(std::io::ErrorKind::InvalidInput)),
+                };
+
+                match (fd, written) {
+                    (1, Ok(())) | (2, Ok(())) => count as u32,
+                    (1, Err(_)) | (2, Err(_)) => (-5i32) as u32,
+                    _ => (-9i32) as u32,
+                }
+            },
+            13 => {
+                let seconds = (self.get_time() / 1_000_000_000) as u32;
+
+                if fd != 0 {
+                    let (pointer, start, _) = self.memory(fd, 4);
+                    self.set_value((pointer, start, 4), seconds);
+                }
+
+                seconds
+            },
+            78 => {
+                let time = self.get_time();
+
+                if fd != 0 {
+                    let (pointer, start, _) = self.memory(fd, 8);
+                    self.set_value((pointer, start, 4), (time / 1_000_000_000) as u32);
+                    self.set_value((pointer, start + 4, 4), (time % 1_000_000_000 / 1000) as u32);
+                }
+
+                0
+            },
+            27 => {
+                let left = self.timer.map_or(0, |(deadline, _)| deadline.saturating_sub(self.virtual_time));
+                self.timer = match fd {
+                    0 => None,
+                    seconds => Some((self.virtual_time + seconds as u64 * 1_000_000_000, 0)),
+                };
+
+                // rounded up, as Linux does not return 0 for an alarm still pending
+                left.div_ceil(1_000_000_000) as u32
+            },
+            104 if fd != 0 => (-22i32) as u32,
+            104 => {
+                if count != 0 {
+                    let (interval, left) = self.timer.map_or((0, 0), |(deadline, interval)|
+                        (interval, deadline.saturating_sub(self.virtual_time)));
+                    let (pointer, start, _) = self.memory(count as u32, 16);
+                    self.set_value((pointer, start, 4), (interval / 1_000_000_000) as u32);
+                    self.set_value((pointer, start + 4, 4), (interval % 1_000_000_000 / 1000) as u32);
+                    self.set_value((pointer, start + 8, 4), (left / 1_000_000_000) as u32);
+                    self.set_value((pointer, start + 12, 4), (left % 1_000_000_000 / 1000) as u32);
+                }
+
+                let time = |vm: &mut VM, address: u32| {
+                    VM::get_value(vm.memory(address, 4)) as u64 * 1_000_000_000 +
+                        VM::get_value(vm.memory(address.wrapping_add(4), 4)) as u64 * 1000
+                };
+                let interval = time(self, buffer);
+                let value = time(self, buffer.wrapping_add(8));
+
+                self.timer = match value {
+                    0 => None,
+                    value => Some((self.virtual_time + value, interval)),
+                };
+
+                0
+            },
+            162 => {
+                let duration = VM::get_value(self.memory(fd, 4)) as u64 * 1_000_000_000 +
+                    VM::get_value(self.memory(fd.wrapping_add(4), 4)) as u64;
+                let wake = self.virtual_time + duration;
+
+                match self.timer {
+                    Some((deadline, _)) if deadline < wake && self.interrupt_flag &&
+                        self.interrupt_vectors.contains_key(&TIMER_VECTOR) => {
+                        self.virtual_time = deadline;
+
+                        if buffer != 0 {
+                            let left = wake - deadline;
+                            let (pointer, start, _) = self.memory(buffer, 8);
+                            self.set_value((pointer, start, 4), (left / 1_000_000_000) as u32);
+                            self.set_value((pointer, start + 4, 4), (left % 1_000_000_000) as u32);
+                        }
+
+                        // EINTR
+                        (-4i32) as u32
+                    },
+                    _ => {
+                        self.virtual_time = wake;
+                        0
+                    },
+                }
+            },
+            // ENOSYS
+            _ => (-38i32) as u32,
+        };
+
+        self.eax = result.to_le_bytes();
+    }
+
+    /// Write to the standard output, or to the captured output if it is captured.
+    fn write_standard_output(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        let bytes = self.charge_output(bytes);
+
+        match &mut self.output {
+            Some(output) => output.write_all(bytes),
+            None => std::io::stdout().write_all(bytes).and_then(|_| std::io::stdout().flush()),
+        }
+    }
+
+    /// Count the output against its quota, and get the bytes within it, which are the ones written.
+    fn charge_output<'a>(&mut self, bytes: &'a [u8]) -> &'a [u8] {
+        let left = self.quotas.output.map_or(bytes.len() as u64, |limit| limit.saturating_sub(self.output_written));
+        self.output_written += bytes.len() as u64;
+        &bytes[..left.min(bytes.len() as u64) as usize]
+    }
+
+    /// Get the bytes of the string at `address` before the first zero byte, or before the end of memory.
+    fn read_string(&self, address: u32) -> Vec<u8> {
+        let start = address.wrapping_sub(self.memory_base) as usize;
+
+        match self.stack.get(start..) {
+            Some(bytes) => bytes.iter().take_while(|byte| **byte != 0).copied().collect(),
+            None => panic!("Invalid memory address: {:#x}", address),
+        }
+    }
+
+    /// Print service, which formats the string at the top of the stack like `printf` of C, taking the arguments from
+    /// the stack above it, and writes the result to the standard output. The number of bytes written is returned in
+    /// `eax`, and the caller removes the arguments, as for a C function. See [`crate::printf`] for the conversions.
+    ///
+    /// ```text
+    /// push eax
+    /// push message    ; "eax = %d\n"
+    /// int 0x82
+    /// add esp, 8
+    /// ```
+    fn print_service(&mut self, position: usize) {
+        let word = |address: u32| match self.read_memory(address, 4) {
+            Some(bytes) => u32::from_le_bytes(bytes.try_into().unwrap()),
+            None => panic!("Invalid memory address: {:#x}", address),
+        };
+
+        let esp = self.get_esp();
+        let format = self.read_string(word(esp));
+        let mut address = esp;
+
+        let output = printf(&format, || {
+            address = address.wrapping_add(4);
+            word(address)
+        }, |address| self.read_string(address));
+
+        let location = self.text[position].get_token_location().to_string();
+
+        let output = match output {
+            Ok(output) => output,
+            Err(msg) => panic!("Runtime Error: {} printf: {}", location, msg),
+        };
+
+        if let Err(err) = self.write_standard_output(&output) {
+            panic!("Runtime Error: {} printf: {}", location, err);
+        }
+
+        self.eax = (output.len() as u32).to_le_bytes();
+    }
+
+    /// Read a byte from the standard input, or from the input if it is set, `None` at the end.
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0];
+
+        let read = match &mut self.input {
+            Some(input) => input.read(&mut byte),
+            None => std::io::stdin().read(&mut byte),
+        };
+
+        match read {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    /// Read a line from the standard input, without the line break.
+    fn read_line(&mut self) -> Vec<u8> {
+        let mut line = Vec::new();
+
+        while let Some(byte) = self.read_byte() {
+            if byte == b'\n' {
+                break;
+            }
+
+            line.push(byte);
+        }
+
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        line
+    }
+
+    /// Input service, like the procedures of the Irvine library for MASM textbooks, which read a line from the
+    /// standard input for every call but `ReadChar`. The function is in `eax`:
+    ///
+    /// 1. `ReadChar`, a byte into `al`, 0 at the end of the input.
+    /// 2. `ReadInt`, a signed decimal integer with an optional sign into `eax`, sets `of` and returns 0 if it is not
+    ///    one or does not fit in 32 bits.
+    /// 3. `ReadDec`, an unsigned decimal integer into `eax`, sets `cf` and returns 0 if it is not one or does not fit
+    ///    in 32 bits.
+    /// 4. `ReadHex`, an unsigned hexadecimal integer into `eax`, sets `cf` and returns 0 if it is not one.
+    /// 5. `ReadString`, at most `ecx` - 1 bytes of a line into the buffer at `edx`, followed by a zero byte, the rest
+    ///    of the line is discarded, returns the number of bytes stored in `eax`.
+    ///
+    /// Spaces around the integers are ignored. An unknown function returns `ENOSYS`.
+    fn input_service(&mut self) {
+        let result = match self.get_eax() {
+            1 => {
+                let byte = self.read_byte().unwrap_or(0);
+                self.eax[0] = byte;
+                return;
+            },
+            2 => {
+                let line = String::from_utf8_lossy(&self.read_line()).trim().to_owned();
+                let value = line.strip_prefix('+').unwrap_or(&line).parse::<i32>();
+                self.of = value.is_err();
+                value.unwrap_or(0) as u32
+            },
+            3 => {
+                let value = String::from_utf8_lossy(&self.read_line()).trim().parse::<u32>();
+                self.cf = value.is_err();
+                value.unwrap_or(0)
+            },
+            4 => {
+                let value = u32::from_str_radix(String::from_utf8_lossy(&self.read_line()).trim(), 16);
+                self.cf = value.is_err();
+                value.unwrap_or(0)
+            },
+            5 => {
+                let (buffer, size) = (self.get_edx(), self.get_ecx() as usize);
+                let mut line = self.read_line();
+                line.truncate(size.saturating_sub(1));
+
+                if size > 0 {
+                    let (pointer, start, _) = self.memory(buffer, line.len() + 1);
+                    let bytes = unsafe { &mut (&mut *pointer)[start..=start + line.len()] };
+                    bytes[..line.len()].copy_from_slice(&line);
+                    bytes[line.len()] = 0;
+                    self.count_written(buffer, line.len() + 1);
+                }
+
+                line.len() as u32
+            },
+            // ENOSYS
+            _ => (-38i32) as u32,
+        };
+
+        self.eax = result.to_le_bytes();
+    }
+
+    /// Get the time the time system calls return in nanoseconds since the epoch, which is the virtual time unless the
+    /// clock is the wall clock.
+    fn get_time(&self) -> u64 {
+        if !self.wall_clock {
+            return self.virtual_time;
+        }
+
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |duration| duration.as_nanos() as u64)
+    }
+
+    /// Random number service, whose generator starts from the same seed every time a program is loaded, so a run can be
+    /// repeated exactly. The function is in `eax`, the argument is in `ebx`, and the result is returned in `eax`.
+    ///
+    /// 0. random 32-bits number.
+    /// 1. random number less than `ebx`, or any 32-bits number if `ebx` is 0.
+    /// 2. seed the generator with `ebx`, returns 0.
+    fn random_service(&mut self) {
+        let bound = self.get_ebx();
+
+        let result = match self.get_eax() {
+            0 => self.service_random.next_u32(),
+            1 if bound == 0 => self.service_random.next_u32(),
+            1 => ((self.service_random.next_u32() as u64 * bound as u64) >> 32) as u32,
+            2 => {
+                self.service_random = Random::new(bound as u64);
+                0
+            },
+            // ENOSYS
+            _ => (-38i32) as u32,
+        };
+
+        self.eax = result.to_le_bytes();
+    }
+
+    /// Set the address of the first byte of memory, the stack starts from the last byte.
+    fn set_memory_base(&mut self, memory_base: u32) {
+        self.memory_base = memory_base;
+        self.esp = (memory_base + (self.stack.len() - 1) as u32).to_le_bytes();
+        self.ebp = self.esp;
+    }
 
     fn reset(&mut self) {
         self.text.clear();
         self.index.clear();
-        self.esp = ((MAX - 1) as u32).to_le_bytes();
-        self.esp = ((MAX - 1) as u32).to_le_bytes();
-        self.eip = [0; 4];
+        self.entrance = 0;
+        self.eax = [0; 4];
+        self.ebx = [0; 4];
+        self.ecx = [0; 4];
+        self.edx = [0; 4];
+        self.esi = [0; 4];
+        self.edi = [0; 4];
+        self.set_memory_base(0);
+        self.position = 0;
+        self.addresses.clear();
+        self.instructions.clear();
+        self.symbols.clear();
+        self.symbols.clear();
         self.cf = false;
         self.zf = false;
         self.sf = false;
         self.of = false;
+        self.pf = false;
+        self.af = false;
+        self.interrupt_flag = true;
+        self.direction_flag = false;
         self.depth = 1;
+        self.max_depth = 1;
+        self.call_stack.clear();
+        self.interrupt_vectors.clear();
+        self.regions.clear();
+        self.stack_range = None;
         self.error_flag_ = false;
+        self.exit_code = None;
+        self.halted = false;
+        self.service_random = Random::new(self.service_seed);
+        self.virtual_time = 0;
+        self.timer = None;
+        self.faults.clear();
+        self.instructions_executed = 0;
+        self.memory_written.set(0);
+        self.output_written = 0;
+        self.quota_exceeded = None;
+        self.exception = None;
+        self.started = None;
+        self.loop_detector.reset();
+        self.cache.iter_mut().for_each(|cache| cache.clear());
+        self.cache_stats.clear();
+        self.executing = None;
+        self.branch_stats.clear();
+        self.pipeline.iter_mut().for_each(|pipeline| pipeline.clear());
+        self.pipeline_stats.clear();
+        self.pipeline_usages.clear();
+        self.pipeline_diagram.clear();
+
+        if let Some(seed) = self.random_seed {
+            let mut random = Random::new(seed);
+
+            for register in [&mut self.eax, &mut self.ebx, &mut self.ecx, &mut self.edx, &mut self.esi, &mut self.edi] {
+                *register = random.next_u32().to_le_bytes();
+            }
+
+            let flags = random.next_u32();
+            self.cf = flags & 1 != 0;
+            self.zf = flags & 2 != 0;
+            self.sf = flags & 4 != 0;
+            self.of = flags & 8 != 0;
+            self.pf = flags & 16 != 0;
+            self.af = flags & 32 != 0;
+
+            random.fill(&mut self.stack);
+        }
+    }
+
+    pub fn get_eax(&self) -> u32 {
+        u32::from_le_bytes(self.eax)
+    }
+
+    pub fn get_ebx(&self) -> u32 {
+        u32::from_le_bytes(self.ebx)
+    }
+
+    pub fn get_ecx(&self) -> u32 {
+        u32::from_le_bytes(self.ecx)
+    }
+
+    pub fn get_edx(&self) -> u32 {
+        u32::from_le_bytes(self.edx)
+    }
+
+    pub fn get_esi(&self) -> u32 {
+        u32::from_le_bytes(self.esi)
+    }
+
+    pub fn get_edi(&self) -> u32 {
+        u32::from_le_bytes(self.edi)
+    }
+
+    pub fn get_esp(&self) -> u32 {
+        u32::from_le_bytes(self.esp)
+    }
+
+    pub fn get_ebp(&self) -> u32 {
+        u32::from_le_bytes(self.ebp)
+    }
+
+    /// Get a 32-bits general register, or `eip`, by its name, such as `eax`.
+    pub fn get_register(&self, name: &str) -> Option<u32> {
+        let register = match name {
+            "eax" => self.eax,
+            "ebx" => self.ebx,
+            "ecx" => self.ecx,
+            "edx" => self.edx,
+            "esi" => self.esi,
+            "edi" => self.edi,
+            "esp" => self.esp,
+            "ebp" => self.ebp,
+            "eip" => self.get_eip().to_le_bytes(),
+            _ => return None,
+        };
+
+        Some(u32::from_le_bytes(register))
+    }
+
+    /// Set a 32-bits general register by its name, such as `eax`, for example before running a loaded program.
+    pub fn set_register(&mut self, name: &str, value: u32) -> Result<(), String> {
+        let register = match name {
+            "eax" => &mut self.eax,
+            "ebx" => &mut self.ebx,
+            "ecx" => &mut self.ecx,
+            "edx" => &mut self.edx,
+            "esi" => &mut self.esi,
+            "edi" => &mut self.edi,
+            "esp" => &mut self.esp,
+            "ebp" => &mut self.ebp,
+            _ => return Err(format!("Unknown register: {}", name)),
+        };
+
+        *register = value.to_le_bytes();
+        Ok(())
+    }
+
+    /// Push command line arguments onto the stack of a loaded program, like Linux does for a new process.
+    ///
+    /// The strings are copied to the top of the stack, then `esp` points to `argc`, followed by the pointers of
+    /// `argv`, a null pointer ending `argv` and a null pointer for the empty environment.
+    pub fn set_arguments(&mut self, arguments: &[String]) {
+        let mut esp = self.get_esp();
+        let mut pointers = Vec::new();
+
+        for argument in arguments.iter().rev() {
+            let bytes = argument.as_bytes();
+            esp -= bytes.len() as u32 + 1;
+
+            let (pointer, start, size) = self.memory(esp, bytes.len() + 1);
+            let memory = unsafe { &mut (&mut *pointer)[start..start + size] };
+            memory[..bytes.len()].copy_from_slice(bytes);
+            memory[bytes.len()] = 0;
+
+            pointers.push(esp);
+        }
+
+        let mut words = vec![arguments.len() as u32];
+        words.extend(pointers.iter().rev());
+        words.push(0);
+        words.push(0);
+
+        esp = (esp & !3) - 4 * words.len() as u32;
+        self.esp = esp.to_le_bytes();
+
+        // written by the host, so not counted against the quota of memory written
+        for (i, word) in words.iter().enumerate() {
+            let (pointer, start, _) = self.memory(esp + 4 * i as u32, 4);
+            unsafe { (&mut *pointer)[start..start + 4].copy_from_slice(&word.to_le_bytes()); }
+        }
+    }
+
+    /// Let the `read` system call read from `input` instead of the standard input.
+    pub fn set_input(&mut self, input: Vec<u8>) {
+        self.input = Some(Cursor::new(input));
+    }
+
+    /// Keep the output of the `write` system call to the standard output, which can be got by [`VM::get_output`].
+    pub fn capture_output(&mut self) {
+        self.output = Some(Vec::new());
+    }
+
+    /// Get the captured standard output, `None` if it is not captured.
+    pub fn get_output(&self) -> Option<&[u8]> {
+        self.output.as_deref()
+    }
+
+    /// Read `size` bytes of memory at `address`, `None` if they are not all in the memory of virtual machine.
+    pub fn read_memory(&self, address: u32, size: usize) -> Option<&[u8]> {
+        let start = address.wrapping_sub(self.memory_base) as usize;
+
+        if start.checked_add(size)? > self.stack.len() {
+            return None;
+        }
+
+        Some(&self.stack[start..start + size])
+    }
+
+    /// Change the size of memory, which is 2 MiB by default and at most 1 GiB. The memory is cleared and the stack starts from its last
+    /// byte again.
+    pub fn set_memory_size(&mut self, size: usize) -> Result<(), String> {
+        if !(4096..=1 << 30).contains(&size) {
+            return Err(format!("Memory Error: {} bytes is not a valid memory size", size));
+        }
+
+        self.stack = vec![0; size].into_boxed_slice();
+        self.set_memory_base(self.memory_base);
+        Ok(())
+    }
+
+    /// Fill the general registers except `esp` and `ebp`, the flags and the memory with garbage from `seed`, instead
+    /// of zeros, when the programs loaded later are loaded, to expose programs which rely on them being zero.
+    pub fn set_random_state(&mut self, seed: Option<u64>) {
+        self.random_seed = seed;
+    }
+
+    /// Seed the random number service of `int 0x81` with `seed` instead of 0, for the programs loaded later.
+    pub fn set_service_seed(&mut self, seed: u64) {
+        self.service_seed = seed;
+        self.service_random = Random::new(seed);
+    }
+
+    /// Make the time system calls return the time of the host instead of the virtual time, which makes runs of
+    /// time-based programs differ.
+    pub fn set_wall_clock(&mut self, wall_clock: bool) {
+        self.wall_clock = wall_clock;
+    }
+
+    /// Skip the instructions which fault, such as one with a bad operand or an unknown instruction, up to `limit`
+    /// faults, and stop at the next one; `None` stops at the first fault, for the programs loaded later.
+    ///
+    /// The rest of the line of a fault is skipped, and the fault is printed unless quiet.
+    pub fn set_fault_limit(&mut self, limit: Option<usize>) {
+        self.fault_limit = limit;
+    }
+
+    /// Set hard limits on the instructions executed, the bytes of memory written, the bytes of stack used, the bytes
+    /// of output written and the milliseconds of wall-clock time of the programs loaded later.
+    ///
+    /// The time is checked between instructions, so a program blocked in a system call, such as reading the standard
+    /// input, is only stopped after the call returns. With `deny_host_files`, the source files loaded later can not
+    /// read other files of the host, `%include`, `include` and `incbin` are errors.
+    ///
+    /// A program which exceeds one is finished, and [`VM::get_quota_exceeded`] tells which quota it exceeded.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::vm::{Quotas, VM};
+    /// let mut vm: VM = Default::default();
+    /// vm.set_quotas(Quotas { instructions: Some(1_000_000), output: Some(65536), deny_host_files: true,
+    ///                        ..Default::default() });
+    /// vm.run_file("./untrusted.asm".to_string());
+    ///
+    /// if let Some(exceeded) = vm.get_quota_exceeded() {
+    ///     eprintln!("{}", exceeded);
+    /// }
+    /// ```
+    pub fn set_quotas(&mut self, quotas: Quotas) {
+        self.quotas = quotas;
+    }
+
+    /// Forbid or require instructions in the programs loaded later, a program which breaks the policy is not loaded.
+    /// Copy the whole state of the virtual machine, such as at a breakpoint, so that the copy can be changed and run,
+    /// for example with another value of a register, while this one stays where it is.
+    ///
+    /// The copy goes on with the rest of the standard input and the output captured so far, but it does not write to
+    /// the trace.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::vm::VM;
+    /// let mut vm: VM = Default::default();
+    /// vm.load_source("inline".to_string(), "main:\n    mov eax, 1\n    ret\n".to_string());
+    /// let mut copy = vm.fork();
+    /// copy.set_register("eax", 0).unwrap();
+    /// copy.execute();
+    /// ```
+    pub fn fork(&self) -> VM {
+        VM {
+            stack: self.stack.clone(),
+            memory_base: self.memory_base,
+            text: self.text.clone(),
+            index: self.index.clone(),
+            entrance: self.entrance,
+            eax: self.eax,
+            ebx: self.ebx,
+            ecx: self.ecx,
+            edx: self.edx,
+            esi: self.esi,
+            edi: self.edi,
+            esp: self.esp,
+            ebp: self.ebp,
+            position: self.position,
+            origin: self.origin,
+            addresses: self.addresses.clone(),
+            instructions: self.instructions.clone(),
+            symbols: self.symbols.clone(),
+            cf: self.cf,
+            zf: self.zf,
+            sf: self.sf,
+            of: self.of,
+            pf: self.pf,
+            af: self.af,
+            interrupt_flag: self.interrupt_flag,
+            direction_flag: self.direction_flag,
+            scanner: Default::default(),
+            source_options: self.source_options.clone(),
+            depth: self.depth,
+            max_depth: self.max_depth,
+            call_depth_limit: self.call_depth_limit,
+            call_stack: self.call_stack.clone(),
+            stack_range: self.stack_range,
+            error_flag_: self.error_flag_,
+            verbosity: self.verbosity,
+            exit_code: self.exit_code,
+            halted: self.halted,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            entry_label: self.entry_label.clone(),
+            interrupt_vectors: self.interrupt_vectors.clone(),
+            regions: self.regions.clone(),
+            ports: self.ports.clone(),
+            cpuid_leaves: self.cpuid_leaves.clone(),
+            random_seed: self.random_seed,
+            service_seed: self.service_seed,
+            service_random: self.service_random.clone(),
+            virtual_time: self.virtual_time,
+            wall_clock: self.wall_clock,
+            timer: self.timer,
+            fault_limit: self.fault_limit,
+            faults: self.faults.clone(),
+            deferred_errors: self.deferred_errors.clone(),
+            quotas: self.quotas,
+            instructions_executed: self.instructions_executed,
+            memory_written: self.memory_written.clone(),
+            output_written: self.output_written,
+            quota_exceeded: self.quota_exceeded.clone(),
+            exception: self.exception.clone(),
+            started: self.started,
+            policy: self.policy.clone(),
+            trace: None,
+            written: RefCell::new(Vec::new()),
+            loop_detection: self.loop_detection,
+            loop_detector: self.loop_detector.clone(),
+            memory_changed: self.memory_changed.clone(),
+            cache: self.cache.clone(),
+            cache_stats: self.cache_stats.clone(),
+            executing: None,
+            immediates: Vec::new(),
+            branch_model: self.branch_model,
+            branch_stats: self.branch_stats.clone(),
+            pipeline: self.pipeline.clone(),
+            pipeline_stats: self.pipeline_stats.clone(),
+            pipeline_usages: self.pipeline_usages.clone(),
+            pipeline_diagram: self.pipeline_diagram.clone(),
+        }
+    }
+
+    /// Stop a program with an error when the next instruction, the registers, the flags and the call depth are the same
+    /// as after an earlier instruction, and no instruction since then changed memory or did input or output by `int`,
+    /// so the program would repeat the same instructions forever. Loops are not detected while a timer is set.
+    pub fn set_loop_detection(&mut self, loop_detection: bool) {
+        self.loop_detection = loop_detection;
+        self.loop_detector.reset();
+    }
+
+    /// Compare the state after the instruction at `start` with the saved one, see [`VM::set_loop_detection`].
+    fn detect_loop(&mut self, start: usize) {
+        let changed = self.memory_changed.replace(false);
+
+        if changed || self.timer.is_some() || self.text[start].get_token_value() == TokenValue::INT {
+            self.loop_detector.reset();
+            return;
+        }
+
+        let state = LoopState {
+            position: self.position,
+            registers: [self.eax, self.ebx, self.ecx, self.edx, self.esi, self.edi, self.esp, self.ebp],
+            flags: [self.cf, self.zf, self.sf, self.of, self.interrupt_flag],
+            depth: self.depth,
+        };
+
+        if let Some(length) = self.loop_detector.check(state) {
+            let location = self.text[start].get_token_location();
+            panic!("Runtime Error: {} Non-terminating loop detected at line {}: the same state repeats every {} \
+                    instructions without changing memory, input or output", location.to_string(), location.get_line(),
+                   length);
+        }
+    }
+
+    /// Simulate an L1 data cache with the geometry of `config`, which every access of an instruction to memory goes
+    /// through, including the stack, or none if `None`. See [`crate::cache`].
+    pub fn set_cache(&mut self, config: Option<CacheConfig>) {
+        self.cache = config.map(Cache::new);
+        self.cache_stats.clear();
+    }
+
+    /// Get the geometry of the simulated data cache, if any.
+    pub fn get_cache_config(&self) -> Option<CacheConfig> {
+        self.cache.as_ref().map(|cache| cache.get_config())
+    }
+
+    /// Get the hits and misses of the data cache of every instruction which accessed memory since the program was
+    /// loaded, in the order of the text, with its location and name.
+    pub fn get_cache_stats(&self) -> Vec<(TokenLocation, String, CacheStats)> {
+        let mut positions: Vec<&usize> = self.cache_stats.keys().collect();
+        positions.sort();
+
+        positions.into_iter().map(|position| {
+            let token = &self.text[*position];
+            (token.get_token_location(), token.get_token_name(), self.cache_stats[position])
+        }).collect()
+    }
+
+    /// Get the hits and misses of the data cache of the instructions after every label, up to the next one, in the
+    /// order of the text. Instructions before the first label are counted under an empty name.
+    pub fn get_cache_stats_by_label(&self) -> Vec<(String, CacheStats)> {
+        let mut positions: Vec<&usize> = self.cache_stats.keys().collect();
+        positions.sort();
+
+        let mut labels: Vec<(String, CacheStats)> = Vec::new();
+
+        for position in positions {
+            let label = self.text[..*position].iter().rev()
+                .find(|token| token.get_token_type() == TokenType::LABEL)
+                .map_or(String::new(), |token| token.get_token_name());
+
+            match labels.last_mut() {
+                Some((last, stats)) if *last == label => stats.add(self.cache_stats[position]),
+                _ => labels.push((label, self.cache_stats[position])),
+            }
+        }
+
+        labels
+    }
+
+    /// Record how often every conditional jump is taken, and with [`BranchModel::TwoBit`] how often a 2-bit predictor
+    /// mispredicts it, or nothing if `None`. See [`crate::branch`].
+    pub fn set_branch_model(&mut self, model: Option<BranchModel>) {
+        self.branch_model = model;
+        self.branch_stats.clear();
+    }
+
+    pub fn get_branch_model(&self) -> Option<BranchModel> {
+        self.branch_model
+    }
+
+    /// Get the record of every conditional jump executed since the program was loaded, in the order of the text, with
+    /// its location and name.
+    pub fn get_branch_stats(&self) -> Vec<(TokenLocation, String, BranchStats)> {
+        let mut positions: Vec<&usize> = self.branch_stats.keys().collect();
+        positions.sort();
+
+        positions.into_iter().map(|position| {
+            let token = &self.text[*position];
+            (token.get_token_location(), token.get_token_name(), self.branch_stats[position])
+        }).collect()
+    }
+
+    /// Put every instruction executed from now on through a model of a 5-stage pipeline, or none if `None`, see
+    /// [`crate::pipeline`].
+    pub fn set_pipeline(&mut self, pipeline: Option<Pipeline>) {
+        self.pipeline = pipeline;
+        self.pipeline_stats.clear();
+        self.pipeline_diagram.clear();
+    }
+
+    pub fn get_pipeline(&self) -> Option<&Pipeline> {
+        self.pipeline.as_ref()
+    }
+
+    /// Get the cycles of every instruction executed since the program was loaded, in the order of the text, with its
+    /// location and text.
+    pub fn get_pipeline_stats(&self) -> Vec<(TokenLocation, String, PipelineStats)> {
+        let mut positions: Vec<&usize> = self.pipeline_stats.keys().collect();
+        positions.sort();
+
+        positions.into_iter().map(|position| {
+            let (_, _, text) = &self.pipeline_usages[position];
+            (self.text[*position].get_token_location(), text.to_owned(), self.pipeline_stats[position])
+        }).collect()
+    }
+
+    /// Get the stages of the first [`PIPELINE_DIAGRAM_LENGTH`] instructions executed, in the order they were executed,
+    /// with their text.
+    pub fn get_pipeline_diagram(&self) -> Vec<(String, Issue)> {
+        self.pipeline_diagram.iter().map(|(position, issue)| (self.pipeline_usages[position].2.to_owned(), *issue))
+            .collect()
+    }
+
+    /// Issue the instruction from the token `instruction`, which was just executed, to the pipeline.
+    fn issue(&mut self, instruction: usize) {
+        let finished = self.is_finished();
+        let text = &self.text;
+        let (usage, end, _) = self.pipeline_usages.entry(instruction).or_insert_with(|| {
+            let mut parser = Parser::new(&text[instruction..]);
+
+            match parser.parse_statement() {
+                Ok(Some(Statement::Instruction(parsed))) => {
+                    // the target of a jump or a call is shown as its label rather than its address
+                    let shown = match parsed.mnemonic {
+                        mnemonic if cfg::is_jump(mnemonic) || (mnemonic == TokenValue::CALL &&
+                            text[instruction + 1].get_token_type() == TokenType::IMMEDIATE_DATA) => {
+                            format!("{} {}", parsed.name, text[instruction + 1].get_token_name())
+                        },
+                        _ => parsed.to_string(),
+                    };
+
+                    (Usage::new(&parsed), instruction + parser.position(), shown)
+                },
+                _ => (Usage::default(), instruction + 1, text[instruction].get_token_name().to_lowercase()),
+            }
+        });
+
+        // a jump which is taken, a call or a return goes on somewhere else than the next instruction
+        let taken = self.position != *end && !finished;
+        let issue = match &mut self.pipeline {
+            Some(pipeline) => pipeline.issue(usage, taken),
+            None => return,
+        };
+
+        let stats = self.pipeline_stats.entry(instruction).or_default();
+        stats.instructions += 1;
+        stats.data_stalls += issue.data_stalls;
+        stats.control_stalls += issue.control_stalls;
+
+        if self.pipeline_diagram.len() < PIPELINE_DIAGRAM_LENGTH {
+            self.pipeline_diagram.push((instruction, issue));
+        }
+    }
+
+    /// Write a record of every instruction executed from now on to `trace` as a line of JSON, see [`crate::trace`].
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write + Send>>) {
+        self.trace = trace;
+        self.written.borrow_mut().clear();
+    }
+
+    /// Write the record of the instruction at `start` whose address is `eip` to the trace.
+    fn write_trace(&mut self, start: usize, eip: u32) {
+        let writes = self.written.take().into_iter()
+            .map(|(address, size)| (address, self.read_memory(address, size).unwrap_or_default().to_vec()))
+            .collect::<Vec<_>>();
+        let record = trace::record(self, &self.text[start].get_token_location(), eip, &writes);
+
+        if let Some(trace) = &mut self.trace {
+            if let Err(err) = writeln!(trace, "{}", record) {
+                panic!("Runtime Error: Can not write the trace, because {}.", err);
+            }
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+    }
+
+    pub fn get_quotas(&self) -> Quotas {
+        self.quotas
+    }
+
+    /// Get the quota which stopped the program, `None` if it has not exceeded any.
+    pub fn get_quota_exceeded(&self) -> Option<&QuotaExceeded> {
+        self.quota_exceeded.as_ref()
+    }
+
+    /// Stop the program because it exceeded `quota`, at the instruction at `position`.
+    fn exceed_quota(&mut self, quota: Quota, limit: u64, position: usize) {
+        let location = self.text.get(position).map(|token| token.get_token_location());
+        self.quota_exceeded = Some(QuotaExceeded { quota, limit, location });
+    }
+
+    /// Get the exception which stopped the program, `None` if it has not raised one without a handler.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::vm::{Exception, VM};
+    /// let mut vm: VM = Default::default();
+    /// vm.run_source("inline".to_string(), "main:\n    mov al, 127\n    add al, 1\n    into\n    ret\n".to_string());
+    ///
+    /// if let Some(raised) = vm.get_exception() {
+    ///     assert_eq!(raised.exception, Exception::Overflow);
+    /// }
+    /// ```
+    pub fn get_exception(&self) -> Option<&ExceptionRaised> {
+        self.exception.as_ref()
+    }
+
+    /// Raise `exception` by the instruction at `position`, calling the handler of its vector, as an interrupt which
+    /// returns to the next instruction, or to the instruction itself for a fault, or stopping the program without
+    /// one.
+    fn raise(&mut self, exception: Exception, position: usize) {
+        match self.interrupt_vectors.get(&exception.vector()).copied() {
+            Some(handler) => {
+                let return_address = if exception.is_fault() { self.addresses[&position].0 } else { self.get_eip() };
+                self.call_handler(position, return_address, handler);
+            },
+            None => {
+                let location = self.text.get(position).map(|token| token.get_token_location());
+                self.exception = Some(ExceptionRaised { exception, location });
+            },
+        }
     }
 
-    pub fn get_eax(&self) -> u32 {
-        u32::from_le_bytes(self.eax)
+    /// Get the faults which were skipped since the program was loaded, see [`VM::set_fault_limit`].
+    pub fn get_faults(&self) -> &[String] {
+        &self.faults
     }
 
-    pub fn get_ebx(&self) -> u32 {
-        u32::from_le_bytes(self.ebx)
+    /// Get the number of instructions executed since the program was loaded.
+    pub fn get_instructions_executed(&self) -> u64 {
+        self.instructions_executed
     }
 
-    pub fn get_ecx(&self) -> u32 {
-        u32::from_le_bytes(self.ecx)
+    /// Get the virtual time since the program was loaded in nanoseconds, [`NANOSECONDS_PER_INSTRUCTION`] for every
+    /// instruction executed.
+    pub fn get_virtual_time(&self) -> u64 {
+        self.virtual_time
     }
 
-    pub fn get_edx(&self) -> u32 {
-        u32::from_le_bytes(self.edx)
+    /// Read the source files loaded later with `options`, such as the aliases of instructions.
+    pub fn set_source_options(&mut self, options: SourceOptions) {
+        self.source_options = options;
+    }
+
+    /// Start from `label` instead of `main`, `start`, `_main` or `_start`, for the source files loaded later.
+    pub fn set_entry_label(&mut self, label: Option<String>) {
+        self.entry_label = label;
+    }
+
+    /// Write `bytes` to memory at `address`, for example to load data before running a loaded program.
+    pub fn write_memory(&mut self, address: u32, bytes: &[u8]) -> Result<(), String> {
+        let start = address.wrapping_sub(self.memory_base) as usize;
+
+        if start.checked_add(bytes.len()).is_none_or(|end| end > self.stack.len()) {
+            return Err(format!("Memory Error: {} bytes at {:#010x} are out of memory", bytes.len(), address));
+        }
+
+        self.stack[start..start + bytes.len()].copy_from_slice(bytes);
+        self.loop_detector.reset();
+        Ok(())
+    }
+
+    /// Get the memory in use, as pairs of address and bytes, so that runs of 16 zero bytes are left out.
+    pub fn get_memory_segments(&self) -> Vec<(u32, Vec<u8>)> {
+        let mut segments: Vec<(u32, Vec<u8>)> = Vec::new();
+
+        for (i, chunk) in self.stack.chunks(16).enumerate() {
+            if chunk.iter().all(|byte| *byte == 0) {
+                continue;
+            }
+
+            let address = self.memory_base.wrapping_add(i as u32 * 16);
+
+            match segments.last_mut() {
+                Some((start, bytes)) if start.wrapping_add(bytes.len() as u32) == address => {
+                    bytes.extend_from_slice(chunk);
+                },
+                _ => segments.push((address, chunk.to_vec())),
+            }
+        }
+
+        segments
+    }
+
+    /// Get the status passed to the `exit` system call, if the program exited by it.
+    pub fn get_exit_code(&self) -> Option<u32> {
+        self.exit_code
+    }
+
+    /// Whether the program stopped by `hlt`, rather than by returning from the entry label or the `exit` system call.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Get the flags as (`cf`, `zf`, `sf`, `of`).
+    pub fn get_flags(&self) -> (bool, bool, bool, bool) {
+        (self.cf, self.zf, self.sf, self.of)
+    }
+
+    pub fn get_parity_flag(&self) -> bool {
+        self.pf
+    }
+
+    pub fn get_adjust_flag(&self) -> bool {
+        self.af
+    }
+
+    pub fn get_direction_flag(&self) -> bool {
+        self.direction_flag
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
     }
 
     pub fn get_text(&self) -> Vec<Token> {
@@ -1336,63 +3785,587 @@ impl VM {
     ///
     /// # Examples
     ///
-    /// ```
-    /// let vm = VM::new("./test.asm".to_string());
+    /// ```no_run
+    /// # use asm_vm::vm::VM;
+    /// let mut vm = VM::new("./test.asm".to_string());
     /// vm.run();
     /// ```
     pub fn run(&mut self) {
         self.preprocess();
+        self.execute();
+    }
 
+    /// Execute the preprocessed text from the entry label.
+    pub fn execute(&mut self) {
         if self.text.len() == 0 {
-            eprintln!("Source file is empty!");
+            if self.verbosity >= Verbosity::NORMAL {
+                eprintln!("Source file is empty!");
+            }
             return;
         }
 
+        while self.step() {}
+    }
+
+    /// Get the location of the instruction which will be executed by the next [`VM::step`], `None` if the program is
+    /// finished.
+    pub fn get_location(&self) -> Option<TokenLocation> {
+        if self.is_finished() {
+            return None;
+        }
+
+        self.text.get(self.next_instruction()).map(|token| token.get_token_location())
+    }
+
+    /// Get the index of the first token of the next instruction, the labels and directives before it are skipped.
+    fn next_instruction(&self) -> usize {
+        let mut position = self.position;
+
+        while position < self.text.len() {
+            if self.text[position].get_token_type() == TokenType::LABEL {
+                position += 2;
+            } else if let Some(length) = self.directive_length(position) {
+                position += length;
+            } else {
+                break;
+            }
+        }
+
+        position
+    }
+
+    /// Get the number of tokens of the directive at `position`, which is not executed, such as `org` and `global`.
+    fn directive_length(&self, position: usize) -> Option<usize> {
+        let token = self.text.get(position).filter(|token| token.get_token_type() == TokenType::INSTRUCTION)?;
+
+        match token.get_token_value() {
+            TokenValue::ORG => Some(2),
+            mnemonic if cfg::is_section(mnemonic) => Some(1),
+            TokenValue::ENDP => Some(2),
+            // a declaration is a list of labels
+            mnemonic if is_declaration(mnemonic) => {
+                let mut length = 2;
+
+                while self.text.get(position + length).is_some_and(|token| token.get_token_value() ==
+                                                                    TokenValue::COMMA) {
+                    length += 2;
+                }
+
+                Some(length)
+            },
+            _ => None,
+        }
+    }
+
+    /// Get `eip`, the address of the instruction which will be executed by the next [`VM::step`].
+    pub fn get_eip(&self) -> u32 {
+        match self.addresses.get(&self.next_instruction()) {
+            Some((address, _)) => *address,
+            // after the last instruction
+            None => self.addresses.values().map(|(address, size)| address.wrapping_add(*size)).max()
+                .unwrap_or(self.origin),
+        }
+    }
+
+    /// Load the first instruction at `origin` instead of 0, for the source files loaded later, such as the address a
+    /// binary image was disassembled from.
+    pub fn set_origin(&mut self, origin: u32) {
+        self.origin = origin;
+    }
+
+    /// Get the most bytes the stack has held during the run, below `esp` when the program started.
+    pub fn get_max_stack_usage(&self) -> u32 {
+        self.stack_range.map_or(0, |(top, lowest)| top.saturating_sub(lowest))
+    }
+
+    /// Get the most calls which have not returned at the same time during the run, 0 if no function is called.
+    pub fn get_max_call_depth(&self) -> u32 {
+        (self.max_depth - 1) as u32
+    }
+
+    /// Set the most calls which may be nested, a `call` beyond it stops the program with a backtrace.
+    pub fn set_call_depth_limit(&mut self, limit: usize) {
+        self.call_depth_limit = limit;
+    }
+
+    /// Describe the calls and interrupts which have not returned, the innermost first, such as
+    /// `#0 main.asm:9:5: call fact`.
+    ///
+    /// Only the frames at both ends of a long backtrace are listed, as deep recursion repeats the same calls.
+    pub fn backtrace(&self) -> String {
+        let mut lines = vec!["Backtrace:".to_string()];
+        let count = self.call_stack.len();
+
+        for (i, position) in self.call_stack.iter().rev().enumerate() {
+            if count > 2 * BACKTRACE_ENDS && i == BACKTRACE_ENDS {
+                lines.push(format!("    ... {} more frames", count - 2 * BACKTRACE_ENDS));
+            }
+
+            if count > 2 * BACKTRACE_ENDS && (BACKTRACE_ENDS..count - BACKTRACE_ENDS).contains(&i) {
+                continue;
+            }
+
+            let instruction = &self.text[*position];
+            let operand = match (instruction.get_token_value(), self.text.get(position + 1)) {
+                (TokenValue::INT, Some(vector)) => format!("{:#x}", vector.get_int_value()),
+                (_, Some(target)) => target.get_token_name(),
+                (_, None) => String::new(),
+            };
+
+            lines.push(format!("    #{} {} {} {}", i, instruction.get_token_location().to_string(),
+                               instruction.get_token_name().to_lowercase(), operand));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Walk the frames of the functions which have not returned along the chain of `ebp`, the innermost first.
+    ///
+    /// Every frame is expected to start with `push ebp` and `mov ebp, esp`, so `[ebp]` is the `ebp` of the caller and
+    /// `[ebp + 4]` the return address. The walk stops at an `ebp` outside the stack or not above the one before it,
+    /// and after one frame for every call which has not returned and one for the entry label.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::vm::VM;
+    /// let mut vm = VM::new("fact.asm".to_string());
+    /// vm.step();
+    ///
+    /// for frame in vm.frames() {
+    ///     println!("{:?} returns to {:?}", frame.function_label, frame.return_address);
+    /// }
+    /// ```
+    pub fn frames(&self) -> Vec<Frame> {
+        let mut frames = Vec::new();
+
+        if self.is_finished() {
+            return frames;
+        }
+
+        let functions = self.functions();
+        let function_label = |address: u32| functions.iter().rev().find(|(start, _)| *start <= address)
+            .map(|(_, name)| name.to_owned());
+
+        let (mut eip, mut esp, mut ebp) = (self.get_eip(), self.get_esp(), self.get_ebp());
+
+        let read_word = |address: u32| self.read_memory(address, 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+
+        for i in 0..=self.call_stack.len() {
+            let saved_ebp = match read_word(ebp) {
+                Some(saved_ebp) if ebp >= esp => saved_ebp,
+                _ => break,
+            };
+
+            let return_address = if i < self.call_stack.len() {
+                match read_word(ebp.wrapping_add(4)) {
+                    Some(return_address) => Some(return_address),
+                    None => break,
+                }
+            } else {
+                None
+            };
+
+            frames.push(Frame { function_label: function_label(eip), return_address, ebp, locals_range: esp..ebp });
+
+            let return_address = match return_address {
+                Some(return_address) if saved_ebp > ebp => return_address,
+                _ => break,
+            };
+
+            eip = return_address;
+            esp = ebp.wrapping_add(8);
+            ebp = saved_ebp;
+        }
+
+        frames
+    }
+
+    /// Get the addresses and labels where functions start, in the order of addresses, which are the targets of
+    /// `call`, the entry label and the handlers `ivt` installs.
+    fn functions(&self) -> Vec<(u32, String)> {
+        let mut functions: Vec<(u32, String)> = self.symbols.iter()
+            .filter(|(_, address)| self.interrupt_vectors.values().any(|handler| handler == *address))
+            .map(|(name, address)| (*address, name.to_owned()))
+            .collect();
+
+        if let Some(entry) = self.text.get(self.entrance as usize) {
+            if let Some(address) = self.symbols.get(&entry.get_token_name()) {
+                functions.push((*address, entry.get_token_name()));
+            }
+        }
+
+        for (i, token) in self.text.iter().enumerate() {
+            if token.get_token_value() != TokenValue::CALL {
+                continue;
+            }
+
+            if let Some(address) = self.text.get(i + 1).and_then(|target| self.symbols.get(&target.get_token_name())) {
+                functions.push((*address, self.text[i + 1].get_token_name()));
+            }
+        }
+
+        functions.sort();
+        functions.dedup_by_key(|(address, _)| *address);
+        functions
+    }
+
+    /// Get the address of a label, `None` if it is not defined.
+    pub fn get_symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Add a named region of memory, such as a block of a heap the host manages, which is described by
+    /// [`VM::symbolize`] until the next program is loaded. Offsets in it are counted from its start.
+    pub fn add_region(&mut self, name: &str, range: Range<u32>) {
+        self.regions.push(Region { name: name.to_string(), base: range.start, range });
+    }
+
+    /// Attach `device` to `port`, which `in` reads from and `out` writes to, replacing the device attached before, or
+    /// detach it if `None`. The devices stay attached when another program is loaded, and forks share them. See
+    /// [`crate::port`].
+    pub fn set_port_device(&mut self, port: u16, device: Option<SharedPortDevice>) {
+        match device {
+            Some(device) => self.ports.insert(port, device),
+            None => self.ports.remove(&port),
+        };
+    }
+
+    /// Set the values of `eax`, `ebx`, `ecx` and `edx` which `cpuid` returns for `leaf`, the value of `eax` before it,
+    /// such as the feature bits of leaf 1 a program detects, or remove the leaf if `None`. The leaves are kept when
+    /// another program is loaded, and are [`DEFAULT_CPUID_LEAVES`] at first.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::vm::VM;
+    /// let mut vm: VM = Default::default();
+    /// // leaf 1 of a processor with cmov, bit 15 of edx
+    /// vm.set_cpuid_leaf(1, Some([0x400, 0, 0, 1 << 15]));
+    /// vm.run_file("./detect.asm".to_string());
+    /// ```
+    pub fn set_cpuid_leaf(&mut self, leaf: u32, values: Option<[u32; 4]>) {
+        match values {
+            Some(values) => self.cpuid_leaves.insert(leaf, values),
+            None => self.cpuid_leaves.remove(&leaf),
+        };
+    }
+
+    /// Get the values of `eax`, `ebx`, `ecx` and `edx` which `cpuid` returns for `leaf`, `None` if it is not set.
+    pub fn get_cpuid_leaf(&self, leaf: u32) -> Option<[u32; 4]> {
+        self.cpuid_leaves.get(&leaf).copied()
+    }
+
+    /// Remove the regions added by [`VM::add_region`] with the name, such as a block of a heap which is freed.
+    pub fn remove_region(&mut self, name: &str) {
+        self.regions.retain(|region| region.name != name);
+    }
+
+    /// Get the named regions of memory: the regions added by [`VM::add_region`], the data after every label, such as
+    /// the bytes of `incbin`, up to the next label, and the frame of every function which has not returned, from its
+    /// `esp` up to its return address, whose offsets are counted from its `ebp`, such as `fact ebp-4`.
+    pub fn regions(&self) -> Vec<Region> {
+        let mut regions = self.regions.to_owned();
+
+        let mut data: Vec<Range<u32>> = self.addresses.iter()
+            .filter(|(position, _)| cfg::is_data(self.text[**position].get_token_value()))
+            .map(|(_, (address, size))| *address..address.wrapping_add(*size))
+            .collect();
+        data.sort_by_key(|range| range.start);
+
+        // data which follows data is one span
+        let spans = data.into_iter().fold(Vec::<Range<u32>>::new(), |mut spans, range| {
+            match spans.last_mut() {
+                Some(span) if span.end == range.start => span.end = range.end,
+                _ => spans.push(range),
+            }
+
+            spans
+        });
+
+        let mut labels: Vec<(u32, &String)> = self.symbols.iter().map(|(name, address)| (*address, name)).collect();
+        labels.sort();
+
+        for (i, (address, name)) in labels.iter().enumerate() {
+            if let Some(span) = spans.iter().find(|span| span.contains(address)) {
+                let next = labels[i + 1..].iter().map(|(next, _)| *next).find(|next| next > address);
+                let end = next.map_or(span.end, |next| next.min(span.end));
+                regions.push(Region { name: name.to_string(), range: *address..end, base: *address });
+            }
+        }
+
+        for frame in self.frames() {
+            let end = frame.ebp.wrapping_add(if frame.return_address.is_some() { 8 } else { 4 });
+            let name = format!("{} ebp", frame.function_label.as_deref().unwrap_or("frame"));
+            regions.push(Region { name, range: frame.locals_range.start..end, base: frame.ebp });
+        }
+
+        regions
+    }
+
+    /// Describe an address by the smallest region it falls in, such as `buffer+4`, `None` if it falls in none.
+    pub fn symbolize(&self, address: u32) -> Option<String> {
+        self.symbolize_in(&self.regions(), address)
+    }
+
+    fn symbolize_in(&self, regions: &[Region], address: u32) -> Option<String> {
+        let region = regions.iter().filter(|region| region.range.contains(&address))
+            .min_by_key(|region| region.range.end.wrapping_sub(region.range.start))?;
+
+        Some(match address.wrapping_sub(region.base) as i32 {
+            0 => region.name.to_owned(),
+            offset => format!("{}{:+}", region.name, offset),
+        })
+    }
+
+    /// Format `size` bytes of memory from `address` as a hexdump, which starts every line with its address and the
+    /// region it falls in, such as `0x00000040 <buffer+4>`, and a new line at the start of every region.
+    ///
+    /// Returns an error if the bytes are outside the memory.
+    pub fn hexdump(&self, address: u32, size: usize) -> Result<String, String> {
+        let bytes = self.read_memory(address, size)
+            .ok_or_else(|| format!("Runtime Error: Invalid memory address: {:#x}", address))?;
+        let regions = self.regions();
+
+        let mut lines: Vec<(String, &[u8])> = Vec::new();
+        let mut start = 0;
+
+        while start < bytes.len() {
+            let line_address = address.wrapping_add(start as u32);
+            let mut end = (start + HEXDUMP_BYTES).min(bytes.len());
+
+            if let Some(next) = (start + 1..end).find(|i| {
+                regions.iter().any(|region| region.range.start == address.wrapping_add(*i as u32))
+            }) {
+                end = next;
+            }
+
+            let annotation = self.symbolize_in(&regions, line_address)
+                .map_or_else(String::new, |symbol| format!("<{}>", symbol));
+            lines.push((format!("{:#010x} {}", line_address, annotation), &bytes[start..end]));
+            start = end;
+        }
+
+        let width = lines.iter().map(|(head, _)| head.len()).max().unwrap_or(0);
+
+        Ok(lines.iter().map(|(head, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let text: String = bytes.iter()
+                .map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' })
+                .collect();
+            format!("{:<width$}  {:<hex_width$}  |{}|\n", head, hex.join(" "), text, width = width,
+                    hex_width = HEXDUMP_BYTES * 3 - 1)
+        }).collect())
+    }
+
+    /// Whether the program has returned from the entry label, exited by the `exit` system call, stopped by `hlt` or by
+    /// an exception without a handler.
+    pub fn is_finished(&self) -> bool {
+        self.text.is_empty() || self.depth == 0 || self.exit_code.is_some() || self.halted ||
+            self.quota_exceeded.is_some() || self.exception.is_some()
+    }
+
+    /// Execute one instruction, the labels and `org` before it are skipped.
+    ///
+    /// Returns `false` if the program is finished, either before or by this instruction.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::vm::VM;
+    /// let mut vm: VM = Default::default();
+    /// vm.load_source("inline".to_string(), "main:\n    mov eax, 1\n    ret\n".to_string());
+    /// while vm.step() {
+    ///     println!("eax: {}", vm.get_eax());
+    /// }
+    /// ```
+    pub fn step(&mut self) -> bool {
+        let limit = match self.fault_limit {
+            Some(limit) if !self.is_finished() => limit,
+            _ => return self.execute_instruction(),
+        };
+
+        let start = self.next_instruction();
+
+        match catch_error(|| self.execute_instruction()) {
+            Ok(running) => running,
+            Err(msg) => {
+                let end = self.line_end(start);
+
+                if self.faults.len() >= limit || end == start {
+                    panic!("{}", msg);
+                }
+
+                if self.verbosity >= Verbosity::NORMAL {
+                    eprintln!("{} (skipped)", msg);
+                }
+
+                self.faults.push(msg);
+                self.position = end;
+                !self.is_finished()
+            },
+        }
+    }
+
+    fn execute_instruction(&mut self) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+
+        let start = self.next_instruction();
+
+        if let Some(limit) = self.quotas.instructions.filter(|limit| self.instructions_executed >= *limit) {
+            self.exceed_quota(Quota::Instructions, limit, start);
+            return false;
+        }
+
+        if let Some(limit) = self.quotas.time {
+            let started = *self.started.get_or_insert_with(Instant::now);
+
+            let check = self.instructions_executed.is_multiple_of(TIME_CHECK_INTERVAL);
+
+            if check && started.elapsed().as_millis() >= limit as u128 {
+                self.exceed_quota(Quota::Time, limit, start);
+                return false;
+            }
+        }
+
+        let eip = if self.trace.is_some() { self.get_eip() } else { 0 };
+        self.executing = Some(start);
+        // a runtime error may have stopped the last instruction before its immediate data was freed
+        self.immediates.clear();
+
+        let esp = self.get_esp();
+        self.stack_range.get_or_insert((esp, esp));
+
+        // first token of the instruction, after the labels before it
+        let instruction;
+
         loop {
-            match self.text[self.get_eip()].get_token_type() {
+            // `org` only moves the addresses, like a label, and a declaration only checks the labels
+            if let Some(length) = self.directive_length(self.position) {
+                self.go_from_here(length as i32);
+                continue;
+            }
+
+            if let Some(msg) = self.deferred_errors.get(&self.position) {
+                panic!("{}", msg);
+            }
+
+            match self.text[self.position].get_token_type() {
                 TokenType::INSTRUCTION => {
-                    match self.text[self.get_eip()].get_token_value() {
+                    instruction = self.position;
+
+                    match self.text[self.position].get_token_value() {
                         TokenValue::MOV => self.mov(),
                         TokenValue::MOVSX => self.movsx(),
                         TokenValue::MOVZX => self.movzx(),
-                        TokenValue::ADD | TokenValue::SUB | TokenValue::AND |
-                            TokenValue::OR | TokenValue::XOR => self.binary_operation(),
+                        TokenValue::LEA => self.lea(),
+                        TokenValue::ADD | TokenValue::SUB | TokenValue::ADC | TokenValue::SBB | TokenValue::AND |
+                            TokenValue::OR | TokenValue::XOR | TokenValue::TEST => self.binary_operation(),
                         TokenValue::MUL => self.mul(),
                         TokenValue::IMUL => self.imul(),
-                        TokenValue::DIV | TokenValue::IDIV => self.div(),
+                        TokenValue::DIV => self.div(),
+                        TokenValue::IDIV => self.idiv(),
                         TokenValue::INC | TokenValue::DEC | TokenValue::NOT | TokenValue::NEG => self.unary_operation(),
+                        TokenValue::BSF | TokenValue::BSR => self.bit_scan(),
                         TokenValue::SHL | TokenValue::SHR | TokenValue::SAR => self.bitshift(),
                         TokenValue::PUSH => self.push(),
                         TokenValue::POP => self.pop(),
+                        TokenValue::PUSHAD => self.pushad(),
+                        TokenValue::POPAD => self.popad(),
+                        TokenValue::PUSHFD => self.pushfd(),
+                        TokenValue::POPFD => self.popfd(),
+                        TokenValue::LAHF => self.lahf(),
+                        TokenValue::SAHF => self.sahf(),
+                        TokenValue::STC | TokenValue::CLC | TokenValue::CMC | TokenValue::STD |
+                            TokenValue::CLD => self.flag_control(),
+                        TokenValue::DAA | TokenValue::DAS => self.decimal_adjust(),
+                        TokenValue::AAA | TokenValue::AAS => self.ascii_adjust(),
                         TokenValue::CMP => self.cmp(),
                         TokenValue::JMP | TokenValue::JE | TokenValue::JNE | TokenValue::JG | TokenValue::JGE | TokenValue::JL |
-                            TokenValue::JLE | TokenValue::JA | TokenValue::JAE | TokenValue::JB | TokenValue::JBE => self.jump(),
+                            TokenValue::JLE | TokenValue::JA | TokenValue::JAE | TokenValue::JB | TokenValue::JBE |
+                            TokenValue::JO | TokenValue::JNO | TokenValue::JS | TokenValue::JNS | TokenValue::JP |
+                            TokenValue::JNP | TokenValue::JECXZ | TokenValue::JCXZ => self.jump(),
+                        TokenValue::SETE | TokenValue::SETNE | TokenValue::SETG | TokenValue::SETGE | TokenValue::SETL |
+                            TokenValue::SETLE | TokenValue::SETA | TokenValue::SETAE | TokenValue::SETB |
+                            TokenValue::SETBE | TokenValue::SETO | TokenValue::SETNO | TokenValue::SETS |
+                            TokenValue::SETNS | TokenValue::SETP | TokenValue::SETNP => self.set(),
                         TokenValue::CALL => self.call(),
                         TokenValue::RET => self.ret(),
                         TokenValue::ENTER => self.enter(),
                         TokenValue::LEAVE => self.leave(),
-                        TokenValue::INT => break,
+                        TokenValue::INT => self.interrupt(),
+                        TokenValue::IVT => self.ivt(),
+                        TokenValue::IRET => self.iret(),
+                        TokenValue::HLT => self.hlt(),
+                        TokenValue::NOP => self.nop(),
+                        TokenValue::INTO => self.interrupt_on_overflow(),
+                        TokenValue::BOUND => self.bound(),
+                        TokenValue::CPUID => self.cpuid(),
+                        TokenValue::IN | TokenValue::OUT => self.port_io(),
+                        mnemonic if cfg::is_data(mnemonic) => {
+                            let token = &self.text[self.position];
+                            panic!("Runtime Error: {} Executing the bytes of {}, which are data",
+                                   token.get_token_location().to_string(), token.get_token_name().to_lowercase());
+                        },
                         _ => self.error_report(&format!("Unexpected instruction: {}",
-                                    self.text[self.get_eip()].get_token_name())),
+                                    self.text[self.position].get_token_name())),
                     }
+
+                    break;
                 },
                 TokenType::LABEL => {
                     self.go_from_here(2);
                 },
-                _ => self.error_report(&format!("Unexpected token: {}", self.text[self.get_eip()].get_token_name())),
+                _ => self.error_report(&format!("Unexpected token: {}", self.text[self.position].get_token_name())),
             }
+        }
+
+        self.virtual_time += NANOSECONDS_PER_INSTRUCTION;
+        self.check_timer();
+
+        let esp = self.get_esp();
+
+        if let Some((_, lowest)) = &mut self.stack_range {
+            *lowest = (*lowest).min(esp);
+        }
+
+        self.instructions_executed += 1;
+        self.executing = None;
+        self.immediates.clear();
 
-            if self.depth == 0 {
+        if self.trace.is_some() {
+            self.write_trace(start, eip);
+        }
+
+        if self.loop_detection {
+            self.detect_loop(start);
+        }
+
+        if self.pipeline.is_some() {
+            self.issue(instruction);
+        }
+
+        // the instruction which exceeds a quota completes, except for the output beyond it
+        for (quota, used) in [(Quota::MemoryWritten, self.memory_written.get()),
+                              (Quota::Stack, self.get_max_stack_usage() as u64), (Quota::Output, self.output_written)] {
+            if let Some(limit) = self.quotas.get(quota).filter(|limit| used > *limit) {
+                self.exceed_quota(quota, limit, start);
                 break;
             }
         }
+
+        !self.is_finished()
     }
 
     /// Run virtual machine with source file.
     /// # Example
     ///
-    /// ```
-    /// let vm = VM::new("./test1.asm".to_string());
+    /// ```no_run
+    /// # use asm_vm::vm::VM;
+    /// let mut vm = VM::new("./test1.asm".to_string());
     /// vm.run_file("./test2.asm".to_string());
     /// ```
     pub fn run_file(&mut self, source_file_name: String) {
@@ -1403,5 +4376,84 @@ impl VM {
         self.run();
     }
 
+    /// Run virtual machine with source code in memory, `source_name` is only used in diagnostics.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::vm::VM;
+    /// let mut vm: VM = Default::default();
+    /// vm.run_source("inline".to_string(), "main:\n    mov eax, 1\n    ret\n".to_string());
+    /// ```
+    pub fn run_source(&mut self, source_name: String, source: String) {
+        self.reset();
+
+        self.scanner = Scanner::from_source(source_name, source);
+
+        self.run();
+    }
+
+    /// Load source code in memory without running it, so that it can be executed by [`VM::step`].
+    pub fn load_source(&mut self, source_name: String, source: String) {
+        self.reset();
+
+        self.scanner = Scanner::from_source(source_name, source);
+
+        self.preprocess();
+    }
+
+    /// Run virtual machine with source code in memory and a memory image, such as the segments of an ELF file.
+    ///
+    /// Every segment is a pair of its address and bytes. The memory starts from the page of the lowest segment, so all
+    /// segments and the stack must fit in the memory of virtual machine.
+    pub fn run_image(&mut self, source_name: String, source: String, segments: &[(u32, Vec<u8>)]) {
+        self.reset();
+
+        let memory_base = segments.iter().map(|(address, _)| *address).min().unwrap_or(0) & !0xfff;
+        self.set_memory_base(memory_base);
+
+        for (address, bytes) in segments {
+            let (pointer, start, size) = self.memory(*address, bytes.len());
+            unsafe { (&mut *pointer)[start..start + size].copy_from_slice(bytes); }
+        }
+
+        self.scanner = Scanner::from_source(source_name, source);
+
+        self.run();
+    }
+
+    /// Run virtual machine with several source files.
+    ///
+    /// The source files are assembled into one text in the given order and share one label table, so `call` and
+    /// jump instructions may target a label defined in any of them.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use asm_vm::vm::VM;
+    /// let mut vm: VM = Default::default();
+    /// vm.run_files(vec!["./main.asm".to_string(), "./lib.asm".to_string()]);
+    /// ```
+    pub fn run_files(&mut self, source_file_names: Vec<String>) {
+        self.load_files(source_file_names);
+        self.execute();
+    }
+
+    /// Load several source files without running them, so that they can be executed by [`VM::step`].
+    pub fn load_files(&mut self, source_file_names: Vec<String>) {
+        self.reset();
+
+        for source_file_name in source_file_names {
+            self.scanner = Scanner::new(source_file_name);
+            self.scan();
+        }
+
+        self.resolve_labels();
+    }
+
 }
 
+/// Whether two locations are on the same line of the same source file.
+fn is_same_line(a: &TokenLocation, b: &TokenLocation) -> bool {
+    a.get_line() == b.get_line() && a.get_source_file_name() == b.get_source_file_name()
+}
@@ -0,0 +1,249 @@
+//! Minimal WebSocket server side (RFC 6455): the opening handshake and text frames, enough for a debugging front end
+//! running in a browser.
+
+use std::io::prelude::*;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// largest message accepted from a client
+const MAX_MESSAGE: usize = 16 * 1024 * 1024;
+
+/// Message received from a client
+pub enum Message {
+    Text(String),
+    Ping(Vec<u8>),
+    Close,
+}
+
+/// Read the HTTP upgrade request from `reader`, and answer it on `writer`.
+pub fn accept(reader: &mut impl BufRead, writer: &mut impl Write) -> Result<(), String> {
+    let mut key = None;
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).map_err(|err| err.to_string())?;
+
+    if !request_line.starts_with("GET ") {
+        return Err(format!("Not a WebSocket handshake: {}", request_line.trim()));
+    }
+
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Err("Connection closed during the WebSocket handshake".to_string());
+        }
+
+        let line = line.trim();
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Sec-WebSocket-Key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let key = match key {
+        Some(key) => key,
+        None => {
+            writer.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n").map_err(|err| err.to_string())?;
+            return Err("Missing Sec-WebSocket-Key".to_string());
+        },
+    };
+
+    let response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                            Sec-WebSocket-Accept: {}\r\n\r\n", accept_key(&key));
+
+    writer.write_all(response.as_bytes()).map_err(|err| err.to_string())
+}
+
+/// Compute `Sec-WebSocket-Accept` from `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+/// Read one message, the fragments of a message are joined. `Ok(None)` if the connection is closed.
+pub fn read_message(reader: &mut impl Read) -> Result<Option<Message>, String> {
+    let mut message = Vec::new();
+    let mut message_opcode = None;
+
+    loop {
+        let mut header = [0; 2];
+
+        match reader.read_exact(&mut header) {
+            Ok(()) => {},
+            Err(ref err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.to_string()),
+        }
+
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0f;
+        let masked = header[1] & 0x80 != 0;
+
+        let size = match header[1] & 0x7f {
+            126 => {
+                let mut size = [0; 2];
+                reader.read_exact(&mut size).map_err(|err| err.to_string())?;
+                u16::from_be_bytes(size) as u64
+            },
+            127 => {
+                let mut size = [0; 8];
+                reader.read_exact(&mut size).map_err(|err| err.to_string())?;
+                u64::from_be_bytes(size)
+            },
+            size => size as u64,
+        };
+
+        if size + message.len() as u64 > MAX_MESSAGE as u64 {
+            return Err(format!("WebSocket message is larger than {} bytes", MAX_MESSAGE));
+        }
+
+        let mut mask = [0; 4];
+
+        if masked {
+            reader.read_exact(&mut mask).map_err(|err| err.to_string())?;
+        }
+
+        let mut payload = vec![0; size as usize];
+        reader.read_exact(&mut payload).map_err(|err| err.to_string())?;
+
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        match opcode {
+            CLOSE => return Ok(Some(Message::Close)),
+            PING => return Ok(Some(Message::Ping(payload))),
+            PONG => continue,
+            TEXT | BINARY if message_opcode.is_none() => message_opcode = Some(opcode),
+            CONTINUATION if message_opcode.is_some() => {},
+            _ => return Err(format!("Unexpected WebSocket opcode: {:#x}", opcode)),
+        }
+
+        message.extend_from_slice(&payload);
+
+        if fin {
+            return match String::from_utf8(message) {
+                Ok(text) => Ok(Some(Message::Text(text))),
+                Err(_) => Err("WebSocket message is not valid UTF-8".to_string()),
+            };
+        }
+    }
+}
+
+/// Write one unfragmented frame, frames of a server are not masked.
+fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> Result<(), String> {
+    let mut frame = vec![0x80 | opcode];
+
+    match payload.len() {
+        size if size < 126 => frame.push(size as u8),
+        size if size <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(size as u16).to_be_bytes());
+        },
+        size => {
+            frame.push(127);
+            frame.extend_from_slice(&(size as u64).to_be_bytes());
+        },
+    }
+
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame).map_err(|err| err.to_string())
+}
+
+pub fn write_text(writer: &mut impl Write, text: &str) -> Result<(), String> {
+    write_frame(writer, TEXT, text.as_bytes())
+}
+
+pub fn write_pong(writer: &mut impl Write, payload: &[u8]) -> Result<(), String> {
+    write_frame(writer, PONG, payload)
+}
+
+pub fn write_close(writer: &mut impl Write) -> Result<(), String> {
+    write_frame(writer, CLOSE, &[])
+}
+
+/// SHA-1 digest, only used by the handshake.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([chunk[i * 4], chunk[i * 4 + 1], chunk[i * 4 + 2], chunk[i * 4 + 3]]);
+        }
+
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (h, value) in h.iter_mut().zip(&[a, b, c, d, e]) {
+            *h = h.wrapping_add(*value);
+        }
+    }
+
+    let mut digest = [0; 20];
+
+    for (i, h) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&h.to_be_bytes());
+    }
+
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(value >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
@@ -0,0 +1,83 @@
+//! Flags of every pair of 8-bit operands, against the flags computed with the arithmetic of `u8` and `i8`.
+
+use asm_vm::flags::{self, Arithmetic};
+
+/// `cf`, `of`, `sf`, `zf`, `af` and `pf` of `result`
+fn expected(result: u8, cf: bool, of: bool, af: bool) -> [bool; 6] {
+    [cf, of, (result as i8) < 0, result == 0, af, result.count_ones().is_multiple_of(2)]
+}
+
+fn actual(arithmetic: Arithmetic) -> [bool; 6] {
+    [arithmetic.cf, arithmetic.of, arithmetic.sf, arithmetic.zf, arithmetic.af, flags::parity(arithmetic.result)]
+}
+
+fn check(operation: &str, first: u8, second: u8, arithmetic: Arithmetic, result: u8, expected: [bool; 6]) {
+    assert_eq!(arithmetic.result, result as u32, "result of {} {:#04x}, {:#04x}", operation, first, second);
+    assert_eq!(actual(arithmetic), expected, "cf, of, sf, zf, af and pf of {} {:#04x}, {:#04x}", operation, first,
+               second);
+}
+
+#[test]
+fn binary_operations() {
+    for first in 0..=u8::MAX {
+        for second in 0..=u8::MAX {
+            let (result, cf) = first.overflowing_add(second);
+            let of = (first as i8).overflowing_add(second as i8).1;
+            let af = (first & 0xf) + (second & 0xf) > 0xf;
+            let arithmetic = flags::add(first as u32, second as u32, 1);
+            check("add", first, second, arithmetic, result, expected(result, cf, of, af));
+
+            let (result, cf) = first.overflowing_sub(second);
+            let of = (first as i8).overflowing_sub(second as i8).1;
+            let af = first & 0xf < second & 0xf;
+            let arithmetic = flags::sub(first as u32, second as u32, 1);
+            check("sub", first, second, arithmetic, result, expected(result, cf, of, af));
+
+            for carry in [false, true] {
+                let (sum, carry_out) = first.overflowing_add(second);
+                let (result, carry_again) = sum.overflowing_add(carry as u8);
+                let (signed_sum, overflow) = (first as i8).overflowing_add(second as i8);
+                let overflow_again = signed_sum.overflowing_add(carry as i8).1;
+                let af = (first & 0xf) + (second & 0xf) + carry as u8 > 0xf;
+                let expected_flags = expected(result, carry_out || carry_again, overflow != overflow_again, af);
+                check("adc", first, second, flags::adc(first as u32, second as u32, carry, 1), result, expected_flags);
+
+                let (difference, borrow_out) = first.overflowing_sub(second);
+                let (result, borrow_again) = difference.overflowing_sub(carry as u8);
+                let (signed_difference, overflow) = (first as i8).overflowing_sub(second as i8);
+                let overflow_again = signed_difference.overflowing_sub(carry as i8).1;
+                let af = first & 0xf < (second & 0xf) + carry as u8;
+                let expected_flags = expected(result, borrow_out || borrow_again, overflow != overflow_again, af);
+                check("sbb", first, second, flags::sbb(first as u32, second as u32, carry, 1), result, expected_flags);
+            }
+
+            // only cf and of are defined after imul, and both tell that the product does not fit in the operand size
+            let overflow = (first as i8).overflowing_mul(second as i8).1;
+            let (product, imul_overflow) = flags::imul(first as u32, second as u32, 1);
+            assert_eq!(product, first as i8 as i64 * second as i8 as i64, "product of imul {:#04x}, {:#04x}", first,
+                       second);
+            assert_eq!(imul_overflow, overflow, "cf and of of imul {:#04x}, {:#04x}", first, second);
+        }
+    }
+}
+
+#[test]
+fn unary_operations() {
+    for operand in 0..=u8::MAX {
+        // inc and dec leave cf unchanged, so the carry they return is the one of adding or subtracting 1
+        let (result, cf) = operand.overflowing_add(1);
+        let of = (operand as i8).overflowing_add(1).1;
+        let af = operand & 0xf == 0xf;
+        check("inc", operand, 1, flags::inc(operand as u32, 1), result, expected(result, cf, of, af));
+
+        let (result, cf) = operand.overflowing_sub(1);
+        let of = (operand as i8).overflowing_sub(1).1;
+        let af = operand & 0xf == 0;
+        check("dec", operand, 1, flags::dec(operand as u32, 1), result, expected(result, cf, of, af));
+
+        let result = operand.wrapping_neg();
+        let of = (operand as i8).overflowing_neg().1;
+        let af = operand & 0xf != 0;
+        check("neg", operand, 0, flags::neg(operand as u32, 1), result, expected(result, operand != 0, of, af));
+    }
+}
@@ -0,0 +1,30 @@
+//! `org`, which sets the address of the statements after it.
+
+use asm_vm::vm::{catch_error, VM};
+
+/// Load `source`, and get the virtual machine or the error which stopped it.
+fn load(source: &str) -> Result<VM, String> {
+    let mut vm = VM::default();
+    catch_error(|| vm.load_source("test.asm".to_string(), source.to_string()))?;
+    Ok(vm)
+}
+
+#[test]
+fn labels_after_org_are_at_its_address() {
+    let vm = load("org 0x7c00\nmain:\n mov eax, 1\nnext:\n ret\n").unwrap();
+    assert_eq!(vm.get_symbol("main"), Some(0x7c00));
+    assert_eq!(vm.get_symbol("next"), Some(0x7c05));
+}
+
+#[test]
+fn statements_up_to_the_end_of_the_address_space_are_laid_out() {
+    let vm = load("org 0xfffffff0\nmain:\n times 14 nop\nlast:\n ret\n").unwrap();
+    assert_eq!(vm.get_symbol("last"), Some(0xfffffffe));
+}
+
+#[test]
+fn statements_beyond_the_address_space_are_a_syntax_error() {
+    let msg = load("org 0xfffffff0\nmain:\n times 8 nop\n mov eax, 1\n mov ebx, 2\n ret\n").err().unwrap();
+    assert!(msg.starts_with("Syntax Error: test.asm:5:2: ") && msg.ends_with("beyond the 32-bits address space"),
+            "{}", msg);
+}
@@ -0,0 +1,63 @@
+//! The embedded suite of `asm-vm selftest`, so that `cargo test` catches a regression of the flags, the stack or the
+//! jumps.
+
+use asm_vm::selftest;
+use asm_vm::vm::{catch_error, VM};
+
+#[test]
+fn every_case_of_the_suite_passes() {
+    let results = selftest::selftest();
+    assert!(!results.is_empty());
+
+    let failures: Vec<String> = results.iter()
+        .filter_map(|result| result.failure.as_ref().map(|failure| format!("{}: {}", result.name, failure)))
+        .collect();
+    assert!(failures.is_empty(), "failed cases of the self-test:\n{}", failures.join("\n"));
+}
+
+/// Load and run `source`, and get the virtual machine after it finishes or the error which stopped it.
+fn run(source: &str) -> Result<VM, String> {
+    let mut vm = VM::default();
+
+    catch_error(|| {
+        vm.load_source("test.asm".to_string(), source.to_string());
+        while vm.step() {}
+    })?;
+
+    Ok(vm)
+}
+
+/// Assert that `source` stops with a runtime error which ends with `reason`.
+fn assert_runtime_error(source: &str, reason: &str) {
+    match run(source) {
+        Ok(_) => panic!("finished, expected Runtime Error: {}", reason),
+        Err(msg) => assert!(msg.starts_with("Runtime Error: ") && msg.ends_with(reason),
+                            "{}, expected Runtime Error: {}", msg, reason),
+    }
+}
+
+#[test]
+fn idiv_of_i32_min_by_minus_one_is_a_runtime_error() {
+    assert_runtime_error("main:\n mov eax, 0x80000000\n mov edx, 0xffffffff\n mov ecx, -1\n idiv ecx\n ret\n",
+                         "The quotient of idiv does not fit in 32 bits");
+    // the quotient of 8 bits, 128, does not fit either
+    assert_runtime_error("main:\n mov ax, 0xff80\n mov cl, -1\n idiv cl\n ret\n",
+                         "The quotient of idiv does not fit in 8 bits");
+}
+
+#[test]
+fn idiv_of_i32_min_by_one_and_by_two_fits() {
+    let vm = run("main:\n mov eax, 0x80000000\n mov edx, 0xffffffff\n mov ecx, 1\n idiv ecx\n mov esi, eax\n \
+                  mov edi, edx\n mov eax, 0x80000000\n mov edx, 0xffffffff\n mov ecx, 2\n idiv ecx\n ret\n").unwrap();
+    assert_eq!(vm.get_register("esi"), Some(i32::MIN as u32));
+    assert_eq!(vm.get_register("edi"), Some(0));
+    assert_eq!(vm.get_register("eax"), Some((i32::MIN / 2) as u32));
+    assert_eq!(vm.get_register("edx"), Some(0));
+}
+
+#[test]
+fn division_by_zero_is_a_runtime_error() {
+    assert_runtime_error("main:\n mov eax, 7\n mov edx, 0\n mov ecx, 0\n idiv ecx\n ret\n", "Division by zero");
+    assert_runtime_error("main:\n mov eax, 7\n mov edx, 0\n mov ecx, 0\n div ecx\n ret\n", "Division by zero");
+    assert_runtime_error("main:\n mov ax, 7\n mov bl, 0\n div bl\n ret\n", "Division by zero");
+}
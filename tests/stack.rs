@@ -0,0 +1,54 @@
+//! `push` and `pop` of 16 bits, which move `esp` by 2 and transfer 2 bytes.
+
+use asm_vm::vm::{catch_error, VM};
+
+/// Load and run `source`, and get the virtual machine after it finishes or the error which stopped it.
+fn run(source: &str) -> Result<VM, String> {
+    let mut vm = VM::default();
+
+    catch_error(|| {
+        vm.load_source("test.asm".to_string(), source.to_string());
+        while vm.step() {}
+    })?;
+
+    Ok(vm)
+}
+
+#[test]
+fn push_of_16_bits_moves_esp_by_2_and_stores_2_bytes() {
+    let vm = run("main:\n mov ecx, esp\n mov eax, 0x11112222\n mov dword ptr [esp - 4], 0xffffffff\n push ax\n \
+                  mov esi, esp\n sub ecx, esp\n mov edi, [esp - 2]\n hlt\n").unwrap();
+    let esp = vm.get_esp();
+    assert_eq!(vm.get_register("ecx"), Some(2));
+    assert_eq!(vm.get_register("esi"), Some(esp));
+    assert_eq!(vm.read_memory(esp, 2), Some(&[0x22, 0x22][..]));
+    // the 2 bytes below are left as they were
+    assert_eq!(vm.read_memory(esp - 2, 2), Some(&[0xff, 0xff][..]));
+    assert_eq!(vm.get_register("edi"), Some(0x2222_ffff));
+}
+
+#[test]
+fn pop_of_16_bits_moves_esp_by_2_and_loads_2_bytes() {
+    let vm = run("main:\n mov ecx, esp\n push 0x33334444\n mov si, 0x5555\n pop si\n pop di\n sub ecx, esp\n ret\n")
+        .unwrap();
+    assert_eq!(vm.get_register("esi"), Some(0x4444));
+    assert_eq!(vm.get_register("edi"), Some(0x3333));
+    assert_eq!(vm.get_register("ecx"), Some(0));
+}
+
+#[test]
+fn pop_of_a_word_of_memory_addresses_it_after_moving_esp() {
+    // `pop word ptr [esp]` writes the word above the one it pops, as esp has moved by 2
+    let vm = run("main:\n sub esp, 8\n mov ecx, esp\n push 0x66667777\n pop word ptr [esp]\n pop ebx\n \
+                  sub ecx, esp\n hlt\n").unwrap();
+    assert_eq!(vm.get_register("ebx").map(|ebx| ebx & 0xffff), Some(0x7777));
+    assert_eq!(vm.get_register("ecx"), Some(0xfffffffe));
+}
+
+#[test]
+fn push_and_pop_of_8_bits_are_syntax_errors() {
+    for source in ["main:\n push al\n ret\n", "main:\n pop bl\n ret\n", "main:\n pop byte ptr [esp]\n ret\n"] {
+        let msg = run(source).err().unwrap_or_else(|| panic!("{:?} finished, expected a syntax error", source));
+        assert!(msg.starts_with("Syntax Error: "), "{}", msg);
+    }
+}